@@ -0,0 +1,188 @@
+//! This module reports every mutation sent by a client, single or bulk, to
+//! an [`AuditHook`], along with the tags set on the request with
+//! [`crate::options::RequestOptions::with_audit_tags`]. The hook is called
+//! before the request is sent, whether or not it succeeds.
+
+use std::{borrow::Cow, fmt::Debug};
+
+use crate::protocol::{
+    Packet, ADDQ_OPCODE, ADD_OPCODE, DECREMENTQ_OPCODE, DECREMENT_OPCODE, DELETEQ_OPCODE,
+    DELETE_OPCODE, INCREMENTQ_OPCODE, INCREMENT_OPCODE, REPLACEQ_OPCODE, REPLACE_OPCODE,
+    SETQ_OPCODE, SET_OPCODE, TOUCH_OPCODE,
+};
+
+/// The kind of mutation reported to an [`AuditHook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    /// A key was set, unconditionally or with a CAS value.
+    Set,
+    /// A key was set only if it was not already set.
+    Add,
+    /// A key was set only if it was already set.
+    Replace,
+    /// A key was deleted.
+    Delete,
+    /// A counter was incremented.
+    Increment,
+    /// A counter was decremented.
+    Decrement,
+    /// The expiration of a key was changed.
+    Touch,
+}
+
+impl AuditOp {
+    /// The mutation made by a request with the given opcode, if any.
+    pub(crate) fn from_opcode(opcode: u8) -> Option<Self> {
+        match opcode {
+            SET_OPCODE | SETQ_OPCODE => Some(AuditOp::Set),
+            ADD_OPCODE | ADDQ_OPCODE => Some(AuditOp::Add),
+            REPLACE_OPCODE | REPLACEQ_OPCODE => Some(AuditOp::Replace),
+            DELETE_OPCODE | DELETEQ_OPCODE => Some(AuditOp::Delete),
+            INCREMENT_OPCODE | INCREMENTQ_OPCODE => Some(AuditOp::Increment),
+            DECREMENT_OPCODE | DECREMENTQ_OPCODE => Some(AuditOp::Decrement),
+            TOUCH_OPCODE => Some(AuditOp::Touch),
+            _ => None,
+        }
+    }
+}
+
+/// A single mutation, reported to [`AuditHook::on_mutation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEvent<'a> {
+    /// The key being mutated.
+    pub key: &'a [u8],
+    /// The kind of mutation.
+    pub op: AuditOp,
+    /// The size of the value written in bytes, as serialized and wrapped in
+    /// the envelope of the client, if any, but before compression. It is 0
+    /// for mutations which write no value.
+    pub size: usize,
+    /// The tags of the request, or none for requests made without options.
+    pub tags: &'a [Cow<'static, str>],
+}
+
+/// Receives every mutation made by the clients created from a config.
+pub trait AuditHook: Debug + Send + Sync {
+    /// Called before a mutation is sent.
+    fn on_mutation(&self, event: &AuditEvent<'_>);
+}
+
+/// Report every mutation among the requests to the hook, if any.
+pub(crate) fn record<'a, I>(hook: Option<&dyn AuditHook>, packets: I, tags: &[Cow<'static, str>])
+where
+    I: IntoIterator<Item = &'a Packet>,
+{
+    let hook = match hook {
+        Some(hook) => hook,
+        None => return,
+    };
+    for packet in packets {
+        if let Some(op) = AuditOp::from_opcode(packet.header.opcode) {
+            let size = packet.value.len();
+            let key = &packet.key;
+            hook.on_mutation(&AuditEvent {
+                key,
+                op,
+                size,
+                tags,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::MockConnection,
+        options::RequestOptions,
+    };
+
+    use super::{AuditEvent, AuditHook, AuditOp};
+
+    type Recorded = (Vec<u8>, AuditOp, usize, Vec<String>);
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<Recorded>>);
+
+    impl AuditHook for Recorder {
+        fn on_mutation(&self, event: &AuditEvent<'_>) {
+            let event = (
+                event.key.to_vec(),
+                event.op,
+                event.size,
+                event.tags.iter().map(|tag| tag.to_string()).collect(),
+            );
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn test_audit_hook() {
+        tokio_test::block_on(async {
+            let recorder = Arc::new(Recorder::default());
+            let cfg = ClientConfig::new_uncompressed(vec!["audit".into()])
+                .with_audit_hook(recorder.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let options = RequestOptions::new().with_audit_tags(["pii"]);
+            client
+                .set_with_options("user", "secret", 0, &options)
+                .await
+                .unwrap();
+            client.get::<_, String>("user").await.unwrap();
+            client.incr("count", 1, 0, 0).await.unwrap();
+            let data = HashMap::from([("a", "1"), ("b", "2")]);
+            client.set_multi(data, 0).await.unwrap();
+            client.delete_multi(&["a"]).await.unwrap();
+
+            // Bulk writes carry the tags of their options, which may be built
+            // at runtime.
+            let tenant = format!("tenant:{}", 7);
+            let options = RequestOptions::new().with_audit_tags([tenant.clone()]);
+            let data = HashMap::from([("c", "3")]);
+            client
+                .set_multi_with_options(data, 0, &options)
+                .await
+                .unwrap();
+            client
+                .delete_multi_with_options(&["c"], &options)
+                .await
+                .unwrap();
+            let mut batch = client.batch();
+            batch.delete("b").unwrap();
+            batch.flush_with_options(&options).await.unwrap();
+            let data = HashMap::from([("d", "4")]);
+            client
+                .add_multi_with_options(data, 0, &options)
+                .await
+                .unwrap();
+            let deltas = HashMap::from([("count", 1)]);
+            client
+                .incr_multi_with_options(deltas, 0, 0, &options)
+                .await
+                .unwrap();
+
+            let mut events = recorder.0.lock().unwrap().clone();
+            events[2..4].sort_by(|a, b| a.0.cmp(&b.0));
+            let (pii, tenant) = (vec!["pii".to_string()], vec![tenant]);
+            let expect = vec![
+                (b"user".to_vec(), AuditOp::Set, 14, pii),
+                (b"count".to_vec(), AuditOp::Increment, 0, vec![]),
+                (b"a".to_vec(), AuditOp::Set, 9, vec![]),
+                (b"b".to_vec(), AuditOp::Set, 9, vec![]),
+                (b"a".to_vec(), AuditOp::Delete, 0, vec![]),
+                (b"c".to_vec(), AuditOp::Set, 9, tenant.clone()),
+                (b"c".to_vec(), AuditOp::Delete, 0, tenant.clone()),
+                (b"b".to_vec(), AuditOp::Delete, 0, tenant.clone()),
+                (b"d".to_vec(), AuditOp::Add, 9, tenant.clone()),
+                (b"count".to_vec(), AuditOp::Increment, 0, tenant),
+            ];
+            assert_eq!(expect, events);
+        });
+    }
+}
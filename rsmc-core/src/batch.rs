@@ -0,0 +1,321 @@
+//! Bulk methods like [`Client::set_multi`] need every key up front, which
+//! does not suit code where the writes of one logical request are made by
+//! several functions. A [`BatchGuard`], created with [`Client::batch`], can be
+//! passed around to collect mutations of any kind and value type, and sends
+//! them all when it is flushed, as a single pipeline per node of quiet
+//! requests terminated by a NOOP.
+//!
+//! Futures cannot be awaited on drop, so a guard dropped without calling
+//! [`BatchGuard::flush`] discards its queued mutations.
+//!
+//! Bulk calls made with unbounded input, such as keys taken from a user
+//! request, can build pipelines of many megabytes which hog a connection.
+//! [`BatchLimits`] configured with
+//! [`crate::client::ClientConfig::with_batch_limits`] bound the number of
+//! keys and the request bytes of every bulk get and write, either rejecting
+//! larger calls with [`Error::BatchTooLarge`] or splitting them into chunks
+//! which are sent one after the other.
+
+use serde::Serialize;
+
+use crate::{
+    client::{BulkUpdateResponse, Client, Compressor, Connection, Error},
+    local::Invalidation,
+    options::RequestOptions,
+    protocol::{Packet, SetExtras, TouchExtras},
+};
+
+/// The largest bulk calls a client makes at once. Without limits, calls of
+/// any size are sent as a single pipeline per node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchLimits {
+    /// The most keys in a single call.
+    pub max_keys: Option<usize>,
+    /// The most bytes of requests in a single call, counting the headers,
+    /// keys and serialized values before compression.
+    pub max_bytes: Option<usize>,
+    /// Split calls over the limits into chunks within them, instead of
+    /// failing with [`Error::BatchTooLarge`]. A single request over the
+    /// byte limit still fails.
+    pub chunk: bool,
+}
+
+impl BatchLimits {
+    /// Create limits which allow calls of any size.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of keys in a single call.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    /// Limit the bytes of requests in a single call.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Split calls over the limits into chunks instead of rejecting them.
+    pub fn with_chunking(mut self, chunk: bool) -> Self {
+        self.chunk = chunk;
+        self
+    }
+
+    fn within(&self, keys: usize, bytes: usize) -> bool {
+        self.max_keys.map_or(true, |max| keys <= max)
+            && self.max_bytes.map_or(true, |max| bytes <= max)
+    }
+
+    /// Split items of the given request sizes into chunks within the limits,
+    /// keeping their order. Items within the limits make a single chunk.
+    pub(crate) fn split<T>(&self, items: Vec<(T, usize)>) -> Result<Vec<Vec<T>>, Error> {
+        let bytes = items.iter().map(|(_, size)| size).sum();
+        if self.within(items.len(), bytes) {
+            return Ok(vec![items.into_iter().map(|(item, _)| item).collect()]);
+        }
+        if !self.chunk {
+            return Err(Error::BatchTooLarge(items.len(), bytes));
+        }
+        let (mut chunks, mut chunk, mut chunk_bytes) = (vec![], vec![], 0);
+        for (item, size) in items {
+            if !self.within(1, size) {
+                return Err(Error::BatchTooLarge(1, size));
+            }
+            if !self.within(chunk.len() + 1, chunk_bytes + size) {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_bytes = 0;
+            }
+            chunk.push(item);
+            chunk_bytes += size;
+        }
+        chunks.push(chunk);
+        Ok(chunks)
+    }
+}
+
+/// A mutation queued in a batch, along with how it invalidates the key in
+/// the local tier.
+#[derive(Debug)]
+pub(crate) struct Queued {
+    pub packet: Packet,
+    pub reason: Invalidation,
+}
+
+impl AsRef<[u8]> for Queued {
+    fn as_ref(&self) -> &[u8] {
+        &self.packet.key
+    }
+}
+
+/// Queues mutations until [`BatchGuard::flush`] sends them together.
+/// Mutations to the same key are sent in the order they were queued.
+#[derive(Debug)]
+pub struct BatchGuard<'a, C: Connection, P: Compressor> {
+    client: &'a mut Client<C, P>,
+    queued: Vec<Queued>,
+}
+
+impl<'a, C: Connection, P: Compressor> BatchGuard<'a, C, P> {
+    pub(crate) fn new(client: &'a mut Client<C, P>) -> Self {
+        Self {
+            client,
+            queued: vec![],
+        }
+    }
+
+    /// Queue setting a key to a value.
+    pub fn set<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &mut self,
+        key: K,
+        value: &V,
+        expire: u32,
+    ) -> Result<(), Error> {
+        let key = key.as_ref();
+        let value = self.client.redact(key, value);
+        let packet = Packet::setq(key, &value, SetExtras::new(0, expire))?;
+        self.queue_store(packet, expire)
+    }
+
+    /// Queue setting a key only if it is not already set. Keys which were
+    /// set are reported with [`crate::protocol::Status::KeyExists`].
+    pub fn add<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &mut self,
+        key: K,
+        value: &V,
+        expire: u32,
+    ) -> Result<(), Error> {
+        let key = key.as_ref();
+        let value = self.client.redact(key, value);
+        let packet = Packet::addq(key, &value, SetExtras::new(0, expire))?;
+        self.queue_store(packet, expire)
+    }
+
+    /// Queue setting a key only if it is already set. Keys which were not
+    /// set are reported with [`crate::protocol::Status::ItemNotStored`].
+    pub fn replace<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &mut self,
+        key: K,
+        value: &V,
+        expire: u32,
+    ) -> Result<(), Error> {
+        let key = key.as_ref();
+        let value = self.client.redact(key, value);
+        let packet = Packet::replaceq(key, &value, SetExtras::new(0, expire))?;
+        self.queue_store(packet, expire)
+    }
+
+    /// Queue deleting a key. Deleting a key which is not set does nothing.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> Result<(), Error> {
+        let packet = Packet::deleteq(key)?;
+        self.queue(packet, Invalidation::Deleted);
+        Ok(())
+    }
+
+    /// Queue changing the expiration of a key. Keys which were not set are
+    /// reported with [`crate::protocol::Status::KeyNotFound`].
+    pub fn touch<K: AsRef<[u8]>>(&mut self, key: K, expire: u32) -> Result<(), Error> {
+        let packet = Packet::touch(key, TouchExtras::new(expire))?;
+        self.queue(packet, Invalidation::Overwritten);
+        Ok(())
+    }
+
+    /// The number of queued mutations.
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Whether no mutations are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Send every queued mutation, returning the errors of those which
+    /// failed by key.
+    pub async fn flush(self) -> BulkUpdateResponse {
+        self.flush_with_options(&RequestOptions::default()).await
+    }
+
+    /// Send every queued mutation like [`BatchGuard::flush`], with options
+    /// overriding the client configuration. Only the deadline, which is
+    /// checked before sending, and the audit tags apply.
+    pub async fn flush_with_options(mut self, options: &RequestOptions) -> BulkUpdateResponse {
+        let queued = std::mem::take(&mut self.queued);
+        self.client.send_batch(queued, options).await
+    }
+
+    fn queue_store(&mut self, packet: Packet, expire: u32) -> Result<(), Error> {
+        let packet = self.client.prepare_store(packet, expire)?;
+        self.queue(packet, Invalidation::Overwritten);
+        Ok(())
+    }
+
+    fn queue(&mut self, packet: Packet, reason: Invalidation) {
+        self.queued.push(Queued { packet, reason });
+    }
+}
+
+impl<C: Connection, P: Compressor> Drop for BatchGuard<'_, C, P> {
+    fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        if !self.queued.is_empty() {
+            tracing::warn!(
+                mutations = self.queued.len(),
+                "dropped a batch without flushing it"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        client::{Client, ClientConfig, Error, NoCompressor},
+        diagnostics::ConfigError,
+        mock::MockConnection,
+        protocol::Status,
+    };
+
+    use super::{BatchGuard, BatchLimits};
+
+    /// Queue writes from a function which knows nothing about the rest of
+    /// the batch.
+    fn record_visit(batch: &mut BatchGuard<'_, MockConnection, NoCompressor>) {
+        batch.set("visits", &3_u32, 0).unwrap();
+        batch.delete("stale").unwrap();
+    }
+
+    #[test]
+    fn test_batch_guard() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["batch:1".into(), "batch:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("stale", "value", 0).await.unwrap();
+            client.set("taken", "value", 0).await.unwrap();
+
+            let mut batch = client.batch();
+            batch.set("name", "value", 0).unwrap();
+            batch.add("taken", "other", 0).unwrap();
+            batch.touch("missing", 60).unwrap();
+            record_visit(&mut batch);
+            assert_eq!(5, batch.len());
+            let mut errors = batch.flush().await.unwrap();
+            let taken = errors.remove(b"taken".as_slice());
+            let missing = errors.remove(b"missing".as_slice());
+            assert!(matches!(taken, Some(Error::Status(Status::KeyExists))));
+            assert!(matches!(missing, Some(Error::Status(Status::KeyNotFound))));
+            assert!(errors.is_empty());
+
+            let name = client.get::<_, String>("name").await.unwrap();
+            assert_eq!(Some("value".to_string()), name);
+            assert_eq!(Some(3), client.get::<_, u32>("visits").await.unwrap());
+            assert_eq!(None, client.get::<_, String>("stale").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_batch_limits() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["batch_limits:1".into()]);
+            let invalid = BatchLimits::new().with_max_keys(0);
+            let invalid = cfg.clone().with_batch_limits(invalid).validate();
+            assert_eq!(Err(ConfigError::InvalidBatchLimits), invalid);
+
+            let limits = BatchLimits::new().with_max_keys(2).with_max_bytes(100);
+            let strict = cfg.clone().with_batch_limits(limits);
+            let mut client = Client::<MockConnection, _>::new(strict).await.unwrap();
+            let keys = ["a", "b", "c"];
+            let err = client.get_multi::<_, String>(&keys).await.unwrap_err();
+            assert!(matches!(err, Error::BatchTooLarge(3, 75)));
+            let err = client.delete_multi(&keys).await.unwrap_err();
+            assert!(matches!(err, Error::BatchTooLarge(3, _)));
+            let deltas = keys.iter().map(|key| (*key, 1)).collect::<HashMap<_, _>>();
+            let err = client.incr_multi(deltas.clone(), 0, 0).await.unwrap_err();
+            assert!(matches!(err, Error::BatchTooLarge(3, 135)));
+
+            let chunked = cfg.with_batch_limits(limits.with_chunking(true));
+            let mut client = Client::<MockConnection, _>::new(chunked).await.unwrap();
+            let data = keys
+                .iter()
+                .map(|key| (*key, "value"))
+                .collect::<HashMap<_, _>>();
+            assert!(client.set_multi(data, 0).await.unwrap().is_empty());
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(3, values.len());
+            assert!(client.delete_multi(&keys).await.unwrap().is_empty());
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(values.is_empty());
+            let (values, errors) = client.incr_multi(deltas, 0, 0).await.unwrap();
+            assert_eq!((3, 0), (values.len(), errors.len()));
+
+            let large = HashMap::from([("large", "x".repeat(100))]);
+            let err = client.set_multi(large, 0).await.unwrap_err();
+            assert!(matches!(err, Error::BatchTooLarge(1, 145)));
+        });
+    }
+}
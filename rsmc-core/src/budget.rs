@@ -0,0 +1,215 @@
+//! An error budget bounds the latency impact of a partial cache outage. When
+//! the recent error rate of a node exceeds the budget, reads routed to that
+//! node fail open and return misses without touching the network until a
+//! cooldown has passed.
+//!
+//! A retry budget bounds the latency impact of a flapping node within a
+//! single bulk call, by capping how many of its keys may be retried.
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Configure when reads to a node should fail open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorBudget {
+    /// The number of recent requests used to compute the error rate.
+    pub window: usize,
+    /// The minimum number of requests in the window before the budget can
+    /// be exhausted.
+    pub min_requests: usize,
+    /// The error rate (between 0 and 1) above which reads fail open.
+    pub max_error_rate: f64,
+    /// How long reads fail open before the node is tried again.
+    pub cooldown: Duration,
+}
+
+impl Default for ErrorBudget {
+    fn default() -> Self {
+        Self {
+            window: 100,
+            min_requests: 20,
+            max_error_rate: 0.5,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Configure how many keys of a single [`crate::client::Client::get_multi`]
+/// may be retried when the connection to their node was closed. Nodes are
+/// retried as a whole, and only while the keys of the node fit in what is
+/// left of the budget of the call, so a node that keeps failing cannot
+/// multiply the latency of the call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryBudget {
+    /// The fraction (between 0 and 1) of the keys of a call which may be
+    /// retried.
+    pub ratio: f64,
+    /// The number of keys which may always be retried, so that small calls
+    /// can retry at all.
+    pub min_keys: usize,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self {
+            ratio: 0.1,
+            min_keys: 10,
+        }
+    }
+}
+
+impl RetryBudget {
+    /// Allow retrying a fraction of the keys of every call.
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio,
+            ..Self::default()
+        }
+    }
+
+    /// Always allow retrying at least this many keys.
+    pub fn with_min_keys(mut self, min_keys: usize) -> Self {
+        self.min_keys = min_keys;
+        self
+    }
+
+    /// Start tracking the retries of a call for the given number of keys.
+    pub(crate) fn start(&self, keys: usize) -> RetryTracker {
+        let ratio = self.ratio.clamp(0.0, 1.0);
+        let allowed = (keys as f64 * ratio).floor() as usize;
+        RetryTracker(AtomicUsize::new(allowed.max(self.min_keys)))
+    }
+}
+
+/// The keys left in the retry budget of a single call, shared by the
+/// pipelines to every node.
+#[derive(Debug)]
+pub(crate) struct RetryTracker(AtomicUsize);
+
+impl RetryTracker {
+    /// Take `keys` from the budget, unless fewer are left.
+    pub fn try_take(&self, keys: usize) -> bool {
+        let take = |left: usize| left.checked_sub(keys);
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, take)
+            .is_ok()
+    }
+}
+
+/// Tracks the recent outcomes of requests to a single node.
+#[derive(Debug, Clone)]
+pub(crate) struct BudgetTracker {
+    budget: ErrorBudget,
+    outcomes: VecDeque<bool>,
+    exhausted_at: Option<Instant>,
+}
+
+impl BudgetTracker {
+    pub fn new(budget: ErrorBudget) -> Self {
+        Self {
+            budget,
+            outcomes: VecDeque::with_capacity(budget.window),
+            exhausted_at: None,
+        }
+    }
+
+    /// Record whether a request which completed at `now` succeeded.
+    pub fn record(&mut self, ok: bool, now: Instant) {
+        if self.outcomes.len() >= self.budget.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(ok);
+
+        let total = self.outcomes.len();
+        let errors = self.outcomes.iter().filter(|ok| !**ok).count();
+        let rate = errors as f64 / total as f64;
+        if total >= self.budget.min_requests && rate > self.budget.max_error_rate {
+            self.exhausted_at = Some(now);
+        }
+    }
+
+    /// How much longer reads fail open from `now`, or None if the budget is
+    /// not exhausted.
+    pub fn exhausted_for(&self, now: Instant) -> Option<Duration> {
+        let at = self.exhausted_at?;
+        let elapsed = now.saturating_duration_since(at);
+        let remaining = self.budget.cooldown.checked_sub(elapsed)?;
+        Some(remaining).filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Exhaust the budget so that reads fail open for the given duration
+    /// from `now`, for example to restore the state of a node from before a
+    /// restart.
+    pub fn exhaust_for(&mut self, remaining: Duration, now: Instant) {
+        let remaining = remaining.min(self.budget.cooldown);
+        let at = (now + remaining).checked_sub(self.budget.cooldown);
+        self.exhausted_at = Some(at.unwrap_or(now));
+    }
+
+    /// Whether the budget is exhausted at `now`, meaning reads should fail
+    /// open.
+    pub fn is_exhausted(&mut self, now: Instant) -> bool {
+        match self.exhausted_at {
+            Some(at) if now.saturating_duration_since(at) < self.budget.cooldown => true,
+            Some(_) => {
+                // The cooldown passed, so give the node a fresh window.
+                self.exhausted_at = None;
+                self.outcomes.clear();
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{BudgetTracker, ErrorBudget, RetryBudget};
+
+    #[test]
+    fn test_error_budget() {
+        let mut tracker = BudgetTracker::new(ErrorBudget {
+            window: 4,
+            min_requests: 2,
+            max_error_rate: 0.5,
+            cooldown: Duration::from_secs(60),
+        });
+        let now = Instant::now();
+        tracker.record(false, now);
+        assert!(!tracker.is_exhausted(now));
+        tracker.record(true, now);
+        assert!(!tracker.is_exhausted(now));
+        tracker.record(false, now);
+        assert!(tracker.is_exhausted(now));
+        let later = now + Duration::from_secs(45);
+        assert_eq!(Some(Duration::from_secs(15)), tracker.exhausted_for(later));
+        assert!(!tracker.is_exhausted(now + Duration::from_secs(60)));
+
+        let mut tracker = BudgetTracker::new(ErrorBudget {
+            cooldown: Duration::from_secs(0),
+            ..ErrorBudget::default()
+        });
+        for _ in 0..20 {
+            tracker.record(false, now);
+        }
+        assert!(!tracker.is_exhausted(now));
+    }
+    #[test]
+    fn test_retry_budget() {
+        let tracker = RetryBudget::new(0.1).with_min_keys(0).start(100);
+        assert!(tracker.try_take(6));
+        assert!(!tracker.try_take(6));
+        assert!(tracker.try_take(4));
+        assert!(!tracker.try_take(1));
+
+        // Small calls can always retry the minimum.
+        let tracker = RetryBudget::new(0.1).with_min_keys(3).start(5);
+        assert!(tracker.try_take(3));
+        assert!(!tracker.try_take(1));
+    }
+}
@@ -0,0 +1,244 @@
+//! A local tier only sees the writes made through its own process, so other
+//! instances keep serving stale values until their entries expire. An
+//! [`InvalidationBus`] shares invalidated keys between instances: every key
+//! invalidated in one local tier is published, and every subscribed tier
+//! drops its own entry for the key.
+//!
+//! The bus is best effort. Messages may be lost, so the local TTL still
+//! bounds how long a stale value can be served. The [`NoopBus`] is used by
+//! default, and a [`UdpBus`] is provided behind the `udp` feature as an
+//! example for small, static clusters.
+
+use std::sync::Arc;
+
+/// A handler receiving keys invalidated by other instances.
+pub type InvalidationHandler = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// Publishes invalidated keys to other instances, and delivers the keys
+/// they invalidate to subscribers.
+pub trait InvalidationBus: Send + Sync + 'static {
+    /// Publish a key invalidated by this instance.
+    fn publish(&self, key: &[u8]);
+
+    /// Call the handler with every key published by other instances.
+    fn subscribe(&self, handler: InvalidationHandler);
+}
+
+/// A bus which does not share invalidations with anyone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopBus;
+
+impl InvalidationBus for NoopBus {
+    fn publish(&self, _key: &[u8]) {}
+
+    fn subscribe(&self, _handler: InvalidationHandler) {}
+}
+
+#[cfg(feature = "udp")]
+pub use udp::UdpBus;
+
+#[cfg(feature = "udp")]
+mod udp {
+    use std::{
+        io::{self, ErrorKind},
+        net::{SocketAddr, ToSocketAddrs, UdpSocket},
+        sync::{Arc, Mutex, Weak},
+        thread,
+        time::Duration,
+    };
+
+    use super::{InvalidationBus, InvalidationHandler};
+
+    /// The largest datagram accepted, which fits any memcached key.
+    const MAX_DATAGRAM: usize = 1024;
+
+    /// How long the receiving thread waits for a datagram before checking
+    /// whether the bus was dropped.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    struct Inner {
+        socket: Arc<UdpSocket>,
+        peers: Vec<SocketAddr>,
+        handlers: Mutex<Vec<InvalidationHandler>>,
+    }
+
+    /// A bus sending every invalidated key as a single datagram to a fixed
+    /// list of peers. A background thread receives the datagrams sent by
+    /// those peers, and ignores any others, until every clone of the bus is
+    /// dropped, which closes the socket.
+    #[derive(Clone)]
+    pub struct UdpBus {
+        inner: Arc<Inner>,
+    }
+
+    impl std::fmt::Debug for UdpBus {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("UdpBus")
+                .field("socket", &self.inner.socket)
+                .field("peers", &self.inner.peers)
+                .finish()
+        }
+    }
+
+    impl UdpBus {
+        /// Listen for invalidations from the peers on the given address,
+        /// and publish them to every peer.
+        pub fn bind<A: ToSocketAddrs>(addr: A, peers: Vec<SocketAddr>) -> io::Result<Self> {
+            let socket = Arc::new(UdpSocket::bind(addr)?);
+            socket.set_read_timeout(Some(POLL_INTERVAL))?;
+            let inner = Arc::new(Inner {
+                socket: socket.clone(),
+                peers,
+                handlers: Mutex::default(),
+            });
+            let weak = Arc::downgrade(&inner);
+            thread::Builder::new()
+                .name("rsmc-udp-bus".into())
+                .spawn(move || receive(&socket, &weak))?;
+            Ok(Self { inner })
+        }
+
+        /// The address the bus listens on.
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.inner.socket.local_addr()
+        }
+    }
+
+    /// Deliver the datagrams sent by peers to the handlers, until the bus is
+    /// dropped or the socket fails.
+    fn receive(socket: &UdpSocket, bus: &Weak<Inner>) {
+        let mut buf = [0; MAX_DATAGRAM];
+        loop {
+            let result = socket.recv_from(&mut buf);
+            let inner = match bus.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            match result {
+                Ok((len, from)) if inner.peers.contains(&from) => {
+                    for handler in inner.handlers.lock().unwrap().iter() {
+                        handler(&buf[..len]);
+                    }
+                }
+                Ok(_) => (),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(_) => return,
+            }
+        }
+    }
+
+    impl InvalidationBus for UdpBus {
+        fn publish(&self, key: &[u8]) {
+            for peer in &self.inner.peers {
+                // Lost invalidations are bounded by the local TTL.
+                let _ = self.inner.socket.send_to(key, peer);
+            }
+        }
+
+        fn subscribe(&self, handler: InvalidationHandler) {
+            self.inner.handlers.lock().unwrap().push(handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{
+        client::{Client, ClientConfig},
+        local::LocalTier,
+        mock::MockConnection,
+        options::{ReadPreference, RequestOptions},
+    };
+
+    use super::{InvalidationBus, InvalidationHandler};
+
+    /// A bus delivering every key to every subscriber in the process.
+    #[derive(Default)]
+    struct MemoryBus {
+        handlers: Mutex<Vec<InvalidationHandler>>,
+    }
+
+    impl InvalidationBus for MemoryBus {
+        fn publish(&self, key: &[u8]) {
+            for handler in self.handlers.lock().unwrap().iter() {
+                handler(key);
+            }
+        }
+
+        fn subscribe(&self, handler: InvalidationHandler) {
+            self.handlers.lock().unwrap().push(handler);
+        }
+    }
+
+    #[test]
+    fn test_invalidation_bus() {
+        tokio_test::block_on(async {
+            let bus = Arc::new(MemoryBus::default());
+            let tier =
+                || LocalTier::new(8, Duration::from_secs(60)).with_invalidation_bus(bus.clone());
+            let (first, second) = (tier(), tier());
+            let config =
+                |local| ClientConfig::new_uncompressed(vec!["bus".into()]).with_local_tier(local);
+            let mut a = Client::<MockConnection, _>::new(config(first))
+                .await
+                .unwrap();
+            let mut b = Client::<MockConnection, _>::new(config(second.clone()))
+                .await
+                .unwrap();
+
+            a.set("key", "1", 0).await.unwrap();
+            assert_eq!(Some("1".to_string()), b.get("key").await.unwrap());
+            assert_eq!(1, second.len());
+
+            // Overwriting the key in one instance drops it from the other.
+            a.set("key", "2", 0).await.unwrap();
+            assert!(second.is_empty());
+            let local_only = RequestOptions::new().with_read_preference(ReadPreference::LocalOnly);
+            let value = b.get_with_options::<_, String>("key", &local_only);
+            assert_eq!(None, value.await.unwrap());
+            assert_eq!(Some("2".to_string()), b.get("key").await.unwrap());
+        });
+    }
+
+    #[cfg(feature = "udp")]
+    #[test]
+    fn test_udp_bus() {
+        use super::UdpBus;
+        use std::net::UdpSocket;
+
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpBus::bind("127.0.0.1:0", vec![peer.local_addr().unwrap()]).unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        receiver.subscribe(Arc::new(move |key| {
+            tx.lock().unwrap().send(key.to_vec()).unwrap()
+        }));
+
+        // Only datagrams from peers are delivered.
+        stranger.send_to(b"stranger", addr).unwrap();
+        peer.send_to(b"key", addr).unwrap();
+        let key = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(b"key".to_vec(), key);
+
+        let sender = UdpBus::bind("127.0.0.1:0", vec![peer.local_addr().unwrap()]).unwrap();
+        sender.publish(b"published");
+        let mut buf = [0; 16];
+        let (len, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(b"published", &buf[..len]);
+
+        // Dropping the bus stops the thread and closes the port.
+        drop(receiver);
+        let rebound = (0..50).any(|_| {
+            std::thread::sleep(Duration::from_millis(20));
+            UdpSocket::bind(addr).is_ok()
+        });
+        assert!(rebound);
+    }
+}
@@ -3,18 +3,65 @@
 //! implementations use the same client interface with the same API.
 
 use crate::{
-    protocol::{Header, Packet, ProtocolError, SetExtras, Status},
-    ring::Ring,
+    audit::{self, AuditHook},
+    batch::{BatchGuard, BatchLimits, Queued},
+    budget::{ErrorBudget, RetryBudget},
+    cold::ColdStart,
+    counter::Counter,
+    diagnostics::{self, ConfigError, DiagnosticReport},
+    envelope::{self, Envelope, HeaderEnvelope, Metadata, BINCODE_SERIALIZER, RAW_SERIALIZER},
+    features::{ClusterFeatures, Feature},
+    hashing::{self, DistributionReport, HashScheme, HashSeeds, DEFAULT_SIZE},
+    hot::HotKeyDetector,
+    instrument::MetricsHook,
+    keys::KeyCodec,
+    limit::{self, InFlightLimits, Limiter, LoadShedder},
+    local::{Invalidation, LocalTier},
+    options::{self, DeadlineSource, ReadPreference, RequestOptions},
+    protocol::{
+        CounterExtras, FlushExtras, Header, Packet, ProtocolError, RequestFields, SetExtras,
+        Status, TouchExtras,
+    },
+    resilience::{ResilienceConfig, ResilienceCounters},
+    resolve::Resolver,
+    ring::{self, Node, Ring},
+    sasl::{Authenticator, SaslMechanism},
+    selftest::{self, SelfTestReport},
+    snapshot::{self, IMPORT_CHUNK},
+    stats::{
+        self, DetailStats, ExtstoreStats, ExtstoreThresholds, ItemStats, NodeHealth, NodeStats,
+        SlabStats,
+    },
+    topology::TopologySnapshot,
+    touch::TouchOnRead,
+    transform::{Redacted, ValueTransform},
+    vbucket::VbucketRouter,
+    warm::WarmPool,
 };
 use async_trait::async_trait;
-use deadpool::managed::{Manager, RecycleResult};
+use bytes::Bytes;
+use deadpool::managed::{
+    Manager, Object, PoolError, RecycleError, RecycleResult, TimeoutType, Timeouts,
+};
+use futures::{
+    future::{join, join_all},
+    io::{AsyncRead, AsyncWrite},
+    Stream, StreamExt,
+};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Error as StdError,
-    fmt::{Display, Formatter, Result as FmtResult},
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    future::Future,
     hash::Hash,
+    io::ErrorKind,
     marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 /// An error causing during client communication with Memcached.
@@ -28,8 +75,74 @@ pub enum Error {
     Bincode(bincode::Error),
     /// An error caused by a non-zero status received from a packet.
     Status(Status),
+    /// A value was flagged as having an envelope, but the envelope was
+    /// missing or truncated.
+    InvalidEnvelope,
+    /// Timed out waiting on the connection pool.
+    PoolTimeout(TimeoutType),
+    /// The connection pool has been closed.
+    PoolClosed,
+    /// Any other error raised by the connection pool, such as a failing hook.
+    Pool(String),
+    /// The client configuration is invalid.
+    Config(ConfigError),
+    /// A feature is not supported by every server in the cluster.
+    Unsupported(Feature),
+    /// A mutation was attempted by a client configured as read-only.
+    ReadOnly,
+    /// The server closed the connection, which memcached does to idle
+    /// connections when started with `-o idle_timeout`.
+    ConnectionClosed,
+    /// The node owning a key failed while handling a bulk request.
+    NodeFailed(String),
+    /// Too many keys failed in a bulk request, according to the configured
+    /// [`MultiGetPolicy`]. Contains the errors for every failed key.
+    BulkFailed(BulkErrResponse),
+    /// The deadline of a request passed before it could be sent, or before
+    /// its response was read.
+    DeadlineExceeded,
+    /// The endpoint could not be resolved to any address.
+    Resolve(String),
+    /// Too many operations were in flight, according to the configured
+    /// [`InFlightLimits`].
+    Overloaded,
+    /// The node with the endpoint stopped responding in the middle of a
+    /// bulk read for longer than [`BulkProgress::max_wait`].
+    Stalled(String),
+    /// The operation was shed by the [`LoadShedder`] without waiting, since
+    /// recent waits for the pool or node took too long.
+    Shed,
+    /// The [`WriteCheck`] vetoed writing a value of the given encoded size.
+    Vetoed(usize),
+    /// A value was compressed with a dictionary id which the compressor
+    /// does not know.
+    UnknownDictionary(u8),
+    /// A bulk call with the given number of keys and request bytes exceeded
+    /// the configured [`BatchLimits`].
+    BatchTooLarge(usize, usize),
+}
+
+impl Error {
+    /// Whether the error means the server closed the connection, in which
+    /// case it is safe to reconnect and retry.
+    pub fn is_connection_closed(&self) -> bool {
+        match self {
+            Error::ConnectionClosed => true,
+            Error::IoError(err) => matches!(
+                err.kind(),
+                ErrorKind::BrokenPipe
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
 }
 
+/// A result whose error defaults to the client [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
 /// The result of of a multi_get() request. A map of all of keys for which
 /// memcached returned a found response, and their corresponding values.
 pub type BulkOkResponse<V> = HashMap<Vec<u8>, V>;
@@ -49,6 +162,50 @@ pub type BulkUpdateResponse = Result<BulkErrResponse, Error>;
 /// underlying issue.
 pub type BulkGetResponse<V> = Result<(BulkOkResponse<V>, BulkErrResponse), Error>;
 
+/// When each phase of a bulk request to a single node completed, measured
+/// from the start of the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeTiming {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// When the requests to the node were ready to be written, after
+    /// waiting for in-flight limits.
+    pub enqueued: Duration,
+    /// When the last request was written, if any was.
+    pub write_complete: Option<Duration>,
+    /// When the first response was read, if any was.
+    pub first_response: Option<Duration>,
+    /// When the last response was read, if any was.
+    pub last_response: Option<Duration>,
+    started: Instant,
+}
+
+impl NodeTiming {
+    fn new(endpoint: &str, started: Instant) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            enqueued: Duration::ZERO,
+            write_complete: None,
+            first_response: None,
+            last_response: None,
+            started,
+        }
+    }
+}
+
+/// The result of [`Client::get_multi_with_options`]: the values found and
+/// the errors for failed keys as in [`BulkGetResponse`], along with the
+/// timing of every node when requested with [`RequestOptions::timing`].
+#[derive(Debug)]
+pub struct BulkGetResult<V> {
+    /// The values found, by key.
+    pub values: BulkOkResponse<V>,
+    /// The errors for failed keys, which can be treated as misses.
+    pub errors: BulkErrResponse,
+    /// The timing of the request to every node, or empty unless requested.
+    pub timings: Vec<NodeTiming>,
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::IoError(err)
@@ -73,6 +230,23 @@ impl From<Status> for Error {
     }
 }
 
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
+impl From<PoolError<Error>> for Error {
+    fn from(err: PoolError<Error>) -> Self {
+        match err {
+            PoolError::Backend(err) => err,
+            PoolError::Timeout(kind) => Self::PoolTimeout(kind),
+            PoolError::Closed => Self::PoolClosed,
+            err => Self::Pool(err.to_string()),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
@@ -80,6 +254,26 @@ impl Display for Error {
             Error::Protocol(err) => write!(f, "ProtocolError: {}", err),
             Error::Bincode(err) => write!(f, "BincodeError: {}", err),
             Error::Status(err) => write!(f, "StatusError: {}", err),
+            Error::InvalidEnvelope => write!(f, "InvalidEnvelope"),
+            Error::PoolTimeout(kind) => write!(f, "PoolTimeout: {:?}", kind),
+            Error::PoolClosed => write!(f, "PoolClosed"),
+            Error::Pool(err) => write!(f, "PoolError: {}", err),
+            Error::Config(err) => write!(f, "ConfigError: {}", err),
+            Error::Unsupported(feature) => write!(f, "Unsupported: {}", feature),
+            Error::ReadOnly => write!(f, "ReadOnly"),
+            Error::ConnectionClosed => write!(f, "ConnectionClosed"),
+            Error::NodeFailed(err) => write!(f, "NodeFailed: {}", err),
+            Error::BulkFailed(errors) => write!(f, "BulkFailed: {} keys failed", errors.len()),
+            Error::DeadlineExceeded => write!(f, "DeadlineExceeded"),
+            Error::Resolve(endpoint) => write!(f, "ResolveError: {}", endpoint),
+            Error::Overloaded => write!(f, "Overloaded"),
+            Error::Stalled(endpoint) => write!(f, "StalledError: {}", endpoint),
+            Error::Shed => write!(f, "Shed"),
+            Error::Vetoed(size) => write!(f, "Vetoed: {} bytes", size),
+            Error::UnknownDictionary(id) => write!(f, "UnknownDictionary: {}", id),
+            Error::BatchTooLarge(keys, bytes) => {
+                write!(f, "BatchTooLarge: {} keys, {} bytes", keys, bytes)
+            }
         }
     }
 }
@@ -91,6 +285,24 @@ impl StdError for Error {
             Error::Protocol(err) => Some(err),
             Error::Bincode(err) => Some(err),
             Error::Status(err) => Some(err),
+            Error::InvalidEnvelope => None,
+            Error::PoolTimeout(_) => None,
+            Error::PoolClosed => None,
+            Error::Pool(_) => None,
+            Error::Config(err) => Some(err),
+            Error::Unsupported(_) => None,
+            Error::ReadOnly => None,
+            Error::ConnectionClosed => None,
+            Error::NodeFailed(_) => None,
+            Error::BulkFailed(_) => None,
+            Error::DeadlineExceeded => None,
+            Error::Resolve(_) => None,
+            Error::Overloaded => None,
+            Error::Stalled(_) => None,
+            Error::Shed => None,
+            Error::Vetoed(_) => None,
+            Error::UnknownDictionary(_) => None,
+            Error::BatchTooLarge(..) => None,
         }
     }
 }
@@ -140,20 +352,117 @@ pub trait Connection: Clone + Sized + Send + Sync + 'static {
     /// Write an entire buffer to the TCP stream.
     async fn write(&mut self, data: &[u8]) -> Result<(), Error>;
 
+    /// Read exactly `n` bytes into a buffer owned by the connection. The
+    /// default implementation reads into a new buffer with
+    /// [`Connection::read`]. Adapters which manage their own buffers, such
+    /// as io_uring or TLS streams, can override this to hand them back
+    /// without copying.
+    async fn read_owned(&mut self, n: usize) -> Result<Bytes, Error> {
+        let mut buf = vec![0_u8; n];
+        let mut filled = 0;
+        while filled < n {
+            let read = if filled == 0 {
+                self.read(&mut buf).await?
+            } else {
+                let mut rest = vec![0_u8; n - filled];
+                let read = self.read(&mut rest).await?;
+                buf[filled..filled + read].copy_from_slice(&rest[..read]);
+                read
+            };
+            if read == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            filled += read;
+        }
+        Ok(buf.into())
+    }
+
     /// Read a packet response, possibly decompressing it. It is most likely
     /// unnecessary to implement this yourself.
     async fn read_packet<P: Compressor>(&mut self, compressor: P) -> Result<Packet, Error> {
-        let mut buf = vec![0_u8; 24];
-        self.read(&mut buf).await?;
-        let header = Header::read_response(&buf[..])?;
-        let mut body = vec![0_u8; header.body_len as usize];
-        if !body.is_empty() {
-            self.read(&mut body).await?;
-        }
+        self.read_packet_limited(compressor, u32::MAX).await
+    }
+
+    /// Read a packet response like [`Connection::read_packet`], but reject
+    /// responses whose body is longer than `max_body` bytes before
+    /// allocating a buffer for it, leaving the body unread.
+    async fn read_packet_limited<P: Compressor>(
+        &mut self,
+        compressor: P,
+        max_body: u32,
+    ) -> Result<Packet, Error> {
+        let (header, body) = self.read_frame(max_body).await?;
         let packet = header.read_packet(&body[..])?;
         compressor.decompress(packet)
     }
 
+    /// Read the header and body of a single response, rejecting bodies
+    /// longer than `max_body` bytes. The default implementation reads them
+    /// with two calls to [`Connection::read_owned`]. Connections whose clones
+    /// share a stream should override this to read the whole frame at once,
+    /// so that a response is never split between clones. This does not
+    /// match responses to requests, so clones sharing a stream must still
+    /// not have requests in flight at the same time.
+    async fn read_frame(&mut self, max_body: u32) -> Result<(Header, Bytes), Error> {
+        let buf = self.read_owned(24).await?;
+        let header = Header::read_response(&buf[..])?;
+        if header.body_len > max_body {
+            return Err(ProtocolError::ResponseTooLarge(header.body_len).into());
+        }
+        let body = self.read_owned(header.body_len as usize).await?;
+        Ok((header, body))
+    }
+
+    /// Split the connection into a reader and a writer which can be used
+    /// concurrently, so that a pipeline of requests can be written while
+    /// the responses are read. Both halves share the underlying socket, so
+    /// implementations should guard reads and writes independently of each
+    /// other. The default implementation simply clones the connection.
+    fn split(&self) -> (Self, Self) {
+        (self.clone(), self.clone())
+    }
+
+    /// Replace the connection with a new one to the given url, after it
+    /// failed or was closed. Implementations that manage their own
+    /// reconnects, such as [`crate::reconnect::ReconnectingConnection`],
+    /// override this to apply their policy.
+    async fn reconnect(&mut self, url: String) -> Result<(), Error> {
+        *self = Self::connect(url).await?;
+        Ok(())
+    }
+
+    /// Wait for the duration, using the timer of the runtime. The core has
+    /// no timer, so the default implementation never completes, which
+    /// disables every timeout built on it, such as
+    /// [`BulkProgress::max_wait`]. Runtime adapters should override this.
+    async fn sleep(_duration: Duration) {
+        futures::future::pending::<()>().await
+    }
+
+    /// The current time, read by the timers of the client, such as error
+    /// budget cooldowns, reconnect backoffs, idle pings, deadlines and the
+    /// TTL of the local tier. Defaults to the system clock. Simulations
+    /// override it along with [`Connection::sleep`] to run on a virtual
+    /// clock.
+    fn now() -> Instant {
+        Instant::now()
+    }
+
+    /// Shut the connection down cleanly once it is no longer used, after a
+    /// QUIT was sent on it. The default implementation does nothing, which
+    /// leaves the socket to be closed when the connection is dropped.
+    /// Runtime adapters should override this to shut the socket down.
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Whether the connection is usable. Implementations that know their
+    /// connection was lost return false, so that the ring treats the node
+    /// as poisoned and reconnects it.
+    fn is_connected(&self) -> bool {
+        true
+    }
+
     /// Write a packet request, possibly compressing it. It is most likely
     /// unnecessary to implement this yourself.
     async fn write_packet<P: Compressor>(
@@ -167,11 +476,210 @@ pub trait Connection: Clone + Sized + Send + Sync + 'static {
     }
 }
 
+/// The request used to check that pooled clients are still alive when they
+/// are created and recycled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAlive {
+    /// Send a NOOP to every node. This is the default.
+    #[default]
+    Noop,
+    /// Send a VERSION to every node, which is useful behind proxies (such as
+    /// mcrouter) that mishandle binary NOOPs.
+    Version,
+    /// Do not check connections at all.
+    Disabled,
+}
+
+/// The protocol spoken to every node.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The binary protocol. This is the default.
+    #[default]
+    Binary,
+    /// The classic text protocol, for proxies which only speak it, such as
+    /// twemproxy. Requests are translated into text commands, so every
+    /// method works the same, except that counters are not created by
+    /// increments of missing keys, stores return no CAS, and SASL is not
+    /// supported. NOOPs are answered without reaching the server, so use
+    /// [`KeepAlive::Version`] to check connections.
+    Ascii,
+}
+
+/// What [`Client::get_multi`] does when some keys fail, either because the
+/// node owning them failed or because the server returned an error.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum MultiGetPolicy {
+    /// Return the values that were found along with the errors for every
+    /// failed key. This is the default.
+    #[default]
+    Partial,
+    /// Fail the whole call with [`Error::BulkFailed`] if any key failed.
+    FailAny,
+    /// Fail the whole call with [`Error::BulkFailed`] if more than the given
+    /// fraction (between 0 and 1) of keys failed.
+    FailAbove(f64),
+}
+
+/// Ramps up the number of requests [`Client::get_multi`] pipelines to a
+/// node whose connection was just made, like TCP slow start. A server that
+/// is recovering from a restart is not hit by a burst of requests at once,
+/// which would otherwise lead to timeouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowStart {
+    /// The number of requests in flight in the first batch on a new
+    /// connection.
+    pub initial: usize,
+    /// The number of requests in flight once the connection has warmed up.
+    pub max: usize,
+}
+
+impl SlowStart {
+    /// Start new connections with `initial` requests in flight, doubling
+    /// after every batch until `max` requests are in flight.
+    pub fn new(initial: usize, max: usize) -> Self {
+        let initial = initial.max(1);
+        Self {
+            initial,
+            max: max.max(initial),
+        }
+    }
+}
+
+/// Bounds how long [`Client::get_multi`] waits for progress from a node in
+/// the middle of a pipeline, so that a server which stalls is detected
+/// instead of hanging the read forever. Quiet gets only respond on a hit, so
+/// a NOOP is written after every `noop_every` keys to guarantee a response
+/// at least that often, even when every key misses.
+///
+/// Timeouts need the timer of the runtime, see [`Connection::sleep`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkProgress {
+    /// The longest time to wait for the next response of a pipeline before
+    /// failing the node with [`Error::Stalled`].
+    pub max_wait: Duration,
+    /// The number of keys between NOOPs in a pipeline.
+    pub noop_every: usize,
+}
+
+impl BulkProgress {
+    /// Wait up to `max_wait` for every response, with a NOOP after every
+    /// 100 keys.
+    pub fn new(max_wait: Duration) -> Self {
+        Self {
+            max_wait,
+            noop_every: 100,
+        }
+    }
+
+    /// Write a NOOP after every `noop_every` keys of a pipeline.
+    pub fn with_noop_every(mut self, noop_every: usize) -> Self {
+        self.noop_every = noop_every.max(1);
+        self
+    }
+}
+
+impl MultiGetPolicy {
+    fn check(&self, errors: BulkErrResponse, total: usize) -> Result<BulkErrResponse, Error> {
+        let failed = match self {
+            MultiGetPolicy::Partial => false,
+            MultiGetPolicy::FailAny => !errors.is_empty(),
+            MultiGetPolicy::FailAbove(max) => errors.len() as f64 > max * total as f64,
+        };
+        match failed {
+            true => Err(Error::BulkFailed(errors)),
+            false => Ok(errors),
+        }
+    }
+}
+
+/// A check of the key and encoded size of every value before it is
+/// written, after serializing, wrapping and compressing it, which can veto
+/// the write by returning false. Vetoed writes fail with [`Error::Vetoed`]
+/// without touching the network, which lets applications enforce their own
+/// payload budgets, or alert on values that grow too large.
+#[derive(Clone)]
+pub struct WriteCheck(Arc<SizeCheck>);
+
+type SizeCheck = dyn Fn(&[u8], usize) -> bool + Send + Sync;
+
+impl Debug for WriteCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("WriteCheck").finish_non_exhaustive()
+    }
+}
+
+impl WriteCheck {
+    /// Call the check with the key and encoded size of every value.
+    pub fn new<F>(check: F) -> Self
+    where
+        F: Fn(&[u8], usize) -> bool + Send + Sync + 'static,
+    {
+        Self(Arc::new(check))
+    }
+
+    /// Veto every value larger than `bytes` once encoded.
+    pub fn max_size(bytes: usize) -> Self {
+        Self::new(move |_, size| size <= bytes)
+    }
+
+    /// Check a packet encoded with the compressor.
+    fn check<Q: Compressor>(&self, compressor: Q, packet: &Packet) -> Result<(), Error> {
+        let size = compressor.compress(packet.clone())?.value.len();
+        match (self.0)(&packet.key, size) {
+            true => Ok(()),
+            false => Err(Error::Vetoed(size)),
+        }
+    }
+}
+
+/// The config most recently applied to a pool with [`reconfigure_pool`],
+/// and its revision.
+type Reconfigured<C, P> = Arc<Mutex<Option<(u64, Arc<ClientConfig<C, P>>)>>>;
+
 /// Set configuration values for a memcached client.
 #[derive(Debug, Clone)]
 pub struct ClientConfig<C: Connection, P: Compressor> {
     endpoints: Vec<String>,
     compressor: P,
+    envelope: Option<u32>,
+    envelope_codec: Arc<dyn Envelope>,
+    error_budget: Option<ErrorBudget>,
+    max_client_age: Option<Duration>,
+    recycle_interval: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    keep_alive: KeepAlive,
+    protocol: Protocol,
+    vbuckets: Option<VbucketRouter>,
+    hash_scheme: HashScheme,
+    hash_seeds: HashSeeds,
+    read_only: bool,
+    enabled: Arc<AtomicBool>,
+    get_ramp: u8,
+    multi_get_policy: MultiGetPolicy,
+    idle_ping: Option<Duration>,
+    max_response_size: Option<u32>,
+    key_codec: KeyCodec,
+    warm: Option<WarmPool<C>>,
+    local: Option<LocalTier>,
+    slow_start: Option<SlowStart>,
+    resolver: Option<Arc<dyn Resolver>>,
+    limiter: Option<Arc<Limiter>>,
+    hot_keys: Option<HotKeyDetector>,
+    cold_start: Option<ColdStart>,
+    topology: Option<TopologySnapshot>,
+    bulk_progress: Option<BulkProgress>,
+    write_check: Option<WriteCheck>,
+    retry_budget: Option<RetryBudget>,
+    deadline_source: Option<Arc<dyn DeadlineSource>>,
+    audit: Option<Arc<dyn AuditHook>>,
+    touch_on_read: Option<TouchOnRead>,
+    batch_limits: Option<BatchLimits>,
+    sasl: Option<Arc<dyn Authenticator>>,
+    resilience: Option<ResilienceConfig>,
+    transform: Option<Arc<dyn ValueTransform>>,
+    reconfigured: Reconfigured<C, P>,
+    #[cfg(feature = "tracing")]
+    trace_sample_rate: f64,
     phantom: PhantomData<C>,
 }
 
@@ -187,9 +695,414 @@ where
         Self {
             endpoints,
             compressor,
+            envelope: None,
+            envelope_codec: Arc::new(HeaderEnvelope),
+            error_budget: None,
+            max_client_age: None,
+            recycle_interval: None,
+            idle_timeout: None,
+            keep_alive: KeepAlive::default(),
+            protocol: Protocol::default(),
+            vbuckets: None,
+            hash_scheme: HashScheme::default(),
+            hash_seeds: HashSeeds::default(),
+            read_only: false,
+            enabled: Arc::new(AtomicBool::new(true)),
+            get_ramp: 100,
+            multi_get_policy: MultiGetPolicy::default(),
+            idle_ping: None,
+            max_response_size: None,
+            key_codec: KeyCodec::default(),
+            warm: None,
+            local: None,
+            slow_start: None,
+            resolver: None,
+            limiter: None,
+            hot_keys: None,
+            cold_start: None,
+            topology: None,
+            bulk_progress: None,
+            write_check: None,
+            retry_budget: None,
+            deadline_source: None,
+            audit: None,
+            touch_on_read: None,
+            batch_limits: None,
+            sasl: None,
+            resilience: None,
+            transform: None,
+            reconfigured: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "tracing")]
+            trace_sample_rate: 0.0,
             phantom: PhantomData,
         }
     }
+
+    /// Store every value written by the client inside of an envelope
+    /// recording the write time, the given application-defined version and
+    /// the serializer used. Envelope metadata can be read back with
+    /// [`Client::get_with_metadata`]. Values are always unwrapped on read,
+    /// whether or not this is enabled.
+    pub fn with_envelope(mut self, version: u32) -> Self {
+        self.envelope = Some(version);
+        self
+    }
+
+    /// Lay out envelopes with the given codec instead of the default
+    /// [`HeaderEnvelope`], for example to share values with clients which
+    /// use a layout of their own. The codec is used to unwrap values on
+    /// read, whether or not envelopes are enabled for writes.
+    pub fn with_envelope_codec<E: Envelope + 'static>(mut self, codec: E) -> Self {
+        self.envelope_codec = Arc::new(codec);
+        self
+    }
+
+    /// Fail open when a node exceeds its error budget: reads routed to the
+    /// node return misses immediately instead of attempting the network
+    /// call, until the budget's cooldown has passed.
+    pub fn with_error_budget(mut self, budget: ErrorBudget) -> Self {
+        self.error_budget = Some(budget);
+        self
+    }
+
+    /// Restore which nodes fail open from a snapshot taken before a restart,
+    /// so that new clients do not have to exhaust the error budget of a
+    /// failing node again. See [`crate::topology`].
+    pub fn with_topology_snapshot(mut self, snapshot: TopologySnapshot) -> Self {
+        self.topology = Some(snapshot);
+        self
+    }
+
+    /// Replace pooled clients once they are older than the given age instead
+    /// of recycling them, so that long-lived processes periodically pick up
+    /// fresh connections.
+    pub fn with_max_client_age(mut self, age: Duration) -> Self {
+        self.max_client_age = Some(age);
+        self
+    }
+
+    /// Only check the connections of a pooled client with a keep alive
+    /// request when it is recycled if the last check was at least this long
+    /// ago, instead of on every checkout, to save a round trip on busy pools.
+    pub fn with_recycle_interval(mut self, interval: Duration) -> Self {
+        self.recycle_interval = Some(interval);
+        self
+    }
+
+    /// Replace pooled clients which have not made a request for at least
+    /// this long instead of recycling them, so that connections the server
+    /// or a firewall may have silently dropped are not reused.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Choose the request used to check connections when pooled clients are
+    /// created and recycled.
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Choose the protocol spoken to every node. See [`Protocol`].
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Route keys with a Couchbase-style vbucket map instead of consistent
+    /// hashing. Keep a clone of the router to refresh the map at runtime;
+    /// every client created from this config shares it.
+    pub fn with_vbucket_router(mut self, router: VbucketRouter) -> Self {
+        self.vbuckets = Some(router);
+        self
+    }
+
+    /// Choose the scheme used to place keys on nodes. Every client sharing a
+    /// cluster, in any language, must use the same scheme to agree on where
+    /// keys live. See [`crate::hashing::placement_diff`] to compare schemes.
+    pub fn with_hash_scheme(mut self, scheme: HashScheme) -> Self {
+        self.hash_scheme = scheme;
+        self
+    }
+
+    /// Choose the murmur3 seeds used by the hashing scheme. Like the scheme
+    /// itself, every client sharing a cluster must use the same seeds.
+    pub fn with_hash_seeds(mut self, seeds: HashSeeds) -> Self {
+        self.hash_seeds = seeds;
+        self
+    }
+
+    /// Make every mutation return [`Error::ReadOnly`] without touching the
+    /// network, while reads continue as usual. This is useful for canaries,
+    /// replicas and drills where writes to the cache must be suppressed.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Only serve the given percentage of gets from memcached; the others
+    /// behave as misses. Keys are bucketed deterministically by their hash,
+    /// so a key is either always or never served while the ramp is fixed,
+    /// which allows gradual rollouts and measuring cache effectiveness.
+    /// Writes are not affected. Defaults to 100.
+    pub fn with_get_ramp(mut self, percent: u8) -> Self {
+        self.get_ramp = percent.min(100);
+        self
+    }
+
+    /// Choose what [`Client::get_multi`] does when some keys fail.
+    pub fn with_multi_get_policy(mut self, policy: MultiGetPolicy) -> Self {
+        self.multi_get_policy = policy;
+        self
+    }
+
+    /// Split the pipelines of [`Client::get_multi`] into batches, which
+    /// start small on new connections and grow until they are `max` long.
+    /// By default every key owned by a node is pipelined at once.
+    pub fn with_slow_start(mut self, slow_start: SlowStart) -> Self {
+        self.slow_start = Some(slow_start);
+        self
+    }
+
+    /// Fail nodes which stop responding in the middle of a
+    /// [`Client::get_multi`] pipeline, instead of waiting on them forever.
+    pub fn with_bulk_progress(mut self, progress: BulkProgress) -> Self {
+        self.bulk_progress = Some(progress);
+        self
+    }
+
+    /// Check the encoded size of every value before writing it, vetoing
+    /// writes that the check rejects. Encoding a value to check it costs an
+    /// extra compression of the value. See [`Client::encoded_size`] to check
+    /// values ahead of time instead.
+    pub fn with_write_check(mut self, check: WriteCheck) -> Self {
+        self.write_check = Some(check);
+        self
+    }
+
+    /// Retry the pipelines of [`Client::get_multi`] to nodes whose connection
+    /// was closed, as often as single requests are retried, within the
+    /// budget. Bulk gets are not retried without a budget, and bulk writes
+    /// are never retried, since not every bulk write is idempotent.
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(budget);
+        self
+    }
+
+    /// Resolve endpoints with the resolver before connecting to them,
+    /// instead of leaving it to the connection. See [`crate::resolve`].
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Bound the deadline of every request by the deadline of the context it
+    /// is made in, such as a task-local deadline set by the runtime adapter.
+    pub fn with_deadline_source(mut self, source: Arc<dyn DeadlineSource>) -> Self {
+        self.deadline_source = Some(source);
+        self
+    }
+
+    /// Report every mutation made by the clients to the audit hook, for
+    /// compliance logging. See [`crate::audit`].
+    pub fn with_audit_hook(mut self, hook: Arc<dyn AuditHook>) -> Self {
+        self.audit = Some(hook);
+        self
+    }
+
+    /// Redact the fields of values selected by the transform before they
+    /// are cached. See [`crate::transform`].
+    pub fn with_value_transform(mut self, transform: Arc<dyn ValueTransform>) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Refresh the expiration of keys matching the policy whenever they are
+    /// read, for sliding expiration. See [`crate::touch`].
+    pub fn with_touch_on_read(mut self, policy: TouchOnRead) -> Self {
+        self.touch_on_read = Some(policy);
+        self
+    }
+
+    /// Bound the number of keys and request bytes of every bulk get and
+    /// write. See [`BatchLimits`].
+    pub fn with_batch_limits(mut self, limits: BatchLimits) -> Self {
+        self.batch_limits = Some(limits);
+        self
+    }
+
+    /// Authenticate every connection with the SASL mechanism, such as
+    /// [`crate::sasl::Plain`], before using it. See [`crate::sasl`].
+    pub fn with_sasl<M: SaslMechanism + Clone + 'static>(mut self, mechanism: M) -> Self {
+        self.sasl = Some(Arc::new(mechanism));
+        self
+    }
+
+    /// Limit the number of operations in flight, across every client created
+    /// from this config. See [`crate::limit`].
+    pub fn with_in_flight_limits(mut self, limits: InFlightLimits) -> Self {
+        let shedder = self.limiter.as_ref().and_then(|l| l.shedder.clone());
+        self.limiter = Some(Arc::new(Limiter::new(limits, shedder)));
+        self
+    }
+
+    /// Report slow waits for nodes to the load shedder, and shed operations
+    /// after waits which are too slow. Waits for nodes only happen with
+    /// [`ClientConfig::with_in_flight_limits`], while waits for the pool are
+    /// measured by getting clients with [`LoadShedder::get`].
+    pub fn with_load_shedder(mut self, shedder: LoadShedder) -> Self {
+        let limits = self.limiter.as_ref().map(|l| l.limits);
+        let limits = limits.unwrap_or_default();
+        self.limiter = Some(Arc::new(Limiter::new(limits, Some(shedder))));
+        self
+    }
+
+    /// Sample the keys requested by every client created from this config
+    /// to find the hottest ones. See [`crate::hot`].
+    pub fn with_hot_key_detector(mut self, detector: HotKeyDetector) -> Self {
+        self.hot_keys = Some(detector);
+        self
+    }
+
+    /// Measure the miss rate of reads to detect a cold cluster, so that
+    /// [`Client::admit_load`] delays loads while it is cold. The detector is
+    /// shared by every client created from this config.
+    pub fn with_cold_start(mut self, cold_start: ColdStart) -> Self {
+        self.cold_start = Some(cold_start);
+        self
+    }
+
+    /// Ping nodes that have been idle for longer than the given interval
+    /// before using them, and in [`Client::ping_idle`]. Choose an interval
+    /// below the server's `idle_timeout`, so that connections are kept open
+    /// or, if they were closed anyway, reconnected before a request fails.
+    pub fn with_idle_ping(mut self, interval: Duration) -> Self {
+        self.idle_ping = Some(interval);
+        self
+    }
+
+    /// Reject responses with a body longer than the given number of bytes
+    /// with [`ProtocolError::ResponseTooLarge`] instead of allocating a
+    /// buffer for them, so a corrupt or malicious header cannot exhaust
+    /// memory. The connection is poisoned, since the rest of the response
+    /// is left unread. Choose a limit above the server's `item_size_max`.
+    /// Responses are unbounded by default.
+    pub fn with_max_response_size(mut self, bytes: u32) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Encode keys on the wire with the given codec, so that binary keys
+    /// such as raw UUIDs survive ASCII tooling and proxies. Keys are still
+    /// routed by their raw bytes, and returned decoded by bulk gets. Every
+    /// client sharing a cluster must use the same codec.
+    pub fn with_key_codec(mut self, codec: KeyCodec) -> Self {
+        self.key_codec = codec;
+        self
+    }
+
+    /// Swap in spare connections from the warm pool when a node reconnects,
+    /// instead of waiting for a new connection and its handshakes. The pool
+    /// is shared by every client created from this config; it must be kept
+    /// filled with [`WarmPool::maintain`].
+    pub fn with_warm_pool(mut self, warm: WarmPool<C>) -> Self {
+        self.warm = Some(warm);
+        self
+    }
+
+    /// Serve recently read values from an in-process local tier in front of
+    /// memcached. The tier is shared by every client created from this
+    /// config. See [`crate::local`] for how entries are invalidated.
+    pub fn with_local_tier(mut self, local: LocalTier) -> Self {
+        let fallback = self.resilience.as_ref().and_then(|r| r.stale_fallback);
+        self.local = match fallback {
+            Some(max_staleness) => Some(local.with_stale_if_error(max_staleness)),
+            None => Some(local),
+        };
+        self
+    }
+
+    /// Configure how the cache degrades in one place, replacing the error
+    /// budget, retry budget, load shedder, read-only setting and kill switch
+    /// of the config, and the stale fallback of its local tier. Every
+    /// degraded operation is counted in the stats of the resilience config.
+    /// See [`crate::resilience`].
+    pub fn with_resilience(mut self, resilience: ResilienceConfig) -> Self {
+        self.error_budget = resilience.circuit_breaker;
+        self.retry_budget = resilience.retry_budget;
+        self.read_only = resilience.suppress_writes;
+        self.enabled = resilience.enabled.clone();
+        let limits = self.limiter.as_ref().map(|l| l.limits);
+        self.limiter = match (limits, resilience.load_shedder.clone()) {
+            (None, None) => None,
+            (limits, shedder) => {
+                let limits = limits.unwrap_or_default();
+                Some(Arc::new(Limiter::new(limits, shedder)))
+            }
+        };
+        if let (Some(local), Some(max_staleness)) = (&self.local, resilience.stale_fallback) {
+            self.local = Some(local.clone().with_stale_if_error(max_staleness));
+        }
+        self.resilience = Some(resilience);
+        self
+    }
+
+    /// Emit `tracing` debug events for a fraction of routing decisions,
+    /// recording the key hash, chosen bucket and endpoint. The rate is
+    /// between 0 (the default, disabled) and 1 (every decision).
+    #[cfg(feature = "tracing")]
+    pub fn with_trace_sample_rate(mut self, rate: f64) -> Self {
+        self.trace_sample_rate = rate;
+        self
+    }
+
+    /// Check the configuration for mistakes that can be found without
+    /// connecting to any servers, such as malformed or duplicate endpoints.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        diagnostics::validate_endpoints(&self.endpoints)?;
+        ring::check_size(&self.endpoints, self.hash_scheme, DEFAULT_SIZE)?;
+        if let Some(budget) = self.error_budget {
+            let rate = budget.max_error_rate;
+            if budget.window == 0
+                || budget.min_requests > budget.window
+                || !(0.0..=1.0).contains(&rate)
+            {
+                return Err(ConfigError::InvalidErrorBudget);
+            }
+        }
+        let zero = Some(Duration::ZERO);
+        if self.recycle_interval == zero || self.idle_timeout == zero {
+            return Err(ConfigError::InvalidPoolTiming);
+        }
+        if let Some(interval) = self.recycle_interval {
+            let limits = [self.idle_timeout, self.max_client_age];
+            if limits.iter().flatten().any(|limit| interval >= *limit) {
+                return Err(ConfigError::InvalidPoolTiming);
+            }
+        }
+        if let Some(limits) = self.batch_limits {
+            if limits.max_keys == Some(0) || limits.max_bytes == Some(0) {
+                return Err(ConfigError::InvalidBatchLimits);
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate the configuration, then connect to every endpoint to check
+    /// its version and settings, reporting everything that was found. This
+    /// never fails; problems are recorded in the returned report instead.
+    pub async fn diagnose(&self) -> DiagnosticReport {
+        let mut report = DiagnosticReport {
+            config_error: self.validate().err(),
+            ..DiagnosticReport::default()
+        };
+        for endpoint in self.endpoints.iter() {
+            let node = diagnostics::diagnose_endpoint::<C>(endpoint.clone()).await;
+            report.nodes.push(node);
+        }
+        report
+    }
 }
 
 impl<C> ClientConfig<C, NoCompressor>
@@ -202,24 +1115,243 @@ where
     }
 }
 
+impl<C: Connection, P: Compressor> ClientConfig<C, P> {
+    /// Apply every setting of the config which lives on the nodes of a ring,
+    /// restoring which nodes fail open from the topology snapshot.
+    fn configure_ring(&self, ring: &mut Ring<C>, topology: Option<&TopologySnapshot>) {
+        if self.hash_seeds != HashSeeds::default() {
+            ring.set_hash_seeds(self.hash_seeds);
+        }
+        if let Some(bytes) = self.max_response_size {
+            ring.set_max_response_size(bytes);
+        }
+        ring.set_key_codec(self.key_codec);
+        if let Some(warm) = &self.warm {
+            ring.set_warm_pool(warm.clone());
+        }
+        if let Some(slow_start) = self.slow_start {
+            ring.set_slow_start(slow_start);
+        }
+        if let Some(budget) = self.error_budget {
+            ring.set_error_budget(budget);
+        }
+        if let Some(snapshot) = topology {
+            ring.restore_topology(snapshot);
+        }
+        if let Some(router) = &self.vbuckets {
+            ring.set_vbucket_router(router.clone());
+        }
+        #[cfg(feature = "tracing")]
+        ring.set_trace_sample_rate(self.trace_sample_rate);
+    }
+}
+
 /// A client manages connections to every node in a memcached cluster using
 /// consistent hashing to decide which connection to use based on the key.
 #[derive(Debug, Clone)]
 pub struct Client<C: Connection, P: Compressor> {
     ring: Ring<C>,
     compressor: P,
+    envelope: Option<u32>,
+    envelope_codec: Arc<dyn Envelope>,
+    keep_alive: KeepAlive,
+    read_only: bool,
+    enabled: Arc<AtomicBool>,
+    get_ramp: u8,
+    multi_get_policy: MultiGetPolicy,
+    idle_ping: Option<Duration>,
+    local: Option<LocalTier>,
+    limiter: Option<Arc<Limiter>>,
+    hot_keys: Option<HotKeyDetector>,
+    cold_start: Option<ColdStart>,
+    bulk_progress: Option<BulkProgress>,
+    write_check: Option<WriteCheck>,
+    retry_budget: Option<RetryBudget>,
+    deadline_source: Option<Arc<dyn DeadlineSource>>,
+    audit: Option<Arc<dyn AuditHook>>,
+    touch_on_read: Option<TouchOnRead>,
+    batch_limits: Option<BatchLimits>,
+    resilience: Option<Arc<ResilienceCounters>>,
+    loader_fallback: bool,
+    transform: Option<Arc<dyn ValueTransform>>,
+    revision: u64,
+    created_at: Instant,
+    checked_at: Instant,
 }
 
 impl<C: Connection, P: Compressor> Client<C, P> {
     /// Create a new client using the client config provided.
     pub async fn new(config: ClientConfig<C, P>) -> Result<Self, Error> {
+        let (endpoints, resolver) = (config.endpoints.clone(), config.resolver.clone());
+        let mut ring =
+            Ring::new_with_resolver(endpoints, config.hash_scheme, DEFAULT_SIZE, resolver).await?;
+        ring.set_protocol(config.protocol);
+        ring.authenticate(config.sasl.clone()).await?;
+        ring.detect_versions().await?;
+        config.configure_ring(&mut ring, config.topology.as_ref());
+        let ClientConfig {
+            compressor,
+            envelope,
+            envelope_codec,
+            keep_alive,
+            read_only,
+            enabled,
+            get_ramp,
+            multi_get_policy,
+            idle_ping,
+            local,
+            limiter,
+            hot_keys,
+            cold_start,
+            bulk_progress,
+            write_check,
+            retry_budget,
+            deadline_source,
+            audit,
+            touch_on_read,
+            batch_limits,
+            resilience,
+            transform,
+            ..
+        } = config;
+        let loader_fallback = resilience.as_ref().is_some_and(|r| r.loader_fallback);
+        let resilience = resilience.map(|resilience| resilience.counters);
+        Ok(Self {
+            ring,
+            compressor,
+            envelope,
+            envelope_codec,
+            keep_alive,
+            read_only,
+            enabled,
+            get_ramp,
+            multi_get_policy,
+            idle_ping,
+            local,
+            limiter,
+            hot_keys,
+            cold_start,
+            bulk_progress,
+            write_check,
+            retry_budget,
+            deadline_source,
+            audit,
+            touch_on_read,
+            batch_limits,
+            resilience,
+            loader_fallback,
+            transform,
+            revision: 0,
+            created_at: C::now(),
+            checked_at: C::now(),
+        })
+    }
+
+    /// Apply a new config to this client without recreating it. Nodes whose
+    /// endpoint is unchanged keep their connections and statistics, nodes
+    /// which were removed are disconnected, and every other setting is
+    /// replaced by the one in the new config. New nodes are connected and
+    /// authenticated before anything is changed, so the client is left
+    /// unchanged if that fails. Kept nodes whose protocol or SASL mechanism
+    /// changed reconnect before their next request.
+    ///
+    /// Nodes which fail open keep failing open, unless the new config has
+    /// its own topology snapshot. Use [`reconfigure_pool`] to apply a new
+    /// config to every client of a pool.
+    pub async fn apply_config(&mut self, config: ClientConfig<C, P>) -> Result<(), Error> {
+        let snapshot = self.ring.topology_snapshot();
+        let (endpoints, resolver) = (config.endpoints.clone(), config.resolver.clone());
+        let (protocol, sasl) = (config.protocol, config.sasl.clone());
+        let scheme = config.hash_scheme;
+        self.ring
+            .rebuild(endpoints, scheme, DEFAULT_SIZE, resolver, protocol, sasl)
+            .await?;
+        let topology = config.topology.as_ref().unwrap_or(&snapshot);
+        config.configure_ring(&mut self.ring, Some(topology));
         let ClientConfig {
-            endpoints,
             compressor,
+            envelope,
+            envelope_codec,
+            keep_alive,
+            read_only,
+            enabled,
+            get_ramp,
+            multi_get_policy,
+            idle_ping,
+            local,
+            limiter,
+            hot_keys,
+            cold_start,
+            bulk_progress,
+            write_check,
+            retry_budget,
+            deadline_source,
+            audit,
+            touch_on_read,
+            batch_limits,
+            resilience,
+            transform,
             ..
         } = config;
-        let ring = Ring::new(endpoints).await?;
-        Ok(Self { ring, compressor })
+        let loader_fallback = resilience.as_ref().is_some_and(|r| r.loader_fallback);
+        let resilience = resilience.map(|resilience| resilience.counters);
+        self.compressor = compressor;
+        self.envelope = envelope;
+        self.envelope_codec = envelope_codec;
+        self.keep_alive = keep_alive;
+        self.read_only = read_only;
+        self.enabled = enabled;
+        self.get_ramp = get_ramp;
+        self.multi_get_policy = multi_get_policy;
+        self.idle_ping = idle_ping;
+        self.local = local;
+        self.limiter = limiter;
+        self.hot_keys = hot_keys;
+        self.cold_start = cold_start;
+        self.bulk_progress = bulk_progress;
+        self.write_check = write_check;
+        self.retry_budget = retry_budget;
+        self.deadline_source = deadline_source;
+        self.audit = audit;
+        self.touch_on_read = touch_on_read;
+        self.batch_limits = batch_limits;
+        self.resilience = resilience;
+        self.loader_fallback = loader_fallback;
+        self.transform = transform;
+        Ok(())
+    }
+
+    /// How long ago this client was created.
+    pub fn age(&self) -> Duration {
+        C::now().saturating_duration_since(self.created_at)
+    }
+
+    /// How long ago any node of this client was last used for a request.
+    pub fn idle_time(&self) -> Duration {
+        self.ring
+            .nodes()
+            .map(Node::idle_time)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Whether any node in the client is degraded, either because its
+    /// connection is poisoned by an I/O or protocol error, or because it has
+    /// exhausted its error budget. Pools replace degraded clients instead of
+    /// recycling them.
+    pub fn is_degraded(&self) -> bool {
+        self.ring
+            .nodes()
+            .any(|node| node.is_poisoned() || node.is_failing_open())
+    }
+
+    /// Send a QUIT to every node and shut its connection down, instead of
+    /// dropping connections abruptly, which servers log as dirty
+    /// disconnects. Every node is closed even if some fail, and the first
+    /// error is returned. The client is degraded afterwards, and reconnects
+    /// if it is used again.
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.ring.close().await
     }
 
     /// Get a single value from memcached. Returns None when the key is not
@@ -228,16 +1360,131 @@ impl<C: Connection, P: Compressor> Client<C, P> {
         &mut self,
         key: K,
     ) -> Result<Option<V>, Error> {
-        let key = key.as_ref();
-        let conn = self.ring.get_conn(key)?;
-        conn.write_packet(self.compressor, Packet::get(key)?)
-            .await?;
+        let result = self.get_with_metadata(key).await?;
+        Ok(result.map(|(value, _)| value))
+    }
+
+    /// Get a single value from memcached like [`Client::get`], with options
+    /// overriding the client configuration for this request.
+    pub async fn get_with_options<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &mut self,
+        key: K,
+        options: &RequestOptions,
+    ) -> Result<Option<V>, Error> {
+        let result = self.get_entry(key.as_ref(), options).await?;
+        Ok(result.map(|(value, _)| value))
+    }
+
+    /// Get a single value from memcached along with the envelope metadata
+    /// stored with it. The metadata is None when the value was not written
+    /// inside of an envelope. Returns None when the key is not found.
+    pub async fn get_with_metadata<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<(V, Option<Metadata>)>, Error> {
+        self.get_entry(key.as_ref(), &RequestOptions::default())
+            .await
+    }
 
-        let packet = conn.read_packet(self.compressor).await?;
+    async fn get_entry<V: DeserializeOwned>(
+        &mut self,
+        key: &[u8],
+        options: &RequestOptions,
+    ) -> Result<Option<(V, Option<Metadata>)>, Error> {
+        if !self.is_enabled() || !hashing::in_ramp(key, self.get_ramp) {
+            return Ok(None);
+        }
+        let local = self.local.clone();
+        let cached = match &local {
+            Some(local) if !options.skip_local_tier => local.get(key, C::now()),
+            _ => None,
+        };
+        if let Some(packet) = cached {
+            return Ok(Some(unwrap_entry(&*self.envelope_codec, packet)?));
+        }
+        if options.read_preference == ReadPreference::LocalOnly {
+            return Ok(None);
+        }
+        if self.ring.get_conn(key)?.is_failing_open() {
+            self.record_failed_open(1);
+            let stale = self.get_stale(key, options);
+            let codec = &*self.envelope_codec;
+            return stale.map(|packet| unwrap_entry(codec, packet)).transpose();
+        }
+        let packet = match self.touch_expire(key) {
+            Some(expire) => Packet::gat(key, TouchExtras::new(expire))?,
+            None => Packet::get(key)?,
+        };
+        let result = self
+            .request_with(key, packet, self.compressor, options)
+            .await;
+        let packet = match result {
+            Ok(packet) => packet,
+            Err(err) => return self.serve_stale(key, options, err),
+        };
         match packet.error_for_status() {
-            Ok(()) => Ok(Some(packet.deserialize_value()?)),
-            Err(Status::KeyNotFound) => Ok(None),
-            Err(status) => Err(status.into()),
+            Ok(()) => {
+                self.record_reads(1, 0);
+                if let Some(local) = &local {
+                    local.insert(key, packet.clone(), C::now());
+                }
+                Ok(Some(unwrap_entry(&*self.envelope_codec, packet)?))
+            }
+            Err(Status::KeyNotFound) => {
+                self.record_reads(0, 1);
+                if let Some(local) = &local {
+                    local.invalidate(key, Invalidation::RemoteMiss);
+                }
+                Ok(None)
+            }
+            Err(status) => self.serve_stale(key, options, status.into()),
+        }
+    }
+
+    /// The expiration to refresh when reading the key, if the touch on read
+    /// policy matches it and the client may write.
+    fn touch_expire(&self, key: &[u8]) -> Option<u32> {
+        match &self.touch_on_read {
+            Some(policy) if !self.read_only => policy.expire_for(key),
+            _ => None,
+        }
+    }
+
+    /// Get the stale local copy of a key whose read failed, if the local
+    /// tier is configured to serve stale values on errors.
+    fn get_stale(&self, key: &[u8], options: &RequestOptions) -> Option<Packet> {
+        match &self.local {
+            Some(local) if !options.skip_local_tier => local.get_stale(key, C::now()),
+            _ => None,
+        }
+    }
+
+    /// Serve the stale local copy of a key whose read failed with `err`, or
+    /// return the error if there is none. Shed reads miss instead, and are
+    /// only counted by the load shedder.
+    fn serve_stale<V: DeserializeOwned>(
+        &self,
+        key: &[u8],
+        options: &RequestOptions,
+        err: Error,
+    ) -> Result<Option<(V, Option<Metadata>)>, Error> {
+        let shed = matches!(err, Error::Shed);
+        match self.get_stale(key, options) {
+            Some(packet) => {
+                if let (false, Some(resilience)) = (shed, &self.resilience) {
+                    resilience.stale();
+                }
+                Ok(Some(unwrap_entry(&*self.envelope_codec, packet)?))
+            }
+            None if shed => Ok(None),
+            None => Err(err),
+        }
+    }
+
+    /// Count reads which missed because their node fails open.
+    fn record_failed_open(&self, reads: usize) {
+        if let Some(resilience) = &self.resilience {
+            resilience.failed_open(reads);
         }
     }
 
@@ -245,51 +1492,305 @@ impl<C: Connection, P: Compressor> Client<C, P> {
     /// a tuple of (ok, err) responses. The error responses can be treated as
     /// misses, but should be logged for visibility. Lots of errors could be
     /// indicative of a serious problem.
-    pub async fn get_multi<'a, K: AsRef<[u8]>, V: DeserializeOwned>(
+    pub async fn get_multi<K: AsRef<[u8]>, V: DeserializeOwned>(
         &mut self,
         keys: &[K],
     ) -> BulkGetResponse<V> {
-        let mut values = HashMap::new();
-        let mut errors = HashMap::new();
-
-        // TODO: parallelize
-        for (conn, mut pipeline) in self.ring.get_conns(keys) {
-            let last_key = pipeline.pop().unwrap();
-            let reqs = pipeline
-                .iter()
-                .map(Packet::getkq)
-                .chain(vec![Packet::getk(last_key)])
-                .collect::<Result<Vec<_>, _>>()?;
+        let options = RequestOptions::default();
+        let result = self.get_multi_with_options(keys, &options).await?;
+        Ok((result.values, result.errors))
+    }
 
-            for packet in reqs {
-                let key = packet.key.clone();
-                let result = conn.write_packet(self.compressor, packet).await;
-                if let Err(err) = result {
-                    errors.insert(key, err);
+    /// Get a single value like [`Client::get`], taking ownership of the key.
+    /// Every client future is `Send` when its keys and values are, so owned
+    /// variants like this one let a task spawned on a multithreaded runtime
+    /// build its arguments inline instead of binding them first.
+    pub async fn get_owned<V: DeserializeOwned>(
+        &mut self,
+        key: Vec<u8>,
+    ) -> Result<Option<V>, Error> {
+        self.get(key).await
+    }
+
+    /// Get multiple values like [`Client::get_multi`], taking ownership of
+    /// the keys.
+    pub async fn get_multi_owned<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &mut self,
+        keys: Vec<K>,
+    ) -> BulkGetResponse<V> {
+        self.get_multi(&keys).await
+    }
+
+    /// Get multiple values like [`Client::get_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk gets honor.
+    pub async fn get_multi_with_options<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &mut self,
+        keys: &[K],
+        options: &RequestOptions,
+    ) -> Result<BulkGetResult<V>, Error> {
+        let (mut result, sent) = match self.batch_limits {
+            None => self.get_multi_chunk(keys, options).await?,
+            Some(limits) => {
+                let items = keys.iter().map(|key| (key, 24 + key.as_ref().len()));
+                let mut result = BulkGetResult {
+                    values: HashMap::new(),
+                    errors: HashMap::new(),
+                    timings: vec![],
+                };
+                let mut sent = 0;
+                for chunk in limits.split(items.collect())? {
+                    let (chunk, chunk_sent) = self.get_multi_chunk(&chunk, options).await?;
+                    result.values.extend(chunk.values);
+                    result.errors.extend(chunk.errors);
+                    result.timings.extend(chunk.timings);
+                    sent += chunk_sent;
+                }
+                (result, sent)
+            }
+        };
+
+        // The policy judges the whole call, not each chunk of it, against
+        // the keys which were actually requested.
+        result.errors = self.multi_get_policy.check(result.errors, sent)?;
+        Ok(result)
+    }
+
+    /// Get multiple values within the batch limits, if any, without applying
+    /// the multi get policy. Also returns the number of keys requested, which
+    /// leaves out duplicates, keys outside of the get ramp and keys of nodes
+    /// failing open.
+    async fn get_multi_chunk<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &mut self,
+        keys: &[K],
+        options: &RequestOptions,
+    ) -> Result<(BulkGetResult<V>, usize), Error> {
+        let options = &self.bound_deadline(options);
+        let mut values = HashMap::new();
+        let mut errors = HashMap::new();
+        let mut timings = vec![];
+        if !self.is_enabled() || keys.is_empty() {
+            let result = BulkGetResult {
+                values,
+                errors,
+                timings,
+            };
+            return Ok((result, 0));
+        }
+        options.check_deadline(C::now())?;
+        let started = C::now();
+        self.record_keys(keys);
+
+        // Reads to nodes that exhausted their error budget fail open, and
+        // keys outside of the get ramp are treated as misses. Duplicate keys
+        // are only requested once, since they would be answered once each.
+        let (get_ramp, idle_ping) = (self.get_ramp, self.idle_ping);
+        let (limiter, progress) = (self.limiter.as_deref(), self.bulk_progress);
+        let decoder = Decoder {
+            compressor: self.compressor,
+            envelope: &*self.envelope_codec,
+            touch: self.touch_on_read.as_ref().filter(|_| !self.read_only),
+        };
+        let retries = match self.retry_budget {
+            Some(_) => options.retries.unwrap_or(1),
+            None => 0,
+        };
+        let budget = self.retry_budget.unwrap_or_default().start(keys.len());
+        let budget = &budget;
+        let mut conns = self.ring.get_conns(keys);
+        let (mut failed_open, mut sent) = (0, 0);
+        conns.retain_mut(|(conn, pipeline)| {
+            let mut seen = HashSet::new();
+            pipeline.retain(|key| {
+                hashing::in_ramp(key.as_ref(), get_ramp) && seen.insert((*key).as_ref())
+            });
+            if conn.is_failing_open() {
+                failed_open += pipeline.len();
+                return false;
+            }
+            sent += pipeline.len();
+            !pipeline.is_empty()
+        });
+        if let Some(resilience) = self.resilience.as_deref() {
+            resilience.failed_open(failed_open);
+        }
+
+        let pipelines = conns.into_iter().map(|(conn, pipeline)| async move {
+            let keys = pipeline
+                .iter()
+                .map(|key| key.as_ref().to_vec())
+                .collect::<Vec<_>>();
+            let mut timing = NodeTiming::new(&conn.endpoint, started);
+            let mut retries = retries;
+            loop {
+                let result = Self::get_pipeline(
+                    conn,
+                    decoder,
+                    idle_ping,
+                    limiter,
+                    progress,
+                    pipeline.clone(),
+                    &mut timing,
+                )
+                .await;
+                let retry = match &result {
+                    Err(err) => err.is_connection_closed() && retries > 0,
+                    Ok(_) => false,
+                };
+                if !retry || !budget.try_take(keys.len()) {
+                    return (keys, result, timing);
+                }
+                retries -= 1;
+                if let Err(err) = options.check_deadline(C::now()) {
+                    return (keys, Err(err), timing);
+                }
+                if let Err(err) = conn.reconnect().await {
+                    return (keys, Err(err), timing);
+                }
+            }
+        });
+
+        for (keys, result, timing) in join_all(pipelines).await {
+            if options.timing {
+                timings.push(timing);
+            }
+            match result {
+                Ok((node_values, node_errors)) => {
+                    values.extend(node_values);
+                    errors.extend(node_errors);
                 }
+                // The keys of a shed node are plain misses.
+                Err(Error::Shed) => {}
+                Err(err) => {
+                    // The keys of a failed node are misses with the error.
+                    let err = err.to_string();
+                    for key in keys {
+                        errors.insert(key, Error::NodeFailed(err.clone()));
+                    }
+                }
+            }
+        }
+
+        let misses = sent.saturating_sub(values.len() + errors.len());
+        self.record_reads(values.len() as u64, misses as u64);
+        let result = BulkGetResult {
+            values,
+            errors,
+            timings,
+        };
+        Ok((result, sent))
+    }
+
+    /// Pipeline gets for every key owned by a single node and read the
+    /// responses, in batches no longer than the pipeline depth of the node.
+    async fn get_pipeline<K: AsRef<[u8]>, V: DeserializeOwned>(
+        conn: &mut Node<C>,
+        decoder: Decoder<'_, P>,
+        idle_ping: Option<Duration>,
+        limiter: Option<&Limiter>,
+        progress: Option<BulkProgress>,
+        pipeline: Vec<&K>,
+        timing: &mut NodeTiming,
+    ) -> Result<(BulkOkResponse<V>, BulkErrResponse), Error> {
+        let _permits = limit::acquire::<C>(limiter, &conn.endpoint).await?;
+        timing.enqueued = C::now().saturating_duration_since(timing.started);
+        let mut values = HashMap::new();
+        let mut errors = HashMap::new();
+        let mut rest = &pipeline[..];
+        while !rest.is_empty() {
+            let depth = conn.pipeline_depth().min(rest.len());
+            let (batch, tail) = rest.split_at(depth);
+            let (batch_values, batch_errors) =
+                Self::get_batch(conn, decoder, idle_ping, progress, batch, timing).await?;
+            values.extend(batch_values);
+            errors.extend(batch_errors);
+            conn.grow_pipeline_depth();
+            rest = tail;
+        }
+        Ok((values, errors))
+    }
+
+    /// Pipeline quiet gets for a batch of keys owned by a single node and read
+    /// the responses until the NOOP ending the pipeline, with NOOPs in between
+    /// when bounding the wait for progress. Only hits are answered, so the
+    /// end is only known from the NOOP, whatever the keys are.
+    async fn get_batch<K: AsRef<[u8]>, V: DeserializeOwned>(
+        conn: &mut Node<C>,
+        decoder: Decoder<'_, P>,
+        idle_ping: Option<Duration>,
+        progress: Option<BulkProgress>,
+        pipeline: &[&K],
+        timing: &mut NodeTiming,
+    ) -> Result<(BulkOkResponse<V>, BulkErrResponse), Error> {
+        let (started, compressor) = (timing.started, decoder.compressor);
+        let noop_every = progress.map_or(usize::MAX, |progress| progress.noop_every);
+        let max_wait = progress.map(|progress| progress.max_wait);
+        let mut reqs = vec![];
+        for (i, key) in pipeline.iter().enumerate() {
+            let touch = decoder
+                .touch
+                .and_then(|policy| policy.expire_for(key.as_ref()));
+            reqs.push(match touch {
+                Some(expire) => Packet::gatkq(key, TouchExtras::new(expire))?,
+                None => Packet::getkq(key)?,
+            });
+            if (i + 1) % noop_every == 0 && i + 1 < pipeline.len() {
+                reqs.push(Packet::noop()?);
             }
         }
+        let mut end = Packet::noop()?;
+        end.header.opaque = END_OF_PIPELINE;
+        reqs.push(end);
 
-        // TODO: parallelize
-        for (conn, mut pipeline) in self.ring.get_conns(keys) {
-            let last_key = pipeline.pop().unwrap();
-            let mut finished = false;
-            while !finished {
-                let packet = conn.read_packet(self.compressor).await?;
+        conn.ensure_connected(idle_ping).await?;
+        let (mut reader, mut writer) = conn.split();
+        let (mut first_response, mut last_response) = (None, None);
+        let sent = C::now();
+        let write = async {
+            let errors = write_pipeline(&mut writer, compressor, reqs).await;
+            (errors, C::now().saturating_duration_since(started))
+        };
+        let read = async {
+            let mut values = HashMap::new();
+            let mut errors = HashMap::new();
+            loop {
+                let packet = reader.read_packet_within(compressor, max_wait).await?;
+                if first_response.is_none() {
+                    let latency = C::now().saturating_duration_since(sent);
+                    reader.counters.record_latency(latency);
+                }
+                last_response = Some(C::now().saturating_duration_since(started));
+                first_response = first_response.or(last_response);
+                if packet.is_noop() {
+                    match packet.header.opaque {
+                        END_OF_PIPELINE => break,
+                        _ => continue,
+                    }
+                }
+                if !packet.is_get() {
+                    let err = ProtocolError::UnexpectedOpcode(packet.header.opcode);
+                    return reader.record(Err(err.into()));
+                }
                 let key = packet.key.clone();
-                finished = key == last_key.as_ref();
                 match packet.error_for_status() {
                     Err(Status::KeyNotFound) => (),
                     Err(err) => {
                         errors.insert(key, Error::Status(err));
                     }
                     Ok(()) => {
+                        let (packet, _) = envelope::unwrap(decoder.envelope, packet)?;
                         values.insert(key, packet.deserialize_value()?);
                     }
                 }
             }
-        }
-
+            Ok::<_, Error>((values, errors))
+        };
+        let ((write_errors, write_complete), read) = join(write, read).await;
+        timing.write_complete = Some(write_complete);
+        timing.first_response = timing.first_response.or(first_response);
+        timing.last_response = last_response.or(timing.last_response);
+        let (values, mut errors) = read?;
+        errors.extend(write_errors);
         Ok((values, errors))
     }
 
@@ -304,64 +1805,495 @@ impl<C: Connection, P: Compressor> Client<C, P> {
         data: &V,
         expire: u32,
     ) -> Result<(), Error> {
+        self.set_with_options(key, data, expire, &RequestOptions::default())
+            .await
+    }
+
+    /// Set a single key/value pair like [`Client::set`], taking ownership of
+    /// the value.
+    pub async fn set_owned<K: AsRef<[u8]>, V: Serialize>(
+        &mut self,
+        key: K,
+        data: V,
+        expire: u32,
+    ) -> Result<(), Error> {
+        self.set(key, &data, expire).await
+    }
+
+    /// Set a single key/value pair like [`Client::set`], with options
+    /// overriding the client configuration for this request.
+    pub async fn set_with_options<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &mut self,
+        key: K,
+        data: &V,
+        expire: u32,
+        options: &RequestOptions,
+    ) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.check_writable()?;
         let key = key.as_ref();
-        let conn = self.ring.get_conn(key)?;
-        let packet = Packet::set(key, data, SetExtras::new(0, expire))?;
-        conn.write_packet(self.compressor, packet).await?;
-        conn.read_packet(self.compressor)
-            .await?
-            .error_for_status()?;
+        let packet = Packet::set(key, &self.redact(key, data), SetExtras::new(0, expire))?;
+        let packet = wrap_envelope(
+            &*self.envelope_codec,
+            self.envelope,
+            packet,
+            expire,
+            BINCODE_SERIALIZER,
+        );
+        let compressor = self.compressor;
+        match options.compress {
+            Some(false) => self.check_write(NoCompressor, &packet)?,
+            _ => self.check_write(compressor, &packet)?,
+        }
+        self.forget_local(key);
+        let response = match options.compress {
+            Some(false) => self.request_with(key, packet, NoCompressor, options).await,
+            _ => self.request_with(key, packet, compressor, options).await,
+        };
+        self.invalidate_local(key, Invalidation::Overwritten);
+        response?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Set a single key to a value that the caller has already serialized.
+    /// The bytes are sent as-is (apart from compression) along with the
+    /// given flags, which is useful when values are pre-serialized or cached
+    /// in their encoded form. Note that the lowest bit of the high byte of
+    /// `flags` is reserved for the compressor.
+    pub async fn set_serialized<K: AsRef<[u8]>, B: Into<Vec<u8>>>(
+        &mut self,
+        key: K,
+        bytes: B,
+        flags: u32,
+        expire: u32,
+    ) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.check_writable()?;
+        let key = key.as_ref();
+        let packet = Packet::set_raw(key, bytes.into(), SetExtras::new(flags, expire))?;
+        let packet = wrap_envelope(
+            &*self.envelope_codec,
+            self.envelope,
+            packet,
+            expire,
+            RAW_SERIALIZER,
+        );
+        self.check_write(self.compressor, &packet)?;
+        self.forget_local(key);
+        let response = self.request(key, packet).await;
+        self.invalidate_local(key, Invalidation::Overwritten);
+        response?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Set a single key to raw bytes with the exact flags given, bypassing
+    /// compression and envelopes entirely. This allows entries to be shared
+    /// with clients in other languages, which use the flags to signal their
+    /// own serialization format.
+    pub async fn set_with_flags<K: AsRef<[u8]>, B: Into<Vec<u8>>>(
+        &mut self,
+        key: K,
+        bytes: B,
+        flags: u32,
+        expire: u32,
+    ) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.check_writable()?;
+        let key = key.as_ref();
+        let packet = Packet::set_raw(key, bytes.into(), SetExtras::new(flags, expire))?;
+        self.check_write(NoCompressor, &packet)?;
+        self.forget_local(key);
+        let options = RequestOptions::default();
+        let response = self.request_with(key, packet, NoCompressor, &options).await;
+        self.invalidate_local(key, Invalidation::Overwritten);
+        response?.error_for_status()?;
         Ok(())
     }
 
+    /// Get the raw bytes and flags stored under a key, exactly as they are
+    /// stored in memcached without decompressing or unwrapping envelopes.
+    /// Returns None when the key is not found.
+    pub async fn get_raw_with_flags<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<(Vec<u8>, u32)>, Error> {
+        let key = key.as_ref();
+        if !self.is_enabled() || !hashing::in_ramp(key, self.get_ramp) {
+            return Ok(None);
+        }
+        if self.ring.get_conn(key)?.is_failing_open() {
+            self.record_failed_open(1);
+            return Ok(None);
+        }
+        self.read_raw_with_flags(key).await
+    }
+
+    /// Get the raw bytes and flags stored under a key like
+    /// [`Client::get_raw_with_flags`], regardless of the kill switch, the get
+    /// ramp and nodes failing open.
+    async fn read_raw_with_flags(&mut self, key: &[u8]) -> Result<Option<(Vec<u8>, u32)>, Error> {
+        let packet = self
+            .request_with(
+                key,
+                Packet::get(key)?,
+                NoCompressor,
+                &RequestOptions::default(),
+            )
+            .await?;
+        match packet.error_for_status() {
+            Ok(()) => {
+                let flags = packet.flags();
+                Ok(Some((packet.value, flags)))
+            }
+            Err(Status::KeyNotFound) => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
+
     /// Set multiple key/value pairs in memcached to expire at the desired
     /// time. A value of 0 means "never expire", but the value could still be
     /// evicted by the LRU cache. Important: if `expire` is set to more than 30
     /// days in the future, then memcached will treat it as a unix timestamp
     /// instead of a duration.
-    pub async fn set_multi<'a, V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+    pub async fn set_multi<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+    ) -> BulkUpdateResponse {
+        self.set_multi_with_options(data, expire, &RequestOptions::default())
+            .await
+    }
+
+    /// Set multiple key/value pairs like [`Client::set_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn set_multi_with_options<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
         &mut self,
         data: HashMap<K, V>,
         expire: u32,
+        options: &RequestOptions,
     ) -> BulkUpdateResponse {
+        let store = |quiet, key: &[u8], value: &Redacted<'_, V>, extras| match quiet {
+            true => Packet::setq(key, value, extras),
+            false => Packet::set(key, value, extras),
+        };
+        self.store_multi(data, expire, options, store).await
+    }
+
+    /// Set every key/value pair produced by a stream, such as rows read from
+    /// a database cursor. Items are batched per node and written with
+    /// [`Client::set_multi`] whenever any node has `max_outstanding` quiet
+    /// sets pending, and the stream is not polled again until the batch is
+    /// acknowledged, so a fast source cannot overwhelm the cluster.
+    pub async fn set_stream<S, K, V>(
+        &mut self,
+        mut items: S,
+        expire: u32,
+        max_outstanding: usize,
+    ) -> BulkUpdateResponse
+    where
+        S: Stream<Item = (K, V)> + Unpin,
+        K: AsRef<[u8]> + Eq + Hash,
+        V: Serialize,
+    {
+        if !self.is_enabled() {
+            return Ok(HashMap::new());
+        }
+        self.check_writable()?;
         let mut errors = HashMap::new();
-        let keys = data.keys().collect::<Vec<_>>();
-        let extras = SetExtras::new(0, expire);
+        let mut batch = HashMap::new();
+        let mut outstanding = vec![0_usize; self.ring.len()];
+        while let Some((key, value)) = items.next().await {
+            let node = self.ring.node_index(key.as_ref());
+            outstanding[node] += 1;
+            batch.insert(key, value);
+            if outstanding[node] >= max_outstanding.max(1) {
+                errors.extend(self.set_multi(std::mem::take(&mut batch), expire).await?);
+                outstanding.iter_mut().for_each(|n| *n = 0);
+            }
+        }
+        if !batch.is_empty() {
+            errors.extend(self.set_multi(batch, expire).await?);
+        }
+        Ok(errors)
+    }
 
-        // TODO: parallelize
-        for (conn, mut pipeline) in self.ring.get_conns(&keys[..]) {
-            let last_key = pipeline.pop().unwrap();
-            let last_val = data.get(last_key).unwrap();
-            let reqs = pipeline
-                .into_iter()
-                .map(|key| (key, data.get(key).unwrap()))
-                .map(|(key, value)| Packet::setq(key, value, extras))
-                .chain(vec![Packet::set(last_key, last_val, extras)])
-                .collect::<Result<Vec<_>, _>>()?;
+    /// Store a key only if it is not already set, the memcached idiom for
+    /// "set if absent". Returns true if the value was stored, or false if the
+    /// key already existed.
+    pub async fn add<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &mut self,
+        key: K,
+        data: &V,
+        expire: u32,
+    ) -> Result<bool, Error> {
+        let key = key.as_ref();
+        let packet = Packet::add(key, &self.redact(key, data), SetExtras::new(0, expire))?;
+        self.store_if(key, packet, expire).await
+    }
 
-            for packet in reqs {
-                let key = packet.key.clone();
-                if let Err(err) = conn.write_packet(self.compressor, packet).await {
-                    errors.insert(key, err);
-                }
+    /// Store a key only if it is already set. Returns true if the value was
+    /// stored, or false if the key was not set.
+    pub async fn replace<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &mut self,
+        key: K,
+        data: &V,
+        expire: u32,
+    ) -> Result<bool, Error> {
+        let key = key.as_ref();
+        let packet = Packet::replace(key, &self.redact(key, data), SetExtras::new(0, expire))?;
+        self.store_if(key, packet, expire).await
+    }
+
+    /// Send a conditional store request, mapping the statuses of a failed
+    /// condition to false so that they are not confused with other errors.
+    async fn store_if(&mut self, key: &[u8], packet: Packet, expire: u32) -> Result<bool, Error> {
+        if !self.is_enabled() {
+            return Ok(false);
+        }
+        self.check_writable()?;
+        let packet = wrap_envelope(
+            &*self.envelope_codec,
+            self.envelope,
+            packet,
+            expire,
+            BINCODE_SERIALIZER,
+        );
+        self.check_write(self.compressor, &packet)?;
+        self.forget_local(key);
+        let response = self.request(key, packet).await;
+        self.invalidate_local(key, Invalidation::Overwritten);
+        match response?.error_for_status() {
+            Ok(()) => Ok(true),
+            Err(Status::KeyExists | Status::KeyNotFound | Status::ItemNotStored) => Ok(false),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Add multiple key/value pairs in memcached, only storing keys that are
+    /// not already set. Keys that were already set are returned in the error
+    /// map with [`Status::KeyExists`], so this can be used to claim a batch
+    /// of work items, where the claimed items are those without errors.
+    pub async fn add_multi<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+    ) -> BulkUpdateResponse {
+        self.add_multi_with_options(data, expire, &RequestOptions::default())
+            .await
+    }
+
+    /// Add multiple key/value pairs like [`Client::add_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn add_multi_with_options<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        let store = |quiet, key: &[u8], value: &Redacted<'_, V>, extras| match quiet {
+            true => Packet::addq(key, value, extras),
+            false => Packet::add(key, value, extras),
+        };
+        self.store_multi(data, expire, options, store).await
+    }
+
+    /// Replace multiple key/value pairs in memcached, only storing keys that
+    /// are already set. Keys that were not set are returned in the error map
+    /// with [`Status::ItemNotStored`].
+    pub async fn replace_multi<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+    ) -> BulkUpdateResponse {
+        self.replace_multi_with_options(data, expire, &RequestOptions::default())
+            .await
+    }
+
+    /// Replace multiple key/value pairs like [`Client::replace_multi`], with
+    /// options overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn replace_multi_with_options<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        let store = |quiet, key: &[u8], value: &Redacted<'_, V>, extras| match quiet {
+            true => Packet::replaceq(key, value, extras),
+            false => Packet::replace(key, value, extras),
+        };
+        self.store_multi(data, expire, options, store).await
+    }
+
+    /// Set multiple key/value pairs in memcached, each only if its CAS value
+    /// from [`Client::gets`] still matches, in a single round trip per node.
+    /// Keys which were changed since their CAS value was read are returned in
+    /// the error map with [`Status::KeyExists`], and keys which were deleted
+    /// with [`Status::KeyNotFound`].
+    pub async fn cas_multi<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, (V, u64)>,
+        expire: u32,
+    ) -> BulkUpdateResponse {
+        self.cas_multi_with_options(data, expire, &RequestOptions::default())
+            .await
+    }
+
+    /// Set multiple key/value pairs like [`Client::cas_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn cas_multi_with_options<V: Serialize, K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        data: HashMap<K, (V, u64)>,
+        expire: u32,
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        let data = data
+            .into_iter()
+            .map(|(key, (value, cas))| (key, WithCas(value, cas)))
+            .collect::<HashMap<_, _>>();
+        let store = |quiet, key: &[u8], value: &Redacted<'_, WithCas<V>>, extras| {
+            let fields = RequestFields::new(value.value().1, 0);
+            match quiet {
+                true => Packet::setq_with(key, value, extras, fields),
+                false => Packet::set_with(key, value, extras, fields),
             }
+        };
+        self.store_multi(data, expire, options, store).await
+    }
+
+    /// Pipeline quiet store requests to every node, terminated by a single
+    /// non-quiet request. Quiet failures do not always echo the key, so
+    /// each request carries its index in the opaque field, which is used to
+    /// map failures back to their keys.
+    async fn store_multi<V, K, F>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+        options: &RequestOptions,
+        store: F,
+    ) -> BulkUpdateResponse
+    where
+        V: Serialize,
+        K: AsRef<[u8]> + Eq + Hash,
+        F: Fn(bool, &[u8], &Redacted<'_, V>, SetExtras) -> bincode::Result<Packet>,
+    {
+        let options = &self.bound_deadline(options);
+        options.check_deadline(C::now())?;
+        let mut errors = HashMap::new();
+        for chunk in self.split_writes(data)? {
+            let chunk = self.store_multi_chunk(chunk, expire, options, &store);
+            errors.extend(chunk.await?);
         }
+        Ok(errors)
+    }
 
-        // TODO: parallelize
-        for (conn, _) in self.ring.get_conns(&keys[..]) {
-            let mut finished = false;
-            while !finished {
-                let packet = conn.read_packet(self.compressor).await?;
-                let key = packet.key.clone();
-                finished = packet.header.vbucket_or_status == 0;
-                match packet.error_for_status() {
-                    Ok(()) => (),
-                    Err(Status::KeyNotFound) => (),
-                    Err(err) => {
-                        errors.insert(key, Error::Status(err));
+    /// Store multiple key/value pairs within the batch limits, if any.
+    async fn store_multi_chunk<V, K, F>(
+        &mut self,
+        data: HashMap<K, V>,
+        expire: u32,
+        options: &RequestOptions,
+        store: &F,
+    ) -> BulkUpdateResponse
+    where
+        V: Serialize,
+        K: AsRef<[u8]> + Eq + Hash,
+        F: Fn(bool, &[u8], &Redacted<'_, V>, SetExtras) -> bincode::Result<Packet>,
+    {
+        if !self.is_enabled() || data.is_empty() {
+            return Ok(HashMap::new());
+        }
+        self.check_writable()?;
+        let mut errors = HashMap::new();
+        let data = self.check_writes(data, expire, &mut errors);
+        if data.is_empty() {
+            return Ok(errors);
+        }
+        let keys = data.keys().collect::<Vec<_>>();
+        let extras = SetExtras::new(0, expire);
+        for key in &keys {
+            self.forget_local(key.as_ref());
+        }
+        self.record_keys(&keys);
+
+        let (compressor, version, idle_ping) = (self.compressor, self.envelope, self.idle_ping);
+        let (codec, limiter) = (&*self.envelope_codec, self.limiter.as_deref());
+        let (audit, transform) = (self.audit.as_deref(), self.transform.as_deref());
+        let tags = &options.audit_tags[..];
+        let pipelines = self
+            .ring
+            .get_conns(&keys[..])
+            .into_iter()
+            .map(|(conn, pipeline)| {
+                let (data, store) = (&data, &store);
+                async move {
+                    let _permits = limit::acquire::<C>(limiter, &conn.endpoint).await?;
+                    let last = pipeline.len() - 1;
+                    let reqs = pipeline
+                        .iter()
+                        .enumerate()
+                        .map(|(i, key)| {
+                            let value = data.get(**key).unwrap();
+                            let value = Redacted::new(value, key.as_ref(), transform);
+                            let mut packet = store(i != last, key.as_ref(), &value, extras)?;
+                            packet.header.opaque = i as u32;
+                            Ok(wrap_envelope(
+                                codec,
+                                version,
+                                packet,
+                                expire,
+                                BINCODE_SERIALIZER,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    audit::record(audit, &reqs, tags);
+                    let last_opcode = reqs[last].header.opcode;
+
+                    conn.ensure_connected(idle_ping).await?;
+                    let (mut reader, mut writer) = conn.split();
+                    let write = write_pipeline(&mut writer, compressor, reqs);
+                    let read = async {
+                        let mut errors = HashMap::new();
+                        loop {
+                            let packet = reader.read_packet(compressor).await?;
+                            let key = pipeline
+                                .get(packet.header.opaque as usize)
+                                .map(|key| key.as_ref().to_vec())
+                                .unwrap_or_else(|| packet.key.clone());
+                            if let Err(err) = packet.error_for_status() {
+                                errors.insert(key, Error::Status(err));
+                            }
+                            if packet.header.opcode == last_opcode {
+                                return Ok::<_, Error>(errors);
+                            }
+                        }
+                    };
+                    let (write_errors, read) = join(write, read).await;
+                    // Responses may be left on the stream after a failed read.
+                    if read.is_err() {
+                        conn.counters.poison();
                     }
+                    let mut errors = read?;
+                    errors.extend(write_errors);
+                    Ok::<_, Error>(errors)
                 }
-            }
+            });
+
+        let results = join_all(pipelines).await;
+        for key in &keys {
+            self.invalidate_local(key.as_ref(), Invalidation::Overwritten);
+        }
+        for result in results {
+            errors.extend(result?);
         }
 
         Ok(errors)
@@ -369,104 +2301,2766 @@ impl<C: Connection, P: Compressor> Client<C, P> {
 
     /// Delete a key from memcached. Does nothing if the key is not set.
     pub async fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> Result<(), Error> {
+        self.delete_with_options(key, &RequestOptions::default())
+            .await
+    }
+
+    /// Delete a key like [`Client::delete`], with options overriding the
+    /// client configuration for this request.
+    pub async fn delete_with_options<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        options: &RequestOptions,
+    ) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.check_writable()?;
         let key = key.as_ref();
-        let conn = self.ring.get_conn(key)?;
-        conn.write_packet(self.compressor, Packet::delete(key)?)
-            .await?;
-        conn.read_packet(self.compressor)
-            .await?
-            .error_for_status()?;
+        let packet = Packet::delete(key)?;
+        self.forget_local(key);
+        let response = self
+            .request_with(key, packet, self.compressor, options)
+            .await;
+        self.invalidate_local(key, Invalidation::Deleted);
+        response?.error_for_status()?;
         Ok(())
     }
 
     /// Delete multiple keys from memcached. Does nothing when a key is unset.
+    /// The keys of each node are deleted with quiet requests terminated by a
+    /// NOOP, so the servers only answer deletes which failed.
     pub async fn delete_multi<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> BulkUpdateResponse {
-        let mut errors = HashMap::new();
+        self.delete_multi_with_options(keys, &RequestOptions::default())
+            .await
+    }
 
-        // TODO: parallelize
-        for (conn, pipeline) in self.ring.get_conns(keys) {
-            let reqs = pipeline
-                .into_iter()
-                .map(Packet::delete)
-                .collect::<Result<Vec<_>, _>>()?;
-            for packet in reqs {
-                let key = packet.key.clone();
-                if let Err(err) = conn.write_packet(self.compressor, packet).await {
-                    errors.insert(key, err);
-                }
+    /// Delete multiple keys like [`Client::delete_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn delete_multi_with_options<K: AsRef<[u8]>>(
+        &mut self,
+        keys: &[K],
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        if !self.is_enabled() || keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+        self.check_writable()?;
+        let queued = keys
+            .iter()
+            .map(|key| {
+                let packet = Packet::deleteq(key)?;
+                let reason = Invalidation::Deleted;
+                Ok(Queued { packet, reason })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.send_batch(queued, options).await
+    }
+
+    /// Start a batch of mutations, which are queued by the returned guard
+    /// and sent together when it is flushed. See [`BatchGuard`].
+    pub fn batch(&mut self) -> BatchGuard<'_, C, P> {
+        BatchGuard::new(self)
+    }
+
+    /// Write the raw values and flags of the keys which are set to a
+    /// snapshot, returning how many were written. Keys which are not set
+    /// are skipped. Every key is read regardless of the kill switch, the get
+    /// ramp and nodes failing open, so that an export which succeeds holds
+    /// every key which is set. See [`crate::snapshot`] for the format.
+    pub async fn export<K: AsRef<[u8]>, I: IntoIterator<Item = K>, W: AsyncWrite + Unpin>(
+        &mut self,
+        keys: I,
+        writer: &mut W,
+    ) -> Result<usize, Error> {
+        snapshot::write_header(writer).await?;
+        let mut written = 0;
+        for key in keys {
+            let key = key.as_ref();
+            if let Some((value, flags)) = self.read_raw_with_flags(key).await? {
+                snapshot::write_entry(writer, key, flags, &value).await?;
+                written += 1;
             }
         }
+        futures::io::AsyncWriteExt::flush(writer).await?;
+        Ok(written)
+    }
 
-        // TODO: parallelize
-        for (conn, pipeline) in self.ring.get_conns(keys) {
-            for _ in pipeline {
-                let packet = conn.read_packet(self.compressor).await?;
-                let key = packet.key.clone();
-                match packet.error_for_status() {
-                    Ok(()) => (),
-                    Err(err) => {
-                        errors.insert(key, Error::Status(err));
+    /// Store every entry of a snapshot written by [`Client::export`] to
+    /// expire at the given time, returning the errors of those which failed
+    /// by key. Values are stored exactly as they were exported.
+    pub async fn import<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        expire: u32,
+    ) -> BulkUpdateResponse {
+        snapshot::read_header(reader).await?;
+        let mut errors = HashMap::new();
+        let mut done = false;
+        while !done {
+            let mut queued = Vec::with_capacity(IMPORT_CHUNK);
+            while queued.len() < IMPORT_CHUNK {
+                let entry = match snapshot::read_entry(reader).await? {
+                    Some(entry) => entry,
+                    None => {
+                        done = true;
+                        break;
                     }
-                }
+                };
+                let extras = SetExtras::new(entry.flags, expire);
+                queued.push(Queued {
+                    packet: Packet::setq_raw(entry.key, entry.value, extras)?,
+                    reason: Invalidation::Overwritten,
+                });
             }
+            let options = RequestOptions::default();
+            let sent = self.send_batch_with(queued, NoCompressor, &options);
+            errors.extend(sent.await?);
         }
-
         Ok(errors)
     }
 
-    async fn keep_alive(&mut self) -> Result<(), Error> {
-        // TODO: verify read_packet returns a noop code
-        for conn in self.ring.into_iter() {
-            conn.write_packet(self.compressor, Packet::noop()?).await?;
-            let packet = conn.read_packet(self.compressor).await?;
-            packet.error_for_status()?;
-        }
-        Ok(())
+    /// Wrap the value of a store request queued in a batch in the envelope,
+    /// and check that it may be written.
+    pub(crate) fn prepare_store(&self, packet: Packet, expire: u32) -> Result<Packet, Error> {
+        let packet = wrap_envelope(
+            &*self.envelope_codec,
+            self.envelope,
+            packet,
+            expire,
+            BINCODE_SERIALIZER,
+        );
+        self.check_write(self.compressor, &packet)?;
+        Ok(packet)
     }
-}
-
-#[async_trait]
-impl<C, P> Manager for ClientConfig<C, P>
-where
-    C: Connection,
-    P: Compressor,
-{
-    type Type = Client<C, P>;
-    type Error = Error;
 
-    async fn create(&self) -> Result<Self::Type, Error> {
-        let mut client = Client::new(self.clone()).await?;
-        client.keep_alive().await?;
-        Ok(client)
+    /// Send queued mutations as a single pipeline per node, terminated by a
+    /// NOOP so that quiet requests are only answered when they fail. Each
+    /// request carries its index in the opaque field, which is used to
+    /// report errors by key. Deletes of keys which are not set succeed.
+    pub(crate) async fn send_batch(
+        &mut self,
+        queued: Vec<Queued>,
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        self.send_batch_with(queued, self.compressor, options).await
     }
 
-    async fn recycle(&self, client: &mut Self::Type) -> RecycleResult<Error> {
-        client.keep_alive().await?;
+    /// Send queued mutations like [`Client::send_batch`], compressing them
+    /// with the given compressor.
+    async fn send_batch_with<Q: Compressor>(
+        &mut self,
+        queued: Vec<Queued>,
+        compressor: Q,
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        let options = &self.bound_deadline(options);
+        options.check_deadline(C::now())?;
+        let chunks = match self.batch_limits {
+            Some(limits) => {
+                let items = queued.into_iter().map(|queued| {
+                    let packet = &queued.packet;
+                    let size = 24 + packet.extras.len() + packet.key.len() + packet.value.len();
+                    (queued, size)
+                });
+                limits.split(items.collect())?
+            }
+            None => vec![queued],
+        };
+        let mut errors = HashMap::new();
+        for chunk in chunks {
+            errors.extend(self.send_batch_chunk(chunk, compressor, options).await?);
+        }
+        Ok(errors)
+    }
+
+    /// Send queued mutations within the batch limits, if any.
+    async fn send_batch_chunk<Q: Compressor>(
+        &mut self,
+        queued: Vec<Queued>,
+        compressor: Q,
+        options: &RequestOptions,
+    ) -> BulkUpdateResponse {
+        let mut errors = HashMap::new();
+        if !self.is_enabled() || queued.is_empty() {
+            return Ok(errors);
+        }
+        self.check_writable()?;
+        for queued in &queued {
+            self.forget_local(&queued.packet.key);
+        }
+        self.record_keys(&queued);
+
+        let idle_ping = self.idle_ping;
+        let (limiter, audit) = (self.limiter.as_deref(), self.audit.as_deref());
+        let tags = &options.audit_tags[..];
+        let pipelines =
+            self.ring
+                .get_conns(&queued)
+                .into_iter()
+                .map(|(conn, pipeline)| async move {
+                    let _permits = limit::acquire::<C>(limiter, &conn.endpoint).await?;
+                    let mut reqs = pipeline
+                        .iter()
+                        .enumerate()
+                        .map(|(i, queued)| {
+                            let mut packet = queued.packet.clone();
+                            packet.header.opaque = i as u32;
+                            packet
+                        })
+                        .collect::<Vec<_>>();
+                    audit::record(audit, &reqs, tags);
+                    let mut noop = Packet::noop()?;
+                    noop.header.opaque = pipeline.len() as u32;
+                    reqs.push(noop);
+
+                    conn.ensure_connected(idle_ping).await?;
+                    let (mut reader, mut writer) = conn.split();
+                    let write = write_pipeline(&mut writer, compressor, reqs);
+                    let read = async {
+                        let mut errors = HashMap::new();
+                        loop {
+                            let packet = reader.read_packet(compressor).await?;
+                            if packet.is_noop() {
+                                return Ok::<_, Error>(errors);
+                            }
+                            let queued = pipeline.get(packet.header.opaque as usize);
+                            let key = queued
+                                .map(|queued| queued.packet.key.clone())
+                                .unwrap_or_else(|| packet.key.clone());
+                            let deleted = queued.is_some_and(|q| q.reason == Invalidation::Deleted);
+                            match packet.error_for_status() {
+                                Ok(()) => (),
+                                Err(Status::KeyNotFound) if deleted => (),
+                                Err(err) => {
+                                    errors.insert(key, Error::Status(err));
+                                }
+                            }
+                        }
+                    };
+                    let (write_errors, read) = join(write, read).await;
+                    let mut errors = read?;
+                    errors.extend(write_errors);
+                    Ok::<_, Error>(errors)
+                });
+
+        let results = join_all(pipelines).await;
+        for queued in &queued {
+            self.invalidate_local(&queued.packet.key, queued.reason);
+        }
+        for result in results {
+            errors.extend(result?);
+        }
+
+        Ok(errors)
+    }
+
+    /// Delete multiple keys like [`Client::delete_multi`], taking ownership
+    /// of the keys.
+    pub async fn delete_multi_owned<K: AsRef<[u8]>>(&mut self, keys: Vec<K>) -> BulkUpdateResponse {
+        self.delete_multi(&keys).await
+    }
+
+    /// Change the expiration of a key without sending its value again.
+    /// Returns true if the key was touched, or false if it was not found.
+    pub async fn touch<K: AsRef<[u8]>>(&mut self, key: K, expire: u32) -> Result<bool, Error> {
+        if !self.is_enabled() {
+            return Ok(false);
+        }
+        self.check_writable()?;
+        let key = key.as_ref();
+        let packet = Packet::touch(key, TouchExtras::new(expire))?;
+        let packet = self.request(key, packet).await?;
+        match packet.error_for_status() {
+            Ok(()) => Ok(true),
+            Err(Status::KeyNotFound) => Ok(false),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Set a key to `new` only if it currently holds `expected`, as a get
+    /// followed by a compare and a CAS write, so that writers racing on the
+    /// same key cannot both succeed. This suits small shared values like
+    /// leader election flags and config toggles. Returns true if the guard
+    /// matched and the value was written, or false if the key is missing,
+    /// holds a different value, or was changed between the get and the set.
+    pub async fn set_if_value<K, V>(
+        &mut self,
+        key: K,
+        expected: &V,
+        new: &V,
+        expire: u32,
+    ) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: Serialize + DeserializeOwned + PartialEq,
+    {
+        if !self.is_enabled() {
+            return Ok(false);
+        }
+        self.check_writable()?;
+        let key = key.as_ref();
+        let cas = match self.gets::<_, V>(key).await? {
+            Some((value, cas)) if value == *expected => cas,
+            _ => return Ok(false),
+        };
+        let (extras, fields) = (SetExtras::new(0, expire), RequestFields::new(cas, 0));
+        let packet = Packet::set_with(key, &self.redact(key, new), extras, fields)?;
+        self.store_if(key, packet, expire).await
+    }
+
+    /// Get a single value along with its CAS value, which can be set on a
+    /// later write to make it fail with [`Status::KeyExists`] if the key was
+    /// changed in the meantime. The read always goes to memcached, bypassing
+    /// the local tier. Returns None when the key is not found.
+    pub async fn gets<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<(V, u64)>, Error> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let key = key.as_ref();
+        let packet = self.request(key, Packet::get(key)?).await?;
+        match packet.error_for_status() {
+            Ok(()) => {
+                let cas = packet.header.cas;
+                let (value, _) = unwrap_entry(&*self.envelope_codec, packet)?;
+                Ok(Some((value, cas)))
+            }
+            Err(Status::KeyNotFound) => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Increment a counter by `delta`, returning the new value. If the key
+    /// does not exist it is created with the `initial` value and `expire`
+    /// expiration. Use an expiration of `u32::MAX` to return
+    /// [`Status::KeyNotFound`] instead of creating the counter.
+    pub async fn incr<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        delta: u64,
+        initial: u64,
+        expire: u32,
+    ) -> Result<u64, Error> {
+        let extras = CounterExtras::new(delta, initial, expire);
+        self.incr_with(key.as_ref(), extras).await
+    }
+
+    /// Decrement a counter by `delta`, returning the new value. Counters
+    /// never go below 0. The `initial` and `expire` values behave the same
+    /// as [`Client::incr`].
+    pub async fn decr<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        delta: u64,
+        initial: u64,
+        expire: u32,
+    ) -> Result<u64, Error> {
+        let extras = CounterExtras::new(delta, initial, expire);
+        self.decr_with(key.as_ref(), extras).await
+    }
+
+    /// Increment multiple counters by their deltas in a single round trip
+    /// per node, returning the new value of every counter. Missing counters
+    /// are created with the `initial` value and `expire` expiration, like
+    /// [`Client::incr`]. Counters which could not be incremented are returned
+    /// in the error map instead.
+    pub async fn incr_multi<K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        deltas: HashMap<K, u64>,
+        initial: u64,
+        expire: u32,
+    ) -> BulkGetResponse<u64> {
+        let options = RequestOptions::default();
+        self.incr_multi_with_options(deltas, initial, expire, &options)
+            .await
+    }
+
+    /// Increment multiple counters like [`Client::incr_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn incr_multi_with_options<K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        deltas: HashMap<K, u64>,
+        initial: u64,
+        expire: u32,
+        options: &RequestOptions,
+    ) -> BulkGetResponse<u64> {
+        let counter =
+            |key: &[u8], delta| Packet::incr(key, CounterExtras::new(delta, initial, expire));
+        self.counter_multi(deltas, initial, options, counter).await
+    }
+
+    /// Decrement multiple counters by their deltas in a single round trip
+    /// per node, like [`Client::incr_multi`]. Counters never go below 0.
+    pub async fn decr_multi<K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        deltas: HashMap<K, u64>,
+        initial: u64,
+        expire: u32,
+    ) -> BulkGetResponse<u64> {
+        let options = RequestOptions::default();
+        self.decr_multi_with_options(deltas, initial, expire, &options)
+            .await
+    }
+
+    /// Decrement multiple counters like [`Client::decr_multi`], with options
+    /// overriding the client configuration for this request. See
+    /// [`RequestOptions`] for the options bulk writes honor.
+    pub async fn decr_multi_with_options<K: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        deltas: HashMap<K, u64>,
+        initial: u64,
+        expire: u32,
+        options: &RequestOptions,
+    ) -> BulkGetResponse<u64> {
+        let counter =
+            |key: &[u8], delta| Packet::decr(key, CounterExtras::new(delta, initial, expire));
+        self.counter_multi(deltas, initial, options, counter).await
+    }
+
+    /// Pipeline counter requests to every node, terminated by a NOOP.
+    /// Counter responses carry no key, so each request carries its index in
+    /// the opaque field, which is used to map responses back to their keys.
+    async fn counter_multi<K, F>(
+        &mut self,
+        deltas: HashMap<K, u64>,
+        initial: u64,
+        options: &RequestOptions,
+        counter: F,
+    ) -> BulkGetResponse<u64>
+    where
+        K: AsRef<[u8]> + Eq + Hash,
+        F: Fn(&[u8], u64) -> bincode::Result<Packet>,
+    {
+        let options = &self.bound_deadline(options);
+        options.check_deadline(C::now())?;
+        let chunks = match self.batch_limits {
+            Some(limits) => {
+                // A counter request is a header, 20 bytes of extras and a key.
+                let items = deltas.into_iter().map(|(key, delta)| {
+                    let size = 44 + key.as_ref().len();
+                    ((key, delta), size)
+                });
+                let chunks = limits.split(items.collect())?.into_iter();
+                chunks.map(|chunk| chunk.into_iter().collect()).collect()
+            }
+            None => vec![deltas],
+        };
+        let (mut values, mut errors) = (HashMap::new(), HashMap::new());
+        for chunk in chunks {
+            let (chunk_values, chunk_errors) = self
+                .counter_multi_chunk(chunk, initial, options, &counter)
+                .await?;
+            values.extend(chunk_values);
+            errors.extend(chunk_errors);
+        }
+        Ok((values, errors))
+    }
+
+    /// Pipeline counter requests within the batch limits, if any.
+    async fn counter_multi_chunk<K, F>(
+        &mut self,
+        deltas: HashMap<K, u64>,
+        initial: u64,
+        options: &RequestOptions,
+        counter: &F,
+    ) -> BulkGetResponse<u64>
+    where
+        K: AsRef<[u8]> + Eq + Hash,
+        F: Fn(&[u8], u64) -> bincode::Result<Packet>,
+    {
+        let mut values = HashMap::new();
+        let mut errors = HashMap::new();
+        if !self.is_enabled() {
+            let keys = deltas.keys().map(|key| key.as_ref().to_vec());
+            return Ok((keys.map(|key| (key, initial)).collect(), errors));
+        }
+        if deltas.is_empty() {
+            return Ok((values, errors));
+        }
+        self.check_writable()?;
+        let keys = deltas.keys().collect::<Vec<_>>();
+        for key in &keys {
+            self.forget_local(key.as_ref());
+        }
+        self.record_keys(&keys);
+
+        let (compressor, idle_ping) = (self.compressor, self.idle_ping);
+        let (limiter, audit) = (self.limiter.as_deref(), self.audit.as_deref());
+        let tags = &options.audit_tags[..];
+        let pipelines = self
+            .ring
+            .get_conns(&keys[..])
+            .into_iter()
+            .map(|(conn, pipeline)| {
+                let deltas = &deltas;
+                async move {
+                    let _permits = limit::acquire::<C>(limiter, &conn.endpoint).await?;
+                    let mut reqs = pipeline
+                        .iter()
+                        .enumerate()
+                        .map(|(i, key)| {
+                            let mut packet = counter(key.as_ref(), deltas[**key])?;
+                            packet.header.opaque = i as u32;
+                            Ok(packet)
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    audit::record(audit, &reqs, tags);
+                    let mut noop = Packet::noop()?;
+                    noop.header.opaque = pipeline.len() as u32;
+                    reqs.push(noop);
+
+                    conn.ensure_connected(idle_ping).await?;
+                    let (mut reader, mut writer) = conn.split();
+                    let write = write_pipeline(&mut writer, compressor, reqs);
+                    let read = async {
+                        let mut values = HashMap::new();
+                        let mut errors = HashMap::new();
+                        loop {
+                            let packet = reader.read_packet(compressor).await?;
+                            if packet.is_noop() {
+                                return Ok::<_, Error>((values, errors));
+                            }
+                            let key = match pipeline.get(packet.header.opaque as usize) {
+                                Some(key) => key.as_ref().to_vec(),
+                                None => continue,
+                            };
+                            let value = packet
+                                .error_for_status()
+                                .map_err(Error::Status)
+                                .and_then(|()| Ok(packet.counter_value()?));
+                            match value {
+                                Ok(value) => {
+                                    values.insert(key, value);
+                                }
+                                Err(err) => {
+                                    errors.insert(key, err);
+                                }
+                            }
+                        }
+                    };
+                    let (write_errors, read) = join(write, read).await;
+                    // Responses may be left on the stream after a failed read.
+                    if read.is_err() {
+                        conn.counters.poison();
+                    }
+                    let (values, mut errors) = read?;
+                    errors.extend(write_errors);
+                    Ok::<_, Error>((values, errors))
+                }
+            });
+
+        let results = join_all(pipelines).await;
+        for key in &keys {
+            self.invalidate_local(key.as_ref(), Invalidation::Overwritten);
+        }
+        for result in results {
+            let (node_values, node_errors) = result?;
+            values.extend(node_values);
+            errors.extend(node_errors);
+        }
+
+        Ok((values, errors))
+    }
+
+    /// Get a handle to the counter stored under the given key.
+    pub fn counter<K: Into<Vec<u8>>>(&mut self, key: K) -> Counter<'_, C, P> {
+        Counter::new(self, key.into())
+    }
+
+    pub(crate) async fn incr_with(
+        &mut self,
+        key: &[u8],
+        extras: CounterExtras,
+    ) -> Result<u64, Error> {
+        if !self.is_enabled() {
+            return Ok(extras.initial);
+        }
+        self.check_writable()?;
+        let packet = Packet::incr(key, extras)?;
+        self.forget_local(key);
+        let response = self.request(key, packet).await;
+        self.invalidate_local(key, Invalidation::Overwritten);
+        let packet = response?;
+        packet.error_for_status()?;
+        Ok(packet.counter_value()?)
+    }
+
+    pub(crate) async fn decr_with(
+        &mut self,
+        key: &[u8],
+        extras: CounterExtras,
+    ) -> Result<u64, Error> {
+        if !self.is_enabled() {
+            return Ok(extras.initial);
+        }
+        self.check_writable()?;
+        let packet = Packet::decr(key, extras)?;
+        self.forget_local(key);
+        let response = self.request(key, packet).await;
+        self.invalidate_local(key, Invalidation::Overwritten);
+        let packet = response?;
+        packet.error_for_status()?;
+        Ok(packet.counter_value()?)
+    }
+
+    /// Ask every node for its server version, by endpoint. The versions
+    /// recorded for [`Client::cluster_features`] are updated as well, for
+    /// example after the servers were upgraded in place.
+    pub async fn version(&mut self) -> Result<HashMap<String, String>, Error> {
+        let idle_ping = self.idle_ping;
+        let mut out = HashMap::new();
+        for node in self.ring.into_iter() {
+            node.ensure_connected(idle_ping).await?;
+            node.detect_version().await?;
+            let version = node.version.clone().unwrap_or_default();
+            out.insert(node.endpoint.clone(), version);
+        }
+        Ok(out)
+    }
+
+    /// Get the server versions recorded for every node when the client
+    /// connected, which can be used to check which features the whole
+    /// cluster supports.
+    pub fn cluster_features(&self) -> ClusterFeatures {
+        self.ring.features()
+    }
+
+    /// Return [`Error::Unsupported`] unless every server in the cluster
+    /// supports the given feature.
+    pub fn require(&self, feature: Feature) -> Result<(), Error> {
+        match self.cluster_features().supports(feature) {
+            true => Ok(()),
+            false => Err(Error::Unsupported(feature)),
+        }
+    }
+
+    /// Get a snapshot of the statistics tracked for every node in the
+    /// cluster, such as request counts, bytes transferred and errors.
+    pub fn node_stats(&self) -> Vec<NodeStats> {
+        self.ring.stats()
+    }
+
+    /// Get a summary of the health of every node in the cluster: whether it
+    /// is healthy, degraded or ejected by its error budget, how many requests
+    /// failed in a row, the last error, the latency average and how long ago
+    /// it last answered. Nothing is sent to the nodes, so this is cheap
+    /// enough to call from a service's health endpoint.
+    pub fn health(&self) -> Vec<NodeHealth> {
+        self.ring.nodes().map(Node::health).collect()
+    }
+
+    /// Get the nodes with the highest moving average latency, slowest first,
+    /// up to `n` of them. Nodes which have not answered a request yet are
+    /// left out.
+    pub fn slowest_nodes(&self, n: usize) -> Vec<(String, Duration)> {
+        let mut nodes = self
+            .ring
+            .nodes()
+            .filter_map(|node| Some((node.endpoint.clone(), node.latency()?)))
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|(_, latency)| std::cmp::Reverse(*latency));
+        nodes.truncate(n);
+        nodes
+    }
+
+    /// Invalidate every item on every node after `delay` seconds, or right
+    /// away with a delay of 0, and clear the local tier. This is meant for
+    /// wiping test environments, since it empties the whole cluster. Returns
+    /// the errors of the nodes which could not be flushed, by endpoint.
+    pub async fn flush_all(&mut self, delay: u32) -> Result<HashMap<String, Error>, Error> {
+        let mut errors = HashMap::new();
+        if !self.is_enabled() {
+            return Ok(errors);
+        }
+        self.check_writable()?;
+        if let Some(local) = &self.local {
+            local.clear();
+        }
+        let (compressor, idle_ping) = (self.compressor, self.idle_ping);
+        let packet = Packet::flush(FlushExtras::new(delay))?;
+        for node in self.ring.into_iter() {
+            let result = match node.ensure_connected(idle_ping).await {
+                Ok(()) => node.send(compressor, packet.clone()).await,
+                Err(err) => Err(err),
+            };
+            let result = result.and_then(|packet| Ok(packet.error_for_status()?));
+            if let Err(err) = result {
+                errors.insert(node.endpoint.clone(), err);
+            }
+        }
+        Ok(errors)
+    }
+
+    /// Get the general stats of every node, such as `curr_items` and
+    /// `evictions`, by endpoint.
+    pub async fn stats(&mut self) -> Result<HashMap<String, HashMap<String, String>>, Error> {
+        Ok(self.server_stats("").await?.into_iter().collect())
+    }
+
+    /// Get the slab allocator stats of every node, showing how memory is
+    /// spread over slab classes of different item sizes.
+    pub async fn slab_stats(&mut self) -> Result<Vec<SlabStats>, Error> {
+        let stats = self.server_stats("slabs").await?;
+        let stats = stats.iter();
+        Ok(stats
+            .map(|(endpoint, stats)| stats::parse_slab_stats(endpoint, stats))
+            .collect())
+    }
+
+    /// Get the item stats of every node by slab class, such as the age of
+    /// the oldest item and the number of evictions, to find out which item
+    /// sizes are under eviction pressure.
+    pub async fn item_stats(&mut self) -> Result<Vec<ItemStats>, Error> {
+        let stats = self.server_stats("items").await?;
+        let stats = stats.iter();
+        Ok(stats
+            .map(|(endpoint, stats)| stats::parse_item_stats(endpoint, stats))
+            .collect())
+    }
+
+    /// Get the commands counted for every key prefix on every node. See
+    /// [`DetailStats`] for how to enable counting on the server.
+    pub async fn detail_stats(&mut self) -> Result<Vec<DetailStats>, Error> {
+        let stats = self.server_stats("detail dump").await?;
+        let stats = stats.iter();
+        Ok(stats
+            .map(|(endpoint, stats)| stats::parse_detail_stats(endpoint, stats))
+            .collect())
+    }
+
+    /// Get the extstore stats of every node, for nodes which store values on
+    /// flash. Nodes without extstore are reported as not enabled.
+    pub async fn extstore_stats(&mut self) -> Result<Vec<ExtstoreStats>, Error> {
+        let stats = self.server_stats("").await?;
+        let stats = stats.iter();
+        Ok(stats
+            .map(|(endpoint, stats)| stats::parse_extstore_stats(endpoint, stats))
+            .collect())
+    }
+
+    /// Get the extstore stats of every node like [`Client::extstore_stats`],
+    /// and report every threshold they cross to the hook `H`. Call this
+    /// periodically to be warned when reads from flash get slow or fail.
+    pub async fn check_extstore<H: MetricsHook>(
+        &mut self,
+        thresholds: &ExtstoreThresholds,
+    ) -> Result<Vec<ExtstoreStats>, Error> {
+        let stats = self.extstore_stats().await?;
+        let hook = H::default();
+        for node in &stats {
+            for warning in node.warnings(thresholds) {
+                hook.on_extstore_warning(&node.endpoint, warning);
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Request a group of stats from every node, by endpoint.
+    async fn server_stats(
+        &mut self,
+        group: &str,
+    ) -> Result<Vec<(String, HashMap<String, String>)>, Error> {
+        let idle_ping = self.idle_ping;
+        let mut out = vec![];
+        for node in self.ring.into_iter() {
+            node.ensure_connected(idle_ping).await?;
+            let stats = stats::read_node_stats(node, group).await?;
+            out.push((node.endpoint.clone(), stats));
+        }
+        Ok(out)
+    }
+
+    /// Write, read, compress, CAS-update and delete a synthetic key on every
+    /// node, checking the CRC of every value read back, to smoke test a new
+    /// cluster or config. Nodes are tested even if they fail open. See
+    /// [`crate::selftest`] for the steps.
+    pub async fn self_test(&mut self) -> Result<SelfTestReport, Error> {
+        self.check_writable()?;
+        let (compressor, idle_ping) = (self.compressor, self.idle_ping);
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let mut report = SelfTestReport::default();
+        for (i, node) in self.ring.into_iter().enumerate() {
+            let key = format!("rsmc:self-test:{}:{}", nonce, i);
+            let node = selftest::test_node(node, compressor, idle_ping, key).await;
+            report.nodes.push(node);
+        }
+        Ok(report)
+    }
+
+    /// Take a snapshot of the nodes of this client and which of them fail
+    /// open, to save before shutting down and restore with
+    /// [`ClientConfig::with_topology_snapshot`].
+    pub fn topology_snapshot(&self) -> TopologySnapshot {
+        self.ring.topology_snapshot()
+    }
+
+    /// Get up to `n` of the hottest keys requested through clients created
+    /// from the same config, with their estimated request counts. This is
+    /// empty unless a [`HotKeyDetector`] is configured.
+    pub fn hot_keys(&self, n: usize) -> Vec<(Vec<u8>, u64)> {
+        match &self.hot_keys {
+            Some(detector) => detector.hot_keys(n),
+            None => vec![],
+        }
+    }
+
+    /// How long to wait before loading a missed value from the source of
+    /// truth, while the configured [`ColdStart`] finds the cluster cold, or
+    /// None to load right away. The core has no timer, so the caller sleeps
+    /// for the delay with the timer of its runtime.
+    pub fn admit_load(&self) -> Option<Duration> {
+        self.cold_start.as_ref().and_then(ColdStart::delay)
+    }
+
+    /// Get a value, or on a miss load it from the source of truth with the
+    /// loader and write it back with the given expiration. Loads wait out
+    /// [`Client::admit_load`] with [`Connection::sleep`] first. Errors of
+    /// the cache are returned, unless the config has a
+    /// [`ResilienceConfig::with_loader_fallback`], in which case a failed
+    /// read loads the value anyway and a failed write is skipped. Values are
+    /// never written back while writes are suppressed.
+    pub async fn get_or_load<K, V, F, Fut, E>(
+        &mut self,
+        key: K,
+        expire: u32,
+        loader: F,
+    ) -> Result<V, E>
+    where
+        K: AsRef<[u8]>,
+        V: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+        E: From<Error>,
+    {
+        let key = key.as_ref();
+        match self.get(key).await {
+            Ok(Some(value)) => return Ok(value),
+            Ok(None) => {}
+            Err(err) => self.fall_back_to_loader(err)?,
+        }
+        if let Some(delay) = self.admit_load() {
+            C::sleep(delay).await;
+        }
+        let value = loader().await?;
+        match self.set(key, &value, expire).await {
+            // Suppressed writes are counted as such, and only skip the cache.
+            Ok(()) | Err(Error::ReadOnly) => {}
+            Err(err) => self.fall_back_to_loader(err)?,
+        }
+        Ok(value)
+    }
+
+    /// Count an error of the cache which the loader falls back on, or return
+    /// it without a loader fallback.
+    fn fall_back_to_loader(&self, err: Error) -> Result<(), Error> {
+        match (self.loader_fallback, &self.resilience) {
+            (true, Some(resilience)) => {
+                resilience.loader_fallback();
+                Ok(())
+            }
+            _ => Err(err),
+        }
+    }
+
+    fn record_reads(&self, hits: u64, misses: u64) {
+        if let Some(cold_start) = &self.cold_start {
+            cold_start.record(hits, misses);
+        }
+    }
+
+    fn record_keys<K: AsRef<[u8]>>(&self, keys: &[K]) {
+        if let Some(detector) = &self.hot_keys {
+            for key in keys {
+                detector.record(key.as_ref());
+            }
+        }
+    }
+
+    /// Measure how evenly the cluster spreads a sample of keys over its
+    /// nodes, with the configured hashing scheme and vbucket map. See
+    /// [`crate::hashing::distribution_report`] to try other schemes.
+    pub fn distribution_report<K: AsRef<[u8]>>(&self, keys: &[K]) -> DistributionReport {
+        self.ring.distribution_report(keys)
+    }
+
+    /// Take the cache out of the request path, or put it back. While
+    /// disabled, every get returns a miss and every write does nothing
+    /// (counters return their initial value), without touching the network.
+    /// Conditional writes such as [`Client::add`], [`Client::replace`],
+    /// [`Client::touch`] and [`Client::set_if_value`] return false, the same
+    /// as when the condition fails, so check [`Client::is_enabled`] to tell
+    /// the two apart. The switch is shared by every client created from the
+    /// same config, so flipping it on one pooled client affects the whole
+    /// pool.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Whether the cache is enabled. See [`Client::set_enabled`].
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Return [`Error::ReadOnly`] if this client is configured as read-only.
+    pub(crate) fn check_writable(&self) -> Result<(), Error> {
+        if !self.read_only {
+            return Ok(());
+        }
+        if let Some(resilience) = &self.resilience {
+            resilience.suppressed();
+        }
+        Err(Error::ReadOnly)
+    }
+
+    /// Serialize, wrap and compress a value the way [`Client::set`] would,
+    /// without sending it, and return the size of the value memcached would
+    /// store. This is the size checked by the [`WriteCheck`].
+    pub fn encoded_size<V: Serialize + ?Sized>(&self, value: &V) -> Result<usize, Error> {
+        let packet = Packet::set(b"", &self.redact(b"", value), SetExtras::new(0, 0))?;
+        let packet = wrap_envelope(
+            &*self.envelope_codec,
+            self.envelope,
+            packet,
+            0,
+            BINCODE_SERIALIZER,
+        );
+        Ok(self.compressor.compress(packet)?.value.len())
+    }
+
+    /// Wrap a value stored under the key to be serialized with the fields
+    /// selected by the configured [`ValueTransform`] redacted.
+    pub(crate) fn redact<'a, V: ?Sized>(&'a self, key: &'a [u8], value: &'a V) -> Redacted<'a, V> {
+        Redacted::new(value, key, self.transform.as_deref())
+    }
+
+    /// Run the configured [`WriteCheck`] on a packet about to be written
+    /// with the compressor.
+    fn check_write<Q: Compressor>(&self, compressor: Q, packet: &Packet) -> Result<(), Error> {
+        match &self.write_check {
+            Some(check) => check.check(compressor, packet),
+            None => Ok(()),
+        }
+    }
+
+    /// Split the values of a bulk write into chunks within the batch limits,
+    /// or a single chunk without limits.
+    fn split_writes<K: AsRef<[u8]> + Eq + Hash, V: Serialize>(
+        &self,
+        data: HashMap<K, V>,
+    ) -> Result<Vec<HashMap<K, V>>, Error> {
+        let limits = match self.batch_limits {
+            Some(limits) => limits,
+            None => return Ok(vec![data]),
+        };
+        let items = data
+            .into_iter()
+            .map(|(key, value)| {
+                let size = bincode::serialized_size(&self.redact(key.as_ref(), &value))? as usize;
+                let size = 32 + key.as_ref().len() + size;
+                Ok(((key, value), size))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let chunks = limits.split(items)?;
+        let chunks = chunks.into_iter().map(|chunk| chunk.into_iter().collect());
+        Ok(chunks.collect())
+    }
+
+    /// Drop the values of a bulk write that the configured [`WriteCheck`]
+    /// vetoes, recording an error for each of them.
+    fn check_writes<K: AsRef<[u8]> + Eq + Hash, V: Serialize>(
+        &self,
+        mut data: HashMap<K, V>,
+        expire: u32,
+        errors: &mut BulkErrResponse,
+    ) -> HashMap<K, V> {
+        if self.write_check.is_none() {
+            return data;
+        }
+        data.retain(|key, value| {
+            let key = key.as_ref();
+            let result = Packet::set(key, &self.redact(key, value), SetExtras::new(0, expire))
+                .map_err(Error::from)
+                .map(|packet| {
+                    wrap_envelope(
+                        &*self.envelope_codec,
+                        self.envelope,
+                        packet,
+                        expire,
+                        BINCODE_SERIALIZER,
+                    )
+                })
+                .and_then(|packet| self.check_write(self.compressor, &packet));
+            match result {
+                Ok(()) => true,
+                Err(err) => {
+                    errors.insert(key.to_vec(), err);
+                    false
+                }
+            }
+        });
+        data
+    }
+
+    /// Drop the local tier entry for a key about to be written, without
+    /// reporting it, so that it is not served while the write is in flight.
+    fn forget_local(&self, key: &[u8]) {
+        if let Some(local) = &self.local {
+            local.remove(key);
+        }
+    }
+
+    /// Drop the local tier entry for a key written through this client, once
+    /// the write completed, and report it. The entry is dropped again since
+    /// another client sharing the tier may have read the old value from
+    /// memcached while the write was in flight.
+    fn invalidate_local(&self, key: &[u8], reason: Invalidation) {
+        if let Some(local) = &self.local {
+            local.invalidate(key, reason);
+        }
+    }
+
+    /// Bound the deadline of the options by the deadline source, if any.
+    fn bound_deadline(&self, options: &RequestOptions) -> RequestOptions {
+        let source = self.deadline_source.as_ref();
+        options.bounded_by(source.and_then(|source| source.deadline()))
+    }
+
+    /// Send a single request to the node owning the key and read the
+    /// response.
+    pub(crate) async fn request(&mut self, key: &[u8], packet: Packet) -> Result<Packet, Error> {
+        let options = RequestOptions::default();
+        self.request_with(key, packet, self.compressor, &options)
+            .await
+    }
+
+    /// Send a single request using the given compressor and options. If the
+    /// server had already closed the connection, for example because it was
+    /// idle for too long, then the node reconnects and the request is
+    /// retried, once unless the options say otherwise. Only idempotent
+    /// requests are retried, since the closed connection may have lost the
+    /// response to a request which was applied. Waiting for the response
+    /// fails once the deadline of the options passes.
+    async fn request_with<Q: Compressor>(
+        &mut self,
+        key: &[u8],
+        mut packet: Packet,
+        compressor: Q,
+        options: &RequestOptions,
+    ) -> Result<Packet, Error> {
+        let options = &self.bound_deadline(options);
+        if let Some(opaque) = options.opaque {
+            packet.header.opaque = opaque;
+        }
+        options.check_deadline(C::now())?;
+        self.record_keys(&[key]);
+        audit::record(self.audit.as_deref(), [&packet], &options.audit_tags);
+        let conn = self.ring.get_conn(key)?;
+        if let (None, Some(baggage)) = (options.opaque, options.baggage) {
+            let sequence = conn.counters.next_opaque();
+            packet.header.opaque = options::encode_baggage(baggage, sequence);
+        }
+        let _permits = limit::acquire::<C>(self.limiter.as_deref(), &conn.endpoint).await?;
+        options.check_deadline(C::now())?;
+        conn.ensure_connected(self.idle_ping).await?;
+        let mut retries = match packet.is_idempotent() {
+            true => options.retries.unwrap_or(1),
+            false => 0,
+        };
+        loop {
+            let remaining = options.remaining(C::now());
+            match conn
+                .send_within(compressor, packet.clone(), remaining)
+                .await
+            {
+                Err(err) if err.is_connection_closed() && retries > 0 => {
+                    retries -= 1;
+                    options.check_deadline(C::now())?;
+                    conn.reconnect().await?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Ping every node that has been idle for longer than the configured
+    /// idle ping interval, reconnecting nodes whose connection was closed.
+    /// Call this periodically, with an interval below the server's idle
+    /// timeout, to keep connections from being closed by the server.
+    pub async fn ping_idle(&mut self) -> Result<(), Error> {
+        for conn in self.ring.into_iter() {
+            conn.ensure_connected(self.idle_ping).await?;
+        }
+        Ok(())
+    }
+
+    async fn keep_alive(&mut self) -> Result<(), Error> {
+        let request = match self.keep_alive {
+            KeepAlive::Noop => Packet::noop()?,
+            KeepAlive::Version => Packet::version()?,
+            KeepAlive::Disabled => return Ok(()),
+        };
+        for conn in self.ring.into_iter() {
+            conn.write_packet(self.compressor, request.clone()).await?;
+            let packet = conn.read_packet(self.compressor).await?;
+            packet.error_for_status()?;
+            if packet.header.opcode != request.header.opcode {
+                let opcode = packet.header.opcode;
+                return Err(ProtocolError::UnexpectedOpcode(opcode).into());
+            }
+        }
         Ok(())
     }
 }
 
-/// A connection pool for multiple connections. Using a pool is recommended
-/// for best performance since it eliminates the overhead of having to
-/// constantly recreate TCP connections, while also balancing the total
-/// number of connections open at a time.
-pub type Pool<C, P> = deadpool::managed::Pool<ClientConfig<C, P>>;
+/// Write every request in a pipeline to a node, collecting the errors for
+/// any requests that could not be written by key.
+async fn write_pipeline<C: Connection, P: Compressor>(
+    writer: &mut Node<C>,
+    compressor: P,
+    reqs: Vec<Packet>,
+) -> BulkErrResponse {
+    let mut errors = HashMap::new();
+    for packet in reqs {
+        let key = packet.key.clone();
+        if let Err(err) = writer.write_packet(compressor, packet).await {
+            // NOOPs written between keys have no key to report.
+            if !key.is_empty() {
+                errors.insert(key, err);
+            }
+        }
+    }
+    errors
+}
 
-#[cfg(test)]
-mod tests {
-    use crate::protocol::ProtocolError;
+/// The opaque of the NOOP ending a get pipeline, which tells it apart from
+/// the NOOPs between keys bounding the wait for progress, which carry 0.
+const END_OF_PIPELINE: u32 = 1;
 
-    use super::Error;
+/// A value paired with the CAS value it is written with, serialized as just
+/// the value.
+struct WithCas<V>(V, u64);
 
-    #[test]
-    fn test_err_display() {
-        assert_eq!(
-            "ProtocolError: Invalid magic byte: 8",
-            format!("{}", Error::Protocol(ProtocolError::InvalidMagic(8)))
+impl<V: Serialize> Serialize for WithCas<V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+/// How the values read by a pipeline are decompressed and unwrapped, and
+/// which of its keys are touched on read.
+#[derive(Clone, Copy)]
+struct Decoder<'a, P> {
+    compressor: P,
+    envelope: &'a dyn Envelope,
+    touch: Option<&'a TouchOnRead>,
+}
+
+fn wrap_envelope(
+    codec: &dyn Envelope,
+    version: Option<u32>,
+    packet: Packet,
+    expire: u32,
+    serializer: u8,
+) -> Packet {
+    match version {
+        Some(version) => envelope::wrap(codec, packet, Metadata::new(expire, version, serializer)),
+        None => packet,
+    }
+}
+
+/// Unwrap the envelope of a value read for a single key and deserialize it.
+fn unwrap_entry<V: DeserializeOwned>(
+    codec: &dyn Envelope,
+    packet: Packet,
+) -> Result<(V, Option<Metadata>), Error> {
+    let (packet, meta) = envelope::unwrap(codec, packet)?;
+    Ok((packet.deserialize_value()?, meta))
+}
+
+#[async_trait]
+impl<C, P> Manager for ClientConfig<C, P>
+where
+    C: Connection,
+    P: Compressor,
+{
+    type Type = Client<C, P>;
+    type Error = Error;
+
+    async fn create(&self) -> Result<Self::Type, Error> {
+        let reconfigured = self.reconfigured.lock().unwrap().clone();
+        let revision = reconfigured.as_ref().map_or(0, |(revision, _)| *revision);
+        let config = reconfigured.as_ref().map_or(self, |(_, config)| config);
+        let mut client = Client::new(config.clone()).await?;
+        client.revision = revision;
+        client.keep_alive().await?;
+        Ok(client)
+    }
+
+    async fn recycle(&self, client: &mut Self::Type) -> RecycleResult<Error> {
+        let reconfigured = self.reconfigured.lock().unwrap().clone();
+        let config = match reconfigured {
+            Some((revision, config)) if client.revision != revision => {
+                client.apply_config((*config).clone()).await?;
+                client.revision = revision;
+                config
+            }
+            Some((_, config)) => config,
+            None => return self.recycle_with(client).await,
+        };
+        config.recycle_with(client).await
+    }
+}
+
+impl<C, P> ClientConfig<C, P>
+where
+    C: Connection,
+    P: Compressor,
+{
+    /// Check that a pooled client can be reused with this config.
+    async fn recycle_with(&self, client: &mut Client<C, P>) -> RecycleResult<Error> {
+        if client.is_degraded() {
+            return Err(RecycleError::StaticMessage("Client is degraded"));
+        }
+        if self.max_client_age.is_some_and(|max| client.age() > max) {
+            return Err(RecycleError::StaticMessage("Client is too old"));
+        }
+        if self
+            .idle_timeout
+            .is_some_and(|max| client.idle_time() > max)
+        {
+            return Err(RecycleError::StaticMessage("Client is idle"));
+        }
+        let checked = C::now().saturating_duration_since(client.checked_at);
+        if self
+            .recycle_interval
+            .is_some_and(|interval| checked < interval)
+        {
+            return Ok(());
+        }
+        client.keep_alive().await?;
+        client.checked_at = C::now();
+        Ok(())
+    }
+}
+
+/// A connection pool for multiple connections. Using a pool is recommended
+/// for best performance since it eliminates the overhead of having to
+/// constantly recreate TCP connections, while also balancing the total
+/// number of connections open at a time.
+pub type Pool<C, P> = deadpool::managed::Pool<ClientConfig<C, P>>;
+
+/// Apply a new config to every client of the pool. Clients created from now
+/// on use the new config, and idle clients apply it with
+/// [`Client::apply_config`] when they are next taken from the pool, or are
+/// replaced if that fails.
+pub fn reconfigure_pool<C: Connection, P: Compressor>(
+    pool: &Pool<C, P>,
+    config: ClientConfig<C, P>,
+) {
+    let mut reconfigured = pool.manager().reconfigured.lock().unwrap();
+    let revision = reconfigured
+        .as_ref()
+        .map_or(1, |(revision, _)| revision + 1);
+    *reconfigured = Some((revision, Arc::new(config)));
+}
+
+/// How long closing a node waits for the reply to its QUIT before shutting
+/// the connection down anyway.
+pub const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often [`drain_pool`] checks whether clients in use were returned.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Close every client of the pool with [`Client::close`] and then the pool
+/// itself, for a graceful shutdown. Clients which are in use are closed as
+/// they are returned to the pool, waiting up to the timeout for them with
+/// [`Connection::sleep`]. Clients still in use after the timeout are dropped
+/// when they are returned to the closed pool, and the drain fails with
+/// [`Error::DeadlineExceeded`] once every other client was closed. Getting a
+/// client from the pool afterwards fails with [`Error::PoolClosed`].
+///
+/// Waiting for clients in use needs a runtime timer. With the default
+/// [`Connection::sleep`], which never completes, a drain with clients in use
+/// and a non-zero timeout never finishes, so pass [`Duration::ZERO`] to close
+/// only the idle clients.
+pub async fn drain_pool<C: Connection, P: Compressor>(
+    pool: &Pool<C, P>,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let started = C::now();
+    let timeouts = Timeouts {
+        wait: Some(Duration::ZERO),
+        ..pool.timeouts()
+    };
+    let mut results = vec![];
+    loop {
+        let idle = pool.status().available.max(0) as usize;
+        let mut clients = Vec::with_capacity(idle);
+        for _ in 0..idle {
+            match pool.timeout_get(&timeouts).await {
+                Ok(client) => clients.push(Object::take(client)),
+                Err(_) => break,
+            }
+        }
+        results.extend(join_all(clients.iter_mut().map(Client::close)).await);
+        // Taking a client out of the pool shrinks it, so the clients left
+        // are the ones in use.
+        if pool.status().size == 0 {
+            break;
+        }
+        let remaining = timeout.saturating_sub(C::now().saturating_duration_since(started));
+        if remaining.is_zero() {
+            results.push(Err(Error::DeadlineExceeded));
+            break;
+        }
+        C::sleep(DRAIN_POLL_INTERVAL.min(remaining)).await;
+    }
+    pool.close();
+    results.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        batch::BatchLimits,
+        budget::{ErrorBudget, RetryBudget},
+        hashing::HashScheme,
+        keys::KeyCodec,
+        mock::{MockConnection, Store},
+        protocol::{Packet, ProtocolError, Status, MAGIC_RESPONSE_VALUE},
+        resolve::StaticResolver,
+        stats::NodeState,
+        testing::Failure,
+        warm::WarmPool,
+    };
+
+    use deadpool::managed::{Manager, PoolError, TimeoutType};
+    use futures::future::join;
+    use std::{
+        collections::HashMap,
+        future::Future,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::Poll,
+        time::{Duration, Instant},
+    };
+
+    use super::{
+        drain_pool, reconfigure_pool, BulkProgress, Client, ClientConfig, ConfigError, Connection,
+        DeadlineSource, Error, Feature, KeepAlive, MultiGetPolicy, NoCompressor, Pool, Protocol,
+        ReadPreference, RequestOptions, SlowStart, WriteCheck,
+    };
+
+    #[test]
+    fn test_err_display() {
+        assert_eq!(
+            "ProtocolError: Invalid magic byte: 8",
+            format!("{}", Error::Protocol(ProtocolError::InvalidMagic(8)))
         );
         assert_eq!(
             "StatusError: Key not found",
             format!("{}", Error::Status(crate::protocol::Status::KeyNotFound))
         );
     }
+
+    #[test]
+    fn test_pool_error() {
+        let err: Error = PoolError::Timeout(TimeoutType::Wait).into();
+        assert_eq!("PoolTimeout: Wait", format!("{}", err));
+        let err: Error = PoolError::Closed.into();
+        assert_eq!("PoolClosed", format!("{}", err));
+        let err: Error = PoolError::Backend(Error::InvalidEnvelope).into();
+        assert_eq!("InvalidEnvelope", format!("{}", err));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_raw_flags() {
+        use crate::zlib::ZlibCompressor;
+        use flate2::Compression;
+
+        tokio_test::block_on(async {
+            let compressor = ZlibCompressor::new(Compression::default(), 1);
+            let cfg = ClientConfig::new(vec!["raw_flags".into()], compressor);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let value = b"a:1:{s:5:\"hello\";s:5:\"world\";}".to_vec();
+            let flags = 0x0100_0001;
+            client
+                .set_with_flags("php", value.clone(), flags, 0)
+                .await
+                .unwrap();
+            let actual = client.get_raw_with_flags("php").await.unwrap();
+            assert_eq!(Some((value, flags)), actual);
+            assert_eq!(None, client.get_raw_with_flags("missing").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_touch() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["touch".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 60).await.unwrap();
+            assert!(client.touch("key", 300).await.unwrap());
+            assert_eq!(
+                Some(300),
+                Store::get("touch").lock().unwrap().expire(b"key")
+            );
+            assert!(!client.touch("missing", 300).await.unwrap());
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_add_replace() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["add_replace".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            assert!(!client.replace("key", "replaced", 0).await.unwrap());
+            assert!(client.add("key", "added", 0).await.unwrap());
+            assert!(!client.add("key", "again", 0).await.unwrap());
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("added".to_string()), value);
+            assert!(client.replace("key", "replaced", 0).await.unwrap());
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("replaced".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_set_if_value() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["set_if_value".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let (leader, follower) = ("a".to_string(), "b".to_string());
+            assert!(!client
+                .set_if_value("leader", &leader, &follower, 0)
+                .await
+                .unwrap());
+            client.set("leader", &leader, 0).await.unwrap();
+            assert!(!client
+                .set_if_value("leader", &follower, &leader, 0)
+                .await
+                .unwrap());
+            assert!(client
+                .set_if_value("leader", &leader, &follower, 0)
+                .await
+                .unwrap());
+            let value = client.get::<_, String>("leader").await.unwrap();
+            assert_eq!(Some(follower), value);
+        });
+    }
+
+    #[test]
+    fn test_gets() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["gets".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            assert_eq!(None, client.gets::<_, String>("key").await.unwrap());
+            client.set("key", "value", 0).await.unwrap();
+            let (value, cas) = client.gets::<_, String>("key").await.unwrap().unwrap();
+            assert_eq!("value", value);
+            assert_ne!(0, cas);
+            client.set("key", "other", 0).await.unwrap();
+            let (value, next) = client.gets::<_, String>("key").await.unwrap().unwrap();
+            assert_eq!("other", value);
+            assert_ne!(cas, next);
+        });
+    }
+
+    #[test]
+    fn test_cas_multi() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["cas_multi:1".into(), "cas_multi:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = (0..6).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            let mut data = HashMap::new();
+            for key in &keys {
+                client.set(key, "old", 0).await.unwrap();
+                let (_, cas) = client.gets::<_, String>(key).await.unwrap().unwrap();
+                data.insert(key.clone(), ("new", cas));
+            }
+            client.set("key0", "raced", 0).await.unwrap();
+            client.delete("key1").await.unwrap();
+
+            let errors = client.cas_multi(data, 0).await.unwrap();
+            assert_eq!(2, errors.len());
+            let err = |key: &str| errors.get(key.as_bytes()).map(|err| err.to_string());
+            assert_eq!(
+                Some(Error::Status(Status::KeyExists).to_string()),
+                err("key0")
+            );
+            assert_eq!(
+                Some(Error::Status(Status::KeyNotFound).to_string()),
+                err("key1")
+            );
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(Some(&"raced".to_string()), values.get("key0".as_bytes()));
+            assert!(keys[2..].iter().all(|k| values[k.as_bytes()] == "new"));
+        });
+    }
+
+    #[test]
+    fn test_counter_multi() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["counter_multi:1".into(), "counter_multi:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.incr("a", 5, 5, 0).await.unwrap();
+            client.set("text", "not a number", 0).await.unwrap();
+            let deltas = HashMap::from([("a", 2), ("b", 3), ("text", 1)]);
+            let (values, errors) = client.incr_multi(deltas, 10, 0).await.unwrap();
+            let expect = HashMap::from([(b"a".to_vec(), 7), (b"b".to_vec(), 10)]);
+            assert_eq!(expect, values);
+            assert_eq!(
+                vec![b"text".to_vec()],
+                errors.into_keys().collect::<Vec<_>>()
+            );
+
+            let deltas = HashMap::from([("a", 10), ("b", 4)]);
+            let (values, errors) = client.decr_multi(deltas, 0, 0).await.unwrap();
+            let expect = HashMap::from([(b"a".to_vec(), 0), (b"b".to_vec(), 6)]);
+            assert_eq!(expect, values);
+            assert!(errors.is_empty());
+
+            // A response to no request in the pipeline is skipped, and the
+            // responses to the pipeline are still read.
+            let conn = client.ring.get_conn(b"a").unwrap();
+            let mut stray = Packet::version().unwrap();
+            stray.header.magic = MAGIC_RESPONSE_VALUE;
+            stray.header.opaque = 1000;
+            conn.conn.inject(&Vec::from(stray));
+            let deltas = HashMap::from([("a", 1)]);
+            let (values, _) = client.incr_multi(deltas, 0, 0).await.unwrap();
+            assert_eq!(HashMap::from([(b"a".to_vec(), 1)]), values);
+            assert!(!client.is_degraded());
+        });
+    }
+
+    #[test]
+    fn test_flush_all() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["flush:1".into(), "flush:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            let keys = (0..10).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in &keys {
+                client.set(key, "value", 0).await.unwrap();
+            }
+            assert!(client.flush_all(0).await.unwrap().is_empty());
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(values.is_empty());
+
+            let mut client = Client::<MockConnection, _>::new(cfg.with_read_only(true))
+                .await
+                .unwrap();
+            assert!(matches!(client.flush_all(0).await, Err(Error::ReadOnly)));
+        });
+    }
+
+    #[test]
+    fn test_cluster_features() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["features".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let features = client.cluster_features();
+            assert_eq!(
+                Some(&"1.6.9".to_string()),
+                features.versions.get("features")
+            );
+            assert!(client.require(Feature::MetaProtocol).is_ok());
+            let versions = client.version().await.unwrap();
+            assert_eq!(Some(&"1.6.9".to_string()), versions.get("features"));
+        });
+    }
+
+    #[test]
+    fn test_bulk_pipelines() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["bulk:1".into(), "bulk:2".into(), "bulk:3".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            let data = keys
+                .iter()
+                .map(|key| (key.clone(), key.to_uppercase()))
+                .collect::<HashMap<_, _>>();
+
+            let errors = client.set_multi(data.clone(), 0).await.unwrap();
+            assert!(errors.is_empty());
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(errors.is_empty());
+            assert_eq!(keys.len(), values.len());
+            for (key, value) in values {
+                assert_eq!(data[&String::from_utf8(key).unwrap()], value);
+            }
+
+            client.delete_multi(&keys).await.unwrap();
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(values.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_delete_multi() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["deleteq".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let data = HashMap::from([("a", "1"), ("b", "2"), ("c", "3")]);
+            client.set_multi(data, 0).await.unwrap();
+            Store::get("deleteq")
+                .lock()
+                .unwrap()
+                .fail(b"b", Failure::Status(Status::Busy));
+
+            // Only the failed delete, the missing key and the NOOP are answered.
+            let received = client.node_stats()[0].bytes_received;
+            let errors = client.delete_multi(&["a", "b", "c", "d"]).await.unwrap();
+            assert_eq!(vec![b"b".to_vec()], errors.into_keys().collect::<Vec<_>>());
+            assert_eq!(received + 72, client.node_stats()[0].bytes_received);
+            let (values, _) = client.get_multi::<_, String>(&["a", "c"]).await.unwrap();
+            assert!(values.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_set_multi_errors() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["setq".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let store = Store::get("setq");
+            store
+                .lock()
+                .unwrap()
+                .fail(b"b", Failure::Status(Status::OutOfMemory));
+
+            // Failures are reported under their own key, wherever they are
+            // in the pipeline, including the final non-quiet set.
+            let keys = (0..20).map(|i| format!("key{}", i));
+            let data = keys.chain(["b".to_string()]).map(|key| (key, "value"));
+            let errors = client.set_multi(data.collect(), 0).await.unwrap();
+            assert_eq!(vec![b"b".to_vec()], errors.into_keys().collect::<Vec<_>>());
+            let data = HashMap::from([("b", "value")]);
+            let errors = client.set_multi(data, 0).await.unwrap();
+            assert!(matches!(
+                errors[&b"b"[..]],
+                Error::Status(Status::OutOfMemory)
+            ));
+            let value = client.get::<_, String>("key0").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_get_multi_framing() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["framing".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            client.set("b", "2", 0).await.unwrap();
+
+            // The last key is requested twice, then missed, without leaving
+            // responses behind for the next request.
+            for keys in [["a", "b", "a"], ["b", "a", "missing"]] {
+                let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+                assert_eq!((2, 0), (values.len(), errors.len()));
+                let value = client.get::<_, String>("b").await.unwrap();
+                assert_eq!(Some("2".to_string()), value);
+            }
+            assert_eq!(0, client.node_stats()[0].errors);
+        });
+    }
+
+    #[test]
+    fn test_slowest_nodes() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["slow:1".into(), "slow:2".into(), "slow:3".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            assert!(client.slowest_nodes(3).is_empty());
+
+            client.set("key", "value", 0).await.unwrap();
+            let slowest = client.slowest_nodes(3);
+            assert_eq!(1, slowest.len());
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            client.get_multi::<_, String>(&keys).await.unwrap();
+            let slowest = client.slowest_nodes(2);
+            assert_eq!(2, slowest.len());
+            assert!(slowest[0].1 >= slowest[1].1);
+            let stats = client.node_stats();
+            assert!(stats.iter().all(|stats| stats.latency.is_some()));
+        });
+    }
+
+    #[test]
+    fn test_write_check() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["veto".into()])
+                .with_write_check(WriteCheck::max_size(64));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let (small, large) = ("small".to_string(), "large".repeat(20));
+            assert_eq!(13, client.encoded_size(&small).unwrap());
+            assert_eq!(108, client.encoded_size(&large).unwrap());
+
+            client.set("a", &small, 0).await.unwrap();
+            let err = client.set("b", &large, 0).await.unwrap_err();
+            assert!(matches!(err, Error::Vetoed(108)));
+            assert!(client.set_with_flags("b", vec![0; 65], 0, 0).await.is_err());
+            assert_eq!(None, client.get::<_, String>("b").await.unwrap());
+
+            // Only vetoed values are left out of bulk writes.
+            let data = HashMap::from([("c", &small), ("d", &large)]);
+            let errors = client.set_multi(data, 0).await.unwrap();
+            assert_eq!(vec![b"d".to_vec()], errors.into_keys().collect::<Vec<_>>());
+            assert_eq!(Some(small), client.get("c").await.unwrap());
+            assert_eq!(None, client.get::<_, String>("d").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["retry:1".to_string(), "retry:2".to_string()];
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            let connect = |budget: Option<RetryBudget>| {
+                let cfg = ClientConfig::new_uncompressed(endpoints.clone());
+                let cfg = match budget {
+                    Some(budget) => cfg.with_retry_budget(budget),
+                    None => cfg,
+                };
+                Client::<MockConnection, _>::new(cfg)
+            };
+            let mut client = connect(None).await.unwrap();
+            for key in &keys {
+                client.set(key, "value", 0).await.unwrap();
+            }
+            let close = |client: &mut Client<MockConnection, NoCompressor>| {
+                client.ring.get_conn(b"key0").unwrap().conn.close();
+            };
+
+            // Without a budget, a closed connection fails every key of the
+            // node.
+            close(&mut client);
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(keys.len(), values.len() + errors.len());
+            assert!(errors
+                .values()
+                .all(|err| matches!(err, Error::NodeFailed(_))));
+            assert!(!errors.is_empty());
+
+            let mut client = connect(Some(RetryBudget::new(1.0))).await.unwrap();
+            close(&mut client);
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(keys.len(), values.len());
+            assert!(errors.is_empty());
+
+            // Nodes with more keys than the budget allows are not retried.
+            let budget = RetryBudget::new(0.1).with_min_keys(0);
+            let mut client = connect(Some(budget)).await.unwrap();
+            close(&mut client);
+            let (_, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(!errors.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_apply_config() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["reload:1".to_string(), "reload:2".to_string()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            for i in 0..10 {
+                client.set(format!("key{}", i), "value", 0).await.unwrap();
+            }
+            let requests = |client: &Client<MockConnection, NoCompressor>| {
+                let stats = client.node_stats().into_iter();
+                stats.map(|s| (s.endpoint, s.requests)).collect::<Vec<_>>()
+            };
+            let before = requests(&client);
+
+            // Unchanged nodes keep their connections and statistics.
+            let endpoints = vec!["reload:1".to_string(), "reload:3".to_string()];
+            let cfg = ClientConfig::new_uncompressed(endpoints.clone()).with_read_only(true);
+            client.apply_config(cfg).await.unwrap();
+            let after = requests(&client);
+            assert_eq!(before[0], after[0]);
+            assert_eq!(("reload:3".to_string(), 1), after[1]);
+            let err = client.set("key0", "value", 0).await.unwrap_err();
+            assert!(matches!(err, Error::ReadOnly));
+
+            // Failing to connect to a new node leaves the client unchanged.
+            let resolver = Arc::new(StaticResolver::new());
+            let cfg = ClientConfig::new_uncompressed(vec!["reload:4".into()])
+                .with_resolver(resolver)
+                .with_protocol(Protocol::Ascii);
+            let err = client.apply_config(cfg).await.unwrap_err();
+            assert!(matches!(err, Error::Resolve(_)));
+            assert_eq!(after, requests(&client));
+            assert!(client
+                .ring
+                .nodes()
+                .all(|node| node.protocol() == Protocol::Binary));
+            assert!(!client.is_degraded());
+
+            // Pooled clients apply the new config when they are next taken.
+            let cfg = ClientConfig::<MockConnection, _>::new_uncompressed(endpoints);
+            let pool = Pool::builder(cfg.clone()).max_size(2).build().unwrap();
+            let mut client = pool.get().await.unwrap();
+            client.set("key0", "value", 0).await.unwrap();
+            drop(client);
+            reconfigure_pool(&pool, cfg.with_read_only(true));
+            let (mut reused, mut created) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+            for client in [&mut reused, &mut created] {
+                let err = client.set("key0", "value", 0).await.unwrap_err();
+                assert!(matches!(err, Error::ReadOnly));
+            }
+        });
+    }
+
+    #[test]
+    fn test_owned_arguments() {
+        fn spawnable<F: Future + Send + 'static>(future: F) -> F {
+            future
+        }
+
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["owned".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let ids = vec![1, 2, 3];
+            let task = spawnable(async move {
+                for id in &ids {
+                    client.set_owned(format!("user:{}", id), *id, 0).await?;
+                }
+                let keys = ids.iter().map(|id| format!("user:{}", id)).collect();
+                let (values, _) = client.get_multi_owned::<String, u32>(keys).await?;
+                let value = client.get_owned::<u32>(b"user:1".to_vec()).await?;
+                let keys = ids.iter().map(|id| format!("user:{}", id)).collect();
+                client.delete_multi_owned::<String>(keys).await?;
+                Ok::<_, Error>((values.len(), value))
+            });
+            assert_eq!((3, Some(1)), task.await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_bulk_timing() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["timing:1".into(), "timing:2".into(), "timing:3".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in &keys {
+                client.set(key, key, 0).await.unwrap();
+            }
+
+            let options = RequestOptions::new();
+            let result = client.get_multi_with_options::<_, String>(&keys, &options);
+            assert!(result.await.unwrap().timings.is_empty());
+
+            let options = RequestOptions::new().with_timing(true);
+            let result = client.get_multi_with_options::<_, String>(&keys, &options);
+            let result = result.await.unwrap();
+            assert_eq!(keys.len(), result.values.len());
+            assert_eq!(3, result.timings.len());
+            for timing in result.timings {
+                let write_complete = timing.write_complete.unwrap();
+                let (first, last) = (
+                    timing.first_response.unwrap(),
+                    timing.last_response.unwrap(),
+                );
+                assert!(timing.enqueued <= write_complete && first <= last);
+            }
+        });
+    }
+
+    #[test]
+    fn test_slow_start() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["slow-start".into()])
+                .with_slow_start(SlowStart::new(2, 8));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let depth = |client: &Client<MockConnection, _>| {
+                client.ring.nodes().next().unwrap().pipeline_depth()
+            };
+            assert_eq!(2, depth(&client));
+
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in &keys {
+                client.set(key, key, 0).await.unwrap();
+            }
+            // Batches of 2, 4, 8 and the remaining 6 keys.
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(errors.is_empty());
+            assert_eq!(keys.len(), values.len());
+            assert_eq!(8, depth(&client));
+
+            client
+                .ring
+                .get_conn("key0")
+                .unwrap()
+                .reconnect()
+                .await
+                .unwrap();
+            assert_eq!(2, depth(&client));
+        });
+    }
+
+    #[test]
+    fn test_add_replace_multi() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["claim:1".into(), "claim:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let claim = |keys: &[&str]| {
+                keys.iter()
+                    .map(|key| (key.to_string(), 1_u32))
+                    .collect::<HashMap<_, _>>()
+            };
+
+            let errors = client.add_multi(claim(&["a", "b", "c"]), 0).await.unwrap();
+            assert!(errors.is_empty());
+            let errors = client.add_multi(claim(&["b", "c", "d"]), 0).await.unwrap();
+            assert_eq!(2, errors.len());
+            assert!(matches!(
+                errors[&b"b"[..]],
+                Error::Status(Status::KeyExists)
+            ));
+            assert!(matches!(
+                errors[&b"c"[..]],
+                Error::Status(Status::KeyExists)
+            ));
+
+            let errors = client.replace_multi(claim(&["a", "e"]), 0).await.unwrap();
+            assert_eq!(1, errors.len());
+            assert!(matches!(
+                errors[&b"e"[..]],
+                Error::Status(Status::ItemNotStored)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_read_only() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["read_only".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints.clone());
+            let mut writer = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            writer.set("key", "value", 0).await.unwrap();
+
+            let cfg = ClientConfig::new_uncompressed(endpoints).with_read_only(true);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let requests = client.node_stats()[0].requests;
+            assert!(matches!(
+                client.set("key", "x", 0).await,
+                Err(Error::ReadOnly)
+            ));
+            assert!(matches!(client.delete("key").await, Err(Error::ReadOnly)));
+            assert!(matches!(
+                client.incr("n", 1, 0, 0).await,
+                Err(Error::ReadOnly)
+            ));
+            let data = HashMap::from([("key", "x")]);
+            assert!(matches!(
+                client.set_multi(data, 0).await,
+                Err(Error::ReadOnly)
+            ));
+            assert_eq!(requests, client.node_stats()[0].requests);
+
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_kill_switch() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["kill_switch".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            let other = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+
+            other.set_enabled(false);
+            assert!(!client.is_enabled());
+            let requests = client.node_stats()[0].requests;
+            assert_eq!(None, client.get::<_, String>("key").await.unwrap());
+            client.set("key", "other", 0).await.unwrap();
+            client.delete("key").await.unwrap();
+            assert_eq!(5, client.incr("n", 1, 5, 0).await.unwrap());
+            let (values, _) = client.get_multi::<_, String>(&["key"]).await.unwrap();
+            assert!(values.is_empty());
+            assert_eq!(requests, client.node_stats()[0].requests);
+
+            other.set_enabled(true);
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_get_ramp() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["get_ramp".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints.clone()).with_get_ramp(50);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = (0..100).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in keys.iter() {
+                client.set(key, "value", 0).await.unwrap();
+            }
+
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(!values.is_empty() && values.len() < keys.len());
+            for key in keys.iter() {
+                let value = client.get::<_, String>(key).await.unwrap();
+                assert_eq!(values.contains_key(key.as_bytes()), value.is_some());
+            }
+        });
+    }
+
+    #[test]
+    fn test_multi_get_policy() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["policy:1".into(), "policy:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in keys.iter() {
+                client.set(key, "value", 0).await.unwrap();
+            }
+
+            // Corrupt the stream of the node owning the first key.
+            client
+                .ring
+                .get_conn(&keys[0])
+                .unwrap()
+                .conn
+                .inject(&[0; 24]);
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(!values.is_empty());
+            assert_eq!(keys.len(), values.len() + errors.len());
+            assert!(matches!(errors[keys[0].as_bytes()], Error::NodeFailed(_)));
+
+            let cfg = cfg.with_multi_get_policy(MultiGetPolicy::FailAny);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            assert!(client.get_multi::<_, String>(&keys).await.is_ok());
+            client
+                .ring
+                .get_conn(&keys[0])
+                .unwrap()
+                .conn
+                .inject(&[0; 24]);
+            let result = client.get_multi::<_, String>(&keys).await;
+            assert!(matches!(result, Err(Error::BulkFailed(_))));
+
+            let cfg = cfg.with_multi_get_policy(MultiGetPolicy::FailAbove(0.99));
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client
+                .ring
+                .get_conn(&keys[0])
+                .unwrap()
+                .conn
+                .inject(&[0; 24]);
+            assert!(client.get_multi::<_, String>(&keys).await.is_ok());
+
+            // The policy judges the whole call when it is split into chunks.
+            let limits = BatchLimits::new().with_max_keys(1).with_chunking(true);
+            let mut client = Client::<MockConnection, _>::new(cfg.with_batch_limits(limits))
+                .await
+                .unwrap();
+            client
+                .ring
+                .get_conn(&keys[0])
+                .unwrap()
+                .conn
+                .inject(&[0; 24]);
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(keys.len(), values.len() + errors.len());
+            assert_eq!(1, errors.len());
+
+            // Duplicate keys are requested, and judged, only once.
+            let cfg = ClientConfig::new_uncompressed(vec!["policy:3".into()])
+                .with_multi_get_policy(MultiGetPolicy::FailAbove(0.5));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.ring.get_conn(b"key").unwrap().conn.inject(&[0; 24]);
+            let result = client.get_multi::<_, String>(&["key"; 3]).await;
+            assert!(matches!(result, Err(Error::BulkFailed(_))));
+        });
+    }
+
+    #[test]
+    fn test_reconnect_closed() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["closed".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+
+            // The server closes the connection while the client is idle.
+            client.ring.get_conn(b"key").unwrap().conn.close();
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+            assert_eq!(1, client.node_stats()[0].reconnects);
+
+            let cfg = cfg.with_idle_ping(Duration::from_secs(0));
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.ring.get_conn(b"key").unwrap().conn.close();
+            client.ping_idle().await.unwrap();
+            assert_eq!(1, client.node_stats()[0].reconnects);
+            assert!(!client.is_degraded());
+
+            // A spare connection from the warm pool is swapped in.
+            let warm = WarmPool::new(1, Duration::from_secs(60));
+            warm.maintain(&["closed".to_string()]).await.unwrap();
+            let cfg = cfg.with_warm_pool(warm.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.ring.get_conn(b"key").unwrap().conn.close();
+            assert!(client.get::<_, String>("key").await.unwrap().is_some());
+            assert_eq!(0, warm.ready("closed"));
+        });
+    }
+
+    #[test]
+    fn test_retry_idempotent() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["lost_reply".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.incr("n", 1, 0, 0).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            let store = Store::get("lost_reply");
+
+            // The increment is applied but its response is lost, so it is not
+            // sent again.
+            store.lock().unwrap().fail(b"n", Failure::LoseReply);
+            let err = client.incr("n", 1, 0, 0).await.unwrap_err();
+            assert!(err.is_connection_closed());
+            store.lock().unwrap().clear_failures();
+            let raw = client.get_raw_with_flags("n").await.unwrap();
+            assert_eq!(Some(b"1".to_vec()), raw.map(|(value, _)| value));
+
+            // Reads are retried after reconnecting.
+            client.ring.get_conn(b"key").unwrap().conn.close();
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_late_responses() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["late".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            client.set("b", "2", 0).await.unwrap();
+
+            // The response to an earlier request arrives a second time.
+            let conn = client.ring.get_conn(b"a").unwrap();
+            let late = conn.send(NoCompressor, Packet::get(b"a").unwrap());
+            let late = late.await.unwrap();
+            let bytes = [
+                &late.header.encode()[..],
+                &late.extras,
+                &late.key,
+                &late.value,
+            ]
+            .concat();
+            conn.conn.inject(&bytes);
+            let value = client.get::<_, String>("b").await.unwrap();
+            assert_eq!(Some("2".to_string()), value);
+            assert_eq!(1, client.node_stats()[0].discarded_responses);
+
+            // A response to no recent request poisons the connection.
+            let mut unknown = late.header;
+            unknown.opaque = late.header.opaque.wrapping_add(1000);
+            let bytes = [&unknown.encode()[..], &late.extras, &late.key, &late.value].concat();
+            client.ring.get_conn(b"a").unwrap().conn.inject(&bytes);
+            let err = client.get::<_, String>("a").await.unwrap_err();
+            let opaque = unknown.opaque;
+            assert!(
+                matches!(err, Error::Protocol(ProtocolError::UnexpectedOpaque(o)) if o == opaque)
+            );
+            assert!(client.is_degraded());
+        });
+    }
+
+    #[test]
+    fn test_deadline_source() {
+        #[derive(Debug)]
+        struct Expired;
+
+        impl DeadlineSource for Expired {
+            fn deadline(&self) -> Option<Instant> {
+                Some(Instant::now())
+            }
+        }
+
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["deadline_source".into()])
+                .with_deadline_source(Arc::new(Expired));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let err = client.set("key", "value", 0).await.unwrap_err();
+            assert!(matches!(err, Error::DeadlineExceeded));
+            let keys = ["key"];
+            let err = client.get_multi::<_, String>(&keys).await.unwrap_err();
+            assert!(matches!(err, Error::DeadlineExceeded));
+        });
+    }
+
+    #[test]
+    fn test_deadline_unresponsive() {
+        static HUNG: AtomicBool = AtomicBool::new(false);
+
+        /// A connection whose reads hang once hung, with a timer which
+        /// expires immediately.
+        #[derive(Debug, Clone)]
+        struct Unresponsive(MockConnection);
+
+        #[async_trait::async_trait]
+        impl Connection for Unresponsive {
+            async fn connect(url: String) -> Result<Self, Error> {
+                Ok(Unresponsive(MockConnection::connect(url).await?))
+            }
+
+            async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+                if HUNG.load(Ordering::Relaxed) {
+                    futures::future::pending::<()>().await;
+                }
+                self.0.read(buf).await
+            }
+
+            async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+                self.0.write(data).await
+            }
+
+            async fn sleep(_duration: Duration) {}
+        }
+
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["unresponsive".into()]);
+            let mut client = Client::<Unresponsive, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+
+            HUNG.store(true, Ordering::Relaxed);
+            let options = RequestOptions::new().with_timeout(Duration::from_secs(60));
+            let get = client.get_with_options::<_, String>("key", &options);
+            assert!(matches!(get.await.unwrap_err(), Error::DeadlineExceeded));
+            assert!(client.is_degraded());
+
+            // The poisoned connection is replaced before the next request.
+            HUNG.store(false, Ordering::Relaxed);
+            let value = client.get_with_options::<_, String>("key", &options);
+            assert_eq!(Some("value".to_string()), value.await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_key_codec() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["codec:1".into(), "codec:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints).with_key_codec(KeyCodec::Hex);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = vec![vec![0x00, 0xff, b' '], vec![0x0a, 0x0d]];
+            for key in &keys {
+                client.set(key, &1_u32, 0).await.unwrap();
+                assert_eq!(Some(1), client.get::<_, u32>(key).await.unwrap());
+            }
+
+            let (values, errors) = client.get_multi::<_, u32>(&keys).await.unwrap();
+            assert!(errors.is_empty());
+            assert_eq!(Some(&1), values.get(&keys[0]));
+            assert_eq!(Some(&1), values.get(&keys[1]));
+
+            // Keys are stored encoded on the node owning the raw key.
+            let endpoint = client.ring.get_conn(&keys[0]).unwrap().endpoint.clone();
+            let store = Store::get(&endpoint);
+            assert!(store.lock().unwrap().expire(b"00ff20").is_some());
+
+            // A response whose key cannot be decoded fails a bulk write
+            // midway, which poisons the connection.
+            let mut stray = Packet::get(b"zz").unwrap();
+            stray.header.magic = MAGIC_RESPONSE_VALUE;
+            let conn = client.ring.get_conn(&keys[0]).unwrap();
+            conn.conn.inject(&Vec::from(stray));
+            let data = HashMap::from([(&keys[0], 2_u32)]);
+            assert!(client.set_multi(data, 0).await.is_err());
+            assert!(client.is_degraded());
+            assert_eq!(Some(2), client.get::<_, u32>(&keys[0]).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_max_response_size() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["max-response".into()])
+                .with_max_response_size(64);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("small", "value", 0).await.unwrap();
+            client.set("large", &"x".repeat(100), 0).await.unwrap();
+
+            let err = client.get::<_, String>("large").await.unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Protocol(ProtocolError::ResponseTooLarge(_))
+            ));
+            assert!(client.is_degraded());
+
+            // The poisoned connection is replaced before the next request.
+            let value = client.get::<_, String>("small").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+        });
+    }
+
+    #[test]
+    fn test_bulk_progress() {
+        static STALLED: AtomicBool = AtomicBool::new(false);
+
+        /// A connection whose reads hang once stalled, with a timer which
+        /// expires immediately.
+        #[derive(Debug, Clone)]
+        struct Stalling(MockConnection);
+
+        #[async_trait::async_trait]
+        impl Connection for Stalling {
+            async fn connect(url: String) -> Result<Self, Error> {
+                Ok(Stalling(MockConnection::connect(url).await?))
+            }
+
+            async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+                if STALLED.load(Ordering::Relaxed) {
+                    futures::future::pending::<()>().await;
+                }
+                self.0.read(buf).await
+            }
+
+            async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+                self.0.write(data).await
+            }
+
+            async fn sleep(_duration: Duration) {}
+        }
+
+        tokio_test::block_on(async {
+            let progress = BulkProgress::new(Duration::from_secs(1)).with_noop_every(2);
+            let cfg = ClientConfig::new_uncompressed(vec!["progress".into()])
+                .with_bulk_progress(progress);
+            let mut client = Client::<Stalling, _>::new(cfg).await.unwrap();
+            let keys = (0..10).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in keys.iter().step_by(3) {
+                client.set(key, key, 0).await.unwrap();
+            }
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(4, values.len());
+            assert!(errors.is_empty());
+
+            // A stalled node fails instead of hanging the read.
+            STALLED.store(true, Ordering::Relaxed);
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(values.is_empty());
+            assert_eq!(keys.len(), errors.len());
+            let err = errors.values().next().unwrap().to_string();
+            assert!(err.contains("StalledError: progress"), "{}", err);
+            assert!(client.is_degraded());
+        });
+    }
+
+    #[test]
+    fn test_read_owned() {
+        /// A connection which reads at most a few bytes at a time.
+        #[derive(Debug, Clone)]
+        struct Trickle(MockConnection);
+
+        #[async_trait::async_trait]
+        impl Connection for Trickle {
+            async fn connect(url: String) -> Result<Self, Error> {
+                Ok(Trickle(MockConnection::connect(url).await?))
+            }
+
+            async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+                let mut chunk = vec![0; buf.len().min(5)];
+                let n = self.0.read(&mut chunk).await?;
+                buf[..n].copy_from_slice(&chunk[..n]);
+                Ok(n)
+            }
+
+            async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+                self.0.write(data).await
+            }
+        }
+
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["read-owned".into()]);
+            let mut client = Client::<Trickle, _>::new(cfg).await.unwrap();
+            client.set("key", &"x".repeat(100), 0).await.unwrap();
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("x".repeat(100)), value);
+
+            let mut conn = Trickle::connect("read-owned".into()).await.unwrap();
+            conn.write_packet(NoCompressor, Packet::get("key").unwrap())
+                .await
+                .unwrap();
+            assert_eq!(24, conn.read_owned(24).await.unwrap().len());
+            let err = conn.read_owned(1024).await.unwrap_err();
+            assert!(err.is_connection_closed());
+        });
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_request_options() {
+        use crate::zlib::ZlibCompressor;
+        use flate2::Compression;
+
+        tokio_test::block_on(async {
+            let compressor = ZlibCompressor::new(Compression::default(), 1);
+            let cfg = ClientConfig::new(vec!["options".into()], compressor);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let options = RequestOptions::new().with_compression(false);
+            client
+                .set_with_options("key", "value", 0, &options)
+                .await
+                .unwrap();
+            let (_, flags) = client.get_raw_with_flags("key").await.unwrap().unwrap();
+            assert_eq!(0, flags & 0x0100_0000);
+            let value = client.get_with_options::<_, String>("key", &options);
+            assert_eq!(Some("value".to_string()), value.await.unwrap());
+
+            let options = RequestOptions::new().with_opaque(42);
+            let packet = Packet::get(b"key").unwrap();
+            let packet = client.request_with(b"key", packet, compressor, &options);
+            assert_eq!(42, packet.await.unwrap().header.opaque);
+
+            let options = RequestOptions::new().with_read_preference(ReadPreference::LocalOnly);
+            let value = client.get_with_options::<_, String>("key", &options);
+            assert_eq!(None, value.await.unwrap());
+
+            let options = RequestOptions::new().with_timeout(Duration::from_secs(0));
+            let err = client.delete_with_options("key", &options).await;
+            assert!(matches!(err, Err(Error::DeadlineExceeded)));
+            assert!(client.get::<_, String>("key").await.unwrap().is_some());
+
+            // Without retries, a closed connection fails the request.
+            client.ring.get_conn(b"key").unwrap().conn.close();
+            let options = RequestOptions::new().with_retries(0);
+            let err = client.get_with_options::<_, String>("key", &options).await;
+            assert!(err.unwrap_err().is_connection_closed());
+        });
+    }
+
+    #[test]
+    fn test_arbitrary_key_sets() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["keysets:1".into(), "keysets:2".into(), "keysets:3".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let empty: &[&str] = &[];
+            let (values, errors) = client.get_multi::<_, u32>(empty).await.unwrap();
+            assert!(values.is_empty() && errors.is_empty());
+            assert!(client
+                .set_multi::<u32, &str>(HashMap::new(), 0)
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(client
+                .add_multi::<u32, &str>(HashMap::new(), 0)
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(client.delete_multi(empty).await.unwrap().is_empty());
+
+            // Generate arbitrary key sets, including duplicate keys, with a
+            // simple linear congruential generator.
+            let mut seed = 0x2545_f491_u32;
+            let mut next = |n: u32| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) % n
+            };
+            let mut expect = HashMap::new();
+            for _ in 0..50 {
+                let len = next(30);
+                let keys = (0..len)
+                    .map(|_| format!("key{}", next(40)))
+                    .collect::<Vec<_>>();
+                if next(2) == 0 {
+                    let data = keys
+                        .iter()
+                        .map(|k| (k.clone(), len))
+                        .collect::<HashMap<_, _>>();
+                    client.set_multi(data.clone(), 0).await.unwrap();
+                    expect.extend(data);
+                }
+                let (values, errors) = client.get_multi::<_, u32>(&keys).await.unwrap();
+                assert!(errors.is_empty());
+                for key in keys.iter() {
+                    assert_eq!(expect.get(key), values.get(key.as_bytes()));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_set_stream() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["stream:1".into(), "stream:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = (0..25).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            let items = futures::stream::iter(keys.clone().into_iter().map(|k| (k, 1_u32)));
+
+            let errors = client.set_stream(items, 0, 4).await.unwrap();
+            assert!(errors.is_empty());
+            let (values, _) = client.get_multi::<_, u32>(&keys).await.unwrap();
+            assert_eq!(keys.len(), values.len());
+        });
+    }
+
+    #[test]
+    fn test_recycle_degraded() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::<MockConnection, _>::new_uncompressed(vec!["recycle".into()])
+                .with_max_client_age(Duration::from_secs(3600));
+            let mut client = cfg.create().await.unwrap();
+            assert!(cfg.recycle(&mut client).await.is_ok());
+
+            // Reading without a request hits EOF and poisons the connection.
+            client
+                .request(b"key", Packet::noop().unwrap())
+                .await
+                .unwrap();
+            let node = client.ring.get_conn(b"key").unwrap();
+            assert!(node.read_packet(NoCompressor).await.is_err());
+            assert!(client.is_degraded());
+            assert!(cfg.recycle(&mut client).await.is_err());
+
+            let cfg = cfg.with_max_client_age(Duration::from_secs(0));
+            let mut client = cfg.create().await.unwrap();
+            assert!(cfg.recycle(&mut client).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_validate_ring_size() {
+        let endpoints = (0..361).map(|i| format!("node{}:11211", i)).collect();
+        let cfg = ClientConfig::<MockConnection, _>::new_uncompressed(endpoints);
+        let invalid = Err(ConfigError::InvalidRingSize);
+        assert_eq!(invalid, cfg.clone().validate());
+        let modulo = cfg.with_hash_scheme(HashScheme::Crc32Modulo);
+        assert_eq!(Ok(()), modulo.validate());
+    }
+
+    #[test]
+    fn test_pool_timing() {
+        tokio_test::block_on(async {
+            let cfg =
+                ClientConfig::<MockConnection, _>::new_uncompressed(vec!["pool_timing:1".into()]);
+            let secs = Duration::from_secs;
+            let invalid = Err(ConfigError::InvalidPoolTiming);
+            assert_eq!(invalid, cfg.clone().with_idle_timeout(secs(0)).validate());
+            let cfg = cfg.with_max_client_age(secs(60));
+            assert_eq!(
+                invalid,
+                cfg.clone().with_recycle_interval(secs(60)).validate()
+            );
+            let cfg = cfg
+                .with_recycle_interval(secs(30))
+                .with_idle_timeout(secs(45));
+            assert_eq!(Ok(()), cfg.validate());
+
+            // Recently checked clients skip the keep alive request.
+            let mut client = cfg.create().await.unwrap();
+            let checked = client.checked_at;
+            assert!(cfg.recycle(&mut client).await.is_ok());
+            assert_eq!(checked, client.checked_at);
+            let cfg = cfg.with_recycle_interval(Duration::from_nanos(1));
+            assert!(cfg.recycle(&mut client).await.is_ok());
+            assert!(client.checked_at > checked);
+
+            // Idle clients are replaced.
+            let cfg = cfg.with_idle_timeout(Duration::from_nanos(1));
+            std::thread::sleep(Duration::from_millis(1));
+            assert!(cfg.recycle(&mut client).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        tokio_test::block_on(async {
+            for keep_alive in [KeepAlive::Noop, KeepAlive::Version, KeepAlive::Disabled] {
+                let cfg =
+                    ClientConfig::<MockConnection, _>::new_uncompressed(vec!["keep_alive".into()])
+                        .with_keep_alive(keep_alive);
+                let mut client = cfg.create().await.unwrap();
+                assert!(cfg.recycle(&mut client).await.is_ok());
+            }
+        });
+    }
+
+    #[test]
+    fn test_close() {
+        /// A connection with a timer which yields once.
+        #[derive(Debug, Clone)]
+        struct Yielding(MockConnection);
+
+        #[async_trait::async_trait]
+        impl Connection for Yielding {
+            async fn connect(url: String) -> Result<Self, Error> {
+                Ok(Yielding(MockConnection::connect(url).await?))
+            }
+
+            async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+                self.0.read(buf).await
+            }
+
+            async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+                self.0.write(data).await
+            }
+
+            async fn sleep(_duration: Duration) {
+                let mut yielded = false;
+                futures::future::poll_fn(|cx| match yielded {
+                    true => Poll::Ready(()),
+                    false => {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                })
+                .await
+            }
+        }
+
+        tokio_test::block_on(async {
+            let quits = |url: &str| Store::get(url).lock().unwrap().quits();
+            let endpoints = vec!["close:1".into(), "close:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            client.close().await.unwrap();
+            assert_eq!((1, 1), (quits("close:1"), quits("close:2")));
+            assert!(client.is_degraded());
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+
+            let ascii = ClientConfig::new_uncompressed(vec!["close:3".into()])
+                .with_protocol(Protocol::Ascii);
+            let mut client = Client::<MockConnection, _>::new(ascii).await.unwrap();
+            client.close().await.unwrap();
+            assert_eq!(1, quits("close:3"));
+
+            // Spares parked in the warm pool are closed with the node.
+            let warm = WarmPool::new(1, Duration::from_secs(60));
+            warm.maintain(&["close:6".to_string()]).await.unwrap();
+            let spared =
+                ClientConfig::new_uncompressed(vec!["close:6".into()]).with_warm_pool(warm.clone());
+            let mut client = Client::<MockConnection, _>::new(spared).await.unwrap();
+            client.close().await.unwrap();
+            assert_eq!(0, warm.ready("close:6"));
+
+            let pool = Pool::builder(cfg).max_size(2).build().unwrap();
+            drop(pool.get().await.unwrap());
+            drain_pool(&pool, Duration::ZERO).await.unwrap();
+            assert_eq!((2, 2), (quits("close:1"), quits("close:2")));
+            assert!(matches!(pool.get().await, Err(PoolError::Closed)));
+
+            // Idle clients are closed without waiting on the timer, which
+            // never completes for the mock connection.
+            let cfg = ClientConfig::new_uncompressed(vec!["close:7".into()]);
+            let pool = Pool::<MockConnection, _>::builder(cfg).build().unwrap();
+            drop(pool.get().await.unwrap());
+            drain_pool(&pool, Duration::from_secs(60)).await.unwrap();
+            assert_eq!(1, quits("close:7"));
+
+            // Clients in use are closed once they are returned, until the
+            // timeout passes.
+            let cfg = ClientConfig::new_uncompressed(vec!["close:4".into()]);
+            let pool = Pool::<MockConnection, _>::builder(cfg)
+                .max_size(2)
+                .build()
+                .unwrap();
+            let (used, idle) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+            drop(idle);
+            let err = drain_pool(&pool, Duration::ZERO).await.unwrap_err();
+            assert!(matches!(err, Error::DeadlineExceeded));
+            assert_eq!(1, quits("close:4"));
+            drop(used);
+
+            let cfg = ClientConfig::new_uncompressed(vec!["close:5".into()]);
+            let pool = Pool::<Yielding, _>::builder(cfg).build().unwrap();
+            let used = pool.get().await.unwrap();
+            let drain = drain_pool(&pool, Duration::from_secs(60));
+            let release = async move { drop(used) };
+            let (result, _) = join(drain, release).await;
+            result.unwrap();
+            assert_eq!(1, quits("close:5"));
+        });
+    }
+
+    #[test]
+    fn test_node_stats() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["node_stats".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            client.get::<_, String>("key").await.unwrap();
+
+            let stats = client.node_stats();
+            assert_eq!(1, stats.len());
+            assert_eq!("node_stats", stats[0].endpoint);
+            assert_eq!(3, stats[0].requests);
+            assert_eq!(0, stats[0].errors);
+            assert!(stats[0].bytes_sent > 48);
+            assert!(stats[0].bytes_received > 48);
+            assert_eq!(1, stats[0].value_sizes.count);
+            assert_eq!(1, stats[0].ttls.buckets[0]);
+        });
+    }
+
+    #[test]
+    fn test_health() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["health".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            let health = client.health();
+            assert_eq!("health", health[0].endpoint);
+            assert_eq!(NodeState::Healthy, health[0].state);
+            assert!(health[0].latency.is_some() && health[0].since_last_success.is_some());
+
+            let store = Store::get("health");
+            store.lock().unwrap().fail(b"key", Failure::Disconnect);
+            client.get::<_, String>("key").await.unwrap_err();
+            let health = client.health();
+            assert_eq!(NodeState::Degraded, health[0].state);
+            assert!(health[0].consecutive_failures > 0);
+            assert!(health[0].last_error.is_some());
+
+            store.lock().unwrap().clear_failures();
+            client.get::<_, String>("key").await.unwrap();
+            let health = client.health();
+            assert_eq!(
+                (NodeState::Healthy, 0),
+                (health[0].state, health[0].consecutive_failures)
+            );
+
+            let budget = ErrorBudget {
+                window: 1,
+                min_requests: 1,
+                max_error_rate: 0.5,
+                cooldown: Duration::from_secs(60),
+            };
+            let cfg = cfg.with_error_budget(budget);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            store.lock().unwrap().fail(b"key", Failure::Disconnect);
+            client.get::<_, String>("key").await.unwrap_err();
+            assert_eq!(NodeState::Ejected, client.health()[0].state);
+        });
+    }
 }
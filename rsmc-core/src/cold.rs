@@ -0,0 +1,236 @@
+//! A cluster which was just started, or flushed, misses on almost every key,
+//! and if every miss goes straight to the database, the database takes the
+//! full read load of every client at once. A [`ColdStart`] measures the miss
+//! rate of the reads made right after the client connects, and while the
+//! cluster is cold, [`crate::client::Client::admit_load`] paces the loads
+//! made on misses to a fixed rate, with jitter so that waiting loaders do
+//! not wake up in lockstep. It returns how long to wait rather than waiting
+//! itself, since the core has no timer; sleep with the timer of the runtime
+//! before loading.
+//!
+//! The cluster is cold while the miss rate of every batch of samples stays
+//! above the threshold. Once a batch falls below it, or the detection window
+//! passes, the cluster is warm for good and loads are never delayed again.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::rng::jitter_fraction;
+
+/// Whether the cluster is cold, as measured by a [`ColdStart`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColdStartPhase {
+    /// Not enough reads were sampled yet to tell.
+    Detecting,
+    /// Reads miss too often, so loads are paced.
+    Cold,
+    /// Reads hit often enough, or the detection window passed.
+    Warm,
+}
+
+/// A snapshot of the state of a [`ColdStart`], for metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColdStartStats {
+    /// Whether the cluster is cold.
+    pub phase: ColdStartPhase,
+    /// The number of hits in the current batch of samples.
+    pub hits: u64,
+    /// The number of misses in the current batch of samples.
+    pub misses: u64,
+    /// The number of loads admitted without waiting while cold.
+    pub admitted: u64,
+    /// The number of loads delayed while cold.
+    pub delayed: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    started: Instant,
+    phase: ColdStartPhase,
+    hits: u64,
+    misses: u64,
+    tokens: f64,
+    refilled: Instant,
+    admitted: u64,
+    delayed: u64,
+}
+
+/// Detects a cold cluster and paces the loads made on misses while it is
+/// cold, shared by every client created from the same config.
+#[derive(Debug, Clone)]
+pub struct ColdStart {
+    loads_per_sec: f64,
+    window: Duration,
+    miss_ratio: f64,
+    samples: u64,
+    jitter: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl ColdStart {
+    /// Admit up to `loads_per_sec` loads every second while the cluster is
+    /// cold. By default the cluster is cold while at least 90% of every 100
+    /// reads miss, within a minute of starting, and delayed loads wait up to
+    /// 100ms of extra jitter.
+    pub fn new(loads_per_sec: f64) -> Self {
+        let now = Instant::now();
+        Self {
+            loads_per_sec: loads_per_sec.max(f64::MIN_POSITIVE),
+            window: Duration::from_secs(60),
+            miss_ratio: 0.9,
+            samples: 100,
+            jitter: Duration::from_millis(100),
+            state: Arc::new(Mutex::new(State {
+                started: now,
+                phase: ColdStartPhase::Detecting,
+                hits: 0,
+                misses: 0,
+                tokens: loads_per_sec.max(1.0),
+                refilled: now,
+                admitted: 0,
+                delayed: 0,
+            })),
+        }
+    }
+
+    /// Only detect a cold cluster within the window after starting.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Consider the cluster cold while at least `miss_ratio` of every batch
+    /// of `samples` reads miss.
+    pub fn with_miss_ratio(mut self, miss_ratio: f64, samples: u64) -> Self {
+        self.miss_ratio = miss_ratio;
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Add up to `jitter` to the wait of every delayed load.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Whether the cluster is cold.
+    pub fn phase(&self) -> ColdStartPhase {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        state.phase
+    }
+
+    /// Get a snapshot of the state, for metrics.
+    pub fn stats(&self) -> ColdStartStats {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        ColdStartStats {
+            phase: state.phase,
+            hits: state.hits,
+            misses: state.misses,
+            admitted: state.admitted,
+            delayed: state.delayed,
+        }
+    }
+
+    /// Record the hits and misses of a read.
+    pub(crate) fn record(&self, hits: u64, misses: u64) {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        if state.phase == ColdStartPhase::Warm {
+            return;
+        }
+        state.hits += hits;
+        state.misses += misses;
+        let total = state.hits + state.misses;
+        if total < self.samples {
+            return;
+        }
+        state.phase = match state.misses as f64 / total as f64 >= self.miss_ratio {
+            true => ColdStartPhase::Cold,
+            false => ColdStartPhase::Warm,
+        };
+        state.hits = 0;
+        state.misses = 0;
+    }
+
+    /// How long to wait before loading a missed value, if at all. Every call
+    /// takes a slot, so concurrent loaders queue up behind each other.
+    pub(crate) fn delay(&self) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        if state.phase != ColdStartPhase::Cold {
+            return None;
+        }
+        let now = Instant::now();
+        let refill = now.duration_since(state.refilled).as_secs_f64() * self.loads_per_sec;
+        state.tokens = (state.tokens + refill).min(self.loads_per_sec.max(1.0));
+        state.refilled = now;
+        state.tokens -= 1.0;
+        if state.tokens >= 0.0 {
+            state.admitted += 1;
+            return None;
+        }
+        state.delayed += 1;
+        let wait = Duration::from_secs_f64(-state.tokens / self.loads_per_sec);
+        Some(wait + self.jitter.mul_f64(jitter_fraction()))
+    }
+
+    /// End detection once the window has passed.
+    fn expire(&self, state: &mut State) {
+        if state.phase != ColdStartPhase::Warm && state.started.elapsed() >= self.window {
+            state.phase = ColdStartPhase::Warm;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::MockConnection,
+    };
+
+    use super::{ColdStart, ColdStartPhase};
+
+    #[test]
+    fn test_cold_start() {
+        tokio_test::block_on(async {
+            let cold = ColdStart::new(2.0)
+                .with_miss_ratio(0.5, 4)
+                .with_jitter(Duration::ZERO);
+            let cfg =
+                ClientConfig::new_uncompressed(vec!["cold".into()]).with_cold_start(cold.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            assert_eq!(ColdStartPhase::Detecting, cold.phase());
+            let keys = ["a", "b", "c", "d"];
+            client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(ColdStartPhase::Cold, cold.phase());
+
+            // A burst of loads is admitted, and later loads are paced.
+            assert_eq!(None, cold.delay());
+            assert_eq!(None, cold.delay());
+            let wait = cold.delay().unwrap();
+            assert!(wait > Duration::from_millis(400), "{:?}", wait);
+            let stats = cold.stats();
+            assert_eq!((2, 1), (stats.admitted, stats.delayed));
+
+            // The cluster warms up once reads hit again.
+            for key in keys {
+                client.set(key, "value", 0).await.unwrap();
+                client.get::<_, String>(key).await.unwrap();
+            }
+            assert_eq!(ColdStartPhase::Warm, cold.phase());
+            assert_eq!(None, cold.delay());
+            assert_eq!(None, client.admit_load());
+
+            // Detection ends after the window.
+            let cold = ColdStart::new(1.0).with_window(Duration::ZERO);
+            assert_eq!(ColdStartPhase::Warm, cold.phase());
+        });
+    }
+}
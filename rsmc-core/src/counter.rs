@@ -0,0 +1,206 @@
+//! This module provides handles for working with memcached counters, which
+//! are values stored as decimal strings that can be atomically incremented
+//! and decremented on the server.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    client::{Client, Compressor, Connection, Error},
+    protocol::{CounterExtras, Packet, SetExtras, Status, TouchExtras},
+};
+
+/// A handle to a single counter, created with [`Client::counter`].
+#[derive(Debug)]
+pub struct Counter<'a, C: Connection, P: Compressor> {
+    client: &'a mut Client<C, P>,
+    key: Vec<u8>,
+    initial: u64,
+    expire: u32,
+}
+
+impl<'a, C: Connection, P: Compressor> Counter<'a, C, P> {
+    pub(crate) fn new(client: &'a mut Client<C, P>, key: Vec<u8>) -> Self {
+        Self {
+            client,
+            key,
+            initial: 0,
+            expire: 0,
+        }
+    }
+
+    /// Set the value and expiration used to create the counter when it does
+    /// not exist yet. By default a missing counter is created at 0 and never
+    /// expires.
+    pub fn with_initial(mut self, initial: u64, expire: u32) -> Self {
+        self.initial = initial;
+        self.expire = expire;
+        self
+    }
+
+    /// Increment the counter, returning the new value.
+    pub async fn incr(&mut self, delta: u64) -> Result<u64, Error> {
+        let extras = CounterExtras::new(delta, self.initial, self.expire);
+        self.client.incr_with(&self.key, extras).await
+    }
+
+    /// Decrement the counter, returning the new value. Memcached will never
+    /// decrement a counter below 0.
+    pub async fn decr(&mut self, delta: u64) -> Result<u64, Error> {
+        let extras = CounterExtras::new(delta, self.initial, self.expire);
+        self.client.decr_with(&self.key, extras).await
+    }
+
+    /// Get the current value of the counter, or None if it does not exist.
+    pub async fn get(&mut self) -> Result<Option<u64>, Error> {
+        if !self.client.is_enabled() {
+            return Ok(None);
+        }
+        let packet = self
+            .client
+            .request(&self.key, Packet::get(&self.key)?)
+            .await?;
+        match packet.error_for_status() {
+            Ok(()) => Ok(parse_counter(&packet.value)),
+            Err(Status::KeyNotFound) => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Overwrite the value of the counter with the desired expiration.
+    pub async fn set(&mut self, value: u64, expire: u32) -> Result<(), Error> {
+        if !self.client.is_enabled() {
+            return Ok(());
+        }
+        self.client.check_writable()?;
+        let bytes = value.to_string().into_bytes();
+        let packet = Packet::set_raw(&self.key, bytes, SetExtras::new(0, expire))?;
+        self.client
+            .request(&self.key, packet)
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Change the expiration of the counter without modifying its value.
+    pub async fn expire(&mut self, expire: u32) -> Result<(), Error> {
+        if !self.client.is_enabled() {
+            return Ok(());
+        }
+        self.client.check_writable()?;
+        let packet = Packet::touch(&self.key, TouchExtras::new(expire))?;
+        self.client
+            .request(&self.key, packet)
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// A counter that accumulates deltas locally and only sends them to
+/// memcached once the flush interval has elapsed. This trades some
+/// staleness for far fewer round trips on high-frequency counters.
+#[derive(Debug, Clone)]
+pub struct BatchedCounter {
+    key: Vec<u8>,
+    pending: i64,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl BatchedCounter {
+    /// Create a new batched counter that flushes at most once per interval.
+    pub fn new<K: Into<Vec<u8>>>(key: K, interval: Duration) -> Self {
+        Self {
+            key: key.into(),
+            pending: 0,
+            interval,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Accumulate a delta locally without sending it.
+    pub fn add(&mut self, delta: i64) {
+        self.pending += delta;
+    }
+
+    /// The sum of the deltas that have not been flushed yet.
+    pub fn pending(&self) -> i64 {
+        self.pending
+    }
+
+    /// Whether the flush interval has elapsed since the last flush.
+    pub fn is_due(&self) -> bool {
+        self.last_flush.elapsed() >= self.interval
+    }
+
+    /// Accumulate a delta, flushing the pending deltas if the interval has
+    /// elapsed. Returns the new counter value when a flush happened.
+    pub async fn record<C: Connection, P: Compressor>(
+        &mut self,
+        client: &mut Client<C, P>,
+        delta: i64,
+    ) -> Result<Option<u64>, Error> {
+        self.add(delta);
+        if !self.is_due() {
+            return Ok(None);
+        }
+        self.flush(client).await.map(Some)
+    }
+
+    /// Send the pending deltas to memcached, returning the new value.
+    pub async fn flush<C: Connection, P: Compressor>(
+        &mut self,
+        client: &mut Client<C, P>,
+    ) -> Result<u64, Error> {
+        let mut counter = client.counter(self.key.clone());
+        let value = match self.pending {
+            delta if delta < 0 => counter.decr(delta.unsigned_abs()).await?,
+            delta => counter.incr(delta as u64).await?,
+        };
+        self.pending = 0;
+        self.last_flush = Instant::now();
+        Ok(value)
+    }
+}
+
+fn parse_counter(bytes: &[u8]) -> Option<u64> {
+    std::str::from_utf8(bytes).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::{MockConnection, Store},
+    };
+
+    use super::BatchedCounter;
+
+    #[test]
+    fn test_counter() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["counter".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+
+            let mut counter = client.counter("hits").with_initial(10, 0);
+            assert_eq!(None, counter.get().await.unwrap());
+            assert_eq!(10, counter.incr(1).await.unwrap());
+            assert_eq!(15, counter.incr(5).await.unwrap());
+            assert_eq!(12, counter.decr(3).await.unwrap());
+            counter.set(100, 0).await.unwrap();
+            assert_eq!(Some(100), counter.get().await.unwrap());
+            counter.expire(60).await.unwrap();
+            let store = Store::get("counter");
+            assert_eq!(Some(60), store.lock().unwrap().expire(b"hits"));
+
+            let mut batched = BatchedCounter::new("hits", Duration::from_secs(3600));
+            assert_eq!(None, batched.record(&mut client, 5).await.unwrap());
+            assert_eq!(None, batched.record(&mut client, -2).await.unwrap());
+            assert_eq!(3, batched.pending());
+            assert_eq!(103, batched.flush(&mut client).await.unwrap());
+            assert_eq!(0, batched.pending());
+        });
+    }
+}
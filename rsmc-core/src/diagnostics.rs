@@ -0,0 +1,216 @@
+//! Many runtime failures are really misconfigurations that can be discovered
+//! at boot. This module validates a client config and diagnoses every
+//! endpoint in it, reporting what was found in a structured report.
+
+use std::{
+    collections::HashSet,
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+use crate::{
+    client::{Connection, Error, NoCompressor},
+    protocol::Packet,
+    stats::read_server_stats,
+};
+
+/// The minimum memcached version that supports the binary protocol.
+pub const MIN_BINARY_VERSION: (u32, u32, u32) = (1, 4, 0);
+
+/// An error caused by an invalid client configuration.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ConfigError {
+    /// No endpoints were configured.
+    NoEndpoints,
+    /// The same endpoint was configured more than once.
+    DuplicateEndpoint(String),
+    /// An endpoint was not of the form `host:port`.
+    InvalidEndpoint(String),
+    /// The error budget has an invalid window, rate or minimum.
+    InvalidErrorBudget,
+    /// A pool timing is zero, or the recycle interval is not shorter than
+    /// the idle timeout and maximum client age.
+    InvalidPoolTiming,
+    /// A batch limit is zero.
+    InvalidBatchLimits,
+    /// The ring has fewer buckets than endpoints, so no endpoint would own
+    /// any of them.
+    InvalidRingSize,
+    /// A vbucket map has no servers, no vbuckets or more than 65536 of them,
+    /// or assigns a vbucket to a server which is not in the map.
+    InvalidVbucketMap,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ConfigError::NoEndpoints => write!(f, "No endpoints configured"),
+            ConfigError::DuplicateEndpoint(e) => write!(f, "Duplicate endpoint: {}", e),
+            ConfigError::InvalidEndpoint(e) => write!(f, "Invalid endpoint: {}", e),
+            ConfigError::InvalidErrorBudget => write!(f, "Invalid error budget"),
+            ConfigError::InvalidPoolTiming => write!(f, "Invalid pool timing"),
+            ConfigError::InvalidBatchLimits => write!(f, "Invalid batch limits"),
+            ConfigError::InvalidRingSize => write!(f, "Invalid ring size"),
+            ConfigError::InvalidVbucketMap => write!(f, "Invalid vbucket map"),
+        }
+    }
+}
+
+impl StdError for ConfigError {}
+
+/// Check that a list of endpoints is non-empty, has no duplicates, and that
+/// every endpoint is of the form `host:port`, or names a local transport as
+/// `unix:<path>` or `pipe:<name>`.
+pub(crate) fn validate_endpoints(endpoints: &[String]) -> Result<(), ConfigError> {
+    if endpoints.is_empty() {
+        return Err(ConfigError::NoEndpoints);
+    }
+    let mut seen = HashSet::new();
+    for endpoint in endpoints {
+        let local = ["unix:", "pipe:"]
+            .iter()
+            .find_map(|scheme| endpoint.strip_prefix(scheme));
+        let valid = match (local, endpoint.rsplit_once(':')) {
+            (Some(path), _) => !path.is_empty(),
+            (None, Some((host, port))) => !host.is_empty() && port.parse::<u16>().is_ok(),
+            (None, None) => false,
+        };
+        if !valid {
+            return Err(ConfigError::InvalidEndpoint(endpoint.clone()));
+        }
+        if !seen.insert(endpoint) {
+            return Err(ConfigError::DuplicateEndpoint(endpoint.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// What was discovered about a single endpoint.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NodeDiagnostic {
+    /// The endpoint that was diagnosed.
+    pub endpoint: String,
+    /// Whether a connection could be made to the endpoint.
+    pub reachable: bool,
+    /// The version reported by the server.
+    pub version: Option<String>,
+    /// Whether the server version supports the binary protocol.
+    pub supports_binary: bool,
+    /// The maximum item size the server accepts, in bytes.
+    pub max_item_size: Option<u64>,
+    /// The error encountered while diagnosing the endpoint.
+    pub error: Option<String>,
+}
+
+impl NodeDiagnostic {
+    /// Whether the endpoint is reachable and usable by this client.
+    pub fn is_healthy(&self) -> bool {
+        self.reachable && self.supports_binary && self.error.is_none()
+    }
+}
+
+/// A structured report of diagnosing every endpoint in a config.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiagnosticReport {
+    /// The problem with the configuration itself, if any.
+    pub config_error: Option<ConfigError>,
+    /// What was discovered about each endpoint.
+    pub nodes: Vec<NodeDiagnostic>,
+}
+
+impl DiagnosticReport {
+    /// Whether the configuration is valid and every endpoint is healthy.
+    pub fn is_healthy(&self) -> bool {
+        self.config_error.is_none() && self.nodes.iter().all(NodeDiagnostic::is_healthy)
+    }
+}
+
+/// Connect to an endpoint and discover its version and settings.
+pub(crate) async fn diagnose_endpoint<C: Connection>(endpoint: String) -> NodeDiagnostic {
+    let mut out = NodeDiagnostic {
+        endpoint: endpoint.clone(),
+        ..NodeDiagnostic::default()
+    };
+    let mut conn = match C::connect(endpoint).await {
+        Ok(conn) => conn,
+        Err(err) => {
+            out.error = Some(err.to_string());
+            return out;
+        }
+    };
+    out.reachable = true;
+    if let Err(err) = diagnose_conn(&mut conn, &mut out).await {
+        out.error = Some(err.to_string());
+    }
+    out
+}
+
+async fn diagnose_conn<C: Connection>(conn: &mut C, out: &mut NodeDiagnostic) -> Result<(), Error> {
+    conn.write_packet(NoCompressor, Packet::version()?).await?;
+    let packet = conn.read_packet(NoCompressor).await?;
+    packet.error_for_status()?;
+    let version = String::from_utf8_lossy(&packet.value).to_string();
+    out.supports_binary = parse_version(&version).is_some_and(|v| v >= MIN_BINARY_VERSION);
+    out.version = Some(version);
+
+    let settings = read_server_stats(conn, "settings").await?;
+    out.max_item_size = settings
+        .get("item_size_max")
+        .and_then(|size| size.parse().ok());
+    Ok(())
+}
+
+/// Parse a memcached version string such as `1.6.9` into its components.
+pub fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version
+        .trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{client::ClientConfig, mock::MockConnection};
+
+    use super::{parse_version, validate_endpoints, ConfigError};
+
+    #[test]
+    fn test_validate_endpoints() {
+        let valid = vec!["localhost:11211".to_string(), "10.0.0.1:11212".to_string()];
+        assert_eq!(Ok(()), validate_endpoints(&valid));
+        assert_eq!(Err(ConfigError::NoEndpoints), validate_endpoints(&[]));
+
+        let dupe = vec!["localhost:11211".to_string(), "localhost:11211".to_string()];
+        let expect = ConfigError::DuplicateEndpoint("localhost:11211".into());
+        assert_eq!(Err(expect), validate_endpoints(&dupe));
+
+        let invalid = vec!["localhost".to_string()];
+        let expect = ConfigError::InvalidEndpoint("localhost".into());
+        assert_eq!(Err(expect), validate_endpoints(&invalid));
+
+        let local = vec![
+            "unix:/tmp/memcached.sock".to_string(),
+            r"pipe:\\.\pipe\mc".into(),
+        ];
+        assert_eq!(Ok(()), validate_endpoints(&local));
+        let expect = ConfigError::InvalidEndpoint("unix:".into());
+        assert_eq!(Err(expect), validate_endpoints(&["unix:".into()]));
+    }
+
+    #[test]
+    fn test_diagnose() {
+        assert_eq!(Some((1, 6, 9)), parse_version("1.6.9"));
+        assert_eq!(Some((1, 4, 0)), parse_version("1.4.0-rc1"));
+        assert_eq!(None, parse_version("unknown"));
+
+        tokio_test::block_on(async {
+            let cfg =
+                ClientConfig::<MockConnection, _>::new_uncompressed(vec!["diagnose:11211".into()]);
+            let report = cfg.diagnose().await;
+            assert!(report.is_healthy());
+            assert_eq!(Some("1.6.9".into()), report.nodes[0].version);
+            assert_eq!(Some(1048576), report.nodes[0].max_item_size);
+        });
+    }
+}
@@ -0,0 +1,163 @@
+//! Moving a cache to a new cluster, or to a new compressor or envelope, is
+//! only safe once the new setup returns the same values as the old one. A
+//! [`DualRead`] reads every key from both the old and the new client,
+//! compares the values, reports how they compared to the [`MetricsHook`],
+//! and serves the value of the configured primary.
+//!
+//! Both clients can point at the same cluster with different compressors or
+//! envelopes to verify a serializer change, or at different clusters to
+//! verify a cluster move.
+
+use futures::future::join;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    client::{Client, Compressor, Connection, Error},
+    instrument::{DualReadEvent, MetricsHook, NoMetrics},
+};
+
+/// Which client of a dual read serves its values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Primary {
+    /// Serve the values of the old client. This is the default, for the
+    /// start of a migration.
+    #[default]
+    Old,
+    /// Serve the values of the new client, once it has been verified.
+    New,
+}
+
+/// Reads every key from an old and a new client and compares the values.
+#[derive(Debug)]
+pub struct DualRead<C: Connection, P: Compressor, Q: Compressor, H: MetricsHook = NoMetrics> {
+    old: Client<C, P>,
+    new: Client<C, Q>,
+    primary: Primary,
+    hook: H,
+}
+
+impl<C: Connection, P: Compressor, Q: Compressor> DualRead<C, P, Q> {
+    /// Read from both clients, serving the values of the old client.
+    pub fn new(old: Client<C, P>, new: Client<C, Q>) -> Self {
+        Self {
+            old,
+            new,
+            primary: Primary::default(),
+            hook: NoMetrics,
+        }
+    }
+}
+
+impl<C: Connection, P: Compressor, Q: Compressor, H: MetricsHook> DualRead<C, P, Q, H> {
+    /// Report how every key compared to a different hook.
+    pub fn with_metrics_hook<G: MetricsHook>(self) -> DualRead<C, P, Q, G> {
+        DualRead {
+            old: self.old,
+            new: self.new,
+            primary: self.primary,
+            hook: G::default(),
+        }
+    }
+
+    /// Choose which client serves values.
+    pub fn with_primary(mut self, primary: Primary) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    /// Get the old client, for example to write to it.
+    pub fn old_client(&mut self) -> &mut Client<C, P> {
+        &mut self.old
+    }
+
+    /// Get the new client, for example to write to it.
+    pub fn new_client(&mut self) -> &mut Client<C, Q> {
+        &mut self.new
+    }
+
+    /// Get a single value from both clients, returning the value of the
+    /// primary. Errors of the primary are returned, while errors of the
+    /// secondary are only reported to the hook.
+    pub async fn get<K: AsRef<[u8]>, V: DeserializeOwned + PartialEq>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<V>, Error> {
+        let key = key.as_ref();
+        let (old, new) = join(self.old.get::<_, V>(key), self.new.get::<_, V>(key)).await;
+        let (primary, secondary) = match self.primary {
+            Primary::Old => (old, new),
+            Primary::New => (new, old),
+        };
+        let primary = primary?;
+        let event = match (&primary, secondary) {
+            (_, Err(_)) => DualReadEvent::SecondaryError,
+            (Some(a), Ok(Some(b))) if *a == b => DualReadEvent::Match,
+            (Some(_), Ok(Some(_))) => DualReadEvent::Mismatch,
+            (None, Ok(Some(_))) => DualReadEvent::MissingInPrimary,
+            (Some(_), Ok(None)) => DualReadEvent::MissingInSecondary,
+            (None, Ok(None)) => DualReadEvent::Match,
+        };
+        self.hook.on_dual_read(key, event);
+        Ok(primary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{
+        client::{Client, ClientConfig},
+        instrument::{DualReadEvent, MetricsHook},
+        mock::MockConnection,
+    };
+
+    use super::{DualRead, Primary};
+
+    static EVENTS: Mutex<Vec<(Vec<u8>, DualReadEvent)>> = Mutex::new(vec![]);
+
+    #[derive(Debug, Default, Clone)]
+    struct RecordingHook;
+
+    impl MetricsHook for RecordingHook {
+        fn on_dual_read(&self, key: &[u8], event: DualReadEvent) {
+            EVENTS.lock().unwrap().push((key.to_vec(), event));
+        }
+    }
+
+    #[test]
+    fn test_dual_read() {
+        tokio_test::block_on(async {
+            let client = |endpoint: &str| {
+                let cfg = ClientConfig::new_uncompressed(vec![endpoint.into()]);
+                Client::<MockConnection, _>::new(cfg)
+            };
+            let (old, new) = (client("dual:old").await.unwrap(), client("dual:new").await);
+            let mut dual = DualRead::new(old, new.unwrap()).with_metrics_hook::<RecordingHook>();
+            dual.old_client().set("same", "1", 0).await.unwrap();
+            dual.new_client().set("same", "1", 0).await.unwrap();
+            dual.old_client().set("changed", "1", 0).await.unwrap();
+            dual.new_client().set("changed", "2", 0).await.unwrap();
+            dual.old_client().set("old", "1", 0).await.unwrap();
+
+            assert_eq!(Some("1".to_string()), dual.get("same").await.unwrap());
+            assert_eq!(Some("1".to_string()), dual.get("changed").await.unwrap());
+            assert_eq!(Some("1".to_string()), dual.get("old").await.unwrap());
+            let mut dual = dual.with_primary(Primary::New);
+            assert_eq!(Some("2".to_string()), dual.get("changed").await.unwrap());
+            assert_eq!(None, dual.get::<_, String>("old").await.unwrap());
+
+            let events = EVENTS.lock().unwrap();
+            assert_eq!(
+                vec![
+                    (b"same".to_vec(), DualReadEvent::Match),
+                    (b"changed".to_vec(), DualReadEvent::Mismatch),
+                    (b"old".to_vec(), DualReadEvent::MissingInSecondary),
+                    (b"changed".to_vec(), DualReadEvent::Mismatch),
+                    (b"old".to_vec(), DualReadEvent::MissingInPrimary),
+                ],
+                *events
+            );
+        });
+    }
+}
@@ -0,0 +1,217 @@
+//! Values can optionally be stored inside of an envelope, which is a small
+//! metadata header written in front of the serialized value. The envelope
+//! records when the value was written, an application-defined version, and
+//! which serializer produced the value, so that staleness decisions can be
+//! made without separate bookkeeping keys.
+//!
+//! The on-wire layout of the envelope is defined by an [`Envelope`], so that
+//! values can be shared with other clients which already use a layout of
+//! their own. The default [`HeaderEnvelope`] writes a fixed 17 byte header.
+
+use std::{
+    convert::TryInto,
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{client::Error, protocol::Packet};
+
+/// The flag bit set on values stored inside of an envelope. This lives in
+/// the high byte of the flags, which is reserved for rsmc.
+pub const ENVELOPE_FLAG: u32 = 0x0200_0000;
+
+/// The serializer id recorded for values serialized with bincode.
+pub const BINCODE_SERIALIZER: u8 = 1;
+
+/// The serializer id recorded for values that were serialized by the caller.
+pub const RAW_SERIALIZER: u8 = 0;
+
+/// The number of bytes taken up by the header of a [`HeaderEnvelope`].
+pub const HEADER_LEN: usize = 17;
+
+/// Metadata stored alongside a value inside of an envelope.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Metadata {
+    /// The unix timestamp (in seconds) when the value was written.
+    pub created_at: u64,
+    /// The expiration the value was written with.
+    pub expire: u32,
+    /// An application-defined version of the value.
+    pub version: u32,
+    /// The id of the serializer used to serialize the value.
+    pub serializer: u8,
+}
+
+impl Metadata {
+    /// Create metadata for a value being written right now.
+    pub fn new(expire: u32, version: u32, serializer: u8) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            created_at,
+            expire,
+            version,
+            serializer,
+        }
+    }
+
+    /// The number of seconds elapsed since the value was written.
+    pub fn age(&self) -> u64 {
+        Metadata::new(0, 0, 0)
+            .created_at
+            .saturating_sub(self.created_at)
+    }
+
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0_u8; HEADER_LEN];
+        out[0..8].copy_from_slice(&self.created_at.to_be_bytes());
+        out[8..12].copy_from_slice(&self.expire.to_be_bytes());
+        out[12..16].copy_from_slice(&self.version.to_be_bytes());
+        out[16] = self.serializer;
+        out
+    }
+
+    fn decode(bytes: &[u8; HEADER_LEN]) -> Self {
+        Self {
+            created_at: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            expire: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+            version: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+            serializer: bytes[16],
+        }
+    }
+}
+
+/// Defines how metadata and a serialized value are laid out on the wire.
+pub trait Envelope: Debug + Send + Sync {
+    /// Encode the metadata and the value into a single stored value.
+    fn encode(&self, meta: &Metadata, value: &[u8]) -> Vec<u8>;
+
+    /// Split a stored value into its metadata and the value inside of it,
+    /// returning [`Error::InvalidEnvelope`] if it cannot be decoded.
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Result<(Metadata, &'a [u8]), Error>;
+}
+
+/// The default envelope, which writes the metadata as a fixed length header
+/// in front of the value.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeaderEnvelope;
+
+impl Envelope for HeaderEnvelope {
+    fn encode(&self, meta: &Metadata, value: &[u8]) -> Vec<u8> {
+        [&meta.encode()[..], value].concat()
+    }
+
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Result<(Metadata, &'a [u8]), Error> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::InvalidEnvelope);
+        }
+        let (header, value) = bytes.split_at(HEADER_LEN);
+        Ok((Metadata::decode(header.try_into().unwrap()), value))
+    }
+}
+
+/// Wrap the value of a packet in an envelope with the given metadata.
+pub(crate) fn wrap(codec: &dyn Envelope, mut packet: Packet, meta: Metadata) -> Packet {
+    let value = codec.encode(&meta, &packet.value);
+    packet.header.body_len =
+        packet.header.body_len - packet.value.len() as u32 + value.len() as u32;
+    packet.value = value;
+    packet.set_flags(packet.flags() | ENVELOPE_FLAG);
+    packet
+}
+
+/// Remove the envelope from a packet value, if it has one, returning the
+/// metadata that was stored inside of it.
+pub(crate) fn unwrap(
+    codec: &dyn Envelope,
+    mut packet: Packet,
+) -> Result<(Packet, Option<Metadata>), Error> {
+    if packet.flags() & ENVELOPE_FLAG == 0 {
+        return Ok((packet, None));
+    }
+    let (meta, value) = codec.decode(&packet.value)?;
+    let value = value.to_vec();
+    packet.header.body_len =
+        packet.header.body_len - packet.value.len() as u32 + value.len() as u32;
+    packet.value = value;
+    packet.set_flags(packet.flags() & !ENVELOPE_FLAG);
+    Ok((packet, Some(meta)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use crate::{
+        client::{Client, ClientConfig, Error},
+        mock::MockConnection,
+        protocol::{Packet, SetExtras},
+    };
+
+    use super::{
+        unwrap, wrap, Envelope, HeaderEnvelope, Metadata, BINCODE_SERIALIZER, ENVELOPE_FLAG,
+    };
+
+    /// Writes the version as a trailer after the value.
+    #[derive(Debug)]
+    struct TrailerEnvelope;
+
+    impl Envelope for TrailerEnvelope {
+        fn encode(&self, meta: &Metadata, value: &[u8]) -> Vec<u8> {
+            [value, &meta.version.to_le_bytes()[..]].concat()
+        }
+
+        fn decode<'a>(&self, bytes: &'a [u8]) -> Result<(Metadata, &'a [u8]), Error> {
+            let split = bytes.len().checked_sub(4).ok_or(Error::InvalidEnvelope)?;
+            let (value, trailer) = bytes.split_at(split);
+            let version = u32::from_le_bytes(trailer.try_into().unwrap());
+            Ok((Metadata::new(0, version, 0), value))
+        }
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let packet = Packet::set(b"key", "value", SetExtras::new(0, 300)).unwrap();
+        let meta = Metadata::new(300, 7, BINCODE_SERIALIZER);
+
+        let wrapped = wrap(&HeaderEnvelope, packet.clone(), meta);
+        assert_eq!(ENVELOPE_FLAG, wrapped.flags());
+        assert!(wrapped.header.body_len > packet.header.body_len);
+
+        let (unwrapped, actual) = unwrap(&HeaderEnvelope, wrapped).unwrap();
+        assert_eq!(packet, unwrapped);
+        assert_eq!(Some(meta), actual);
+
+        let (plain, actual) = unwrap(&HeaderEnvelope, packet.clone()).unwrap();
+        assert_eq!(packet, plain);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn test_envelope_codec() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["envelope:codec".into()])
+                .with_envelope(3)
+                .with_envelope_codec(TrailerEnvelope);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            let (value, meta) = client
+                .get_with_metadata::<_, String>("key")
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!("value", value);
+            assert_eq!(3, meta.unwrap().version);
+            let keys = ["key".to_string()];
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(Some(&"value".to_string()), values.get("key".as_bytes()));
+
+            // The version is stored after the value.
+            let (raw, flags) = client.get_raw_with_flags("key").await.unwrap().unwrap();
+            assert_eq!(ENVELOPE_FLAG, flags & ENVELOPE_FLAG);
+            assert!(raw.ends_with(&3_u32.to_le_bytes()));
+        });
+    }
+}
@@ -0,0 +1,90 @@
+//! The client records the version of every server it connects to, so that
+//! higher-level features can choose codepaths, or refuse clearly, depending
+//! on what the whole cluster supports.
+
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+use crate::diagnostics::parse_version;
+
+/// A server feature that is only available in some memcached versions.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Feature {
+    /// The binary protocol used by this client.
+    BinaryProtocol,
+    /// The TOUCH command, which updates the expiration of a key.
+    Touch,
+    /// The GAT command, which gets a key and updates its expiration.
+    GetAndTouch,
+    /// The text-based meta protocol.
+    MetaProtocol,
+}
+
+impl Feature {
+    /// The minimum server version that supports this feature.
+    pub fn min_version(&self) -> (u32, u32, u32) {
+        match self {
+            Feature::BinaryProtocol => (1, 4, 0),
+            Feature::Touch => (1, 4, 8),
+            Feature::GetAndTouch => (1, 4, 8),
+            Feature::MetaProtocol => (1, 6, 0),
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Feature::BinaryProtocol => write!(f, "binary protocol"),
+            Feature::Touch => write!(f, "touch"),
+            Feature::GetAndTouch => write!(f, "get and touch"),
+            Feature::MetaProtocol => write!(f, "meta protocol"),
+        }
+    }
+}
+
+/// The versions of every server in the cluster, recorded on connect.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClusterFeatures {
+    /// The version string reported by each endpoint.
+    pub versions: HashMap<String, String>,
+}
+
+impl ClusterFeatures {
+    /// The lowest version of any server in the cluster, or None if any
+    /// version could not be parsed.
+    pub fn min_version(&self) -> Option<(u32, u32, u32)> {
+        self.versions
+            .values()
+            .map(|version| parse_version(version))
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .min()
+    }
+
+    /// Whether every server in the cluster supports the given feature.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.min_version()
+            .is_some_and(|version| version >= feature.min_version())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClusterFeatures, Feature};
+
+    #[test]
+    fn test_cluster_features() {
+        let mut features = ClusterFeatures::default();
+        features.versions.insert("a:11211".into(), "1.6.9".into());
+        features.versions.insert("b:11211".into(), "1.5.22".into());
+        assert_eq!(Some((1, 5, 22)), features.min_version());
+        assert!(features.supports(Feature::GetAndTouch));
+        assert!(!features.supports(Feature::MetaProtocol));
+
+        features.versions.insert("c:11211".into(), "garbage".into());
+        assert!(!features.supports(Feature::BinaryProtocol));
+    }
+}
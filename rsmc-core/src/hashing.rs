@@ -0,0 +1,364 @@
+//! Hashing schemes decide which node in the ring owns a key. Clients written
+//! in other languages place keys differently, so a fleet mixing clients must
+//! agree on a scheme for every client to read what the others wrote. This
+//! module implements the schemes supported by the ring, and can compare the
+//! placement of keys under two schemes.
+
+use murmur3::murmur3_32;
+
+use crate::vbucket::crc32;
+
+/// The default number of buckets divided between the nodes in the ring.
+pub(crate) const DEFAULT_SIZE: usize = 360;
+
+/// The murmur3 seed used to bucket keys into percentages, distinct from the
+/// seed used for placement so that ramps are not correlated with nodes.
+const RAMP_SEED: u32 = 0x7261_6d70;
+
+/// Whether a key falls within the first `percent` of 100 buckets.
+pub(crate) fn in_ramp(key: &[u8], percent: u8) -> bool {
+    percent >= 100 || murmur3_32(&mut &key[..], RAMP_SEED).unwrap() % 100 < percent as u32
+}
+
+/// The scheme used to map keys to nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashScheme {
+    /// Consistent hashing where each node owns the points
+    /// `murmur3(endpoint, seed = i)` on the ring, and keys are hashed with
+    /// `murmur3(key, seed = 0)`, with the default [`HashSeeds`]. This is the
+    /// scheme rsmc has always used.
+    #[default]
+    Murmur3,
+    /// The CRC-32 of the key modulo the number of nodes, as used by the Go
+    /// client `gomemcache`. Adding or removing a node moves most keys.
+    Crc32Modulo,
+}
+
+/// The murmur3 seeds used by the consistent hashing schemes. The defaults
+/// of 0 give the placement rsmc has always used; other seeds place keys
+/// differently on the same endpoints, for example to keep isolated
+/// environments from sharing a placement, or to match other tooling. The
+/// [`HashScheme::Crc32Modulo`] scheme has no seed and ignores them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashSeeds {
+    /// The seed keys are hashed with to find their position on the ring.
+    pub key: u32,
+    /// The seed node points are hashed with. Under [`HashScheme::Murmur3`]
+    /// the points of a node are hashed with consecutive seeds starting at
+    /// this one, so seeds close to each other share most of their points.
+    pub placement: u32,
+}
+
+impl HashSeeds {
+    /// Hash keys and node points with the given seeds.
+    pub fn new(key: u32, placement: u32) -> Self {
+        Self { key, placement }
+    }
+}
+
+/// The placement of keys on the nodes of a ring under a single scheme.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Placement {
+    pub scheme: HashScheme,
+    pub seeds: HashSeeds,
+    pub size: usize,
+    pub buckets: Vec<(u32, usize)>,
+    pub nodes: usize,
+}
+
+impl Placement {
+    /// Divide a ring of the given size between the endpoints.
+    pub fn new(scheme: HashScheme, endpoints: &[String], size: usize) -> Self {
+        Self::new_with_seeds(scheme, endpoints, size, HashSeeds::default())
+    }
+
+    /// Divide a ring of the given size between the endpoints, hashing with
+    /// the given seeds.
+    pub fn new_with_seeds(
+        scheme: HashScheme,
+        endpoints: &[String],
+        size: usize,
+        seeds: HashSeeds,
+    ) -> Self {
+        let mut buckets = vec![];
+        // In this scheme, each connection gets an equal share of the ring space.
+        let share = size / endpoints.len().max(1);
+        for (conn_index, url) in endpoints.iter().enumerate() {
+            for i in 0..share {
+                let k = match scheme {
+                    HashScheme::Murmur3 => {
+                        let seed = seeds.placement.wrapping_add(i as u32);
+                        murmur3_32(&mut url.as_bytes(), seed).unwrap()
+                    }
+                    HashScheme::Crc32Modulo => break,
+                };
+                buckets.push((k, conn_index))
+            }
+        }
+        buckets.sort_unstable();
+        Self {
+            scheme,
+            seeds,
+            size,
+            buckets,
+            nodes: endpoints.len(),
+        }
+    }
+
+    /// Find the hash of a key, the bucket containing it, and the index of the
+    /// node owning that bucket, or `None` if there are no nodes.
+    pub fn locate(&self, key: &[u8]) -> Option<(u32, usize, usize)> {
+        if self.nodes == 0 || self.buckets.is_empty() && self.scheme != HashScheme::Crc32Modulo {
+            return None;
+        }
+        if self.scheme == HashScheme::Crc32Modulo {
+            let hash = crc32(key);
+            let node = hash as usize % self.nodes;
+            return Some((hash, node, node));
+        }
+        // Find the position of the hash on the ring
+        let ring_pos = murmur3_32(&mut &key[..], self.seeds.key).unwrap();
+        // Find the bucket containing the ring position
+        let bucket_search = self.buckets.binary_search_by_key(&ring_pos, |(i, _)| *i);
+        let bucket_index = bucket_search.unwrap_or_else(|next_bucket| next_bucket);
+        let bucket_index = bucket_index % self.buckets.len();
+        // Return the connection owning that bucket
+        let (_, conn_index) = self.buckets[bucket_index];
+        Some((ring_pos, bucket_index, conn_index))
+    }
+}
+
+/// A key placed on different endpoints by two hashing schemes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Moved {
+    /// The key that moved.
+    pub key: Vec<u8>,
+    /// The endpoint owning the key under the first scheme.
+    pub from: String,
+    /// The endpoint owning the key under the second scheme.
+    pub to: String,
+}
+
+/// The difference between the placement of keys under two schemes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlacementDiff {
+    /// The number of keys compared.
+    pub total: usize,
+    /// The keys placed on different endpoints.
+    pub moved: Vec<Moved>,
+}
+
+impl PlacementDiff {
+    /// The fraction of keys (between 0 and 1) placed on different endpoints.
+    pub fn moved_fraction(&self) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => self.moved.len() as f64 / total as f64,
+        }
+    }
+}
+
+/// Compare where two hashing schemes place a sample of keys on the given
+/// endpoints, for example to check that a migration to a scheme shared with
+/// clients in other languages moves an acceptable fraction of keys.
+pub fn placement_diff<K: AsRef<[u8]>>(
+    endpoints: &[String],
+    a: HashScheme,
+    b: HashScheme,
+    keys: &[K],
+) -> PlacementDiff {
+    let mut out = PlacementDiff {
+        total: keys.len(),
+        ..PlacementDiff::default()
+    };
+    if endpoints.is_empty() {
+        return out;
+    }
+    let (a, b) = (
+        Placement::new(a, endpoints, DEFAULT_SIZE),
+        Placement::new(b, endpoints, DEFAULT_SIZE),
+    );
+    for key in keys {
+        let key = key.as_ref();
+        let (from, to) = match (a.locate(key), b.locate(key)) {
+            (Some((_, _, from)), Some((_, _, to))) => (from, to),
+            _ => continue,
+        };
+        if from != to {
+            out.moved.push(Moved {
+                key: key.to_vec(),
+                from: endpoints[from].clone(),
+                to: endpoints[to].clone(),
+            });
+        }
+    }
+    out
+}
+
+/// The number of sample keys placed on a single node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeShare {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// The number of sample keys placed on the node.
+    pub keys: usize,
+    /// The fraction (between 0 and 1) of sample keys placed on the node.
+    pub share: f64,
+}
+
+/// How evenly a sample of keys is spread over the nodes of a ring.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DistributionReport {
+    /// The number of keys sampled.
+    pub total: usize,
+    /// The share of the keys placed on each node.
+    pub nodes: Vec<NodeShare>,
+    /// The standard deviation of the shares of the nodes.
+    pub std_dev: f64,
+    /// The largest share of any node.
+    pub max_share: f64,
+    /// The smallest share of any node.
+    pub min_share: f64,
+}
+
+impl DistributionReport {
+    /// Build a report from the number of keys placed on each endpoint.
+    pub(crate) fn new(endpoints: &[String], counts: &[usize]) -> Self {
+        let total = counts.iter().sum::<usize>();
+        if endpoints.is_empty() || total == 0 {
+            return Self {
+                total,
+                ..Self::default()
+            };
+        }
+        let nodes = endpoints
+            .iter()
+            .zip(counts)
+            .map(|(endpoint, keys)| NodeShare {
+                endpoint: endpoint.clone(),
+                keys: *keys,
+                share: *keys as f64 / total as f64,
+            })
+            .collect::<Vec<_>>();
+        let mean = 1.0 / nodes.len() as f64;
+        let variance =
+            nodes.iter().map(|n| (n.share - mean).powi(2)).sum::<f64>() / nodes.len() as f64;
+        let shares = nodes.iter().map(|n| n.share);
+        Self {
+            total,
+            std_dev: variance.sqrt(),
+            max_share: shares.clone().fold(0.0, f64::max),
+            min_share: shares.fold(1.0, f64::min),
+            nodes,
+        }
+    }
+
+    /// The ratio of the largest share to the smallest, which is 1 for a
+    /// perfectly even distribution and infinite when a node has no keys.
+    pub fn imbalance(&self) -> f64 {
+        self.max_share / self.min_share
+    }
+}
+
+/// Measure how evenly a scheme spreads a sample of keys over the given
+/// endpoints when each node owns `size / endpoints` points on the ring, so
+/// that schemes and ring sizes can be tuned with real key patterns before
+/// deploying.
+pub fn distribution_report<K: AsRef<[u8]>>(
+    endpoints: &[String],
+    scheme: HashScheme,
+    size: usize,
+    keys: &[K],
+) -> DistributionReport {
+    if endpoints.is_empty() {
+        return DistributionReport::default();
+    }
+    let placement = Placement::new(scheme, endpoints, size);
+    let mut counts = vec![0; endpoints.len()];
+    for key in keys {
+        if let Some((_, _, node)) = placement.locate(key.as_ref()) {
+            counts[node] += 1;
+        }
+    }
+    DistributionReport::new(endpoints, &counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{distribution_report, placement_diff, HashScheme, HashSeeds, Placement};
+
+    #[test]
+    fn test_placement_diff() {
+        let endpoints = vec!["a:11211".to_string(), "b:11211".to_string()];
+        let keys = (0..100).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+
+        let same = placement_diff(&endpoints, HashScheme::Murmur3, HashScheme::Murmur3, &keys);
+        assert_eq!(100, same.total);
+        assert!(same.moved.is_empty());
+
+        let a = HashScheme::Murmur3;
+        let b = HashScheme::Crc32Modulo;
+        let diff = placement_diff(&endpoints, a, b, &keys);
+        assert!(diff.moved_fraction() > 0.0 && diff.moved_fraction() < 1.0);
+        let modulo = Placement::new(b, &endpoints, 360);
+        for moved in diff.moved {
+            let (_, _, node) = modulo.locate(&moved.key).unwrap();
+            assert_eq!(endpoints[node], moved.to);
+        }
+
+        for scheme in [HashScheme::Murmur3, HashScheme::Crc32Modulo] {
+            assert_eq!(None, Placement::new(scheme, &[], 360).locate(b"key"));
+        }
+    }
+
+    #[test]
+    fn test_hash_seeds() {
+        let endpoints = (0..4)
+            .map(|i| format!("node{}:11211", i))
+            .collect::<Vec<_>>();
+        let keys = (0..1000).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+        let nodes = |placement: &Placement| {
+            let keys = keys.iter();
+            keys.map(|key| placement.locate(key.as_bytes()).unwrap().2)
+                .collect::<Vec<_>>()
+        };
+        let scheme = HashScheme::Murmur3;
+        let default = Placement::new(scheme, &endpoints, 360);
+        let zero = Placement::new_with_seeds(scheme, &endpoints, 360, HashSeeds::new(0, 0));
+        assert_eq!(nodes(&default), nodes(&zero));
+        for seeds in [HashSeeds::new(7, 0), HashSeeds::new(0, 1000)] {
+            let seeded = Placement::new_with_seeds(scheme, &endpoints, 360, seeds);
+            let moved = nodes(&default)
+                .into_iter()
+                .zip(nodes(&seeded))
+                .filter(|(a, b)| a != b)
+                .count();
+            assert!(moved > 500, "{:?} moved {}", seeds, moved);
+        }
+        let modulo = Placement::new(HashScheme::Crc32Modulo, &endpoints, 360);
+        let seeded = Placement::new_with_seeds(
+            HashScheme::Crc32Modulo,
+            &endpoints,
+            360,
+            HashSeeds::new(7, 7),
+        );
+        assert_eq!(nodes(&modulo), nodes(&seeded));
+    }
+
+    #[test]
+    fn test_distribution_report() {
+        let endpoints = vec!["a:11211".to_string(), "b:11211".to_string()];
+        let keys = (0..10000).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+        let report = distribution_report(&endpoints, HashScheme::Murmur3, 360, &keys);
+        assert_eq!(10000, report.total);
+        assert_eq!(10000, report.nodes.iter().map(|n| n.keys).sum::<usize>());
+        assert!(report.min_share <= 0.5 && report.max_share >= 0.5);
+        assert!(report.imbalance() >= 1.0 && report.imbalance() < 1.5);
+        let half = (report.max_share - 0.5).abs();
+        assert!((report.std_dev - half).abs() < 1e-9);
+
+        let empty: &[&str] = &[];
+        let report = distribution_report(&endpoints, HashScheme::Murmur3, 360, empty);
+        assert!(report.nodes.is_empty());
+    }
+}
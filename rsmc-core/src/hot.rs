@@ -0,0 +1,216 @@
+//! Keys are rarely requested uniformly: a handful of hot keys often account
+//! for most of the traffic, and since every key lives on a single node, they
+//! turn that node into a hot spot. A [`HotKeyDetector`] samples the keys
+//! requested by the client into a count-min sketch, and keeps the keys with
+//! the highest estimated counts, so that hot keys can be found directly from
+//! the client with [`crate::client::Client::hot_keys`].
+//!
+//! Counts decay by half at a fixed interval of samples, so the detector
+//! follows the keys which are hot now rather than those which were hot when
+//! the process started.
+
+use murmur3::murmur3_32;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The estimated counts of sampled keys, along with the candidates for the
+/// hottest keys.
+#[derive(Debug)]
+struct Sketch {
+    rows: Vec<Vec<u64>>,
+    candidates: HashMap<Vec<u8>, u64>,
+    samples: u64,
+}
+
+impl Sketch {
+    /// Count the key in every row, returning its estimated count, which is
+    /// the lowest count of the key in any row.
+    fn increment(&mut self, key: &[u8]) -> u64 {
+        let width = self.rows[0].len() as u32;
+        let mut estimate = u64::MAX;
+        for (seed, row) in self.rows.iter_mut().enumerate() {
+            let index = murmur3_32(&mut &key[..], seed as u32).unwrap() % width;
+            let count = &mut row[index as usize];
+            *count += 1;
+            estimate = estimate.min(*count);
+        }
+        estimate
+    }
+
+    fn decay(&mut self) {
+        for count in self.rows.iter_mut().flatten() {
+            *count /= 2;
+        }
+        self.candidates.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+    }
+}
+
+/// Samples requested keys and estimates which are requested the most,
+/// shared by every client created from the same config.
+#[derive(Clone)]
+pub struct HotKeyDetector {
+    every: u64,
+    capacity: usize,
+    decay_every: u64,
+    seen: Arc<AtomicU64>,
+    sketch: Arc<Mutex<Sketch>>,
+}
+
+impl Debug for HotKeyDetector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("HotKeyDetector")
+            .field("every", &self.every)
+            .field("capacity", &self.capacity)
+            .field("decay_every", &self.decay_every)
+            .finish()
+    }
+}
+
+impl Default for HotKeyDetector {
+    fn default() -> Self {
+        Self::new(1.0 / 16.0)
+    }
+}
+
+impl HotKeyDetector {
+    /// Sample a fraction of requested keys, given as a rate between 0 and 1,
+    /// into a sketch of 4 rows of 1024 counters, keeping the 32 hottest
+    /// keys. Counts decay by half every 65536 samples.
+    pub fn new(rate: f64) -> Self {
+        let every = match rate > 0.0 {
+            true => (1.0 / rate.min(1.0)).round() as u64,
+            false => u64::MAX,
+        };
+        Self {
+            every,
+            capacity: 32,
+            decay_every: 65536,
+            seen: Arc::new(AtomicU64::new(0)),
+            sketch: Arc::new(Mutex::new(Sketch {
+                rows: vec![vec![0; 1024]; 4],
+                candidates: HashMap::new(),
+                samples: 0,
+            })),
+        }
+    }
+
+    /// Use a sketch of `depth` rows of `width` counters. Wider sketches
+    /// overestimate less, and deeper sketches are less likely to.
+    pub fn with_sketch(mut self, width: usize, depth: usize) -> Self {
+        let rows = vec![vec![0; width.max(1)]; depth.max(1)];
+        self.sketch = Arc::new(Mutex::new(Sketch {
+            rows,
+            candidates: HashMap::new(),
+            samples: 0,
+        }));
+        self
+    }
+
+    /// Keep up to `capacity` candidates for the hottest keys.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Halve every count after the given number of samples. A value of 0
+    /// never decays the counts.
+    pub fn with_decay(mut self, samples: u64) -> Self {
+        self.decay_every = samples;
+        self
+    }
+
+    /// Record a request for a key, if it is sampled.
+    pub fn record(&self, key: &[u8]) {
+        if self.every == u64::MAX || self.capacity == 0 {
+            return;
+        }
+        if self.seen.fetch_add(1, Ordering::Relaxed) % self.every != 0 {
+            return;
+        }
+        let mut sketch = self.sketch.lock().unwrap();
+        sketch.samples += 1;
+        if self.decay_every > 0 && sketch.samples % self.decay_every == 0 {
+            sketch.decay();
+        }
+        let estimate = sketch.increment(key);
+        let candidates = &mut sketch.candidates;
+        if candidates.len() >= self.capacity && !candidates.contains_key(key) {
+            let coldest = candidates.iter().min_by_key(|(_, count)| **count);
+            match coldest {
+                Some((coldest, count)) if *count < estimate => {
+                    let coldest = coldest.clone();
+                    candidates.remove(&coldest);
+                }
+                _ => return,
+            }
+        }
+        candidates.insert(key.to_vec(), estimate);
+    }
+
+    /// Get up to `n` of the hottest keys, hottest first, with the estimated
+    /// number of requests for each. Estimates are scaled up by the sample
+    /// rate, and may overestimate but never underestimate the sampled count.
+    pub fn hot_keys(&self, n: usize) -> Vec<(Vec<u8>, u64)> {
+        let sketch = self.sketch.lock().unwrap();
+        let mut keys = sketch
+            .candidates
+            .iter()
+            .map(|(key, count)| (key.clone(), count.saturating_mul(self.every)))
+            .collect::<Vec<_>>();
+        keys.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        keys.truncate(n);
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::MockConnection,
+    };
+
+    use super::HotKeyDetector;
+
+    #[test]
+    fn test_hot_keys() {
+        tokio_test::block_on(async {
+            let detector = HotKeyDetector::new(1.0).with_capacity(2);
+            let cfg = ClientConfig::new_uncompressed(vec!["hot".into()])
+                .with_hot_key_detector(detector.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("hot", "value", 0).await.unwrap();
+            for i in 0..100 {
+                client.get::<_, String>("hot").await.unwrap();
+                client.get::<_, String>(format!("cold{}", i)).await.unwrap();
+                if i % 2 == 0 {
+                    client.get::<_, String>("warm").await.unwrap();
+                }
+            }
+            let keys = ["hot", "warm"].map(String::from);
+            client.get_multi::<_, String>(&keys).await.unwrap();
+
+            let hot = client.hot_keys(2);
+            assert_eq!(b"hot".to_vec(), hot[0].0);
+            assert_eq!(102, hot[0].1);
+            assert_eq!(b"warm".to_vec(), hot[1].0);
+            assert_eq!(51, hot[1].1);
+
+            // Sampled counts are scaled by the rate, and decay over time.
+            let detector = HotKeyDetector::new(0.5).with_decay(4);
+            for _ in 0..8 {
+                detector.record(b"key");
+            }
+            assert_eq!(vec![(b"key".to_vec(), 4)], detector.hot_keys(1));
+        });
+    }
+}
@@ -0,0 +1,298 @@
+//! Connection implementations for every async runtime need the same timing
+//! and byte counting code to be observable. This module provides a decorator
+//! around any [`Connection`] which measures every read and write and reports
+//! them to a [`MetricsHook`], so adapters get observability for free.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Connection, Error},
+    options,
+    protocol::Header,
+};
+
+/// Receives measurements of the reads and writes on a connection. Every
+/// method has an empty default implementation, so implementors only need to
+/// handle the events they care about.
+///
+/// The hook is created with [`Default`] whenever a connection is made, so
+/// hooks that aggregate across connections should share their state, for
+/// example through a static.
+pub trait MetricsHook: Debug + Default + Clone + Send + Sync + 'static {
+    /// Called after a connection to the endpoint is attempted.
+    fn on_connect(&self, _endpoint: &str, _elapsed: Duration, _ok: bool) {}
+
+    /// Called after a read from the endpoint, with the number of bytes read.
+    fn on_read(&self, _endpoint: &str, _bytes: usize, _elapsed: Duration, _ok: bool) {}
+
+    /// Called after a write to the endpoint, with the number of bytes written.
+    fn on_write(&self, _endpoint: &str, _bytes: usize, _elapsed: Duration, _ok: bool) {}
+
+    /// Called after a compressor decided whether to compress a value.
+    fn on_compress(&self, _event: CompressionEvent) {}
+
+    /// Called after a dual read compared the values of a key.
+    fn on_dual_read(&self, _key: &[u8], _event: DualReadEvent) {}
+
+    /// Called when the extstore stats of the endpoint cross a threshold.
+    fn on_extstore_warning(&self, _endpoint: &str, _warning: ExtstoreWarning) {}
+
+    /// Called after reading a response to a request sent with
+    /// [`crate::options::RequestOptions::with_baggage`], with its token.
+    fn on_baggage(&self, _endpoint: &str, _baggage: u16) {}
+}
+
+/// What a compressor did with a single value, reported to
+/// [`MetricsHook::on_compress`] to find out whether compression settings
+/// are paying for their CPU cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionEvent {
+    /// The value was compressed from `original` to `compressed` bytes.
+    Compressed { original: usize, compressed: usize },
+    /// The value of this many bytes was below the minimum size to compress.
+    SkippedSmall(usize),
+    /// The value of this many bytes did not get smaller when compressed, so
+    /// it was stored uncompressed.
+    SkippedIncompressible(usize),
+}
+
+impl CompressionEvent {
+    /// The number of bytes saved by compressing the value.
+    pub fn bytes_saved(&self) -> usize {
+        match self {
+            CompressionEvent::Compressed {
+                original,
+                compressed,
+            } => original.saturating_sub(*compressed),
+            _ => 0,
+        }
+    }
+}
+
+/// How the secondary value of a dual read compared to the primary value,
+/// reported to [`MetricsHook::on_dual_read`] to find out whether a
+/// migration is safe to finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DualReadEvent {
+    /// Both reads returned the same value, or both missed.
+    Match,
+    /// Both reads returned a value, but the values differ.
+    Mismatch,
+    /// Only the secondary read returned a value.
+    MissingInPrimary,
+    /// Only the primary read returned a value.
+    MissingInSecondary,
+    /// The secondary read failed, so nothing was compared.
+    SecondaryError,
+}
+
+/// A sign that reads from the flash storage of an extstore node are getting
+/// slow or failing, reported to [`MetricsHook::on_extstore_warning`] by
+/// [`crate::client::Client::check_extstore`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtstoreWarning {
+    /// This many reads and writes are queued for flash, above the threshold.
+    IoQueue(u64),
+    /// This fraction of reads from flash were aborted or ran out of memory,
+    /// above the threshold.
+    AbortedReads(f64),
+    /// This many values read from flash failed their CRC check.
+    BadCrc(u64),
+    /// Every flash page is in use, so storing new values evicts old pages.
+    NoFreePages,
+}
+
+/// A [`MetricsHook`] that ignores every measurement.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoMetrics;
+
+impl MetricsHook for NoMetrics {}
+
+/// A connection which measures the bytes and durations of every read and
+/// write on the inner connection and reports them to the hook `H`. Use it
+/// in place of the inner connection type, e.g.
+/// `ClientConfig<InstrumentedConnection<TokioConnection, MyHook>, _>`.
+#[derive(Debug, Clone)]
+pub struct InstrumentedConnection<C: Connection + Debug, H: MetricsHook> {
+    inner: C,
+    endpoint: String,
+    hook: H,
+}
+
+impl<C: Connection + Debug, H: MetricsHook> InstrumentedConnection<C, H> {
+    /// Wrap an existing connection to the given endpoint.
+    pub fn new(inner: C, endpoint: String, hook: H) -> Self {
+        Self {
+            inner,
+            endpoint,
+            hook,
+        }
+    }
+
+    /// Get the wrapped connection.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<C: Connection + Debug, H: MetricsHook> Connection for InstrumentedConnection<C, H> {
+    async fn connect(url: String) -> Result<Self, Error> {
+        let hook = H::default();
+        let start = Instant::now();
+        let result = C::connect(url.clone()).await;
+        hook.on_connect(&url, start.elapsed(), result.is_ok());
+        Ok(Self::new(result?, url, hook))
+    }
+
+    async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let start = Instant::now();
+        let result = self.inner.read(buf).await;
+        let bytes = *result.as_ref().unwrap_or(&0);
+        self.hook
+            .on_read(&self.endpoint, bytes, start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let start = Instant::now();
+        let result = self.inner.write(data).await;
+        let bytes = if result.is_ok() { data.len() } else { 0 };
+        self.hook
+            .on_write(&self.endpoint, bytes, start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn read_owned(&mut self, n: usize) -> Result<Bytes, Error> {
+        let start = Instant::now();
+        let result = self.inner.read_owned(n).await;
+        let bytes = result.as_ref().map_or(0, Bytes::len);
+        self.hook
+            .on_read(&self.endpoint, bytes, start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn read_frame(&mut self, max_body: u32) -> Result<(Header, Bytes), Error> {
+        let start = Instant::now();
+        let result = self.inner.read_frame(max_body).await;
+        let bytes = result.as_ref().map_or(0, |(_, body)| 24 + body.len());
+        self.hook
+            .on_read(&self.endpoint, bytes, start.elapsed(), result.is_ok());
+        if let Ok((header, _)) = &result {
+            if let Some(baggage) = options::decode_baggage(header.opaque) {
+                self.hook.on_baggage(&self.endpoint, baggage);
+            }
+        }
+        result
+    }
+
+    async fn reconnect(&mut self, url: String) -> Result<(), Error> {
+        let start = Instant::now();
+        let result = self.inner.reconnect(url.clone()).await;
+        self.hook.on_connect(&url, start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn sleep(duration: Duration) {
+        C::sleep(duration).await
+    }
+
+    fn now() -> Instant {
+        C::now()
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        self.inner.shutdown().await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn split(&self) -> (Self, Self) {
+        let (reader, writer) = self.inner.split();
+        let reader = Self::new(reader, self.endpoint.clone(), self.hook.clone());
+        let writer = Self::new(writer, self.endpoint.clone(), self.hook.clone());
+        (reader, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::MockConnection,
+        options::{decode_baggage, RequestOptions},
+    };
+
+    use super::{InstrumentedConnection, MetricsHook};
+
+    static READ: AtomicUsize = AtomicUsize::new(0);
+    static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+    static BAGGAGE: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Default, Clone)]
+    struct CountingHook;
+
+    impl MetricsHook for CountingHook {
+        fn on_read(&self, _: &str, bytes: usize, _: Duration, _: bool) {
+            READ.fetch_add(bytes, Ordering::Relaxed);
+        }
+
+        fn on_write(&self, _: &str, bytes: usize, _: Duration, _: bool) {
+            WRITTEN.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct BaggageHook;
+
+    impl MetricsHook for BaggageHook {
+        fn on_baggage(&self, _: &str, baggage: u16) {
+            BAGGAGE.store(baggage as usize, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_instrumented_connection() {
+        tokio_test::block_on(async {
+            type Conn = InstrumentedConnection<MockConnection, CountingHook>;
+            let cfg = ClientConfig::new_uncompressed(vec!["instrumented".into()]);
+            let mut client = Client::<Conn, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            client.get::<_, String>("key").await.unwrap();
+
+            let stats = &client.node_stats()[0];
+            assert_eq!(stats.bytes_sent as usize, WRITTEN.load(Ordering::Relaxed));
+            assert_eq!(stats.bytes_received as usize, READ.load(Ordering::Relaxed));
+        });
+    }
+
+    #[test]
+    fn test_baggage() {
+        tokio_test::block_on(async {
+            type Conn = InstrumentedConnection<MockConnection, BaggageHook>;
+            let cfg = ClientConfig::new_uncompressed(vec!["baggage".into()]);
+            let mut client = Client::<Conn, _>::new(cfg).await.unwrap();
+            let options = RequestOptions::new().with_baggage(0x1234);
+            client
+                .set_with_options("key", "value", 0, &options)
+                .await
+                .unwrap();
+            assert_eq!(0x1234, BAGGAGE.load(Ordering::Relaxed));
+
+            assert_eq!(None, decode_baggage(42));
+            assert_eq!(Some(0x1234), decode_baggage(0x9234_0001));
+        });
+    }
+}
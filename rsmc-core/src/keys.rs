@@ -0,0 +1,130 @@
+//! Keys are arbitrary bytes in the binary protocol, but ASCII tooling and
+//! proxies in front of memcached mangle keys containing spaces, control
+//! characters or invalid UTF-8, such as raw UUIDs. A [`KeyCodec`] encodes
+//! keys into printable ASCII on the wire, while keys are still routed by
+//! their raw bytes and returned to callers decoded.
+
+use crate::protocol::ProtocolError;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const HEX_ALPHABET: &[u8; 16] = b"0123456789abcdef";
+
+/// How keys are encoded on the wire.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCodec {
+    /// Send keys as-is. This is the default.
+    #[default]
+    Raw,
+    /// Send keys as lowercase hexadecimal, doubling their length.
+    Hex,
+    /// Send keys as padded standard base64, growing them by a third.
+    Base64,
+}
+
+impl KeyCodec {
+    /// Encode a raw key for the wire.
+    pub fn encode(&self, key: &[u8]) -> Vec<u8> {
+        match self {
+            KeyCodec::Raw => key.to_vec(),
+            KeyCodec::Hex => key
+                .iter()
+                .flat_map(|b| {
+                    [
+                        HEX_ALPHABET[(b >> 4) as usize],
+                        HEX_ALPHABET[(b & 0xf) as usize],
+                    ]
+                })
+                .collect(),
+            KeyCodec::Base64 => {
+                let mut out = Vec::with_capacity((key.len() + 2) / 3 * 4);
+                for chunk in key.chunks(3) {
+                    let n = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0_u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+                    for i in 0..4 {
+                        match i <= chunk.len() {
+                            true => out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]),
+                            false => out.push(b'='),
+                        }
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    /// Decode a key read from the wire back into its raw bytes.
+    pub fn decode(&self, key: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        match self {
+            KeyCodec::Raw => Ok(key.to_vec()),
+            KeyCodec::Hex => {
+                if key.len() % 2 != 0 {
+                    return Err(ProtocolError::InvalidKeyEncoding);
+                }
+                key.chunks(2)
+                    .map(|pair| {
+                        Ok(digit(HEX_ALPHABET, pair[0])? << 4 | digit(HEX_ALPHABET, pair[1])?)
+                    })
+                    .collect()
+            }
+            KeyCodec::Base64 => {
+                if key.len() % 4 != 0 {
+                    return Err(ProtocolError::InvalidKeyEncoding);
+                }
+                let mut out = Vec::with_capacity(key.len() / 4 * 3);
+                for chunk in key.chunks(4) {
+                    let padding = chunk.iter().rev().take_while(|b| **b == b'=').count();
+                    if padding > 2 {
+                        return Err(ProtocolError::InvalidKeyEncoding);
+                    }
+                    let mut n = 0_u32;
+                    for (i, b) in chunk[..4 - padding].iter().enumerate() {
+                        n |= (digit(BASE64_ALPHABET, *b)? as u32) << (18 - 6 * i);
+                    }
+                    out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+fn digit(alphabet: &[u8], byte: u8) -> Result<u8, ProtocolError> {
+    match alphabet.iter().position(|b| *b == byte) {
+        Some(i) => Ok(i as u8),
+        None => Err(ProtocolError::InvalidKeyEncoding),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyCodec;
+    use crate::protocol::ProtocolError;
+
+    #[test]
+    fn test_key_codec() {
+        assert_eq!(b"Zm9vYg==".to_vec(), KeyCodec::Base64.encode(b"foob"));
+        assert_eq!(b"Zm9vYmE=".to_vec(), KeyCodec::Base64.encode(b"fooba"));
+        assert_eq!(b"Zm9vYmFy".to_vec(), KeyCodec::Base64.encode(b"foobar"));
+        assert_eq!(
+            b"00ff10".to_vec(),
+            KeyCodec::Hex.encode(&[0x00, 0xff, 0x10])
+        );
+
+        let uuid = (0..16).map(|i| i * 17).collect::<Vec<u8>>();
+        for codec in [KeyCodec::Raw, KeyCodec::Hex, KeyCodec::Base64] {
+            for len in 0..uuid.len() {
+                let encoded = codec.encode(&uuid[..len]);
+                assert_eq!(Ok(uuid[..len].to_vec()), codec.decode(&encoded));
+            }
+        }
+
+        let invalid = Err(ProtocolError::InvalidKeyEncoding);
+        assert_eq!(invalid, KeyCodec::Hex.decode(b"abc"));
+        assert_eq!(invalid, KeyCodec::Hex.decode(b"zz"));
+        assert_eq!(invalid, KeyCodec::Base64.decode(b"Zm9"));
+        assert_eq!(invalid, KeyCodec::Base64.decode(b"Z==="));
+    }
+}
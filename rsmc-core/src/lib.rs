@@ -2,9 +2,49 @@
 //! async runtimes. If compression is undesired, it is possible to disable the
 //! `zlib` feature (on by default.)
 
+pub mod audit;
+pub mod batch;
+pub mod budget;
+pub mod bus;
 pub mod client;
+pub mod cold;
+pub mod counter;
+pub mod diagnostics;
+pub mod dual;
+pub mod envelope;
+pub mod features;
+pub mod hashing;
+pub mod hot;
+pub mod instrument;
+pub mod keys;
+pub mod limit;
+pub mod local;
+pub mod multi;
+pub mod options;
+pub mod prelude;
 pub(crate) mod protocol;
+pub mod reconnect;
+pub mod resilience;
+pub mod resolve;
 pub(crate) mod ring;
+pub(crate) mod rng;
+pub mod sasl;
+pub mod selftest;
+pub mod snapshot;
+pub mod stats;
+pub mod topology;
+pub mod touch;
+pub mod transform;
+pub mod vbucket;
+pub mod warm;
+pub mod wire;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+#[cfg(any(test, feature = "testing"))]
+pub mod sim;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 #[cfg(feature = "zlib")]
 pub mod zlib;
@@ -0,0 +1,467 @@
+//! When a cache is cold, for example right after a deploy or a restart,
+//! every request misses at once and the servers see a burst of traffic
+//! from every client. [`InFlightLimits`] caps the number of operations in
+//! flight, across every client created from the same config, both globally
+//! and to each node.
+//!
+//! A single-key request is one operation. A bulk request is one operation
+//! for each node it sends a pipeline to.
+//!
+//! When the cache tier itself is the bottleneck, waiting for it only adds
+//! to the latency of every request. A [`LoadShedder`] reports waits for a
+//! client from the pool, or for a slot to a node, that take longer than a
+//! threshold, and can shed operations for a while after a wait beyond a
+//! second threshold, so that reads miss instantly instead of queueing.
+
+use deadpool::managed::Object;
+use futures::future::poll_fn;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::client::{ClientConfig, Compressor, Connection, Error, Pool};
+
+/// What an operation does when the limits are reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Wait until another operation completes. This is the default.
+    #[default]
+    Wait,
+    /// Wait until another operation completes, failing with
+    /// [`Error::Overloaded`] when one completes after the timeout. The core
+    /// has no timer, so the timeout is only checked when an operation
+    /// completes; runtime adapters should also wrap the call in their own
+    /// timeout.
+    WaitFor(Duration),
+    /// Fail immediately with [`Error::Overloaded`].
+    FailFast,
+}
+
+/// Limits on the number of operations in flight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InFlightLimits {
+    /// The maximum number of operations in flight to all nodes.
+    pub global: Option<usize>,
+    /// The maximum number of operations in flight to each node.
+    pub per_node: Option<usize>,
+    /// What an operation does when a limit is reached.
+    pub queue: QueuePolicy,
+}
+
+impl InFlightLimits {
+    /// Create limits which do not limit anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of operations in flight to all nodes.
+    pub fn with_global(mut self, max: usize) -> Self {
+        self.global = Some(max.max(1));
+        self
+    }
+
+    /// Limit the number of operations in flight to each node.
+    pub fn with_per_node(mut self, max: usize) -> Self {
+        self.per_node = Some(max.max(1));
+        self
+    }
+
+    /// Choose what operations do when a limit is reached.
+    pub fn with_queue_policy(mut self, queue: QueuePolicy) -> Self {
+        self.queue = queue;
+        self
+    }
+}
+
+/// What an operation waited for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitSource<'a> {
+    /// A client from the pool, see [`LoadShedder::get`].
+    Pool,
+    /// A slot for an operation on the node with the endpoint.
+    Node(&'a str),
+}
+
+/// A callback receiving every wait longer than the warning threshold of a
+/// [`LoadShedder`].
+pub type WaitCallback = Arc<dyn Fn(WaitSource<'_>, Duration) + Send + Sync>;
+
+/// Reports slow waits for the pool or for nodes, and sheds operations
+/// after waits which are too slow. It is shared by every client created
+/// from the same config.
+#[derive(Clone)]
+pub struct LoadShedder {
+    warn_after: Duration,
+    shed_after: Option<Duration>,
+    cooldown: Duration,
+    on_wait: Option<WaitCallback>,
+    shedding: Arc<Mutex<HashMap<Option<String>, Instant>>>,
+    shed: Arc<AtomicU64>,
+}
+
+impl Debug for LoadShedder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("LoadShedder")
+            .field("warn_after", &self.warn_after)
+            .field("shed_after", &self.shed_after)
+            .field("cooldown", &self.cooldown)
+            .finish()
+    }
+}
+
+impl LoadShedder {
+    /// Report every wait longer than `warn_after`, without shedding.
+    pub fn new(warn_after: Duration) -> Self {
+        Self {
+            warn_after,
+            shed_after: None,
+            cooldown: Duration::from_secs(1),
+            on_wait: None,
+            shedding: Arc::new(Mutex::new(HashMap::new())),
+            shed: Arc::default(),
+        }
+    }
+
+    /// Call the callback with every wait longer than the warning threshold.
+    pub fn with_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(WaitSource<'_>, Duration) + Send + Sync + 'static,
+    {
+        self.on_wait = Some(Arc::new(callback));
+        self
+    }
+
+    /// Shed operations waiting for the same pool or node for the cooldown
+    /// after any wait for it took longer than `shed_after`. Shed reads miss,
+    /// and shed writes fail with [`Error::Shed`], without waiting.
+    pub fn with_shedding(mut self, shed_after: Duration) -> Self {
+        self.shed_after = Some(shed_after);
+        self
+    }
+
+    /// Shed operations for this long after a wait beyond the shedding
+    /// threshold. Defaults to a second.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Whether operations waiting for the source are currently shed, on the
+    /// system clock.
+    pub fn is_shedding(&self, source: WaitSource<'_>) -> bool {
+        self.is_shedding_at(source, Instant::now())
+    }
+
+    /// Whether operations waiting for the source are shed at `now`.
+    pub(crate) fn is_shedding_at(&self, source: WaitSource<'_>, now: Instant) -> bool {
+        let shedding = self.shedding.lock().unwrap();
+        match shedding.get(&Self::scope(source)) {
+            Some(since) => now.saturating_duration_since(*since) < self.cooldown,
+            None => false,
+        }
+    }
+
+    /// Get a client from the pool, measuring how long it took, or fail
+    /// with [`Error::Shed`] without waiting while the pool is shed.
+    pub async fn get<C: Connection, P: Compressor>(
+        &self,
+        pool: &Pool<C, P>,
+    ) -> Result<Object<ClientConfig<C, P>>, Error> {
+        self.check(WaitSource::Pool, C::now())?;
+        let started = C::now();
+        let client = pool.get().await;
+        let now = C::now();
+        self.record(
+            WaitSource::Pool,
+            now.saturating_duration_since(started),
+            now,
+        );
+        Ok(client?)
+    }
+
+    /// Return [`Error::Shed`] if operations waiting for the source are shed
+    /// at `now`.
+    pub(crate) fn check(&self, source: WaitSource<'_>, now: Instant) -> Result<(), Error> {
+        match self.is_shedding_at(source, now) {
+            true => {
+                self.shed.fetch_add(1, Ordering::Relaxed);
+                Err(Error::Shed)
+            }
+            false => Ok(()),
+        }
+    }
+
+    /// The number of operations shed so far, by every clone.
+    pub(crate) fn shed_count(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    /// Record how long an operation waited for the source, until `now`.
+    pub(crate) fn record(&self, source: WaitSource<'_>, wait: Duration, now: Instant) {
+        if wait <= self.warn_after {
+            return;
+        }
+        if let Some(callback) = &self.on_wait {
+            callback(source, wait);
+        }
+        if self.shed_after.is_some_and(|shed_after| wait > shed_after) {
+            let mut shedding = self.shedding.lock().unwrap();
+            shedding.insert(Self::scope(source), now);
+        }
+    }
+
+    fn scope(source: WaitSource<'_>) -> Option<String> {
+        match source {
+            WaitSource::Pool => None,
+            WaitSource::Node(endpoint) => Some(endpoint.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    waiters: VecDeque<Waker>,
+}
+
+/// Counts the operations in flight against a maximum, and wakes waiting
+/// operations as others complete.
+#[derive(Debug)]
+struct Semaphore {
+    max: usize,
+    state: Mutex<State>,
+}
+
+/// A slot taken from a semaphore, which is released when dropped.
+#[derive(Debug)]
+struct Permit(Arc<Semaphore>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.in_flight -= 1;
+        // Waiters may have given up, so all of them are woken to race for
+        // the slot rather than waking one which may never poll again.
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl Semaphore {
+    fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    async fn acquire<C: Connection>(self: &Arc<Self>, queue: QueuePolicy) -> Result<Permit, Error> {
+        let deadline = match queue {
+            QueuePolicy::WaitFor(timeout) => Some(C::now() + timeout),
+            _ => None,
+        };
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.in_flight < self.max {
+                state.in_flight += 1;
+                return Poll::Ready(Ok(Permit(self.clone())));
+            }
+            let expired = deadline.is_some_and(|deadline| C::now() >= deadline);
+            if queue == QueuePolicy::FailFast || expired {
+                return Poll::Ready(Err(Error::Overloaded));
+            }
+            state.waiters.push_back(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// The slots taken by a single operation.
+#[derive(Debug)]
+pub(crate) struct Permits {
+    _global: Option<Permit>,
+    _node: Option<Permit>,
+}
+
+/// The in-flight limits shared by every client created from a config.
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    pub(crate) limits: InFlightLimits,
+    pub(crate) shedder: Option<LoadShedder>,
+    global: Option<Arc<Semaphore>>,
+    nodes: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: InFlightLimits, shedder: Option<LoadShedder>) -> Self {
+        Self {
+            limits,
+            shedder,
+            global: limits.global.map(Semaphore::new),
+            nodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a slot for an operation on the node, waiting or failing as
+    /// configured when a limit is reached, and reporting the wait to the
+    /// load shedder.
+    pub(crate) async fn acquire<C: Connection>(&self, endpoint: &str) -> Result<Permits, Error> {
+        let shedder = match &self.shedder {
+            Some(shedder) => shedder,
+            None => return self.acquire_slots::<C>(endpoint).await,
+        };
+        shedder.check(WaitSource::Node(endpoint), C::now())?;
+        let started = C::now();
+        let permits = self.acquire_slots::<C>(endpoint).await;
+        let now = C::now();
+        shedder.record(
+            WaitSource::Node(endpoint),
+            now.saturating_duration_since(started),
+            now,
+        );
+        permits
+    }
+
+    async fn acquire_slots<C: Connection>(&self, endpoint: &str) -> Result<Permits, Error> {
+        let node = self.limits.per_node.map(|max| {
+            let mut nodes = self.nodes.lock().unwrap();
+            let semaphore = nodes.entry(endpoint.to_string());
+            semaphore.or_insert_with(|| Semaphore::new(max)).clone()
+        });
+        let queue = self.limits.queue;
+        let global = match &self.global {
+            Some(global) => Some(global.acquire::<C>(queue).await?),
+            None => None,
+        };
+        let node = match &node {
+            Some(node) => Some(node.acquire::<C>(queue).await?),
+            None => None,
+        };
+        Ok(Permits {
+            _global: global,
+            _node: node,
+        })
+    }
+}
+
+/// Take a slot for an operation on the node, if there are limits.
+pub(crate) async fn acquire<C: Connection>(
+    limiter: Option<&Limiter>,
+    endpoint: &str,
+) -> Result<Option<Permits>, Error> {
+    match limiter {
+        Some(limiter) => Ok(Some(limiter.acquire::<C>(endpoint).await?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future::join, FutureExt};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        client::{Client, ClientConfig, Error, Pool},
+        mock::MockConnection,
+    };
+
+    use super::{InFlightLimits, Limiter, LoadShedder, QueuePolicy, WaitSource};
+
+    #[test]
+    fn test_in_flight_limits() {
+        tokio_test::block_on(async {
+            let limits = InFlightLimits::new().with_global(3).with_per_node(1);
+            let limiter = Limiter::new(limits.with_queue_policy(QueuePolicy::FailFast), None);
+            let a = limiter.acquire::<MockConnection>("a").await.unwrap();
+            let err = limiter.acquire::<MockConnection>("a").await.unwrap_err();
+            assert!(matches!(err, Error::Overloaded));
+            let b = limiter.acquire::<MockConnection>("b").await.unwrap();
+            let _c = limiter.acquire::<MockConnection>("c").await.unwrap();
+            let err = limiter.acquire::<MockConnection>("d").await.unwrap_err();
+            assert!(matches!(err, Error::Overloaded));
+            drop((a, b));
+            limiter.acquire::<MockConnection>("a").await.unwrap();
+
+            // Waiting operations proceed once the slot is released.
+            let limiter = Limiter::new(limits, None);
+            let held = limiter.acquire::<MockConnection>("a").await.unwrap();
+            let mut waiting = limiter.acquire::<MockConnection>("a").boxed();
+            assert!((&mut waiting).now_or_never().is_none());
+            let release = async move { drop(held) };
+            let (permits, _) = join(waiting, release).await;
+            assert!(permits.is_ok());
+
+            // Bulk requests to several nodes queue for the single slot.
+            let endpoints = vec!["limit:1".into(), "limit:2".into(), "limit:3".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints)
+                .with_in_flight_limits(InFlightLimits::new().with_global(1));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            let data = keys.iter().map(|key| (key, key)).collect::<HashMap<_, _>>();
+            assert!(client.set_multi(data, 0).await.unwrap().is_empty());
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(keys.len(), values.len());
+        });
+    }
+    #[test]
+    fn test_load_shedding() {
+        tokio_test::block_on(async {
+            let waits = Arc::new(Mutex::new(vec![]));
+            let log = waits.clone();
+            let shedder = LoadShedder::new(Duration::from_millis(5))
+                .with_shedding(Duration::from_millis(50))
+                .with_cooldown(Duration::from_secs(60))
+                .with_callback(move |source, wait| {
+                    let source = match source {
+                        WaitSource::Pool => "pool".to_string(),
+                        WaitSource::Node(endpoint) => endpoint.to_string(),
+                    };
+                    log.lock().unwrap().push((source, wait));
+                });
+            let cfg = ClientConfig::new_uncompressed(vec!["shed:1".into()])
+                .with_load_shedder(shedder.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+
+            // Only waits beyond the thresholds are reported and shed.
+            let node = WaitSource::Node("shed:1");
+            shedder.record(node, Duration::from_millis(1), Instant::now());
+            shedder.record(node, Duration::from_millis(10), Instant::now());
+            assert!(!shedder.is_shedding(node));
+            shedder.record(node, Duration::from_millis(100), Instant::now());
+            assert!(shedder.is_shedding(node));
+            assert!(!shedder.is_shedding(WaitSource::Pool));
+            assert_eq!(2, waits.lock().unwrap().len());
+
+            // Shed reads miss and shed writes fail, without waiting.
+            assert_eq!(None, client.get::<_, String>("key").await.unwrap());
+            let err = client.set("key", "value", 0).await.unwrap_err();
+            assert!(matches!(err, Error::Shed));
+            let (values, errors) = client.get_multi::<_, String>(&["key"]).await.unwrap();
+            assert!(values.is_empty() && errors.is_empty());
+
+            let pool = Pool::builder(cfg).max_size(1).build().unwrap();
+            assert!(shedder.get(&pool).await.is_ok());
+            shedder.record(WaitSource::Pool, Duration::from_millis(100), Instant::now());
+            assert!(matches!(shedder.get(&pool).await, Err(Error::Shed)));
+            assert_eq!(
+                ("pool".to_string(), Duration::from_millis(100)),
+                waits.lock().unwrap()[2]
+            );
+            assert_eq!(4, shedder.shed_count());
+        });
+    }
+}
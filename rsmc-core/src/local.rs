@@ -0,0 +1,345 @@
+//! A local tier keeps recently read values in process memory in front of
+//! memcached, trading freshness for latency. Entries are only served for a
+//! short TTL, since another instance may change or delete the key in
+//! memcached at any time:
+//!
+//! - Writes and deletes through the client drop the local entry, and once
+//!   they complete, drop it again and report an [`Invalidation`] to the
+//!   callback, which is the place to broadcast the key to other instances.
+//! - Entries older than the local TTL are never served. The next read goes
+//!   to memcached, and if memcached says the key is gone, the expired entry
+//!   is dropped and reported as [`Invalidation::RemoteMiss`].
+//! - Every invalidated key is also published to the [`InvalidationBus`] of
+//!   the tier, which drops the entries held by other instances.
+//!
+//! With [`LocalTier::with_stale_if_error`], expired entries which are still
+//! held are served when the read from memcached fails, rather than misses,
+//! which keeps hot keys available through a brief outage of a node.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bus::{InvalidationBus, NoopBus},
+    protocol::Packet,
+};
+
+/// Why a key was invalidated in the local tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidation {
+    /// The key was deleted through this client.
+    Deleted,
+    /// The key was written through this client.
+    Overwritten,
+    /// The local tier held an entry for a key that memcached no longer has,
+    /// because it was deleted elsewhere, expired or was evicted.
+    RemoteMiss,
+}
+
+/// A callback receiving every key invalidated in the local tier.
+pub type InvalidationCallback = Arc<dyn Fn(&[u8], Invalidation) + Send + Sync>;
+
+#[derive(Debug, Default)]
+struct Entries {
+    values: HashMap<Vec<u8>, (Packet, Instant)>,
+    order: VecDeque<Vec<u8>>,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &[u8]) -> bool {
+        self.order.retain(|k| k != key);
+        self.values.remove(key).is_some()
+    }
+}
+
+/// An in-process cache of values read from memcached, shared by every
+/// client created from the same config.
+#[derive(Clone)]
+pub struct LocalTier {
+    capacity: usize,
+    ttl: Duration,
+    stale_if_error: Option<Duration>,
+    entries: Arc<Mutex<Entries>>,
+    on_invalidate: Option<InvalidationCallback>,
+    bus: Arc<dyn InvalidationBus>,
+}
+
+impl Debug for LocalTier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("LocalTier")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("stale_if_error", &self.stale_if_error)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl LocalTier {
+    /// Keep up to `capacity` values, each served for at most `ttl` after it
+    /// was read from memcached. The oldest values are evicted first.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            stale_if_error: None,
+            entries: Arc::new(Mutex::new(Entries::default())),
+            on_invalidate: None,
+            bus: Arc::new(NoopBus),
+        }
+    }
+
+    /// Serve entries read from memcached up to `max_staleness` ago, even
+    /// past their TTL, when reading the key from memcached fails or its node
+    /// fails open. Misses are never served stale, and entries invalidated by
+    /// writes are gone, so only values that memcached may still hold are
+    /// served.
+    pub fn with_stale_if_error(mut self, max_staleness: Duration) -> Self {
+        self.stale_if_error = Some(max_staleness);
+        self
+    }
+
+    /// Call the callback with every key invalidated in the local tier.
+    pub fn with_invalidation_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&[u8], Invalidation) + Send + Sync + 'static,
+    {
+        self.on_invalidate = Some(Arc::new(callback));
+        self
+    }
+
+    /// Publish every key invalidated in the local tier to the bus, and drop
+    /// the keys published by other instances.
+    pub fn with_invalidation_bus(mut self, bus: Arc<dyn InvalidationBus>) -> Self {
+        let entries = self.entries.clone();
+        bus.subscribe(Arc::new(move |key| {
+            entries.lock().unwrap().remove(key);
+        }));
+        self.bus = bus;
+        self
+    }
+
+    /// The number of entries held, including expired ones.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().values.len()
+    }
+
+    /// Whether no entries are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the entry for a key, for example when another instance reports
+    /// that it changed, without calling the invalidation callback.
+    pub fn remove(&self, key: &[u8]) -> bool {
+        self.entries.lock().unwrap().remove(key)
+    }
+
+    /// Drop every entry.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.values.clear();
+        entries.order.clear();
+    }
+
+    /// Get the response for a key, unless it is missing or expired at
+    /// `now`.
+    pub(crate) fn get(&self, key: &[u8], now: Instant) -> Option<Packet> {
+        let entries = self.entries.lock().unwrap();
+        match entries.values.get(key) {
+            Some((packet, stored_at)) if now.saturating_duration_since(*stored_at) < self.ttl => {
+                Some(packet.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the response for a key after reading it from memcached failed,
+    /// unless it is missing or older than the max staleness at `now`.
+    pub(crate) fn get_stale(&self, key: &[u8], now: Instant) -> Option<Packet> {
+        let max_staleness = self.stale_if_error?;
+        let entries = self.entries.lock().unwrap();
+        match entries.values.get(key) {
+            Some((packet, stored_at))
+                if now.saturating_duration_since(*stored_at) < max_staleness =>
+            {
+                Some(packet.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Store the response read from memcached for a key at `now`.
+    pub(crate) fn insert(&self, key: &[u8], packet: Packet, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.values.contains_key(key) {
+            entries.order.retain(|k| k != key);
+        }
+        while entries.values.len() >= self.capacity {
+            match entries.order.pop_front() {
+                Some(oldest) => entries.values.remove(&oldest),
+                None => break,
+            };
+        }
+        entries.order.push_back(key.to_vec());
+        entries.values.insert(key.to_vec(), (packet, now));
+    }
+
+    /// Drop the entry for a key and report why. Remote misses are only
+    /// reported when an entry was actually held.
+    pub(crate) fn invalidate(&self, key: &[u8], reason: Invalidation) {
+        let removed = self.remove(key);
+        if removed || reason != Invalidation::RemoteMiss {
+            if let Some(callback) = &self.on_invalidate {
+                callback(key, reason);
+            }
+            self.bus.publish(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::{Failure, MockConnection, Store},
+        options::{ReadPreference, RequestOptions},
+    };
+
+    use super::{Invalidation, LocalTier};
+
+    #[test]
+    fn test_stale_if_error() {
+        tokio_test::block_on(async {
+            let local = LocalTier::new(2, Duration::from_secs(0));
+            let stale = local.clone().with_stale_if_error(Duration::from_secs(60));
+            let connect = |local: LocalTier| {
+                let cfg = ClientConfig::new_uncompressed(vec!["local:stale".into()]);
+                Client::<MockConnection, _>::new(cfg.with_local_tier(local))
+            };
+            let mut client = connect(stale).await.unwrap();
+            let mut strict = connect(local).await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            assert_eq!(Some("1".to_string()), client.get("a").await.unwrap());
+
+            // Expired entries are only served when the read fails.
+            let store = Store::get("local:stale");
+            store.lock().unwrap().fail(b"a", Failure::Disconnect);
+            assert_eq!(Some("1".to_string()), client.get("a").await.unwrap());
+            assert!(strict.get::<_, String>("a").await.is_err());
+            store.lock().unwrap().clear_failures();
+
+            // Misses are never served stale.
+            client.delete("a").await.unwrap();
+            assert_eq!(None, client.get::<_, String>("a").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_invalidate_after_write() {
+        tokio_test::block_on(async {
+            // Record whether memcached held the key when it was reported, to
+            // check that keys are reported once the write completed.
+            let invalidated = Arc::new(Mutex::new(vec![]));
+            let log = invalidated.clone();
+            let local = LocalTier::new(2, Duration::from_secs(60)).with_invalidation_callback(
+                move |key, reason| {
+                    let held = Store::get("local:order").lock().unwrap().expire(key);
+                    log.lock()
+                        .unwrap()
+                        .push((key.to_vec(), reason, held.is_some()));
+                },
+            );
+            let cfg =
+                ClientConfig::new_uncompressed(vec!["local:order".into()]).with_local_tier(local);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            let data = std::collections::HashMap::from([("b", "1")]);
+            assert!(client.set_multi(data, 0).await.unwrap().is_empty());
+            client.delete("a").await.unwrap();
+            assert!(client.delete_multi(&["b"]).await.unwrap().is_empty());
+
+            assert_eq!(
+                vec![
+                    (b"a".to_vec(), Invalidation::Overwritten, true),
+                    (b"b".to_vec(), Invalidation::Overwritten, true),
+                    (b"a".to_vec(), Invalidation::Deleted, false),
+                    (b"b".to_vec(), Invalidation::Deleted, false),
+                ],
+                *invalidated.lock().unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn test_local_tier() {
+        tokio_test::block_on(async {
+            let invalidated = Arc::new(Mutex::new(vec![]));
+            let log = invalidated.clone();
+            let local = LocalTier::new(2, Duration::from_secs(60)).with_invalidation_callback(
+                move |key, reason| log.lock().unwrap().push((key.to_vec(), reason)),
+            );
+            let cfg =
+                ClientConfig::new_uncompressed(vec!["local".into()]).with_local_tier(local.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            assert_eq!(Some("1".to_string()), client.get("a").await.unwrap());
+            assert_eq!(1, local.len());
+
+            // Served locally, even after another instance deleted the key.
+            let mut other = Client::<MockConnection, _>::new(ClientConfig::new_uncompressed(vec![
+                "local".into(),
+            ]))
+            .await
+            .unwrap();
+            other.delete("a").await.unwrap();
+            let local_only = RequestOptions::new().with_read_preference(ReadPreference::LocalOnly);
+            let value = client.get_with_options::<_, String>("a", &local_only);
+            assert_eq!(Some("1".to_string()), value.await.unwrap());
+            let skip = RequestOptions::new().with_skip_local_tier(true);
+            let value = client.get_with_options::<_, String>("a", &skip);
+            assert_eq!(None, value.await.unwrap());
+
+            // Writes and deletes through the client invalidate the entry.
+            client.set("a", "2", 0).await.unwrap();
+            assert_eq!(Some("2".to_string()), client.get("a").await.unwrap());
+            client.delete("a").await.unwrap();
+            assert!(local.is_empty());
+
+            // Expired entries are dropped when memcached misses.
+            let expired = LocalTier::new(2, Duration::from_secs(0));
+            let cfg = ClientConfig::new_uncompressed(vec!["local".into()])
+                .with_local_tier(expired.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("b", "1", 0).await.unwrap();
+            assert_eq!(Some("1".to_string()), client.get("b").await.unwrap());
+            assert_eq!(1, expired.len());
+            other.delete("b").await.unwrap();
+            assert_eq!(None, client.get::<_, String>("b").await.unwrap());
+            assert!(expired.is_empty());
+
+            let invalidated = invalidated.lock().unwrap();
+            assert_eq!(
+                vec![
+                    (b"a".to_vec(), Invalidation::Overwritten),
+                    (b"a".to_vec(), Invalidation::RemoteMiss),
+                    (b"a".to_vec(), Invalidation::Overwritten),
+                    (b"a".to_vec(), Invalidation::Deleted),
+                ],
+                *invalidated
+            );
+        });
+    }
+}
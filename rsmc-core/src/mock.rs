@@ -0,0 +1,547 @@
+//! An in-memory memcached server speaking the binary protocol, used to test
+//! the client without touching the network. Connections to the same url
+//! share the same store, so a cluster can be simulated by connecting to
+//! several distinct urls. Enable the `testing` feature to use it outside of
+//! this crate.
+
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::TryInto,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    client::{Connection, Error},
+    protocol::{
+        Packet, Status, ADDQ_OPCODE, ADD_OPCODE, DECREMENTQ_OPCODE, DECREMENT_OPCODE,
+        DELETEQ_OPCODE, DELETE_OPCODE, FLUSH_OPCODE, GATKQ_OPCODE, GAT_OPCODE, GETKQ_OPCODE,
+        GETK_OPCODE, GETQ_OPCODE, GET_OPCODE, INCREMENTQ_OPCODE, INCREMENT_OPCODE,
+        MAGIC_REQUEST_VALUE, MAGIC_RESPONSE_VALUE, NOOP_OPCODE, QUIT_OPCODE, REPLACEQ_OPCODE,
+        REPLACE_OPCODE, SASL_AUTH_OPCODE, SASL_STEP_OPCODE, SETQ_OPCODE, SET_OPCODE, STAT_OPCODE,
+        TOUCH_OPCODE, VERSION_OPCODE,
+    },
+};
+
+const KEY_NOT_FOUND: u16 = 0x01;
+const KEY_EXISTS: u16 = 0x02;
+const ITEM_NOT_STORED: u16 = 0x05;
+const NON_NUMERIC: u16 = 0x06;
+const AUTH_ERROR: u16 = 0x20;
+const AUTH_CONTINUE: u16 = 0x21;
+const UNKNOWN_COMMAND: u16 = 0x81;
+
+static STORES: Mutex<Option<HashMap<String, Arc<Mutex<Store>>>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    value: Vec<u8>,
+    flags: u32,
+    expire: u32,
+    cas: u64,
+}
+
+/// A canned failure returned by a mock server for requests to a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    /// Respond with the given error status.
+    Status(Status),
+    /// Close the connection without responding.
+    Disconnect,
+    /// Apply the request, then close the connection without responding, as
+    /// if the response was lost.
+    LoseReply,
+}
+
+/// The data held by a single mock memcached server.
+#[derive(Debug, Default)]
+pub struct Store {
+    items: HashMap<Vec<u8>, Item>,
+    failures: HashMap<Vec<u8>, Failure>,
+    credentials: Option<Vec<u8>>,
+    next_cas: u64,
+    quits: usize,
+}
+
+impl Store {
+    /// Get the store shared by all connections to the given url.
+    pub fn get(url: &str) -> Arc<Mutex<Store>> {
+        let mut stores = STORES.lock().unwrap();
+        let stores = stores.get_or_insert_with(HashMap::new);
+        stores.entry(url.to_string()).or_default().clone()
+    }
+
+    /// Get the expiration a key was last stored or touched with.
+    pub fn expire(&self, key: &[u8]) -> Option<u32> {
+        self.items.get(key).map(|item| item.expire)
+    }
+
+    /// The number of connections which quit cleanly, over either protocol.
+    pub fn quits(&self) -> usize {
+        self.quits
+    }
+
+    /// Fail every request to the key until the failure is cleared.
+    pub fn fail(&mut self, key: &[u8], failure: Failure) {
+        self.failures.insert(key.to_vec(), failure);
+    }
+
+    /// Stop failing requests to every key.
+    pub fn clear_failures(&mut self) {
+        self.failures.clear();
+    }
+
+    /// Refuse every request but VERSION with an authentication error until
+    /// the connection has authenticated with these credentials using SASL
+    /// PLAIN.
+    pub fn require_auth(&mut self, username: &str, password: &str) {
+        let credentials = format!("\0{}\0{}", username, password);
+        self.credentials = Some(credentials.into_bytes());
+    }
+
+    /// Handle a SASL request. Only PLAIN is supported, and an empty initial
+    /// response is answered with an empty challenge, as RFC 4616 allows.
+    fn authenticate(&self, req: &Packet, authenticated: &AtomicBool) -> u16 {
+        match (req.key.as_slice(), &self.credentials) {
+            (b"PLAIN", _) if req.value.is_empty() => AUTH_CONTINUE,
+            (b"PLAIN", Some(credentials)) if &req.value != credentials => AUTH_ERROR,
+            (b"PLAIN", _) => {
+                authenticated.store(true, Ordering::Relaxed);
+                0
+            }
+            _ => AUTH_ERROR,
+        }
+    }
+
+    fn stats(&self, group: &[u8]) -> Vec<(String, String)> {
+        match group {
+            b"" => vec![
+                ("version".into(), "1.6.9".into()),
+                ("curr_items".into(), self.items.len().to_string()),
+            ],
+            b"settings" => vec![("item_size_max".into(), "1048576".into())],
+            // Every item lives in a single slab class, and never expires.
+            b"slabs" => vec![
+                ("1:chunk_size".into(), "96".into()),
+                ("1:used_chunks".into(), self.items.len().to_string()),
+                ("active_slabs".into(), "1".into()),
+            ],
+            b"items" => vec![
+                ("items:1:number".into(), self.items.len().to_string()),
+                ("items:1:age".into(), "0".into()),
+                ("items:1:evicted".into(), "0".into()),
+            ],
+            // Counts the items stored under each prefix as sets.
+            b"detail dump" => {
+                let mut sets = BTreeMap::new();
+                for key in self.items.keys() {
+                    let key = String::from_utf8_lossy(key);
+                    if let Some((prefix, _)) = key.split_once(':') {
+                        *sets.entry(prefix.to_string()).or_insert(0) += 1;
+                    }
+                }
+                let mut dump = String::new();
+                for (prefix, sets) in sets {
+                    let line = format!("PREFIX {} get 0 hit 0 set {} del 0\r\n", prefix, sets);
+                    dump.push_str(&line);
+                }
+                dump.push_str("END\r\n");
+                vec![("detailed".into(), dump)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn store(&mut self, key: Vec<u8>, value: Vec<u8>, flags: u32, expire: u32) -> u64 {
+        self.next_cas += 1;
+        let cas = self.next_cas;
+        let item = Item {
+            value,
+            flags,
+            expire,
+            cas,
+        };
+        self.items.insert(key, item);
+        cas
+    }
+
+    fn handle(&mut self, req: Packet, authenticated: &AtomicBool) -> Option<Packet> {
+        let opcode = req.header.opcode;
+        let failure = match self.failures.get(&req.key) {
+            Some(Failure::Status(status)) if req.has_item_key() => Some(*status),
+            _ => None,
+        };
+        let quiet = matches!(
+            opcode,
+            GETQ_OPCODE
+                | GETKQ_OPCODE
+                | GATKQ_OPCODE
+                | SETQ_OPCODE
+                | ADDQ_OPCODE
+                | REPLACEQ_OPCODE
+                | DELETEQ_OPCODE
+                | INCREMENTQ_OPCODE
+                | DECREMENTQ_OPCODE
+        );
+        let with_key = matches!(opcode, GETK_OPCODE | GETKQ_OPCODE | GATKQ_OPCODE);
+        let mut res = Packet::default();
+        res.header.magic = MAGIC_RESPONSE_VALUE;
+        res.header.opcode = opcode;
+        res.header.opaque = req.header.opaque;
+
+        let locked = self.credentials.is_some() && !authenticated.load(Ordering::Relaxed);
+        let status = match opcode {
+            SASL_AUTH_OPCODE | SASL_STEP_OPCODE => self.authenticate(&req, authenticated),
+            QUIT_OPCODE => {
+                self.quits += 1;
+                0
+            }
+            _ if locked && opcode != VERSION_OPCODE => AUTH_ERROR,
+            _ if failure.is_some() => failure.map_or(0, u16::from),
+            GET_OPCODE | GETQ_OPCODE | GETK_OPCODE | GETKQ_OPCODE => {
+                match self.items.get(&req.key) {
+                    Some(item) => {
+                        res.extras = item.flags.to_be_bytes().to_vec();
+                        res.value = item.value.clone();
+                        res.header.cas = item.cas;
+                        0
+                    }
+                    None if quiet => return None,
+                    None => KEY_NOT_FOUND,
+                }
+            }
+            GAT_OPCODE | GATKQ_OPCODE => {
+                let expire = u32::from_be_bytes(req.extras[0..4].try_into().unwrap());
+                match self.items.get_mut(&req.key) {
+                    Some(item) => {
+                        item.expire = expire;
+                        res.extras = item.flags.to_be_bytes().to_vec();
+                        res.value = item.value.clone();
+                        res.header.cas = item.cas;
+                        0
+                    }
+                    None if quiet => return None,
+                    None => KEY_NOT_FOUND,
+                }
+            }
+            SET_OPCODE | SETQ_OPCODE | ADD_OPCODE | ADDQ_OPCODE | REPLACE_OPCODE
+            | REPLACEQ_OPCODE => {
+                let flags = u32::from_be_bytes(req.extras[0..4].try_into().unwrap());
+                let expire = u32::from_be_bytes(req.extras[4..8].try_into().unwrap());
+                let existing = self.items.get(&req.key).map(|item| item.cas);
+                let is_add = matches!(opcode, ADD_OPCODE | ADDQ_OPCODE);
+                let is_replace = matches!(opcode, REPLACE_OPCODE | REPLACEQ_OPCODE);
+                match existing {
+                    Some(_) if is_add => KEY_EXISTS,
+                    None if is_replace => ITEM_NOT_STORED,
+                    None if req.header.cas != 0 => KEY_NOT_FOUND,
+                    Some(cas) if req.header.cas != 0 && req.header.cas != cas => KEY_EXISTS,
+                    _ => {
+                        let key = req.key.clone();
+                        res.header.cas = self.store(key, req.value.clone(), flags, expire);
+                        0
+                    }
+                }
+            }
+            DELETE_OPCODE | DELETEQ_OPCODE => match self.items.remove(&req.key) {
+                Some(_) => 0,
+                None => KEY_NOT_FOUND,
+            },
+            INCREMENT_OPCODE | INCREMENTQ_OPCODE | DECREMENT_OPCODE | DECREMENTQ_OPCODE => {
+                let delta = u64::from_be_bytes(req.extras[0..8].try_into().unwrap());
+                let initial = u64::from_be_bytes(req.extras[8..16].try_into().unwrap());
+                let expire = u32::from_be_bytes(req.extras[16..20].try_into().unwrap());
+                let incr = matches!(opcode, INCREMENT_OPCODE | INCREMENTQ_OPCODE);
+                let current = self.items.get(&req.key).map(|item| {
+                    let text = String::from_utf8_lossy(&item.value).to_string();
+                    (text.parse::<u64>().ok(), item.flags, item.expire)
+                });
+                let next = match current {
+                    None if expire == u32::MAX => Err(KEY_NOT_FOUND),
+                    None => Ok((initial, 0, expire)),
+                    Some((None, _, _)) => Err(NON_NUMERIC),
+                    Some((Some(n), flags, expire)) if incr => {
+                        Ok((n.wrapping_add(delta), flags, expire))
+                    }
+                    Some((Some(n), flags, expire)) => Ok((n.saturating_sub(delta), flags, expire)),
+                };
+                match next {
+                    Ok((n, flags, expire)) => {
+                        let value = n.to_string().into_bytes();
+                        res.header.cas = self.store(req.key.clone(), value, flags, expire);
+                        res.value = n.to_be_bytes().to_vec();
+                        0
+                    }
+                    Err(status) => status,
+                }
+            }
+            TOUCH_OPCODE => {
+                let expire = u32::from_be_bytes(req.extras[0..4].try_into().unwrap());
+                match self.items.get_mut(&req.key) {
+                    Some(item) => {
+                        item.expire = expire;
+                        0
+                    }
+                    None => KEY_NOT_FOUND,
+                }
+            }
+            FLUSH_OPCODE => {
+                // The mock has no clock, so delayed flushes never happen.
+                let delay = req.extras.get(0..4).map(|bytes| bytes.try_into().unwrap());
+                if delay.map_or(0, u32::from_be_bytes) == 0 {
+                    self.items.clear();
+                }
+                0
+            }
+            NOOP_OPCODE => 0,
+            VERSION_OPCODE => {
+                res.value = b"1.6.9".to_vec();
+                0
+            }
+            _ => UNKNOWN_COMMAND,
+        };
+
+        if quiet && status == 0 && !matches!(opcode, GETQ_OPCODE | GETKQ_OPCODE | GATKQ_OPCODE) {
+            return None;
+        }
+        if with_key {
+            res.key = req.key;
+        }
+        if status != 0 {
+            res.extras.clear();
+            res.value.clear();
+        }
+        res.header.vbucket_or_status = status;
+        res.header.key_length = res.key.len() as u16;
+        res.header.extras_length = res.extras.len() as u8;
+        res.header.body_len = (res.extras.len() + res.key.len() + res.value.len()) as u32;
+        Some(res)
+    }
+
+    /// Handle a command of the text protocol, by translating it into a binary
+    /// request and the response back into a text reply. Returns None if the
+    /// connection should be closed instead.
+    fn handle_text(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let end = data.windows(2).position(|w| w == b"\r\n")?;
+        let line = String::from_utf8_lossy(&data[..end]).to_string();
+        let words = line.split(' ').collect::<Vec<_>>();
+        let block = data.get(end + 2..data.len() - 2).unwrap_or_default();
+        let word = |i: usize| words.get(i).copied().unwrap_or_default();
+        let num = |i: usize| word(i).parse::<u64>().unwrap_or_default();
+        let (key, mut extras, mut value, mut cas) = (word(1), vec![], vec![], 0);
+        if matches!(word(0), "get" | "gets") {
+            let mut reply = vec![];
+            for key in &words[1..] {
+                let mut req = Packet::default();
+                req.header.opcode = GETK_OPCODE;
+                req.key = key.as_bytes().to_vec();
+                let res = self.handle(req, &AtomicBool::new(true))?;
+                if res.header.vbucket_or_status == 0 {
+                    let flags = u32::from_be_bytes(res.extras[0..4].try_into().unwrap());
+                    let (len, cas) = (res.value.len(), res.header.cas);
+                    let header = format!("VALUE {} {} {} {}\r\n", key, flags, len, cas);
+                    reply.extend([header.as_bytes(), &res.value, b"\r\n"].concat());
+                }
+            }
+            reply.extend_from_slice(b"END\r\n");
+            return Some(reply);
+        }
+        let opcode = match word(0) {
+            "gats" => {
+                extras.extend((num(1) as u32).to_be_bytes());
+                GAT_OPCODE
+            }
+            command @ ("set" | "add" | "replace" | "cas") => {
+                extras.extend((num(2) as u32).to_be_bytes());
+                extras.extend((num(3) as u32).to_be_bytes());
+                value = block.to_vec();
+                cas = num(5);
+                match command {
+                    "add" => ADD_OPCODE,
+                    "replace" => REPLACE_OPCODE,
+                    _ => SET_OPCODE,
+                }
+            }
+            "delete" => DELETE_OPCODE,
+            command @ ("incr" | "decr") => {
+                extras.extend(num(2).to_be_bytes());
+                extras.extend(0_u64.to_be_bytes());
+                extras.extend(u32::MAX.to_be_bytes());
+                match command {
+                    "incr" => INCREMENT_OPCODE,
+                    _ => DECREMENT_OPCODE,
+                }
+            }
+            "touch" => {
+                extras.extend((num(2) as u32).to_be_bytes());
+                TOUCH_OPCODE
+            }
+            "flush_all" => {
+                extras.extend((num(1) as u32).to_be_bytes());
+                FLUSH_OPCODE
+            }
+            "version" => VERSION_OPCODE,
+            "quit" => {
+                self.quits += 1;
+                return None;
+            }
+            "stats" => {
+                let group = line.strip_prefix("stats").unwrap_or_default().trim();
+                let mut reply = String::new();
+                for (name, value) in self.stats(group.as_bytes()) {
+                    match group {
+                        // The dump is sent as is, terminated by its own END.
+                        "detail dump" => return Some(value.into_bytes()),
+                        _ => reply.push_str(&format!("STAT {} {}\r\n", name, value)),
+                    }
+                }
+                return Some(format!("{}END\r\n", reply).into_bytes());
+            }
+            _ => return Some(b"ERROR\r\n".to_vec()),
+        };
+        let key = match opcode {
+            GAT_OPCODE => word(2),
+            _ => key,
+        };
+        let mut req = Packet::default();
+        req.header.opcode = opcode;
+        req.header.cas = cas;
+        req.key = key.as_bytes().to_vec();
+        req.extras = extras;
+        req.value = value;
+        let res = self.handle(req, &AtomicBool::new(true))?;
+
+        let reply = match (opcode, res.header.vbucket_or_status) {
+            (GAT_OPCODE, 0) => {
+                let flags = u32::from_be_bytes(res.extras[0..4].try_into().unwrap());
+                let (len, cas) = (res.value.len(), res.header.cas);
+                let header = format!("VALUE {} {} {} {}\r\n", key, flags, len, cas);
+                let reply = [header.as_bytes(), &res.value, b"\r\nEND\r\n"].concat();
+                return Some(reply);
+            }
+            (GAT_OPCODE, KEY_NOT_FOUND) => "END".to_string(),
+            (ADD_OPCODE, KEY_EXISTS) | (_, ITEM_NOT_STORED) => "NOT_STORED".to_string(),
+            (_, KEY_EXISTS) => "EXISTS".to_string(),
+            (_, KEY_NOT_FOUND) => "NOT_FOUND".to_string(),
+            (_, NON_NUMERIC) => {
+                "CLIENT_ERROR cannot increment or decrement non-numeric value".into()
+            }
+            (SET_OPCODE | ADD_OPCODE | REPLACE_OPCODE, 0) => "STORED".to_string(),
+            (DELETE_OPCODE, 0) => "DELETED".to_string(),
+            (INCREMENT_OPCODE | DECREMENT_OPCODE, 0) => {
+                u64::from_be_bytes(res.value[..].try_into().unwrap()).to_string()
+            }
+            (TOUCH_OPCODE, 0) => "TOUCHED".to_string(),
+            (FLUSH_OPCODE, 0) => "OK".to_string(),
+            (VERSION_OPCODE, 0) => format!("VERSION {}", String::from_utf8_lossy(&res.value)),
+            (_, status) => format!("SERVER_ERROR {}", Status::from(status)),
+        };
+        Some(format!("{}\r\n", reply).into_bytes())
+    }
+}
+
+/// A connection to an in-memory mock memcached server.
+#[derive(Debug, Clone)]
+pub struct MockConnection {
+    store: Arc<Mutex<Store>>,
+    responses: Arc<Mutex<VecDeque<u8>>>,
+    closed: Arc<AtomicBool>,
+    authenticated: Arc<AtomicBool>,
+}
+
+impl MockConnection {
+    /// Queue raw bytes to be read before any real responses, for example to
+    /// simulate a corrupted stream.
+    pub fn inject(&self, bytes: &[u8]) {
+        self.responses.lock().unwrap().extend(bytes);
+    }
+
+    /// Simulate the server closing the connection: writes are silently
+    /// dropped and reads hit EOF.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.responses.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl Connection for MockConnection {
+    async fn connect(url: String) -> Result<Self, Error> {
+        Ok(MockConnection {
+            store: Store::get(&url),
+            responses: Arc::new(Mutex::new(VecDeque::new())),
+            closed: Arc::new(AtomicBool::new(false)),
+            authenticated: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut responses = self.responses.lock().unwrap();
+        let n = buf.len().min(responses.len());
+        for (dst, src) in buf.iter_mut().zip(responses.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if data.first() != Some(&MAGIC_REQUEST_VALUE) {
+            let reply = self.store.lock().unwrap().handle_text(data);
+            match reply {
+                Some(reply) => self.responses.lock().unwrap().extend(reply),
+                None => self.close(),
+            }
+            return Ok(());
+        }
+        let req = Packet::read_request(data)?;
+        if req.header.opcode == STAT_OPCODE {
+            // Stats are returned as a stream of packets terminated by one
+            // with an empty key.
+            let stats = self.store.lock().unwrap().stats(&req.key);
+            let terminator = (String::new(), String::new());
+            for (key, value) in stats.into_iter().chain(vec![terminator]) {
+                let mut res = Packet::default();
+                res.header.magic = MAGIC_RESPONSE_VALUE;
+                res.header.opcode = STAT_OPCODE;
+                res.header.opaque = req.header.opaque;
+                res.header.key_length = key.len() as u16;
+                res.header.body_len = (key.len() + value.len()) as u32;
+                res.key = key.into_bytes();
+                res.value = value.into_bytes();
+                let bytes: Vec<u8> = res.into();
+                self.responses.lock().unwrap().extend(bytes);
+            }
+            return Ok(());
+        }
+        let failure = match req.has_item_key() {
+            true => self.store.lock().unwrap().failures.get(&req.key).copied(),
+            false => None,
+        };
+        if failure == Some(Failure::Disconnect) {
+            self.close();
+        } else if failure == Some(Failure::LoseReply) {
+            self.store.lock().unwrap().handle(req, &self.authenticated);
+            self.close();
+        } else if let Some(res) = self.store.lock().unwrap().handle(req, &self.authenticated) {
+            let quit = res.header.opcode == QUIT_OPCODE;
+            let bytes: Vec<u8> = res.into();
+            self.responses.lock().unwrap().extend(bytes);
+            if quit {
+                // The reply to a QUIT is still read before EOF.
+                self.closed.store(true, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        self.closed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
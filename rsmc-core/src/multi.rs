@@ -0,0 +1,183 @@
+//! Applications often keep several logical caches, such as sessions, page
+//! fragments and API responses, in separate memcached clusters with their
+//! own settings. A [`MultiCache`] builds a pool for each of them from a
+//! single [`MultiCacheConfig`], and looks them up by name.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+
+use crate::client::{ClientConfig, Compressor, Connection, Error, Pool};
+
+/// The configuration of a single logical cache.
+#[derive(Debug, Clone)]
+pub struct CacheConfig<C: Connection, P: Compressor> {
+    client: ClientConfig<C, P>,
+    default_ttl: u32,
+    max_size: usize,
+}
+
+impl<C: Connection, P: Compressor> CacheConfig<C, P> {
+    /// Create a cache from the config of its clients, which sets the
+    /// endpoints, compressor, envelope and every other client setting.
+    pub fn new(client: ClientConfig<C, P>) -> Self {
+        Self {
+            client,
+            default_ttl: 0,
+            max_size: 16,
+        }
+    }
+
+    /// Set the expiration used by [`Cache::set`]. Defaults to 0, meaning
+    /// values never expire.
+    pub fn with_default_ttl(mut self, ttl: u32) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Set the maximum number of clients in the pool. Defaults to 16.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+/// The configuration of every logical cache, by name.
+#[derive(Debug, Clone)]
+pub struct MultiCacheConfig<C: Connection, P: Compressor> {
+    caches: BTreeMap<String, CacheConfig<C, P>>,
+}
+
+impl<C: Connection, P: Compressor> Default for MultiCacheConfig<C, P> {
+    fn default() -> Self {
+        Self {
+            caches: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C: Connection, P: Compressor> MultiCacheConfig<C, P> {
+    /// Create a config without any caches.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a cache with the given name, replacing any cache with that name.
+    pub fn with_cache<S: Into<String>>(mut self, name: S, cache: CacheConfig<C, P>) -> Self {
+        self.caches.insert(name.into(), cache);
+        self
+    }
+}
+
+/// A pool of clients for a single logical cache, along with its defaults.
+#[derive(Debug, Clone)]
+pub struct Cache<C: Connection, P: Compressor> {
+    pool: Pool<C, P>,
+    default_ttl: u32,
+}
+
+impl<C: Connection, P: Compressor> Cache<C, P> {
+    /// Get the pool of clients, for operations not covered by the cache.
+    pub fn pool(&self) -> &Pool<C, P> {
+        &self.pool
+    }
+
+    /// Get the expiration used by [`Cache::set`].
+    pub fn default_ttl(&self) -> u32 {
+        self.default_ttl
+    }
+
+    /// Get a single value with a client from the pool.
+    pub async fn get<K: AsRef<[u8]>, V: DeserializeOwned>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, Error> {
+        self.pool.get().await?.get(key).await
+    }
+
+    /// Set a single value with a client from the pool, expiring after the
+    /// default TTL of the cache.
+    pub async fn set<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        &self,
+        key: K,
+        data: &V,
+    ) -> Result<(), Error> {
+        let ttl = self.default_ttl;
+        self.pool.get().await?.set(key, data, ttl).await
+    }
+
+    /// Delete a single key with a client from the pool.
+    pub async fn delete<K: AsRef<[u8]>>(&self, key: K) -> Result<(), Error> {
+        self.pool.get().await?.delete(key).await
+    }
+}
+
+/// A registry of logical caches, each with its own pool of clients.
+#[derive(Debug, Clone)]
+pub struct MultiCache<C: Connection, P: Compressor> {
+    caches: BTreeMap<String, Cache<C, P>>,
+}
+
+impl<C: Connection, P: Compressor> MultiCache<C, P> {
+    /// Validate every cache in the config and build its pool. Connections
+    /// are made lazily, when clients are first taken from the pools.
+    pub fn new(config: MultiCacheConfig<C, P>) -> Result<Self, Error> {
+        let mut caches = BTreeMap::new();
+        for (name, cache) in config.caches {
+            cache.client.validate()?;
+            let pool = Pool::builder(cache.client)
+                .max_size(cache.max_size)
+                .build()
+                .map_err(|err| Error::Pool(format!("{}: {}", name, err)))?;
+            let default_ttl = cache.default_ttl;
+            caches.insert(name, Cache { pool, default_ttl });
+        }
+        Ok(Self { caches })
+    }
+
+    /// Get the cache with the given name.
+    pub fn cache(&self, name: &str) -> Option<&Cache<C, P>> {
+        self.caches.get(name)
+    }
+
+    /// Iterate over the names of every cache.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.caches.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{client::ClientConfig, mock::MockConnection};
+
+    use super::{CacheConfig, MultiCache, MultiCacheConfig};
+
+    #[test]
+    fn test_multi_cache() {
+        tokio_test::block_on(async {
+            let sessions = ClientConfig::new_uncompressed(vec!["sessions:11211".into()]);
+            let fragments = ClientConfig::new_uncompressed(vec!["fragments:11211".into()]);
+            let config = MultiCacheConfig::new()
+                .with_cache("sessions", CacheConfig::new(sessions).with_default_ttl(60))
+                .with_cache("fragments", CacheConfig::new(fragments));
+            let caches = MultiCache::<MockConnection, _>::new(config).unwrap();
+            assert_eq!(
+                vec!["fragments", "sessions"],
+                caches.names().collect::<Vec<_>>()
+            );
+            assert!(caches.cache("api").is_none());
+
+            let sessions = caches.cache("sessions").unwrap();
+            assert_eq!(60, sessions.default_ttl());
+            sessions.set("user", "alice").await.unwrap();
+            let user = sessions.get::<_, String>("user").await.unwrap();
+            assert_eq!(Some("alice".to_string()), user);
+
+            let fragments = caches.cache("fragments").unwrap();
+            assert_eq!(None, fragments.get::<_, String>("user").await.unwrap());
+
+            let invalid = ClientConfig::new_uncompressed(vec![]);
+            let config = MultiCacheConfig::new().with_cache("api", CacheConfig::new(invalid));
+            assert!(MultiCache::<MockConnection, _>::new(config).is_err());
+        });
+    }
+}
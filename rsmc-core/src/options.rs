@@ -0,0 +1,209 @@
+//! Per-call options for requests. Knobs which only make sense for some
+//! calls are collected in [`RequestOptions`] and passed to the
+//! `*_with_options` variants of the client methods, instead of adding a new
+//! method for every combination.
+//!
+//! A [`DeadlineSource`] supplies a deadline from the context a request is
+//! made in, such as a task-local set by a runtime adapter, so that the
+//! latency budget of a whole request is respected by every cache operation
+//! made for it without threading options through every call site.
+//!
+//! Requests may carry a short correlation token, or baggage, in the opaque
+//! field of their header, set with [`RequestOptions::with_baggage`]. The
+//! server echoes it back, and the client reports it to the
+//! [`crate::instrument::MetricsHook`] and to trace events with the response,
+//! so operators can match packets in server-side captures with the
+//! application requests that sent them using [`decode_baggage`].
+
+use std::{
+    borrow::Cow,
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use crate::client::Error;
+
+/// Where a read may be served from, trading freshness for latency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadPreference {
+    /// Read from the node owning the key. This is the default.
+    #[default]
+    Primary,
+    /// Read from any copy of the key, which may be stale. Without replicas
+    /// this is the same as [`ReadPreference::Primary`].
+    ReplicaOk,
+    /// Only read from the local tier, without touching the network. Keys
+    /// missing from the local tier, or all keys when there is none, miss.
+    LocalOnly,
+}
+
+/// The bit set in the opaque of every request carrying baggage. Opaques
+/// chosen by the client itself never set it.
+pub(crate) const BAGGAGE_FLAG: u32 = 1 << 31;
+
+/// The largest correlation token which fits in an opaque.
+pub const MAX_BAGGAGE: u16 = 0x7fff;
+
+/// Build the opaque of a request carrying baggage. The lower 16 bits keep a
+/// sequence number from the connection, so that responses to concurrent
+/// requests with the same baggage can still be told apart.
+pub(crate) fn encode_baggage(baggage: u16, sequence: u32) -> u32 {
+    BAGGAGE_FLAG | (u32::from(baggage & MAX_BAGGAGE) << 16) | (sequence & 0xffff)
+}
+
+/// The correlation token carried in an opaque, or None if the request was
+/// not sent with [`RequestOptions::with_baggage`].
+pub fn decode_baggage(opaque: u32) -> Option<u16> {
+    match opaque & BAGGAGE_FLAG {
+        0 => None,
+        _ => Some((opaque >> 16) as u16 & MAX_BAGGAGE),
+    }
+}
+
+/// Supplies the deadline of the context a request is made in. The earlier of
+/// this deadline and the deadline of the request options is used.
+pub trait DeadlineSource: Debug + Send + Sync {
+    /// The deadline of the current context, if any.
+    fn deadline(&self) -> Option<Instant>;
+}
+
+/// Options overriding the client configuration for a single request. The
+/// default options behave exactly like the methods without options.
+///
+/// Bulk requests honor only some of the options. Bulk gets honor the
+/// deadline, which is checked before sending, the retries, which only apply
+/// with a [`crate::budget::RetryBudget`], and the timing. Bulk writes honor
+/// the deadline, which is checked before sending, and the audit tags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    /// Fail with [`Error::DeadlineExceeded`] once this instant has passed,
+    /// whether before sending the request, before retrying it, or while
+    /// waiting for the response of a single key request. Waiting is bounded
+    /// with [`crate::client::Connection::sleep`], so it is only interrupted
+    /// by connections with a timer, such as those of the runtime adapters.
+    pub deadline: Option<Instant>,
+    /// How many times the request is retried after the server closed the
+    /// connection. Defaults to once.
+    pub retries: Option<u32>,
+    /// Whether the value written by the request is compressed with the
+    /// client's compressor. Defaults to true. Reads always decompress.
+    pub compress: Option<bool>,
+    /// The opaque field sent in the request header, which the server echoes
+    /// back unchanged. This is useful to tag requests in packet captures
+    /// and proxy logs.
+    pub opaque: Option<u32>,
+    /// A correlation token encoded in the opaque field, up to
+    /// [`MAX_BAGGAGE`]. Ignored when the opaque is set explicitly.
+    pub baggage: Option<u16>,
+    /// Go straight to memcached, bypassing any local tier in front of it.
+    pub skip_local_tier: bool,
+    /// Where reads may be served from.
+    pub read_preference: ReadPreference,
+    /// Measure when each phase of a bulk request to every node completed.
+    pub timing: bool,
+    /// Tags passed to the audit hook with every mutation made by the
+    /// request. See [`crate::audit`].
+    pub audit_tags: Vec<Cow<'static, str>>,
+}
+
+impl RequestOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up on the request once the deadline has passed, as read by
+    /// [`crate::client::Connection::now`].
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Give up on the request once the timeout has elapsed from now, on the
+    /// system clock. Connections with a clock of their own, such as the
+    /// simulated ones, need a deadline from [`RequestOptions::with_deadline`]
+    /// instead.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Retry the request this many times after the server closed the
+    /// connection, instead of once.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Enable or disable compressing the value written by the request.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = Some(compress);
+        self
+    }
+
+    /// Tag the request with the given opaque value.
+    pub fn with_opaque(mut self, opaque: u32) -> Self {
+        self.opaque = Some(opaque);
+        self
+    }
+
+    /// Carry a correlation token in the opaque field of the request, which
+    /// is reported with the response. Only the lower 15 bits are kept.
+    pub fn with_baggage(mut self, baggage: u16) -> Self {
+        self.baggage = Some(baggage);
+        self
+    }
+
+    /// Bypass any local tier in front of memcached.
+    pub fn with_skip_local_tier(mut self, skip: bool) -> Self {
+        self.skip_local_tier = skip;
+        self
+    }
+
+    /// Choose where reads may be served from.
+    pub fn with_read_preference(mut self, preference: ReadPreference) -> Self {
+        self.read_preference = preference;
+        self
+    }
+
+    /// Measure the timing of bulk requests to every node.
+    pub fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Tag the mutations made by the request for the audit hook. Tags may be
+    /// static strings or built at runtime, such as a tenant id.
+    pub fn with_audit_tags<I, T>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Cow<'static, str>>,
+    {
+        self.audit_tags = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Keep the earlier of the deadline of the options and the given one.
+    pub(crate) fn bounded_by(&self, deadline: Option<Instant>) -> Self {
+        let mut options = self.clone();
+        options.deadline = match (self.deadline, deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        options
+    }
+
+    /// The time left from `now` until the deadline, if any.
+    pub(crate) fn remaining(&self, now: Instant) -> Option<Duration> {
+        let deadline = self.deadline?;
+        Some(deadline.saturating_duration_since(now))
+    }
+
+    /// Return [`Error::DeadlineExceeded`] if the deadline has passed at
+    /// `now`.
+    pub(crate) fn check_deadline(&self, now: Instant) -> Result<(), Error> {
+        match self.deadline {
+            Some(deadline) if now >= deadline => Err(Error::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+}
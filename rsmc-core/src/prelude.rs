@@ -0,0 +1,50 @@
+//! The prelude re-exports the types needed by most users of rsmc, so that
+//! downstream code only needs a single import line:
+//!
+//! ```
+//! use rsmc_core::prelude::*;
+//! ```
+
+pub use crate::{
+    audit::{AuditEvent, AuditHook, AuditOp},
+    batch::{BatchGuard, BatchLimits},
+    budget::{ErrorBudget, RetryBudget},
+    bus::{InvalidationBus, NoopBus},
+    client::{
+        BulkGetResult, BulkProgress, Client, ClientConfig, Compressor, Connection, Error,
+        KeepAlive, MultiGetPolicy, NoCompressor, NodeTiming, Pool, Protocol, Result, SlowStart,
+        WriteCheck,
+    },
+    cold::{ColdStart, ColdStartPhase, ColdStartStats},
+    counter::{BatchedCounter, Counter},
+    dual::{DualRead, Primary},
+    envelope::{Envelope, HeaderEnvelope, Metadata},
+    features::Feature,
+    hashing::{HashScheme, HashSeeds},
+    hot::HotKeyDetector,
+    instrument::{
+        CompressionEvent, DualReadEvent, ExtstoreWarning, InstrumentedConnection, MetricsHook,
+    },
+    keys::KeyCodec,
+    limit::{InFlightLimits, LoadShedder, QueuePolicy, WaitSource},
+    local::{Invalidation, LocalTier},
+    multi::{Cache, CacheConfig, MultiCache, MultiCacheConfig},
+    options::{DeadlineSource, ReadPreference, RequestOptions},
+    reconnect::{ConnectionState, ReconnectPolicy, ReconnectingConnection},
+    resilience::{ResilienceConfig, ResilienceStats},
+    resolve::{IpPreference, Resolver, StaticResolver, SystemResolver},
+    sasl::{Plain, SaslMechanism},
+    selftest::{NodeSelfTest, SelfTestReport, SelfTestStep},
+    stats::{
+        DetailStats, ExtstoreStats, ExtstoreThresholds, Histogram, ItemClass, ItemStats,
+        NodeHealth, NodeState, NodeStats, PrefixStats, SlabClass, SlabStats,
+    },
+    topology::{NodeSnapshot, TopologySnapshot},
+    touch::TouchOnRead,
+    transform::{RedactFields, ValueTransform},
+    vbucket::{VbucketMap, VbucketRouter},
+    warm::WarmPool,
+};
+
+#[cfg(feature = "zlib")]
+pub use crate::zlib::{CompressionBands, CompressionDictionaries, ZlibCompressor};
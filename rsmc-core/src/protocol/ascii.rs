@@ -0,0 +1,607 @@
+//! A codec for the classic text protocol, for proxies which do not speak the
+//! binary protocol, such as twemproxy. The rest of the client keeps building
+//! binary requests, which are translated into text commands when they are
+//! written, and their replies are translated back into binary responses
+//! carrying the opaque of the request, in the order the requests were
+//! written.
+//!
+//! The text protocol has no quiet commands, so the replies to quiet requests
+//! which succeeded, or missed for gets, are dropped after they are read, and
+//! NOOPs are answered without sending anything. Quiet gets are held until
+//! the next request is written, and sent together as a single multi-key
+//! `gets`, so that a pipelined bulk get costs one command per batch. QUITs
+//! are sent but answered locally, since the server closes the connection
+//! instead of replying.
+//! Commands without a text equivalent, such as SASL, are answered with
+//! [`Status::NotSupported`], and keys which the text protocol cannot carry
+//! with [`Status::InvalidArguments`], without being sent. Increments and
+//! decrements of missing keys fail with [`Status::KeyNotFound`] instead of
+//! creating the counter, and responses to stores carry no CAS.
+
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    sync::{Arc, Mutex},
+};
+
+use crate::client::{Connection, Error};
+
+use super::{
+    Packet, ProtocolError, Status, ADDQ_OPCODE, ADD_OPCODE, DECREMENTQ_OPCODE, DECREMENT_OPCODE,
+    DELETEQ_OPCODE, DELETE_OPCODE, FLUSH_OPCODE, GATKQ_OPCODE, GAT_OPCODE, GETKQ_OPCODE,
+    GETK_OPCODE, GETQ_OPCODE, GET_OPCODE, INCREMENTQ_OPCODE, INCREMENT_OPCODE,
+    MAGIC_RESPONSE_VALUE, NOOP_OPCODE, QUIT_OPCODE, REPLACEQ_OPCODE, REPLACE_OPCODE, SETQ_OPCODE,
+    SET_OPCODE, STAT_OPCODE, TOUCH_OPCODE, VERSION_OPCODE,
+};
+
+/// The longest key the text protocol accepts.
+const MAX_KEY_LEN: usize = 250;
+
+/// How many bytes are read from the connection at once.
+const READ_SIZE: usize = 4096;
+
+/// The most keys sent in a single multi-key get.
+const MAX_GET_KEYS: usize = 100;
+
+/// A request waiting for its reply.
+#[derive(Debug)]
+struct Pending {
+    opcode: u8,
+    opaque: u32,
+    key: Vec<u8>,
+    /// The status of a request answered without sending it.
+    local: Option<Status>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The requests waiting for a reply, in the order they were sent. The
+    /// keys of a multi-key get share a single reply.
+    pending: VecDeque<Vec<Pending>>,
+    /// Quiet gets which were not sent yet.
+    gets: Vec<Pending>,
+    /// Bytes read from the connection but not parsed yet.
+    buf: Vec<u8>,
+    /// The buffer read into, kept between reads.
+    chunk: Vec<u8>,
+    /// Responses parsed from a reply but not returned yet, for replies such
+    /// as stats which translate into several responses.
+    ready: VecDeque<Packet>,
+}
+
+/// Translates the requests written to a connection into text commands, and
+/// the replies read from it into responses. Clones share the same state, so
+/// that the halves of a split connection agree on the pending requests.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AsciiCodec {
+    state: Arc<Mutex<State>>,
+}
+
+impl AsciiCodec {
+    /// Forget every pending request and unread byte, after the connection
+    /// was replaced.
+    pub(crate) fn reset(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    /// Write the request as a text command.
+    pub(crate) async fn write_packet<C: Connection>(
+        &self,
+        conn: &mut C,
+        packet: Packet,
+    ) -> Result<(), Error> {
+        let (command, local) = match encode(&packet) {
+            Ok(Some(command)) => (Some(command), None),
+            Ok(None) => (None, Some(Status::NoError)),
+            Err(status) => (None, Some(status)),
+        };
+        // The server closes the connection instead of replying to a QUIT.
+        let local = match packet.header.opcode {
+            QUIT_OPCODE => Some(Status::NoError),
+            _ => local,
+        };
+        let batched = is_quiet_get(packet.header.opcode) && local.is_none();
+        let pending = Pending {
+            opcode: packet.header.opcode,
+            opaque: packet.header.opaque,
+            key: packet.key,
+            local,
+        };
+        let command = {
+            let mut state = self.state.lock().unwrap();
+            match batched {
+                true => {
+                    state.gets.push(pending);
+                    match state.gets.len() >= MAX_GET_KEYS {
+                        true => state.flush_gets(),
+                        false => None,
+                    }
+                }
+                false => {
+                    let gets = state.flush_gets();
+                    state.pending.push_back(vec![pending]);
+                    match (gets, command) {
+                        (Some(gets), Some(command)) => Some([gets, command].concat()),
+                        (gets, command) => gets.or(command),
+                    }
+                }
+            }
+        };
+        match command {
+            Some(command) => conn.write(&command).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Read the response to the oldest pending request which has one,
+    /// rejecting values longer than `max_body` bytes.
+    pub(crate) async fn read_packet<C: Connection>(
+        &self,
+        conn: &mut C,
+        max_body: u32,
+    ) -> Result<Packet, Error> {
+        loop {
+            let pending = {
+                let mut state = self.state.lock().unwrap();
+                if let Some(packet) = state.ready.pop_front() {
+                    return Ok(packet);
+                }
+                state.pending.pop_front()
+            };
+            let requests = pending.ok_or(ProtocolError::InvalidReply)?;
+            let first = &requests[0];
+            let responses = match first.local {
+                Some(status) => vec![response(first, status)],
+                None if is_quiet_get(first.opcode) => {
+                    self.read_gets(conn, &requests, max_body).await?
+                }
+                None => self.read_reply(conn, first, max_body).await?,
+            };
+            let responses = responses.into_iter().filter(|res| !is_silent(res));
+            self.state.lock().unwrap().ready.extend(responses);
+        }
+    }
+
+    /// Read the reply to a request, translated into responses.
+    async fn read_reply<C: Connection>(
+        &self,
+        conn: &mut C,
+        pending: &Pending,
+        max_body: u32,
+    ) -> Result<Vec<Packet>, Error> {
+        let line = self.read_line(conn).await?;
+        if let Some(status) = error_status(&line) {
+            return Ok(vec![response(pending, status)]);
+        }
+        let mut res = response(pending, Status::NoError);
+        let is_get = res.is_get();
+        let status = match (pending.opcode, line.as_str()) {
+            (_, "END") if is_get => Status::KeyNotFound,
+            _ if is_get => {
+                let (_, flags, len, cas) = parse_value(&line)?;
+                let value = self.read_block(conn, len, max_body).await?;
+                if self.read_line(conn).await? != "END" {
+                    return Err(ProtocolError::InvalidReply.into());
+                }
+                if matches!(pending.opcode, GETK_OPCODE | GETKQ_OPCODE | GATKQ_OPCODE) {
+                    res.key = pending.key.clone();
+                }
+                res.extras = flags.to_be_bytes().to_vec();
+                res.value = value;
+                res.header.cas = cas;
+                Status::NoError
+            }
+            (_, "STORED" | "DELETED" | "TOUCHED" | "OK") => Status::NoError,
+            (ADD_OPCODE | ADDQ_OPCODE, "NOT_STORED") => Status::KeyExists,
+            (_, "NOT_STORED") => Status::ItemNotStored,
+            (_, "EXISTS") => Status::KeyExists,
+            (_, "NOT_FOUND") => Status::KeyNotFound,
+            (INCREMENT_OPCODE | INCREMENTQ_OPCODE | DECREMENT_OPCODE | DECREMENTQ_OPCODE, _) => {
+                let value = line.parse::<u64>().or(Err(ProtocolError::InvalidReply))?;
+                res.value = value.to_be_bytes().to_vec();
+                Status::NoError
+            }
+            (VERSION_OPCODE, _) => match line.strip_prefix("VERSION ") {
+                Some(version) => {
+                    res.value = version.as_bytes().to_vec();
+                    Status::NoError
+                }
+                None => return Err(ProtocolError::InvalidReply.into()),
+            },
+            (STAT_OPCODE, _) => return self.read_stats(conn, pending, line).await,
+            _ => return Err(ProtocolError::InvalidReply.into()),
+        };
+        Ok(vec![finish(res, status)])
+    }
+
+    /// Read the reply to a multi-key get, translated into a response to
+    /// every request in order, which misses for keys without a value.
+    async fn read_gets<C: Connection>(
+        &self,
+        conn: &mut C,
+        requests: &[Pending],
+        max_body: u32,
+    ) -> Result<Vec<Packet>, Error> {
+        let mut hits = vec![None; requests.len()];
+        loop {
+            let line = self.read_line(conn).await?;
+            if let Some(status) = error_status(&line) {
+                return Ok(requests.iter().map(|req| response(req, status)).collect());
+            }
+            if line == "END" {
+                break;
+            }
+            let (key, flags, len, cas) = parse_value(&line)?;
+            let value = self.read_block(conn, len, max_body).await?;
+            let i = requests
+                .iter()
+                .zip(&hits)
+                .position(|(req, hit)| hit.is_none() && req.key == key.as_bytes())
+                .ok_or(ProtocolError::InvalidReply)?;
+            let mut res = response(&requests[i], Status::NoError);
+            if requests[i].opcode == GETKQ_OPCODE {
+                res.key = requests[i].key.clone();
+            }
+            res.extras = flags.to_be_bytes().to_vec();
+            res.value = value;
+            res.header.cas = cas;
+            hits[i] = Some(finish(res, Status::NoError));
+        }
+        let responses = requests
+            .iter()
+            .zip(hits)
+            .map(|(req, hit)| hit.unwrap_or_else(|| response(req, Status::KeyNotFound)));
+        Ok(responses.collect())
+    }
+
+    /// Read the data block following a VALUE line, without its CRLF.
+    async fn read_block<C: Connection>(
+        &self,
+        conn: &mut C,
+        len: usize,
+        max_body: u32,
+    ) -> Result<Vec<u8>, Error> {
+        if len > max_body as usize {
+            return Err(ProtocolError::ResponseTooLarge(len as u32).into());
+        }
+        let mut value = self.read_exact(conn, len + 2).await?;
+        if !value.ends_with(b"\r\n") {
+            return Err(ProtocolError::InvalidReply.into());
+        }
+        value.truncate(len);
+        Ok(value)
+    }
+
+    /// Read the lines of a stats reply, starting with the given one, into a
+    /// response per stat followed by one with an empty key. Lines which are
+    /// not stats, such as those of `stats detail dump`, are collected into a
+    /// single `detailed` stat.
+    async fn read_stats<C: Connection>(
+        &self,
+        conn: &mut C,
+        pending: &Pending,
+        mut line: String,
+    ) -> Result<Vec<Packet>, Error> {
+        let (mut out, mut detailed) = (vec![], String::new());
+        while line != "END" {
+            match line.strip_prefix("STAT ") {
+                Some(stat) => {
+                    let (name, value) = stat.split_once(' ').unwrap_or((stat, ""));
+                    let mut res = response(pending, Status::NoError);
+                    res.key = name.as_bytes().to_vec();
+                    res.value = value.as_bytes().to_vec();
+                    out.push(finish(res, Status::NoError));
+                }
+                None => {
+                    detailed.push_str(&line);
+                    detailed.push_str("\r\n");
+                }
+            }
+            line = self.read_line(conn).await?;
+        }
+        if !detailed.is_empty() {
+            let mut res = response(pending, Status::NoError);
+            res.key = b"detailed".to_vec();
+            res.value = detailed.into_bytes();
+            out.push(finish(res, Status::NoError));
+        }
+        out.push(response(pending, Status::NoError));
+        Ok(out)
+    }
+
+    /// Read a line, without its CRLF.
+    async fn read_line<C: Connection>(&self, conn: &mut C) -> Result<String, Error> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(end) = state.buf.windows(2).position(|w| w == b"\r\n") {
+                    let line = String::from_utf8_lossy(&state.buf[..end]).to_string();
+                    state.buf.drain(..end + 2);
+                    return Ok(line);
+                }
+            }
+            self.fill(conn).await?;
+        }
+    }
+
+    /// Read exactly `n` bytes.
+    async fn read_exact<C: Connection>(&self, conn: &mut C, n: usize) -> Result<Vec<u8>, Error> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.buf.len() >= n {
+                    return Ok(state.buf.drain(..n).collect());
+                }
+            }
+            self.fill(conn).await?;
+        }
+    }
+
+    /// Read whatever the connection has to offer into the buffer.
+    async fn fill<C: Connection>(&self, conn: &mut C) -> Result<(), Error> {
+        let mut chunk = std::mem::take(&mut self.state.lock().unwrap().chunk);
+        chunk.resize(READ_SIZE, 0);
+        let read = conn.read(&mut chunk).await;
+        let mut state = self.state.lock().unwrap();
+        let result = match read {
+            Ok(0) => Err(Error::ConnectionClosed),
+            Ok(n) => {
+                state.buf.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        };
+        state.chunk = chunk;
+        result
+    }
+}
+
+impl State {
+    /// Queue the held quiet gets for a single reply, returning the multi-key
+    /// get to send for them, if any.
+    fn flush_gets(&mut self) -> Option<Vec<u8>> {
+        if self.gets.is_empty() {
+            return None;
+        }
+        let gets = std::mem::take(&mut self.gets);
+        let mut command = b"gets".to_vec();
+        for get in &gets {
+            command.push(b' ');
+            command.extend_from_slice(&get.key);
+        }
+        command.extend_from_slice(b"\r\n");
+        self.pending.push_back(gets);
+        Some(command)
+    }
+}
+
+/// Whether the opcode is a quiet get without a touch, which is sent as part
+/// of a multi-key get.
+fn is_quiet_get(opcode: u8) -> bool {
+    matches!(opcode, GETQ_OPCODE | GETKQ_OPCODE)
+}
+
+/// Translate a request into a text command, or None for a NOOP, which has
+/// no text equivalent and needs none. Requests which cannot be sent fail
+/// with the status to answer them with.
+fn encode(packet: &Packet) -> Result<Option<Vec<u8>>, Status> {
+    let opcode = packet.header.opcode;
+    let key = match packet.has_item_key() {
+        true => text_key(&packet.key)?,
+        false => String::from_utf8_lossy(&packet.key).to_string(),
+    };
+    let extra = |offset: usize| extra_u32(&packet.extras, offset);
+    let line = match opcode {
+        GET_OPCODE | GETQ_OPCODE | GETK_OPCODE | GETKQ_OPCODE => format!("gets {}", key),
+        GAT_OPCODE | GATKQ_OPCODE => format!("gats {} {}", extra(0), key),
+        SET_OPCODE | SETQ_OPCODE | ADD_OPCODE | ADDQ_OPCODE | REPLACE_OPCODE | REPLACEQ_OPCODE => {
+            let command = match opcode {
+                SET_OPCODE | SETQ_OPCODE if packet.header.cas != 0 => "cas",
+                SET_OPCODE | SETQ_OPCODE => "set",
+                ADD_OPCODE | ADDQ_OPCODE => "add",
+                _ => "replace",
+            };
+            let (flags, expire, len) = (extra(0), extra(4), packet.value.len());
+            let mut line = format!("{} {} {} {} {}", command, key, flags, expire, len);
+            if command == "cas" {
+                line.push_str(&format!(" {}", packet.header.cas));
+            }
+            let mut command = line.into_bytes();
+            command.extend_from_slice(b"\r\n");
+            command.extend_from_slice(&packet.value);
+            command.extend_from_slice(b"\r\n");
+            return Ok(Some(command));
+        }
+        DELETE_OPCODE | DELETEQ_OPCODE => format!("delete {}", key),
+        INCREMENT_OPCODE | INCREMENTQ_OPCODE | DECREMENT_OPCODE | DECREMENTQ_OPCODE => {
+            let command = match opcode {
+                INCREMENT_OPCODE | INCREMENTQ_OPCODE => "incr",
+                _ => "decr",
+            };
+            let delta = match packet.extras.get(0..8) {
+                Some(bytes) => u64::from_be_bytes(bytes.try_into().unwrap()),
+                None => 0,
+            };
+            format!("{} {} {}", command, key, delta)
+        }
+        TOUCH_OPCODE => format!("touch {} {}", key, extra(0)),
+        FLUSH_OPCODE => match extra(0) {
+            0 => "flush_all".to_string(),
+            delay => format!("flush_all {}", delay),
+        },
+        VERSION_OPCODE => "version".to_string(),
+        QUIT_OPCODE => "quit".to_string(),
+        STAT_OPCODE if key.is_empty() => "stats".to_string(),
+        STAT_OPCODE => format!("stats {}", key),
+        NOOP_OPCODE => return Ok(None),
+        _ => return Err(Status::NotSupported),
+    };
+    Ok(Some(format!("{}\r\n", line).into_bytes()))
+}
+
+/// The key as text, if the text protocol can carry it.
+fn text_key(key: &[u8]) -> Result<String, Status> {
+    let valid = key.iter().all(|byte| byte.is_ascii_graphic());
+    match std::str::from_utf8(key) {
+        Ok(key) if valid && !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(key.to_string()),
+        _ => Err(Status::InvalidArguments),
+    }
+}
+
+fn extra_u32(extras: &[u8], offset: usize) -> u32 {
+    match extras.get(offset..offset + 4) {
+        Some(bytes) => u32::from_be_bytes(bytes.try_into().unwrap()),
+        None => 0,
+    }
+}
+
+/// Parse the key, flags, length and CAS of a
+/// `VALUE <key> <flags> <bytes> [<cas>]` line.
+fn parse_value(line: &str) -> Result<(&str, u32, usize, u64), ProtocolError> {
+    let mut words = line.split(' ');
+    let (value, key, flags, len) = (words.next(), words.next(), words.next(), words.next());
+    let parsed = match (value, key, flags, len) {
+        (Some("VALUE"), Some(key), Some(flags), Some(len)) => {
+            let parsed = flags.parse().ok().zip(len.parse().ok());
+            parsed.map(|(flags, len)| (key, flags, len))
+        }
+        _ => None,
+    };
+    let (key, flags, len) = parsed.ok_or(ProtocolError::InvalidReply)?;
+    let cas = words.next().and_then(|cas| cas.parse().ok()).unwrap_or(0);
+    Ok((key, flags, len, cas))
+}
+
+/// The status of an error reply, which any command may get.
+fn error_status(line: &str) -> Option<Status> {
+    let status = match line.split_once(' ').map_or(line, |(kind, _)| kind) {
+        "ERROR" => Status::UnknownCommand,
+        "CLIENT_ERROR" => Status::InvalidArguments,
+        "SERVER_ERROR" if line.contains("too large") => Status::ValueTooLarge,
+        "SERVER_ERROR" if line.contains("out of memory") => Status::OutOfMemory,
+        "SERVER_ERROR" => Status::InternalError,
+        _ => return None,
+    };
+    Some(status)
+}
+
+/// Whether the binary protocol would have sent no response at all, for a
+/// quiet request which succeeded or a quiet get which missed.
+fn is_silent(res: &Packet) -> bool {
+    let status = res.error_for_status();
+    match res.header.opcode {
+        GETQ_OPCODE | GETKQ_OPCODE | GATKQ_OPCODE => status == Err(Status::KeyNotFound),
+        SETQ_OPCODE | ADDQ_OPCODE | REPLACEQ_OPCODE | DELETEQ_OPCODE | INCREMENTQ_OPCODE
+        | DECREMENTQ_OPCODE => status.is_ok(),
+        _ => false,
+    }
+}
+
+/// An empty response to the request with the status.
+fn response(pending: &Pending, status: Status) -> Packet {
+    let mut res = Packet::default();
+    res.header.magic = MAGIC_RESPONSE_VALUE;
+    res.header.opcode = pending.opcode;
+    res.header.opaque = pending.opaque;
+    finish(res, status)
+}
+
+/// Set the status and the lengths of the body in the header.
+fn finish(mut res: Packet, status: Status) -> Packet {
+    res.header.vbucket_or_status = status.into();
+    res.header.key_length = res.key.len() as u16;
+    res.header.extras_length = res.extras.len() as u8;
+    res.header.body_len = (res.extras.len() + res.key.len() + res.value.len()) as u32;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{
+        client::{Client, ClientConfig, Connection, Error, KeepAlive, Protocol},
+        mock::MockConnection,
+        protocol::Status,
+    };
+
+    #[test]
+    fn test_ascii_protocol() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["ascii:1".to_string(), "ascii:2".to_string()];
+            let cfg = ClientConfig::new_uncompressed(endpoints.clone())
+                .with_protocol(Protocol::Ascii)
+                .with_keep_alive(KeepAlive::Version);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+            assert!(!client.add("key", "other", 0).await.unwrap());
+            let (_, cas) = client.gets::<_, String>("key").await.unwrap().unwrap();
+            assert_ne!(0, cas);
+
+            let keys = ["key", "a", "b", "missing"];
+            let data = keys[1..3].iter().map(|key| (*key, "value")).collect();
+            assert!(client.set_multi(data, 0).await.unwrap().is_empty());
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(3, values.len());
+            assert!(errors.is_empty());
+            assert!(client.delete_multi(&keys).await.unwrap().is_empty());
+            assert_eq!(None, client.get::<_, String>("a").await.unwrap());
+
+            // Counters are only created over the binary protocol.
+            let err = client.incr("hits", 1, 0, 0).await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::KeyNotFound)));
+            let binary = ClientConfig::new_uncompressed(endpoints);
+            let mut binary = Client::<MockConnection, _>::new(binary).await.unwrap();
+            assert_eq!(5, binary.incr("hits", 1, 5, 0).await.unwrap());
+            assert_eq!(7, client.incr("hits", 2, 0, 0).await.unwrap());
+
+            let err = client.get::<_, String>("bad key").await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::InvalidArguments)));
+            let stats = client.item_stats().await.unwrap();
+            assert_eq!(2, stats.len());
+        });
+    }
+
+    #[test]
+    fn test_multi_key_get() {
+        static WRITES: Mutex<Vec<Vec<u8>>> = Mutex::new(vec![]);
+
+        /// A connection recording every command written to it.
+        #[derive(Debug, Clone)]
+        struct Recording(MockConnection);
+
+        #[async_trait::async_trait]
+        impl Connection for Recording {
+            async fn connect(url: String) -> Result<Self, Error> {
+                Ok(Recording(MockConnection::connect(url).await?))
+            }
+
+            async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+                self.0.read(buf).await
+            }
+
+            async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+                WRITES.lock().unwrap().push(data.to_vec());
+                self.0.write(data).await
+            }
+        }
+
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["ascii:multi".into()])
+                .with_protocol(Protocol::Ascii);
+            let mut client = Client::<Recording, _>::new(cfg).await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            client.set("c", "3", 0).await.unwrap();
+            WRITES.lock().unwrap().clear();
+
+            let keys = ["a", "b", "c"];
+            let (values, errors) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert!(errors.is_empty());
+            assert_eq!(2, values.len());
+            assert_eq!(Some(&"3".to_string()), values.get(&b"c"[..]));
+            assert_eq!(vec![b"gets a b c\r\n".to_vec()], *WRITES.lock().unwrap());
+        });
+    }
+}
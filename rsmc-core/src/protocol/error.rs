@@ -3,6 +3,7 @@ use std::{
     fmt::{Display, Formatter, Result as FmtResult},
 };
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Status {
     UnknownStatus,
@@ -35,8 +36,10 @@ impl From<u16> for Status {
             0x05 => Status::ItemNotStored,
             0x06 => Status::IncrDecrOnNonNumericValue,
             0x07 => Status::VbucketBelongsToAnotherServer,
-            0x08 => Status::AuthenticationError,
-            0x09 => Status::AuthenticationContinue,
+            // memcached itself sends 0x20 and 0x21 instead of the documented
+            // statuses.
+            0x08 | 0x20 => Status::AuthenticationError,
+            0x09 | 0x21 => Status::AuthenticationContinue,
             0x81 => Status::UnknownCommand,
             0x82 => Status::OutOfMemory,
             0x83 => Status::NotSupported,
@@ -48,6 +51,30 @@ impl From<u16> for Status {
     }
 }
 
+impl From<Status> for u16 {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::NoError => 0x00,
+            Status::KeyNotFound => 0x01,
+            Status::KeyExists => 0x02,
+            Status::ValueTooLarge => 0x03,
+            Status::InvalidArguments => 0x04,
+            Status::ItemNotStored => 0x05,
+            Status::IncrDecrOnNonNumericValue => 0x06,
+            Status::VbucketBelongsToAnotherServer => 0x07,
+            Status::AuthenticationError => 0x08,
+            Status::AuthenticationContinue => 0x09,
+            Status::UnknownCommand => 0x81,
+            Status::OutOfMemory => 0x82,
+            Status::NotSupported => 0x83,
+            Status::InternalError => 0x84,
+            Status::Busy => 0x85,
+            Status::TemporaryFailure => 0x86,
+            Status::UnknownStatus => 0xffff,
+        }
+    }
+}
+
 impl Display for Status {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
@@ -79,6 +106,18 @@ pub enum ProtocolError {
     InvalidMagic(u8),
     PacketTooSmall,
     BodySizeMismatch,
+    UnexpectedOpcode(u8),
+    /// The extras and key lengths in the header exceed the body length.
+    InvalidLength,
+    /// The body length in the header exceeds the maximum response size.
+    ResponseTooLarge(u32),
+    /// A key returned by the server could not be decoded by the key codec.
+    InvalidKeyEncoding,
+    /// A response carried an opaque which no recent request was sent with.
+    UnexpectedOpaque(u32),
+    /// A reply of the text protocol could not be parsed, or was read while
+    /// no request was waiting for one.
+    InvalidReply,
 }
 
 impl Display for ProtocolError {
@@ -87,6 +126,14 @@ impl Display for ProtocolError {
             ProtocolError::InvalidMagic(byte) => write!(f, "Invalid magic byte: {}", byte),
             ProtocolError::PacketTooSmall => write!(f, "Packet too small"),
             ProtocolError::BodySizeMismatch => write!(f, "Body size mismatch"),
+            ProtocolError::UnexpectedOpcode(op) => write!(f, "Unexpected opcode: {}", op),
+            ProtocolError::InvalidLength => write!(f, "Extras and key exceed the body length"),
+            ProtocolError::ResponseTooLarge(len) => {
+                write!(f, "Response body of {} bytes exceeds the maximum size", len)
+            }
+            ProtocolError::InvalidKeyEncoding => write!(f, "Invalid key encoding"),
+            ProtocolError::UnexpectedOpaque(opaque) => write!(f, "Unexpected opaque: {}", opaque),
+            ProtocolError::InvalidReply => write!(f, "Invalid text protocol reply"),
         }
     }
 }
@@ -1,24 +1,42 @@
+mod ascii;
 mod error;
 mod packet;
 
+pub(crate) use ascii::AsciiCodec;
 pub use error::{ProtocolError, Status};
-pub(crate) use packet::{Header, Packet, SetExtras};
+pub use packet::Header;
+pub use packet::{CounterExtras, FlushExtras, Packet, RequestFields, SetExtras, TouchExtras};
 
-const MAGIC_REQUEST_VALUE: u8 = 0x80;
-const MAGIC_RESPONSE_VALUE: u8 = 0x81;
+pub(crate) const MAGIC_REQUEST_VALUE: u8 = 0x80;
+pub(crate) const MAGIC_RESPONSE_VALUE: u8 = 0x81;
 
-const GET_OPCODE: u8 = 0x00;
-const GETK_OPCODE: u8 = 0x0c;
-const GETQ_OPCODE: u8 = 0x09;
-const GETKQ_OPCODE: u8 = 0x0d;
+pub(crate) const GET_OPCODE: u8 = 0x00;
+pub(crate) const GETK_OPCODE: u8 = 0x0c;
+pub(crate) const GETQ_OPCODE: u8 = 0x09;
+pub(crate) const GETKQ_OPCODE: u8 = 0x0d;
 
-const SET_OPCODE: u8 = 0x01;
-const SETQ_OPCODE: u8 = 0x11;
-const ADD_OPCODE: u8 = 0x02;
-const ADDQ_OPCODE: u8 = 0x12;
-const REPLACE_OPCODE: u8 = 0x03;
-const REPLACEQ_OPCODE: u8 = 0x13;
-const DELETE_OPCODE: u8 = 0x04;
+pub(crate) const SET_OPCODE: u8 = 0x01;
+pub(crate) const SETQ_OPCODE: u8 = 0x11;
+pub(crate) const ADD_OPCODE: u8 = 0x02;
+pub(crate) const ADDQ_OPCODE: u8 = 0x12;
+pub(crate) const REPLACE_OPCODE: u8 = 0x03;
+pub(crate) const REPLACEQ_OPCODE: u8 = 0x13;
+pub(crate) const DELETE_OPCODE: u8 = 0x04;
+pub(crate) const DELETEQ_OPCODE: u8 = 0x14;
 
-const NOOP_OPCODE: u8 = 0x0a;
-const VERSION_OPCODE: u8 = 0x0b;
+pub(crate) const INCREMENT_OPCODE: u8 = 0x05;
+pub(crate) const DECREMENT_OPCODE: u8 = 0x06;
+pub(crate) const INCREMENTQ_OPCODE: u8 = 0x15;
+pub(crate) const DECREMENTQ_OPCODE: u8 = 0x16;
+pub(crate) const TOUCH_OPCODE: u8 = 0x1c;
+pub(crate) const GAT_OPCODE: u8 = 0x1d;
+pub(crate) const GATKQ_OPCODE: u8 = 0x24;
+pub(crate) const FLUSH_OPCODE: u8 = 0x08;
+
+pub(crate) const STAT_OPCODE: u8 = 0x10;
+pub(crate) const NOOP_OPCODE: u8 = 0x0a;
+pub(crate) const VERSION_OPCODE: u8 = 0x0b;
+pub(crate) const QUIT_OPCODE: u8 = 0x07;
+
+pub(crate) const SASL_AUTH_OPCODE: u8 = 0x21;
+pub(crate) const SASL_STEP_OPCODE: u8 = 0x22;
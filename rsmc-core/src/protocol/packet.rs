@@ -4,30 +4,63 @@ use bincode::{DefaultOptions, Options};
 use serde::{de::DeserializeOwned, Serialize};
 
 use super::{
-    ProtocolError, Status, ADDQ_OPCODE, ADD_OPCODE, DELETE_OPCODE, GETKQ_OPCODE, GETK_OPCODE,
-    GETQ_OPCODE, GET_OPCODE, MAGIC_REQUEST_VALUE, MAGIC_RESPONSE_VALUE, NOOP_OPCODE,
-    REPLACEQ_OPCODE, REPLACE_OPCODE, SETQ_OPCODE, SET_OPCODE, VERSION_OPCODE,
+    ProtocolError, Status, ADDQ_OPCODE, ADD_OPCODE, DECREMENTQ_OPCODE, DECREMENT_OPCODE,
+    DELETEQ_OPCODE, DELETE_OPCODE, FLUSH_OPCODE, GATKQ_OPCODE, GAT_OPCODE, GETKQ_OPCODE,
+    GETK_OPCODE, GETQ_OPCODE, GET_OPCODE, INCREMENTQ_OPCODE, INCREMENT_OPCODE, MAGIC_REQUEST_VALUE,
+    MAGIC_RESPONSE_VALUE, NOOP_OPCODE, QUIT_OPCODE, REPLACEQ_OPCODE, REPLACE_OPCODE,
+    SASL_AUTH_OPCODE, SASL_STEP_OPCODE, SETQ_OPCODE, SET_OPCODE, STAT_OPCODE, TOUCH_OPCODE,
+    VERSION_OPCODE,
 };
 
-#[derive(Debug, Default, PartialEq, Clone, Copy)]
+/// The 24 byte header of every binary protocol packet. Every field is sent
+/// in network byte order.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
 pub struct Header {
+    /// 0x80 for requests and 0x81 for responses.
     pub magic: u8,
+    /// The command, such as 0x00 for GET.
     pub opcode: u8,
+    /// The length of the key following the extras.
     pub key_length: u16,
+    /// The length of the extras following the header.
     pub extras_length: u8,
+    /// Reserved for future use, always 0.
     pub data_type: u8,
+    /// The vbucket of the key in requests, and the status in responses.
     pub vbucket_or_status: u16,
+    /// The combined length of the extras, key and value.
     pub body_len: u32,
+    /// An arbitrary value echoed back in the response.
     pub opaque: u32,
+    /// The version of the item, or 0 for none.
     pub cas: u64,
 }
 
 impl Header {
-    pub fn read_packet(self, body: &[u8]) -> Result<Packet, ProtocolError> {
+    /// Encode the header into its 24 bytes on the wire.
+    pub fn encode(&self) -> [u8; 24] {
+        let mut bytes = [0; 24];
+        bytes[0] = self.magic;
+        bytes[1] = self.opcode;
+        bytes[2..4].copy_from_slice(&self.key_length.to_be_bytes());
+        bytes[4] = self.extras_length;
+        bytes[5] = self.data_type;
+        bytes[6..8].copy_from_slice(&self.vbucket_or_status.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.body_len.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.opaque.to_be_bytes());
+        bytes[16..24].copy_from_slice(&self.cas.to_be_bytes());
+        bytes
+    }
+
+    pub(crate) fn read_packet(self, body: &[u8]) -> Result<Packet, ProtocolError> {
         if body.len() != self.body_len as usize {
             // The body length does not match the header
             return Err(ProtocolError::BodySizeMismatch);
         }
+        if self.extras_length as usize + self.key_length as usize > body.len() {
+            // The extras and key cannot fit in the body
+            return Err(ProtocolError::InvalidLength);
+        }
 
         let (extras, body) = body.split_at(self.extras_length as usize);
         let (key, value) = body.split_at(self.key_length as usize);
@@ -40,13 +73,23 @@ impl Header {
         })
     }
 
+    /// Decode a response header from the first 24 bytes of the buffer.
     pub fn read_response(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        Header::read(bytes, MAGIC_RESPONSE_VALUE)
+    }
+
+    /// Decode a request header from the first 24 bytes of the buffer.
+    pub fn read_request(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        Header::read(bytes, MAGIC_REQUEST_VALUE)
+    }
+
+    fn read(bytes: &[u8], expect_magic: u8) -> Result<Self, ProtocolError> {
         if bytes.len() < 24 {
             // The header must be 24 bytes
             return Err(ProtocolError::PacketTooSmall);
         }
         let magic = u8::from_be_bytes(bytes[0..1].try_into().unwrap());
-        if magic != MAGIC_RESPONSE_VALUE {
+        if magic != expect_magic {
             return Err(ProtocolError::InvalidMagic(magic));
         }
         Ok(Header {
@@ -78,6 +121,69 @@ impl SetExtras {
     }
 }
 
+#[derive(
+    Debug, Default, PartialEq, Clone, Copy, ::serde_derive::Serialize, ::serde_derive::Deserialize,
+)]
+#[repr(C)]
+pub struct CounterExtras {
+    pub delta: u64,
+    pub initial: u64,
+    pub expire: u32,
+}
+
+impl CounterExtras {
+    pub fn new(delta: u64, initial: u64, expire: u32) -> Self {
+        Self {
+            delta,
+            initial,
+            expire,
+        }
+    }
+}
+
+#[derive(
+    Debug, Default, PartialEq, Clone, Copy, ::serde_derive::Serialize, ::serde_derive::Deserialize,
+)]
+#[repr(C)]
+pub struct TouchExtras {
+    pub expire: u32,
+}
+
+impl TouchExtras {
+    pub fn new(expire: u32) -> Self {
+        Self { expire }
+    }
+}
+
+#[derive(
+    Debug, Default, PartialEq, Clone, Copy, ::serde_derive::Serialize, ::serde_derive::Deserialize,
+)]
+#[repr(C)]
+pub struct FlushExtras {
+    pub delay: u32,
+}
+
+impl FlushExtras {
+    pub fn new(delay: u32) -> Self {
+        Self { delay }
+    }
+}
+
+/// The fields of a request header which are not derived from its body: the
+/// CAS value a store or delete must match, or 0 to store unconditionally,
+/// and the vbucket id of the key, for servers and proxies routing by vbucket.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct RequestFields {
+    pub cas: u64,
+    pub vbucket: u16,
+}
+
+impl RequestFields {
+    pub fn new(cas: u64, vbucket: u16) -> Self {
+        Self { cas, vbucket }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct Packet {
     pub header: Header,
@@ -87,11 +193,40 @@ pub struct Packet {
 }
 
 impl Packet {
-    fn new_request<K: AsRef<[u8]>, V: Serialize + ?Sized, E: Serialize>(
+    /// Parse a complete request from a buffer, as a server would. Malformed
+    /// input returns a [`ProtocolError`] rather than panicking, whatever the
+    /// lengths in the header claim.
+    pub fn read_request(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let header = Header::read_request(bytes)?;
+        header.read_packet(&bytes[24..])
+    }
+
+    /// Parse a complete response from a buffer, as a client would.
+    pub fn read_response(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let header = Header::read_response(bytes)?;
+        header.read_packet(&bytes[24..])
+    }
+
+    /// Create a request with any opcode, serializing the value with bincode.
+    pub fn new_request<K: AsRef<[u8]>, V: Serialize + ?Sized, E: Serialize>(
         opcode: u8,
         key: K,
         extras: &E,
         value: &V,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        let value = bincode::serialize(value)?;
+        Packet::new_raw_request(opcode, key, extras, value, fields)
+    }
+
+    /// Create a request whose value is already serialized, so the bytes are
+    /// moved into the packet as-is without going through bincode.
+    pub fn new_raw_request<K: AsRef<[u8]>, E: Serialize>(
+        opcode: u8,
+        key: K,
+        extras: &E,
+        value: Vec<u8>,
+        fields: RequestFields,
     ) -> bincode::Result<Self> {
         let config = DefaultOptions::new()
             .with_big_endian()
@@ -99,10 +234,11 @@ impl Packet {
 
         let mut packet = Packet::default();
         let key = key.as_ref();
-        let value = bincode::serialize(value)?;
         let extras = config.serialize(extras)?;
         packet.header.magic = MAGIC_REQUEST_VALUE;
         packet.header.opcode = opcode;
+        packet.header.vbucket_or_status = fields.vbucket;
+        packet.header.cas = fields.cas;
         packet.header.key_length = key.len() as u16;
         packet.header.extras_length = extras.len() as u8;
         packet.header.body_len = (extras.len() + key.len() + value.len()) as u32;
@@ -113,19 +249,19 @@ impl Packet {
     }
 
     pub fn get<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
-        Packet::new_request(GET_OPCODE, key, b"", b"")
+        Packet::new_request(GET_OPCODE, key, b"", b"", RequestFields::default())
     }
 
     pub fn getk<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
-        Packet::new_request(GETK_OPCODE, key, b"", b"")
+        Packet::new_request(GETK_OPCODE, key, b"", b"", RequestFields::default())
     }
 
     pub fn getq<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
-        Packet::new_request(GETQ_OPCODE, key, b"", b"")
+        Packet::new_request(GETQ_OPCODE, key, b"", b"", RequestFields::default())
     }
 
     pub fn getkq<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
-        Packet::new_request(GETKQ_OPCODE, key, b"", b"")
+        Packet::new_request(GETKQ_OPCODE, key, b"", b"", RequestFields::default())
     }
 
     pub fn set<K: AsRef<[u8]>, V: Serialize + ?Sized>(
@@ -133,7 +269,33 @@ impl Packet {
         value: &V,
         extras: SetExtras,
     ) -> bincode::Result<Self> {
-        Packet::new_request(SET_OPCODE, key, &extras, value)
+        Packet::set_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn set_with<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        key: K,
+        value: &V,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_request(SET_OPCODE, key, &extras, value, fields)
+    }
+
+    pub fn set_raw<K: AsRef<[u8]>>(
+        key: K,
+        value: Vec<u8>,
+        extras: SetExtras,
+    ) -> bincode::Result<Self> {
+        Packet::set_raw_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn set_raw_with<K: AsRef<[u8]>>(
+        key: K,
+        value: Vec<u8>,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_raw_request(SET_OPCODE, key, &extras, value, fields)
     }
 
     pub fn setq<K: AsRef<[u8]>, V: Serialize + ?Sized>(
@@ -141,7 +303,24 @@ impl Packet {
         value: &V,
         extras: SetExtras,
     ) -> bincode::Result<Self> {
-        Packet::new_request(SETQ_OPCODE, key, &extras, value)
+        Packet::setq_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn setq_with<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        key: K,
+        value: &V,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_request(SETQ_OPCODE, key, &extras, value, fields)
+    }
+
+    pub fn setq_raw<K: AsRef<[u8]>>(
+        key: K,
+        value: Vec<u8>,
+        extras: SetExtras,
+    ) -> bincode::Result<Self> {
+        Packet::new_raw_request(SETQ_OPCODE, key, &extras, value, RequestFields::default())
     }
 
     pub fn add<K: AsRef<[u8]>, V: Serialize + ?Sized>(
@@ -149,7 +328,16 @@ impl Packet {
         value: &V,
         extras: SetExtras,
     ) -> bincode::Result<Self> {
-        Packet::new_request(ADD_OPCODE, key, &extras, value)
+        Packet::add_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn add_with<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        key: K,
+        value: &V,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_request(ADD_OPCODE, key, &extras, value, fields)
     }
 
     pub fn addq<K: AsRef<[u8]>, V: Serialize + ?Sized>(
@@ -157,7 +345,16 @@ impl Packet {
         value: &V,
         extras: SetExtras,
     ) -> bincode::Result<Self> {
-        Packet::new_request(ADDQ_OPCODE, key, &extras, value)
+        Packet::addq_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn addq_with<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        key: K,
+        value: &V,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_request(ADDQ_OPCODE, key, &extras, value, fields)
     }
 
     pub fn replace<K: AsRef<[u8]>, V: Serialize + ?Sized>(
@@ -165,7 +362,16 @@ impl Packet {
         value: &V,
         extras: SetExtras,
     ) -> bincode::Result<Self> {
-        Packet::new_request(REPLACE_OPCODE, key, &extras, value)
+        Packet::replace_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn replace_with<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        key: K,
+        value: &V,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_request(REPLACE_OPCODE, key, &extras, value, fields)
     }
 
     pub fn replaceq<K: AsRef<[u8]>, V: Serialize + ?Sized>(
@@ -173,19 +379,230 @@ impl Packet {
         value: &V,
         extras: SetExtras,
     ) -> bincode::Result<Self> {
-        Packet::new_request(REPLACEQ_OPCODE, key, &extras, value)
+        Packet::replaceq_with(key, value, extras, RequestFields::default())
+    }
+
+    pub fn replaceq_with<K: AsRef<[u8]>, V: Serialize + ?Sized>(
+        key: K,
+        value: &V,
+        extras: SetExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_request(REPLACEQ_OPCODE, key, &extras, value, fields)
     }
 
     pub fn delete<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
-        Packet::new_request(DELETE_OPCODE, key, b"", b"")
+        Packet::delete_with(key, RequestFields::default())
+    }
+
+    pub fn delete_with<K: AsRef<[u8]>>(key: K, fields: RequestFields) -> bincode::Result<Self> {
+        Packet::new_request(DELETE_OPCODE, key, b"", b"", fields)
+    }
+
+    pub fn deleteq<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
+        Packet::deleteq_with(key, RequestFields::default())
+    }
+
+    pub fn deleteq_with<K: AsRef<[u8]>>(key: K, fields: RequestFields) -> bincode::Result<Self> {
+        Packet::new_request(DELETEQ_OPCODE, key, b"", b"", fields)
+    }
+
+    pub fn incr<K: AsRef<[u8]>>(key: K, extras: CounterExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(
+            INCREMENT_OPCODE,
+            key,
+            &extras,
+            vec![],
+            RequestFields::default(),
+        )
+    }
+
+    pub fn incrq<K: AsRef<[u8]>>(key: K, extras: CounterExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(
+            INCREMENTQ_OPCODE,
+            key,
+            &extras,
+            vec![],
+            RequestFields::default(),
+        )
+    }
+
+    pub fn decr<K: AsRef<[u8]>>(key: K, extras: CounterExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(
+            DECREMENT_OPCODE,
+            key,
+            &extras,
+            vec![],
+            RequestFields::default(),
+        )
+    }
+
+    pub fn decrq<K: AsRef<[u8]>>(key: K, extras: CounterExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(
+            DECREMENTQ_OPCODE,
+            key,
+            &extras,
+            vec![],
+            RequestFields::default(),
+        )
+    }
+
+    pub fn touch<K: AsRef<[u8]>>(key: K, extras: TouchExtras) -> bincode::Result<Self> {
+        Packet::touch_with(key, extras, RequestFields::default())
+    }
+
+    pub fn touch_with<K: AsRef<[u8]>>(
+        key: K,
+        extras: TouchExtras,
+        fields: RequestFields,
+    ) -> bincode::Result<Self> {
+        Packet::new_raw_request(TOUCH_OPCODE, key, &extras, vec![], fields)
+    }
+
+    pub fn gat<K: AsRef<[u8]>>(key: K, extras: TouchExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(GAT_OPCODE, key, &extras, vec![], RequestFields::default())
+    }
+
+    pub fn gatkq<K: AsRef<[u8]>>(key: K, extras: TouchExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(GATKQ_OPCODE, key, &extras, vec![], RequestFields::default())
+    }
+
+    pub fn flush(extras: FlushExtras) -> bincode::Result<Self> {
+        Packet::new_raw_request(FLUSH_OPCODE, b"", &extras, vec![], RequestFields::default())
     }
 
     pub fn noop() -> bincode::Result<Self> {
-        Packet::new_request(NOOP_OPCODE, b"", b"", b"")
+        Packet::new_request(NOOP_OPCODE, b"", b"", b"", RequestFields::default())
     }
 
     pub fn version() -> bincode::Result<Self> {
-        Packet::new_request(VERSION_OPCODE, b"", b"", b"")
+        Packet::new_request(VERSION_OPCODE, b"", b"", b"", RequestFields::default())
+    }
+
+    pub fn quit() -> bincode::Result<Self> {
+        Packet::new_request(QUIT_OPCODE, b"", b"", b"", RequestFields::default())
+    }
+
+    pub fn sasl_auth(mechanism: &str, response: Vec<u8>) -> bincode::Result<Self> {
+        Packet::new_raw_request(
+            SASL_AUTH_OPCODE,
+            mechanism,
+            b"",
+            response,
+            RequestFields::default(),
+        )
+    }
+
+    pub fn sasl_step(mechanism: &str, response: Vec<u8>) -> bincode::Result<Self> {
+        Packet::new_raw_request(
+            SASL_STEP_OPCODE,
+            mechanism,
+            b"",
+            response,
+            RequestFields::default(),
+        )
+    }
+
+    pub fn stat<K: AsRef<[u8]>>(key: K) -> bincode::Result<Self> {
+        Packet::new_raw_request(STAT_OPCODE, key, b"", vec![], RequestFields::default())
+    }
+
+    /// The CAS value and vbucket id of a request.
+    pub fn fields(&self) -> RequestFields {
+        RequestFields::new(self.header.cas, self.header.vbucket_or_status)
+    }
+
+    /// Replace the CAS value and vbucket id of a request.
+    pub fn set_fields(&mut self, fields: RequestFields) {
+        self.header.cas = fields.cas;
+        self.header.vbucket_or_status = fields.vbucket;
+    }
+
+    pub fn flags(&self) -> u32 {
+        match self.extras.get(0..4) {
+            Some(bytes) => u32::from_be_bytes(bytes.try_into().unwrap()),
+            None => 0,
+        }
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        if let Some(bytes) = self.extras.get_mut(0..4) {
+            bytes.copy_from_slice(&flags.to_be_bytes());
+        }
+    }
+
+    /// The expiration of a store request, which follows the flags in the
+    /// extras.
+    pub fn expire(&self) -> u32 {
+        match self.extras.get(4..8) {
+            Some(bytes) => u32::from_be_bytes(bytes.try_into().unwrap()),
+            None => 0,
+        }
+    }
+
+    /// Whether this is a request to store a value, such as a set, add or
+    /// replace.
+    pub fn is_store(&self) -> bool {
+        matches!(
+            self.header.opcode,
+            SET_OPCODE | SETQ_OPCODE | ADD_OPCODE | ADDQ_OPCODE | REPLACE_OPCODE | REPLACEQ_OPCODE
+        )
+    }
+
+    /// Whether this is a get request or response, of any variant, including
+    /// gets which touch the key.
+    pub fn is_get(&self) -> bool {
+        matches!(
+            self.header.opcode,
+            GET_OPCODE | GETQ_OPCODE | GETK_OPCODE | GETKQ_OPCODE | GAT_OPCODE | GATKQ_OPCODE
+        )
+    }
+
+    /// Whether this is a NOOP request or response.
+    pub fn is_noop(&self) -> bool {
+        self.header.opcode == NOOP_OPCODE
+    }
+
+    /// Whether sending this request twice has the same effect as sending it
+    /// once, so that it can be sent again when its response was lost. Only
+    /// reads, touches, deletes and plain sets are: counters would move twice,
+    /// and a repeated CAS or conditional store would fail after the first
+    /// one landed.
+    pub fn is_idempotent(&self) -> bool {
+        match self.header.opcode {
+            SET_OPCODE | SETQ_OPCODE | DELETE_OPCODE | DELETEQ_OPCODE => self.header.cas == 0,
+            TOUCH_OPCODE | NOOP_OPCODE | VERSION_OPCODE | STAT_OPCODE | FLUSH_OPCODE => true,
+            _ => self.is_get(),
+        }
+    }
+
+    /// Whether the key of this packet names an item, as opposed to, say, the
+    /// group of a stats request.
+    pub fn has_item_key(&self) -> bool {
+        matches!(
+            self.header.opcode,
+            GET_OPCODE
+                | GETQ_OPCODE
+                | GETK_OPCODE
+                | GETKQ_OPCODE
+                | DELETE_OPCODE
+                | DELETEQ_OPCODE
+                | INCREMENT_OPCODE
+                | INCREMENTQ_OPCODE
+                | DECREMENT_OPCODE
+                | DECREMENTQ_OPCODE
+                | TOUCH_OPCODE
+                | GAT_OPCODE
+                | GATKQ_OPCODE
+        ) || self.is_store()
+    }
+
+    /// Replace the key, updating the lengths in the header.
+    pub fn set_key(&mut self, key: Vec<u8>) {
+        let body_len = self.header.body_len as usize - self.key.len() + key.len();
+        self.header.key_length = key.len() as u16;
+        self.header.body_len = body_len as u32;
+        self.key = key;
     }
 
     pub fn error_for_status(&self) -> Result<(), Status> {
@@ -198,20 +615,20 @@ impl Packet {
     pub fn deserialize_value<V: DeserializeOwned>(&self) -> bincode::Result<V> {
         bincode::deserialize(&self.value)
     }
+
+    /// Read the 64-bit counter value returned by an increment or decrement.
+    pub fn counter_value(&self) -> Result<u64, ProtocolError> {
+        match self.value[..].try_into() {
+            Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+            Err(_) => Err(ProtocolError::BodySizeMismatch),
+        }
+    }
 }
 
 impl From<Packet> for Vec<u8> {
     fn from(p: Packet) -> Self {
-        vec![
-            &p.header.magic.to_be_bytes()[..],
-            &p.header.opcode.to_be_bytes()[..],
-            &p.header.key_length.to_be_bytes()[..],
-            &p.header.extras_length.to_be_bytes()[..],
-            &p.header.data_type.to_be_bytes()[..],
-            &p.header.vbucket_or_status.to_be_bytes()[..],
-            &p.header.body_len.to_be_bytes()[..],
-            &p.header.opaque.to_be_bytes()[..],
-            &p.header.cas.to_be_bytes()[..],
+        [
+            &p.header.encode()[..],
             &p.extras[..],
             &p.key[..],
             &p.value[..],
@@ -222,8 +639,54 @@ impl From<Packet> for Vec<u8> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Packet, SetExtras};
-    use crate::protocol::Header;
+    use super::{CounterExtras, Packet, RequestFields, SetExtras, TouchExtras};
+    use crate::protocol::{Header, ProtocolError};
+
+    #[test]
+    fn test_adversarial_lengths() {
+        let packet = Packet::set(b"key", b"value", SetExtras::new(0, 0)).unwrap();
+        let bytes: Vec<u8> = packet.clone().into();
+        assert_eq!(packet, Packet::read_request(&bytes).unwrap());
+
+        // The key length claims more bytes than the body holds.
+        let mut bad = bytes.clone();
+        bad[2..4].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert_eq!(
+            Err(ProtocolError::InvalidLength),
+            Packet::read_request(&bad)
+        );
+        let mut bad = bytes.clone();
+        bad[4] = u8::MAX;
+        assert_eq!(
+            Err(ProtocolError::InvalidLength),
+            Packet::read_request(&bad)
+        );
+        assert_eq!(
+            Err(ProtocolError::PacketTooSmall),
+            Packet::read_request(&bytes[..10])
+        );
+        assert_eq!(
+            Err(ProtocolError::BodySizeMismatch),
+            Packet::read_request(&bytes[..30])
+        );
+
+        // Arbitrary bytes never panic.
+        let mut seed = 7_u32;
+        for len in 0..200 {
+            let noise = (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (seed >> 16) as u8
+                })
+                .collect::<Vec<_>>();
+            let mut framed = noise.clone();
+            if let Some(magic) = framed.first_mut() {
+                *magic = 0x80;
+            }
+            let _ = Packet::read_request(&noise);
+            let _ = Packet::read_request(&framed);
+        }
+    }
 
     #[test]
     fn test_packet_identity() {
@@ -278,6 +741,34 @@ mod tests {
         assert_eq!(packet, actual_packet);
     }
 
+    #[test]
+    fn test_request_fields() {
+        let fields = RequestFields::new(0x0102030405060708, 0x0a0b);
+        let extras = SetExtras::new(0, 0);
+        let packet = Packet::set_raw_with(b"key", b"value".to_vec(), extras, fields).unwrap();
+        let bytes: Vec<u8> = packet.clone().into();
+        assert_eq!([0x0a, 0x0b], bytes[6..8]);
+        assert_eq!([1, 2, 3, 4, 5, 6, 7, 8], bytes[16..24]);
+        assert_eq!(packet, Packet::read_request(&bytes).unwrap());
+
+        let custom = Packet::new_raw_request(0x01, b"key", &extras, b"value".to_vec(), fields);
+        assert_eq!(packet, custom.unwrap());
+        let plain = Packet::set_raw(b"key", b"value".to_vec(), extras).unwrap();
+        assert_eq!((0, 0), (plain.header.cas, plain.header.vbucket_or_status));
+
+        let touch = TouchExtras::new(60);
+        let packets = [
+            Packet::add_with(b"key", "value", extras, fields).unwrap(),
+            Packet::replaceq_with(b"key", "value", extras, fields).unwrap(),
+            Packet::delete_with(b"key", fields).unwrap(),
+            Packet::touch_with(b"key", touch, fields).unwrap(),
+        ];
+        assert!(packets.iter().all(|packet| packet.fields() == fields));
+        let mut plain = plain;
+        plain.set_fields(fields);
+        assert_eq!(fields, plain.fields());
+    }
+
     #[test]
     fn test_extras() {
         let extras = SetExtras::new(0x00000000, 0xABCD0000);
@@ -286,4 +777,32 @@ mod tests {
         let expect = vec![0, 0, 0, 0, 0xAB, 0xCD, 0x00, 0x00];
         assert_eq!(expect, actual);
     }
+
+    #[test]
+    fn test_counter_extras() {
+        let packet = Packet::incr(b"key", CounterExtras::new(1, 5, 0xdeadbeef)).unwrap();
+        let expect = vec![
+            0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 5, 0xde, 0xad, 0xbe, 0xef,
+        ];
+        assert_eq!(expect, packet.extras);
+        assert_eq!(20, packet.header.extras_length);
+        assert_eq!(23, packet.header.body_len);
+    }
+
+    #[test]
+    fn test_borrowed_values() {
+        use std::{borrow::Cow, sync::Arc};
+
+        let extras = SetExtras::new(0, 0);
+        let owned = Packet::set(b"key", &String::from("value"), extras).unwrap();
+        let borrowed = Packet::set(b"key", "value", extras).unwrap();
+        let cow = Packet::set(b"key", &Cow::Borrowed("value"), extras).unwrap();
+        let arc = Packet::set(b"key", &Arc::new(String::from("value")), extras).unwrap();
+        assert_eq!(owned, borrowed);
+        assert_eq!(owned, cow);
+        assert_eq!(owned, arc);
+
+        let raw = Packet::set_raw(b"key", owned.value.clone(), extras).unwrap();
+        assert_eq!(owned, raw);
+    }
 }
@@ -0,0 +1,413 @@
+//! Every runtime adapter needs to replace connections that were dropped by
+//! the server or the network, without hammering a server that is down. This
+//! module provides a decorator around any [`Connection`] which owns that
+//! logic: once the inner connection fails it is dropped, and a new one is
+//! made before the next write, no sooner than a [`ReconnectPolicy`] allows.
+//!
+//! When a node dies under load, every pooled client would otherwise retry
+//! at once. The backoff is therefore shared by every connection to the same
+//! endpoint in the process: once an attempt to connect to an endpoint has
+//! failed, a single connection at a time may try again, after a jittered
+//! backoff, and the others fail fast with [`Error::ConnectionClosed`] until
+//! one of them succeeds. The fleet makes a bounded trickle of attempts
+//! instead of a reconnect storm.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Connection, Error},
+    protocol::Header,
+    rng::jitter_fraction,
+};
+
+static ENDPOINTS: Mutex<Option<HashMap<String, Arc<Mutex<Attempts>>>>> = Mutex::new(None);
+
+/// The state of a [`ReconnectingConnection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The inner connection is open.
+    Connected,
+    /// The inner connection failed, and will be replaced before the next
+    /// write once the backoff has passed.
+    Disconnected,
+}
+
+/// Decides how long a [`ReconnectingConnection`] waits between attempts to
+/// reconnect, and receives its state changes. The policy is created with
+/// [`Default`] whenever a connection is made, so policies that report to
+/// the rest of the application should share their state, for example
+/// through a static.
+pub trait ReconnectPolicy: Debug + Default + Clone + Send + Sync + 'static {
+    /// How long to wait after the given number of consecutive failed
+    /// attempts before trying again. Defaults to an exponential backoff
+    /// starting at 100ms and capped at 10 seconds.
+    fn backoff(&self, failures: u32) -> Duration {
+        let exp = failures.saturating_sub(1).min(7);
+        Duration::from_millis(100 << exp).min(Duration::from_secs(10))
+    }
+
+    /// The random wait added to a backoff, so that connections which failed
+    /// together do not try again together. Defaults to up to half of the
+    /// backoff.
+    fn jitter(&self, backoff: Duration) -> Duration {
+        backoff.mul_f64(jitter_fraction() / 2.0)
+    }
+
+    /// Called when the connection to the endpoint is lost or restored.
+    fn on_state_change(&self, _endpoint: &str, _state: ConnectionState) {}
+}
+
+/// The default [`ReconnectPolicy`], with an exponential backoff and no
+/// state change hook.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExponentialBackoff;
+
+impl ReconnectPolicy for ExponentialBackoff {}
+
+/// The attempts to connect to an endpoint, shared by every connection to
+/// it in the process.
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: u32,
+    retry_at: Option<Instant>,
+    probing: bool,
+}
+
+impl Attempts {
+    /// Get the attempts shared by all connections to the endpoint.
+    fn get(endpoint: &str) -> Arc<Mutex<Attempts>> {
+        let mut endpoints = ENDPOINTS.lock().unwrap();
+        let endpoints = endpoints.get_or_insert_with(HashMap::new);
+        endpoints.entry(endpoint.to_string()).or_default().clone()
+    }
+}
+
+/// Allows a single attempt at a time to connect to an endpoint which is
+/// down, releasing it when dropped, even if the attempt was cancelled.
+struct Probe(Arc<Mutex<Attempts>>);
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().probing = false;
+    }
+}
+
+/// Connect to the endpoint, unless it is down and another connection is
+/// already trying to reach it, or its backoff has not passed yet.
+async fn connect_shared<C: Connection, R: ReconnectPolicy>(
+    endpoint: &str,
+    policy: &R,
+) -> Result<C, Error> {
+    let attempts = Attempts::get(endpoint);
+    let _probe = {
+        let mut state = attempts.lock().unwrap();
+        if state.failures == 0 {
+            None
+        } else if state.probing || state.retry_at.is_some_and(|at| C::now() < at) {
+            return Err(Error::ConnectionClosed);
+        } else {
+            state.probing = true;
+            Some(Probe(attempts.clone()))
+        }
+    };
+    let result = C::connect(endpoint.to_string()).await;
+    let mut state = attempts.lock().unwrap();
+    match &result {
+        Ok(_) => {
+            state.failures = 0;
+            state.retry_at = None;
+        }
+        Err(_) => {
+            state.failures += 1;
+            let backoff = policy.backoff(state.failures);
+            state.retry_at = Some(C::now() + backoff + policy.jitter(backoff));
+        }
+    }
+    result
+}
+
+#[derive(Debug)]
+struct Shared<C> {
+    conn: Option<C>,
+}
+
+/// A connection which replaces the inner connection after it fails, with a
+/// backoff between attempts decided by the policy `R` and shared by every
+/// connection to the endpoint. Use it in place of
+/// the inner connection type, e.g.
+/// `ClientConfig<ReconnectingConnection<TokioConnection>, _>`. Clones and
+/// halves of a split share the same inner connection, so a reconnect by
+/// either is seen by both.
+///
+/// Requests in flight when the connection fails are not retried, since
+/// their responses are lost, but the ring sees the node as poisoned and
+/// reconnects through [`Connection::reconnect`], which honors the backoff.
+#[derive(Debug, Clone)]
+pub struct ReconnectingConnection<C: Connection + Debug, R: ReconnectPolicy = ExponentialBackoff> {
+    endpoint: String,
+    policy: R,
+    shared: Arc<Mutex<Shared<C>>>,
+}
+
+impl<C: Connection + Debug, R: ReconnectPolicy> ReconnectingConnection<C, R> {
+    /// Wrap an existing connection to the given endpoint.
+    pub fn new(inner: C, endpoint: String, policy: R) -> Self {
+        let shared = Shared { conn: Some(inner) };
+        Self {
+            endpoint,
+            policy,
+            shared: Arc::new(Mutex::new(shared)),
+        }
+    }
+
+    /// Get the current inner connection, if it is connected.
+    pub fn inner(&self) -> Option<C> {
+        self.shared.lock().unwrap().conn.clone()
+    }
+
+    /// Get the state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        match self.shared.lock().unwrap().conn {
+            Some(_) => ConnectionState::Connected,
+            None => ConnectionState::Disconnected,
+        }
+    }
+
+    fn conn(&self) -> Result<C, Error> {
+        self.inner().ok_or(Error::ConnectionClosed)
+    }
+
+    /// Drop the inner connection, so that it is replaced before the next
+    /// write.
+    fn disconnect(&self) {
+        let dropped = self.shared.lock().unwrap().conn.take().is_some();
+        if dropped {
+            let state = ConnectionState::Disconnected;
+            self.policy.on_state_change(&self.endpoint, state);
+        }
+    }
+
+    /// Make a new inner connection, unless another connection to the
+    /// endpoint is already trying to, or the backoff since the last failed
+    /// attempt has not passed yet.
+    async fn try_reconnect(&self) -> Result<(), Error> {
+        let conn = connect_shared(&self.endpoint, &self.policy).await?;
+        self.shared.lock().unwrap().conn = Some(conn);
+        let state = ConnectionState::Connected;
+        self.policy.on_state_change(&self.endpoint, state);
+        Ok(())
+    }
+
+    fn check<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        if matches!(
+            result,
+            Err(Error::IoError(_)) | Err(Error::ConnectionClosed)
+        ) {
+            self.disconnect();
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<C: Connection + Debug, R: ReconnectPolicy> Connection for ReconnectingConnection<C, R> {
+    async fn connect(url: String) -> Result<Self, Error> {
+        let policy = R::default();
+        let conn = connect_shared(&url, &policy).await?;
+        Ok(Self::new(conn, url, policy))
+    }
+
+    async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut conn = self.conn()?;
+        let result = match conn.read(buf).await {
+            Ok(0) if !buf.is_empty() => Err(Error::ConnectionClosed),
+            result => result,
+        };
+        self.check(result)
+    }
+
+    async fn read_owned(&mut self, n: usize) -> Result<Bytes, Error> {
+        let mut conn = self.conn()?;
+        let result = conn.read_owned(n).await;
+        self.check(result)
+    }
+
+    async fn read_frame(&mut self, max_body: u32) -> Result<(Header, Bytes), Error> {
+        let mut conn = self.conn()?;
+        let result = conn.read_frame(max_body).await;
+        self.check(result)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        if !self.is_connected() {
+            self.try_reconnect().await?;
+        }
+        let mut conn = self.conn()?;
+        let result = conn.write(data).await;
+        self.check(result)
+    }
+
+    async fn reconnect(&mut self, _url: String) -> Result<(), Error> {
+        // The stream may be in an unknown state, so it is always replaced.
+        self.disconnect();
+        self.try_reconnect().await
+    }
+
+    async fn sleep(duration: Duration) {
+        C::sleep(duration).await
+    }
+
+    fn now() -> Instant {
+        C::now()
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        let result = match self.inner() {
+            Some(mut conn) => conn.shutdown().await,
+            None => Ok(()),
+        };
+        self.disconnect();
+        result
+    }
+
+    fn is_connected(&self) -> bool {
+        self.state() == ConnectionState::Connected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use std::{
+        io::ErrorKind,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        client::{Connection, Error, NoCompressor},
+        mock::MockConnection,
+        protocol::Packet,
+    };
+
+    use super::{Attempts, ConnectionState, ReconnectPolicy, ReconnectingConnection};
+
+    static DISCONNECTS: AtomicUsize = AtomicUsize::new(0);
+    static CONNECTS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Default, Clone)]
+    struct CountingPolicy;
+
+    impl ReconnectPolicy for CountingPolicy {
+        fn on_state_change(&self, _: &str, state: ConnectionState) {
+            let counter = match state {
+                ConnectionState::Connected => &CONNECTS,
+                ConnectionState::Disconnected => &DISCONNECTS,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_reconnecting_connection() {
+        tokio_test::block_on(async {
+            type Conn = ReconnectingConnection<MockConnection, CountingPolicy>;
+            let mut conn = Conn::connect("reconnecting".into()).await.unwrap();
+            conn.write_packet(NoCompressor, Packet::noop().unwrap())
+                .await
+                .unwrap();
+            conn.read_packet(NoCompressor).await.unwrap();
+
+            conn.inner().unwrap().close();
+            conn.write_packet(NoCompressor, Packet::noop().unwrap())
+                .await
+                .unwrap();
+            let err = conn.read_packet(NoCompressor).await.unwrap_err();
+            assert!(err.is_connection_closed());
+            assert!(!conn.is_connected());
+            assert_eq!(1, DISCONNECTS.load(Ordering::Relaxed));
+
+            // The next write replaces the connection.
+            conn.write_packet(NoCompressor, Packet::noop().unwrap())
+                .await
+                .unwrap();
+            conn.read_packet(NoCompressor).await.unwrap();
+            assert_eq!(ConnectionState::Connected, conn.state());
+            assert_eq!(1, CONNECTS.load(Ordering::Relaxed));
+        });
+
+        let policy = CountingPolicy;
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(400), policy.backoff(3));
+        assert_eq!(Duration::from_secs(10), policy.backoff(100));
+    }
+
+    static DOWN: AtomicBool = AtomicBool::new(true);
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A connection to a server which refuses connections while it is down.
+    #[derive(Debug, Clone)]
+    struct FlakyConn;
+
+    #[async_trait]
+    impl Connection for FlakyConn {
+        async fn connect(_: String) -> Result<Self, Error> {
+            ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+            match DOWN.load(Ordering::Relaxed) {
+                true => Err(Error::IoError(ErrorKind::ConnectionRefused.into())),
+                false => Ok(FlakyConn),
+            }
+        }
+        async fn read(&mut self, _: &mut Vec<u8>) -> Result<usize, Error> {
+            Ok(0)
+        }
+        async fn write(&mut self, _: &[u8]) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct NoBackoff;
+
+    impl ReconnectPolicy for NoBackoff {
+        fn backoff(&self, _: u32) -> Duration {
+            Duration::ZERO
+        }
+    }
+
+    #[test]
+    fn test_shared_backoff() {
+        tokio_test::block_on(async {
+            type Conn = ReconnectingConnection<FlakyConn>;
+            let err = Conn::connect("flaky:1".into()).await.unwrap_err();
+            assert!(matches!(err, Error::IoError(_)));
+            // Other connections to the endpoint wait out the backoff.
+            for _ in 0..10 {
+                let err = Conn::connect("flaky:1".into()).await.unwrap_err();
+                assert!(err.is_connection_closed());
+            }
+            assert_eq!(1, ATTEMPTS.load(Ordering::Relaxed));
+
+            type Eager = ReconnectingConnection<FlakyConn, NoBackoff>;
+            assert!(Eager::connect("flaky:2".into()).await.is_err());
+            let attempts = Attempts::get("flaky:2");
+            attempts.lock().unwrap().probing = true;
+            let err = Eager::connect("flaky:2".into()).await.unwrap_err();
+            assert!(err.is_connection_closed());
+            assert_eq!(2, ATTEMPTS.load(Ordering::Relaxed));
+
+            attempts.lock().unwrap().probing = false;
+            DOWN.store(false, Ordering::Relaxed);
+            assert!(Eager::connect("flaky:2".into()).await.is_ok());
+            assert_eq!(0, attempts.lock().unwrap().failures);
+            assert!(!attempts.lock().unwrap().probing);
+        });
+    }
+}
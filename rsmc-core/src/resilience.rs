@@ -0,0 +1,286 @@
+//! How the cache degrades when memcached misbehaves is spread over several
+//! knobs: error budgets make reads to failing nodes fail open, retry budgets
+//! cap retries to flapping nodes, the load shedder drops operations when the
+//! cluster is too slow, the local tier can serve stale values on errors, and
+//! writes can be suppressed or the cache switched off entirely. A
+//! [`ResilienceConfig`] sets all of them in one place with
+//! [`crate::client::ClientConfig::with_resilience`], and counts every
+//! degraded operation in one set of [`ResilienceStats`], so that dashboards
+//! see the same numbers whichever knob kicked in. Each degraded operation
+//! is counted once, by the knob which degraded it first.
+//!
+//! With a loader fallback, [`crate::client::Client::get_or_load`] treats a
+//! cache which fails like a miss, and loads the value from the source of
+//! truth instead of failing the request.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    budget::{ErrorBudget, RetryBudget},
+    limit::LoadShedder,
+};
+
+/// Counts of the operations degraded by a [`ResilienceConfig`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResilienceStats {
+    /// Whether the cache is enabled, or switched off by the kill switch.
+    pub enabled: bool,
+    /// Reads which did not touch the network because their node exhausted
+    /// its error budget, whether they missed or were served a stale value.
+    pub failed_open_reads: u64,
+    /// Reads which failed with an error, and were served a stale local value
+    /// instead.
+    pub stale_reads: u64,
+    /// Operations dropped by the load shedder, including gets from the pool
+    /// with [`LoadShedder::get`]. A bulk operation is dropped once for each
+    /// node it waits for.
+    pub shed: u64,
+    /// Writes rejected because writes are suppressed.
+    pub suppressed_writes: u64,
+    /// Failed reads and writes of [`crate::client::Client::get_or_load`]
+    /// which fell back to the loader, or skipped writing its value back.
+    pub loader_fallbacks: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ResilienceCounters {
+    failed_open_reads: AtomicU64,
+    stale_reads: AtomicU64,
+    suppressed_writes: AtomicU64,
+    loader_fallbacks: AtomicU64,
+}
+
+impl ResilienceCounters {
+    pub fn failed_open(&self, reads: usize) {
+        let reads = reads as u64;
+        self.failed_open_reads.fetch_add(reads, Ordering::Relaxed);
+    }
+
+    pub fn stale(&self) {
+        self.stale_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn suppressed(&self) {
+        self.suppressed_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn loader_fallback(&self) {
+        self.loader_fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Every setting deciding how the cache degrades, applied together. Clones
+/// share the kill switch and the stats, so keep one to flip the switch and
+/// read the stats of every client created from the config.
+#[derive(Debug, Clone)]
+pub struct ResilienceConfig {
+    pub(crate) circuit_breaker: Option<ErrorBudget>,
+    pub(crate) retry_budget: Option<RetryBudget>,
+    pub(crate) load_shedder: Option<LoadShedder>,
+    pub(crate) stale_fallback: Option<Duration>,
+    pub(crate) suppress_writes: bool,
+    pub(crate) loader_fallback: bool,
+    pub(crate) enabled: Arc<AtomicBool>,
+    pub(crate) counters: Arc<ResilienceCounters>,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        Self {
+            circuit_breaker: None,
+            retry_budget: None,
+            load_shedder: None,
+            stale_fallback: None,
+            suppress_writes: false,
+            loader_fallback: false,
+            enabled: Arc::new(AtomicBool::new(true)),
+            counters: Arc::default(),
+        }
+    }
+}
+
+impl ResilienceConfig {
+    /// Create a config which never degrades, apart from the kill switch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make reads to a node fail open once its error rate exceeds the
+    /// budget, until the cooldown has passed.
+    pub fn with_circuit_breaker(mut self, budget: ErrorBudget) -> Self {
+        self.circuit_breaker = Some(budget);
+        self
+    }
+
+    /// Cap how many keys of a bulk get are retried after their node closed
+    /// the connection.
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(budget);
+        self
+    }
+
+    /// Shed operations after waits for the cluster which are too slow.
+    pub fn with_load_shedder(mut self, shedder: LoadShedder) -> Self {
+        self.load_shedder = Some(shedder);
+        self
+    }
+
+    /// Fall back to local values up to `max_staleness` past their TTL when
+    /// a read fails. This only applies when the config has a local tier.
+    pub fn with_stale_fallback(mut self, max_staleness: Duration) -> Self {
+        self.stale_fallback = Some(max_staleness);
+        self
+    }
+
+    /// Reject every write with [`crate::client::Error::ReadOnly`], while
+    /// reads continue as usual.
+    pub fn with_write_suppression(mut self, suppress: bool) -> Self {
+        self.suppress_writes = suppress;
+        self
+    }
+
+    /// Load values with the loader of [`crate::client::Client::get_or_load`]
+    /// when reading them from the cache fails, and skip writing them back
+    /// when that fails, instead of returning the error of the cache.
+    pub fn with_loader_fallback(mut self, fallback: bool) -> Self {
+        self.loader_fallback = fallback;
+        self
+    }
+
+    /// Take the cache out of the request path of every client created from
+    /// the config, or put it back. See
+    /// [`crate::client::Client::set_enabled`].
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Get the counts of degraded operations so far.
+    pub fn stats(&self) -> ResilienceStats {
+        let counters = &self.counters;
+        let shed = self
+            .load_shedder
+            .as_ref()
+            .map_or(0, LoadShedder::shed_count);
+        ResilienceStats {
+            enabled: self.enabled.load(Ordering::Relaxed),
+            failed_open_reads: counters.failed_open_reads.load(Ordering::Relaxed),
+            stale_reads: counters.stale_reads.load(Ordering::Relaxed),
+            shed,
+            suppressed_writes: counters.suppressed_writes.load(Ordering::Relaxed),
+            loader_fallbacks: counters.loader_fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        budget::ErrorBudget,
+        client::{Client, ClientConfig, Error},
+        limit::{LoadShedder, WaitSource},
+        local::LocalTier,
+        mock::{MockConnection, Store},
+        protocol::Status,
+        testing::Failure,
+    };
+
+    use super::ResilienceConfig;
+
+    #[test]
+    fn test_resilience() {
+        tokio_test::block_on(async {
+            let budget = ErrorBudget {
+                window: 2,
+                min_requests: 2,
+                max_error_rate: 0.5,
+                cooldown: Duration::from_secs(60),
+            };
+            let resilience = ResilienceConfig::new()
+                .with_circuit_breaker(budget)
+                .with_stale_fallback(Duration::from_secs(60));
+            let local = LocalTier::new(16, Duration::ZERO);
+            let cfg = ClientConfig::new_uncompressed(vec!["resilience".into()])
+                .with_local_tier(local)
+                .with_resilience(resilience.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            client.get::<_, String>("key").await.unwrap();
+
+            // Failed reads are served stale values, until the node fails open.
+            Store::get("resilience")
+                .lock()
+                .unwrap()
+                .fail(b"key", Failure::Disconnect);
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+            client.get::<_, String>("key").await.unwrap();
+            client.get_multi::<_, String>(&["a", "b"]).await.unwrap();
+            let stats = resilience.stats();
+            assert_eq!((1, 3), (stats.stale_reads, stats.failed_open_reads));
+
+            let suppressed = cfg.with_resilience(resilience.clone().with_write_suppression(true));
+            let mut client = Client::<MockConnection, _>::new(suppressed).await.unwrap();
+            let err = client.set("key", "value", 0).await.unwrap_err();
+            assert!(matches!(err, Error::ReadOnly));
+            assert_eq!(1, resilience.stats().suppressed_writes);
+
+            resilience.set_enabled(false);
+            assert!(!client.is_enabled() && !resilience.stats().enabled);
+        });
+    }
+
+    #[test]
+    fn test_loader_fallback() {
+        tokio_test::block_on(async {
+            let shedder = LoadShedder::new(Duration::ZERO).with_shedding(Duration::from_secs(1));
+            let resilience = ResilienceConfig::new()
+                .with_load_shedder(shedder.clone())
+                .with_loader_fallback(true);
+            let cfg = ClientConfig::new_uncompressed(vec!["resilience:loader".into()])
+                .with_resilience(resilience.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            let load = || async { Ok::<_, Error>("loaded".to_string()) };
+
+            // Failed reads and writes fall back to the loader.
+            Store::get("resilience:loader")
+                .lock()
+                .unwrap()
+                .fail(b"key", Failure::Status(Status::Busy));
+            let value = client.get_or_load("key", 0, load).await.unwrap();
+            assert_eq!("loaded", value);
+            assert_eq!(2, resilience.stats().loader_fallbacks);
+
+            // Without the fallback, the error of the cache is returned.
+            let strict = cfg
+                .clone()
+                .with_resilience(resilience.clone().with_loader_fallback(false));
+            let mut client = Client::<MockConnection, _>::new(strict).await.unwrap();
+            let err = client.get_or_load("key", 0, load).await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::Busy)));
+
+            // Shed writes are counted once, by the load shedder.
+            shedder.record(
+                WaitSource::Node("resilience:loader"),
+                Duration::from_secs(2),
+                Instant::now(),
+            );
+            let err = client.set("other", "value", 0).await.unwrap_err();
+            assert!(matches!(err, Error::Shed));
+            let stats = resilience.stats();
+            assert_eq!((1, 2), (stats.shed, stats.loader_fallbacks));
+
+            // Passing no load shedder removes the previous one.
+            let cfg = cfg.with_resilience(ResilienceConfig::new());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("other", "value", 0).await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,189 @@
+//! By default every endpoint is handed to [`Connection::connect`] as is, and
+//! the runtime adapter resolves it with the system resolver. Configuring a
+//! [`Resolver`] resolves endpoints before connecting instead, which allows
+//! plugging in another DNS implementation, caching lookups, preferring an
+//! address family, or testing connection logic without real DNS.
+//!
+//! Endpoints keep their configured names in the ring, so resolving them to
+//! different addresses never moves keys between nodes.
+
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+};
+
+use crate::client::{Connection, Error};
+
+/// Resolves an endpoint to the addresses to connect to, in order of
+/// preference.
+#[async_trait]
+pub trait Resolver: Debug + Send + Sync + 'static {
+    /// Resolve the endpoint, such as `cache.example.com:11211`.
+    async fn resolve(&self, endpoint: &str) -> Result<Vec<SocketAddr>, Error>;
+}
+
+/// Which address families a resolver returns, and in which order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Keep the addresses in the order they were resolved. This is the
+    /// default.
+    #[default]
+    Any,
+    /// Try IPv4 addresses before IPv6 addresses.
+    V4First,
+    /// Try IPv6 addresses before IPv4 addresses.
+    V6First,
+    /// Only use IPv4 addresses.
+    V4Only,
+    /// Only use IPv6 addresses.
+    V6Only,
+}
+
+impl IpPreference {
+    /// Filter and reorder resolved addresses by the preference.
+    pub fn apply(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            IpPreference::Any => (),
+            IpPreference::V4First => addrs.sort_by_key(|addr| addr.is_ipv6()),
+            IpPreference::V6First => addrs.sort_by_key(|addr| addr.is_ipv4()),
+            IpPreference::V4Only => addrs.retain(SocketAddr::is_ipv4),
+            IpPreference::V6Only => addrs.retain(SocketAddr::is_ipv6),
+        }
+        addrs
+    }
+}
+
+/// Resolves endpoints with the system resolver. Lookups block the calling
+/// thread, so prefer a resolver provided by your runtime adapter where
+/// lookups may be slow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver {
+    preference: IpPreference,
+}
+
+impl SystemResolver {
+    /// Create a resolver keeping addresses in the order they were resolved.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter and reorder addresses by the preference.
+    pub fn with_preference(mut self, preference: IpPreference) -> Self {
+        self.preference = preference;
+        self
+    }
+}
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, endpoint: &str) -> Result<Vec<SocketAddr>, Error> {
+        let addrs = endpoint.to_socket_addrs()?.collect();
+        Ok(self.preference.apply(addrs))
+    }
+}
+
+/// Resolves endpoints from a fixed table, for tests and for overriding DNS.
+/// Endpoints missing from the table fail to resolve.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<SocketAddr>>,
+}
+
+impl StaticResolver {
+    /// Create a resolver with an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the endpoint to the given addresses.
+    pub fn with_host<S: Into<String>>(mut self, endpoint: S, addrs: Vec<SocketAddr>) -> Self {
+        self.hosts.insert(endpoint.into(), addrs);
+        self
+    }
+}
+
+#[async_trait]
+impl Resolver for StaticResolver {
+    async fn resolve(&self, endpoint: &str) -> Result<Vec<SocketAddr>, Error> {
+        match self.hosts.get(endpoint) {
+            Some(addrs) => Ok(addrs.clone()),
+            None => Err(Error::Resolve(endpoint.to_string())),
+        }
+    }
+}
+
+/// Get the addresses to connect to for an endpoint, which is the endpoint
+/// itself when there is no resolver.
+pub(crate) async fn addresses(
+    endpoint: &str,
+    resolver: Option<&Arc<dyn Resolver>>,
+) -> Result<Vec<String>, Error> {
+    let resolver = match resolver {
+        Some(resolver) => resolver,
+        None => return Ok(vec![endpoint.to_string()]),
+    };
+    let addrs = resolver.resolve(endpoint).await?;
+    if addrs.is_empty() {
+        return Err(Error::Resolve(endpoint.to_string()));
+    }
+    Ok(addrs.iter().map(SocketAddr::to_string).collect())
+}
+
+/// Connect to the first address of the endpoint which accepts a connection,
+/// returning the error of the last address otherwise.
+pub(crate) async fn connect<C: Connection>(
+    endpoint: &str,
+    resolver: Option<&Arc<dyn Resolver>>,
+) -> Result<C, Error> {
+    let mut result = Err(Error::Resolve(endpoint.to_string()));
+    for address in addresses(endpoint, resolver).await? {
+        result = C::connect(address).await;
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use crate::{
+        client::{Client, ClientConfig, Error},
+        mock::{MockConnection, Store},
+    };
+
+    use super::{IpPreference, Resolver, StaticResolver, SystemResolver};
+
+    #[test]
+    fn test_resolver() {
+        tokio_test::block_on(async {
+            let v4: SocketAddr = "10.0.0.1:11211".parse().unwrap();
+            let v6: SocketAddr = "[fd00::1]:11211".parse().unwrap();
+            assert_eq!(vec![v4, v6], IpPreference::V4First.apply(vec![v6, v4]));
+            assert_eq!(vec![v6], IpPreference::V6Only.apply(vec![v6, v4]));
+            let local: SocketAddr = "127.0.0.1:11211".parse().unwrap();
+            let system = SystemResolver::new().resolve("127.0.0.1:11211").await;
+            assert_eq!(vec![local], system.unwrap());
+
+            // Nodes keep their names, but connect to the resolved address.
+            let resolver = StaticResolver::new().with_host("cache:11211", vec![v4]);
+            let cfg = ClientConfig::new_uncompressed(vec!["cache:11211".into()])
+                .with_resolver(Arc::new(resolver));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            assert_eq!("cache:11211", client.node_stats()[0].endpoint);
+            let store = Store::get("10.0.0.1:11211");
+            assert!(store.lock().unwrap().expire(b"key").is_some());
+
+            let cfg = ClientConfig::new_uncompressed(vec!["unknown:11211".into()])
+                .with_resolver(Arc::new(StaticResolver::new()));
+            let err = Client::<MockConnection, _>::new(cfg).await.unwrap_err();
+            assert!(matches!(err, Error::Resolve(endpoint) if endpoint == "unknown:11211"));
+        });
+    }
+}
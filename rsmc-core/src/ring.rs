@@ -1,8 +1,30 @@
-use murmur3::murmur3_32;
+use futures::{
+    future::{join_all, select, Either},
+    pin_mut,
+};
+#[cfg(feature = "tracing")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::client::{Connection, Error};
-
-const DEFAULT_SIZE: usize = 360;
+use crate::{
+    budget::{BudgetTracker, ErrorBudget},
+    client::{Compressor, Connection, Error, NoCompressor, Protocol, SlowStart, QUIT_TIMEOUT},
+    diagnostics::ConfigError,
+    features::ClusterFeatures,
+    hashing::{DistributionReport, HashScheme, HashSeeds, Placement, DEFAULT_SIZE},
+    keys::KeyCodec,
+    protocol::{AsciiCodec, Packet, ProtocolError},
+    resolve::{self, Resolver},
+    sasl::{self, Authenticator},
+    stats::{NodeCounters, NodeHealth, NodeStats},
+    topology::{NodeSnapshot, TopologySnapshot},
+    vbucket::VbucketRouter,
+    warm::WarmPool,
+};
 
 /// A ring manages multiple connections, using consistent hashing
 /// to map a key to a connection in the ring. If a connection is
@@ -10,8 +32,478 @@ const DEFAULT_SIZE: usize = 360;
 /// be reshuffled.
 #[derive(Debug, Clone)]
 pub struct Ring<C: Connection> {
-    conns: Vec<C>,
-    buckets: Vec<(u32, usize)>,
+    conns: Vec<Node<C>>,
+    placement: Placement,
+    vbuckets: Option<VbucketRouter>,
+    protocol: Protocol,
+    #[cfg(feature = "tracing")]
+    sampler: Option<RouteSampler>,
+}
+
+/// Samples one in every `every` routing decisions to emit as trace events.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone)]
+struct RouteSampler {
+    every: u64,
+    count: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "tracing")]
+impl RouteSampler {
+    fn sample(&self) -> bool {
+        self.count.fetch_add(1, Ordering::Relaxed) % self.every == 0
+    }
+}
+
+/// A node is a connection to a single endpoint in the ring, along with
+/// the statistics tracked for that endpoint.
+#[derive(Debug, Clone)]
+pub struct Node<C: Connection> {
+    pub endpoint: String,
+    pub conn: C,
+    pub(crate) counters: Arc<NodeCounters>,
+    pub(crate) budget: Option<Arc<Mutex<BudgetTracker>>>,
+    pub(crate) vbuckets: Option<VbucketRouter>,
+    pub version: Option<String>,
+    pub(crate) max_response_size: u32,
+    pub(crate) key_codec: KeyCodec,
+    pub(crate) warm: Option<WarmPool<C>>,
+    pub(crate) slow_start: Option<SlowStart>,
+    pub(crate) sasl: Option<Arc<dyn Authenticator>>,
+    ascii: Option<AsciiCodec>,
+    resolver: Option<Arc<dyn Resolver>>,
+    depth: usize,
+    last_used: Instant,
+}
+
+impl<C: Connection> Node<C> {
+    async fn connect(endpoint: String, resolver: Option<Arc<dyn Resolver>>) -> Result<Self, Error> {
+        let conn = resolve::connect(&endpoint, resolver.as_ref()).await?;
+        let counters = Arc::new(NodeCounters::new());
+        Ok(Self {
+            endpoint,
+            conn,
+            counters,
+            budget: None,
+            vbuckets: None,
+            version: None,
+            max_response_size: u32::MAX,
+            key_codec: KeyCodec::Raw,
+            warm: None,
+            slow_start: None,
+            sasl: None,
+            ascii: None,
+            resolver,
+            depth: usize::MAX,
+            last_used: C::now(),
+        })
+    }
+
+    /// Read a packet from the connection, recording it in the node stats.
+    pub async fn read_packet<P: Compressor>(&mut self, compressor: P) -> Result<Packet, Error> {
+        let max_body = self.max_response_size;
+        let result = match self.ascii.clone() {
+            Some(codec) => codec.read_packet(&mut self.conn, max_body).await,
+            None => self.conn.read_packet_limited(NoCompressor, max_body).await,
+        };
+        let packet = self.record(result)?;
+        if let Some(budget) = &self.budget {
+            budget.lock().unwrap().record(true, C::now());
+        }
+        self.counters.record_success();
+        self.counters
+            .record_read(24 + packet.header.body_len as usize);
+        let mut packet = compressor.decompress(packet)?;
+        if self.key_codec != KeyCodec::Raw && packet.has_item_key() && !packet.key.is_empty() {
+            let key = self.key_codec.decode(&packet.key)?;
+            packet.set_key(key);
+        }
+        Ok(packet)
+    }
+
+    /// Read a packet like [`Node::read_packet`], failing with
+    /// [`Error::Stalled`] and poisoning the connection if no packet was read
+    /// within `max_wait`.
+    pub async fn read_packet_within<P: Compressor>(
+        &mut self,
+        compressor: P,
+        max_wait: Option<Duration>,
+    ) -> Result<Packet, Error> {
+        let max_wait = match max_wait {
+            Some(max_wait) => max_wait,
+            None => return self.read_packet(compressor).await,
+        };
+        let read = {
+            let (read, timer) = (self.read_packet(compressor), C::sleep(max_wait));
+            pin_mut!(read, timer);
+            match select(read, timer).await {
+                Either::Left((result, _)) => Some(result),
+                Either::Right(_) => None,
+            }
+        };
+        match read {
+            Some(result) => result,
+            None => self.record(Err(Error::Stalled(self.endpoint.clone()))),
+        }
+    }
+
+    /// Write a packet to the connection, recording it in the node stats.
+    pub async fn write_packet<P: Compressor>(
+        &mut self,
+        compressor: P,
+        packet: Packet,
+    ) -> Result<(), Error> {
+        let mut packet = compressor.compress(packet)?;
+        if let Some(vbuckets) = &self.vbuckets {
+            vbuckets.apply(&mut packet);
+        }
+        if self.key_codec != KeyCodec::Raw && packet.has_item_key() {
+            let key = self.key_codec.encode(&packet.key);
+            packet.set_key(key);
+        }
+        let bytes = 24 + packet.header.body_len as usize;
+        let store = match packet.is_store() {
+            true => Some((packet.value.len(), packet.expire())),
+            false => None,
+        };
+        let result = match self.ascii.clone() {
+            Some(codec) => codec.write_packet(&mut self.conn, packet).await,
+            None => self.conn.write_packet(NoCompressor, packet).await,
+        };
+        self.record(result)?;
+        self.counters.record_write(bytes);
+        if let Some((value_size, ttl)) = store {
+            self.counters.record_store(value_size, ttl);
+        }
+        Ok(())
+    }
+
+    /// Write a request and read its response. Requests without an opaque
+    /// are given one, and responses to other recent requests on the
+    /// connection, such as late responses to requests abandoned after a
+    /// timeout, are discarded instead of being returned. A response to no
+    /// recent request fails with [`ProtocolError::UnexpectedOpaque`].
+    pub async fn send<P: Compressor>(
+        &mut self,
+        compressor: P,
+        mut packet: Packet,
+    ) -> Result<Packet, Error> {
+        if packet.header.opaque == 0 {
+            packet.header.opaque = self.counters.next_opaque();
+        }
+        let opaque = packet.header.opaque;
+        let sent = C::now();
+        self.write_packet(compressor, packet).await?;
+        self.counters.record_sent(opaque);
+        loop {
+            let packet = self.read_packet(compressor).await?;
+            if packet.header.opaque == opaque {
+                let latency = C::now().saturating_duration_since(sent);
+                self.counters.record_latency(latency);
+                #[cfg(feature = "tracing")]
+                if let Some(baggage) = crate::options::decode_baggage(opaque) {
+                    tracing::debug!(
+                        endpoint = %self.endpoint,
+                        baggage,
+                        ?latency,
+                        "read a response carrying baggage"
+                    );
+                }
+                return Ok(packet);
+            }
+            if !self.counters.is_recent(packet.header.opaque) {
+                let err = ProtocolError::UnexpectedOpaque(packet.header.opaque);
+                return self.record(Err(err.into()));
+            }
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                endpoint = %self.endpoint,
+                opaque = packet.header.opaque,
+                "discarded a late response"
+            );
+            self.counters.record_discarded();
+        }
+    }
+
+    /// Send a request like [`Node::send`], failing with
+    /// [`Error::DeadlineExceeded`] and poisoning the connection if no
+    /// response was read within `max_wait`, since the abandoned exchange
+    /// may have left a partial request or response on the stream.
+    pub async fn send_within<P: Compressor>(
+        &mut self,
+        compressor: P,
+        packet: Packet,
+        max_wait: Option<Duration>,
+    ) -> Result<Packet, Error> {
+        let max_wait = match max_wait {
+            Some(max_wait) => max_wait,
+            None => return self.send(compressor, packet).await,
+        };
+        let sent = {
+            let (send, timer) = (self.send(compressor, packet), C::sleep(max_wait));
+            pin_mut!(send, timer);
+            match select(send, timer).await {
+                Either::Left((result, _)) => Some(result),
+                Either::Right(_) => None,
+            }
+        };
+        match sent {
+            Some(result) => result,
+            None => {
+                self.counters.poison();
+                self.record(Err(Error::DeadlineExceeded))
+            }
+        }
+    }
+
+    /// Replace the connection with a new one to the same endpoint, taking a
+    /// spare from the warm pool if one is ready.
+    pub async fn reconnect(&mut self) -> Result<(), Error> {
+        let spare = self
+            .warm
+            .as_ref()
+            .and_then(|warm| warm.take(&self.endpoint));
+        let result = match spare {
+            Some(conn) => {
+                self.conn = conn;
+                Ok(())
+            }
+            None => self.reconnect_resolved().await,
+        };
+        self.record(result)?;
+        self.counters.record_reconnect();
+        self.reset_pipeline_depth();
+        if let Some(codec) = &self.ascii {
+            codec.reset();
+        }
+        self.authenticate().await
+    }
+
+    /// Send a QUIT and shut the connection down, so that the server sees a
+    /// clean disconnect. A poisoned connection is shut down without a QUIT,
+    /// since its stream may be in an unknown state, and the reply to the
+    /// QUIT is awaited for at most [`crate::client::QUIT_TIMEOUT`]. Spare
+    /// connections to the endpoint parked in the warm pool are shut down
+    /// too. The node is poisoned afterwards, so that it reconnects if it is
+    /// used again.
+    pub async fn close(&mut self) -> Result<(), Error> {
+        let quit = match self.is_poisoned() {
+            true => Ok(()),
+            false => self
+                .send_within(NoCompressor, Packet::quit()?, Some(QUIT_TIMEOUT))
+                .await
+                .and_then(|packet| Ok(packet.error_for_status()?)),
+        };
+        self.counters.poison();
+        let shutdown = self.conn.shutdown().await;
+        let spares = match &self.warm {
+            Some(warm) => warm.close(&self.endpoint).await,
+            None => Ok(()),
+        };
+        quit.and(shutdown).and(spares)
+    }
+
+    /// Authenticate the connection with the SASL mechanism of the node, if
+    /// any. A failed exchange poisons the connection, so that it is
+    /// authenticated again after reconnecting.
+    pub(crate) async fn authenticate(&mut self) -> Result<(), Error> {
+        let mechanism = match &self.sasl {
+            Some(sasl) => sasl.start(),
+            None => return Ok(()),
+        };
+        let result = sasl::authenticate(self, mechanism).await;
+        if result.is_err() {
+            self.counters.poison();
+        }
+        self.record(result)
+    }
+
+    /// Reconnect to the first resolved address of the endpoint which
+    /// accepts a connection.
+    async fn reconnect_resolved(&mut self) -> Result<(), Error> {
+        let mut result = Err(Error::Resolve(self.endpoint.clone()));
+        for address in resolve::addresses(&self.endpoint, self.resolver.as_ref()).await? {
+            result = self.conn.reconnect(address).await;
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// The number of requests which may be pipelined at once.
+    pub(crate) fn pipeline_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Double the pipeline depth after a batch, up to the maximum of the
+    /// slow start.
+    pub(crate) fn grow_pipeline_depth(&mut self) {
+        if let Some(slow_start) = self.slow_start {
+            self.depth = self.depth.saturating_mul(2).min(slow_start.max);
+        }
+    }
+
+    /// Drop every setting applied by the ring, keeping the connection, its
+    /// statistics, the detected version, its protocol and the SASL mechanism
+    /// it authenticated with, so that the node can be reused by a ring with
+    /// different settings.
+    fn reset_settings(mut self) -> Self {
+        self.budget = None;
+        self.vbuckets = None;
+        self.max_response_size = u32::MAX;
+        self.key_codec = KeyCodec::Raw;
+        self.warm = None;
+        self.slow_start = None;
+        self
+    }
+
+    /// Start the pipeline depth over, since the connection is new.
+    fn reset_pipeline_depth(&mut self) {
+        self.depth = match self.slow_start {
+            Some(slow_start) => slow_start.initial,
+            None => usize::MAX,
+        };
+    }
+
+    /// How long ago the node was last used for a request.
+    pub(crate) fn idle_time(&self) -> Duration {
+        C::now().saturating_duration_since(self.last_used)
+    }
+
+    /// Prepare the node to be used for a request. If the node has been idle
+    /// for longer than `idle_ping`, then it is pinged first to find out if
+    /// the server closed the connection. Poisoned connections, including
+    /// closed ones, are replaced by reconnecting.
+    pub async fn ensure_connected(&mut self, idle_ping: Option<Duration>) -> Result<(), Error> {
+        let idle = idle_ping.is_some_and(|interval| self.idle_time() >= interval);
+        if idle && !self.is_poisoned() {
+            // A failed ping poisons the connection, so the error is dropped.
+            let _ = self.send(NoCompressor, Packet::noop()?).await;
+        }
+        if self.is_poisoned() {
+            self.reconnect().await?;
+        }
+        self.last_used = C::now();
+        Ok(())
+    }
+
+    /// Split the node into a reader and a writer which share the same
+    /// connection and statistics, but can be used concurrently.
+    pub fn split(&self) -> (Self, Self) {
+        let (reader, writer) = self.conn.split();
+        let reader = Self {
+            conn: reader,
+            ..self.clone()
+        };
+        let writer = Self {
+            conn: writer,
+            ..self.clone()
+        };
+        (reader, writer)
+    }
+
+    /// Ask the server for its version and record it on the node.
+    pub async fn detect_version(&mut self) -> Result<(), Error> {
+        self.write_packet(NoCompressor, Packet::version()?).await?;
+        let packet = self.read_packet(NoCompressor).await?;
+        packet.error_for_status()?;
+        self.version = Some(String::from_utf8_lossy(&packet.value).to_string());
+        Ok(())
+    }
+
+    /// Speak the protocol on the connection. A connection which was already
+    /// used with another protocol is poisoned, so that it is replaced.
+    fn set_protocol(&mut self, protocol: Protocol) {
+        if protocol == self.protocol() {
+            return;
+        }
+        self.ascii = match protocol {
+            Protocol::Binary => None,
+            Protocol::Ascii => Some(AsciiCodec::default()),
+        };
+        if self.version.is_some() {
+            self.counters.poison();
+        }
+    }
+
+    /// The protocol spoken on the connection.
+    pub fn protocol(&self) -> Protocol {
+        match self.ascii {
+            Some(_) => Protocol::Ascii,
+            None => Protocol::Binary,
+        }
+    }
+
+    /// Whether the connection to this node is poisoned by an I/O or protocol
+    /// error, which may leave the stream in an unknown state, or is known to
+    /// be disconnected.
+    pub fn is_poisoned(&self) -> bool {
+        self.counters.is_poisoned() || !self.conn.is_connected()
+    }
+
+    /// Whether this node exhausted its error budget, in which case reads
+    /// should fail open instead of using the connection.
+    pub fn is_failing_open(&self) -> bool {
+        match &self.budget {
+            Some(budget) => budget.lock().unwrap().is_exhausted(C::now()),
+            None => false,
+        }
+    }
+
+    /// The moving average of the latency of this node, or None if no
+    /// response was read yet.
+    pub fn latency(&self) -> Option<Duration> {
+        self.counters.latency()
+    }
+
+    /// Get a snapshot of the statistics for this node.
+    pub fn stats(&self) -> NodeStats {
+        self.counters.snapshot(&self.endpoint)
+    }
+
+    /// Get a summary of the health of this node.
+    pub fn health(&self) -> NodeHealth {
+        let (poisoned, ejected) = (self.is_poisoned(), self.is_failing_open());
+        self.counters.health(&self.endpoint, poisoned, ejected)
+    }
+
+    /// Record the outcome of an operation on the connection, poisoning it
+    /// on errors which leave it in an unknown state.
+    pub(crate) fn record<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        if let Err(err) = &result {
+            self.counters.record_error(err);
+            if matches!(
+                err,
+                Error::IoError(_)
+                    | Error::Protocol(_)
+                    | Error::ConnectionClosed
+                    | Error::Stalled(_)
+            ) {
+                self.counters.poison();
+            }
+            if let Some(budget) = &self.budget {
+                budget.lock().unwrap().record(false, C::now());
+            }
+        }
+        result
+    }
+}
+
+/// Check that every endpoint owns some of the buckets of a ring of the
+/// given size. Modulo hashing has no buckets, so only needs an endpoint.
+pub(crate) fn check_size(
+    urls: &[String],
+    scheme: HashScheme,
+    size: usize,
+) -> Result<(), ConfigError> {
+    if urls.is_empty() {
+        return Err(ConfigError::NoEndpoints);
+    }
+    if scheme != HashScheme::Crc32Modulo && size < urls.len() {
+        return Err(ConfigError::InvalidRingSize);
+    }
+    Ok(())
 }
 
 impl<C: Connection> Ring<C> {
@@ -24,33 +516,153 @@ impl<C: Connection> Ring<C> {
     /// ring into buckets so that each connection owns some fraction
     /// of the buckets in the ring.
     pub async fn new_with_size(urls: Vec<String>, size: usize) -> Result<Self, Error> {
+        Ring::new_with_scheme(urls, HashScheme::default(), size).await
+    }
+
+    /// Create a new ring using the given scheme to place keys on nodes.
+    pub async fn new_with_scheme(
+        urls: Vec<String>,
+        scheme: HashScheme,
+        size: usize,
+    ) -> Result<Self, Error> {
+        Ring::new_with_resolver(urls, scheme, size, None).await
+    }
+
+    /// Create a new ring, resolving the urls with the resolver before
+    /// connecting to them.
+    pub async fn new_with_resolver(
+        urls: Vec<String>,
+        scheme: HashScheme,
+        size: usize,
+        resolver: Option<Arc<dyn Resolver>>,
+    ) -> Result<Self, Error> {
+        check_size(&urls, scheme, size)?;
+        let placement = Placement::new(scheme, &urls, size);
         let mut conns = vec![];
-        let mut buckets = vec![];
-        // In this scheme, each connection gets an equal share of the ring space.
-        let share = size / urls.len();
-        for (conn_index, url) in urls.into_iter().enumerate() {
-            for i in 0..share {
-                let k = murmur3_32(&mut url.as_bytes(), i as u32)?;
-                buckets.push((k, conn_index))
+        for url in urls {
+            conns.push(Node::connect(url, resolver.clone()).await?);
+        }
+
+        Ok(Self {
+            conns,
+            placement,
+            vbuckets: None,
+            protocol: Protocol::default(),
+            #[cfg(feature = "tracing")]
+            sampler: None,
+        })
+    }
+
+    /// Replace the nodes of the ring with nodes for the urls, speaking the
+    /// protocol and authenticating with the SASL mechanism, reusing the
+    /// connections of nodes whose endpoint is unchanged. New nodes are
+    /// connected, authenticated and their versions detected first, so the
+    /// ring is left untouched if that fails. Reused nodes whose protocol or
+    /// mechanism changed are poisoned, so that they reconnect with the new
+    /// ones before their next request. Every other setting of the ring is
+    /// dropped.
+    pub(crate) async fn rebuild(
+        &mut self,
+        urls: Vec<String>,
+        scheme: HashScheme,
+        size: usize,
+        resolver: Option<Arc<dyn Resolver>>,
+        protocol: Protocol,
+        sasl: Option<Arc<dyn Authenticator>>,
+    ) -> Result<(), Error> {
+        check_size(&urls, scheme, size)?;
+        let mut reusable = self.endpoints();
+        let mut fresh = VecDeque::new();
+        for url in &urls {
+            match reusable.iter().position(|endpoint| endpoint == url) {
+                Some(i) => {
+                    reusable.swap_remove(i);
+                }
+                None => {
+                    let mut node = Node::connect(url.clone(), resolver.clone()).await?;
+                    node.set_protocol(protocol);
+                    node.sasl = sasl.clone();
+                    node.authenticate().await?;
+                    node.detect_version().await?;
+                    fresh.push_back(node);
+                }
             }
-            conns.push(C::connect(url).await?);
         }
 
-        buckets.sort_unstable();
-        Ok(Self { conns, buckets })
+        let mut old = std::mem::take(&mut self.conns);
+        for url in &urls {
+            let node = match old.iter().position(|node| &node.endpoint == url) {
+                Some(i) => {
+                    let mut node = old.swap_remove(i).reset_settings();
+                    node.set_protocol(protocol);
+                    let same_sasl = match (&node.sasl, &sasl) {
+                        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                        (a, b) => a.is_none() && b.is_none(),
+                    };
+                    if !same_sasl {
+                        node.sasl = sasl.clone();
+                        node.counters.poison();
+                    }
+                    node
+                }
+                None => fresh.pop_front().unwrap(),
+            };
+            self.conns.push(node);
+        }
+        self.protocol = protocol;
+        self.placement = Placement::new(scheme, &urls, size);
+        self.vbuckets = None;
+        #[cfg(feature = "tracing")]
+        {
+            self.sampler = None;
+        }
+        Ok(())
+    }
+
+    /// The endpoints of every node in the ring, in order.
+    pub fn endpoints(&self) -> Vec<String> {
+        self.conns
+            .iter()
+            .map(|node| node.endpoint.clone())
+            .collect()
     }
 
     /// Get the connection owning the bucket containing the given key.
-    pub fn get_conn<K: AsRef<[u8]>>(&mut self, key: K) -> Result<&mut C, Error> {
+    pub fn get_conn<K: AsRef<[u8]>>(&mut self, key: K) -> Result<&mut Node<C>, Error> {
         let conn_index = self.find_bucket(key.as_ref());
         Ok(&mut self.conns[conn_index])
     }
 
+    /// Iterate over every node in the ring.
+    pub fn nodes(&self) -> std::slice::Iter<'_, Node<C>> {
+        self.conns.iter()
+    }
+
+    /// The number of nodes in the ring.
+    pub fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    /// Get the index of the node owning the bucket containing the given key.
+    pub fn node_index<K: AsRef<[u8]>>(&self, key: K) -> usize {
+        self.find_bucket(key.as_ref())
+    }
+
+    /// Measure how evenly the ring spreads a sample of keys over its nodes.
+    pub fn distribution_report<K: AsRef<[u8]>>(&self, keys: &[K]) -> DistributionReport {
+        let mut counts = vec![0; self.conns.len()];
+        for key in keys {
+            counts[self.node_index(key)] += 1;
+        }
+        let endpoints = self.conns.iter().map(|node| node.endpoint.clone());
+        DistributionReport::new(&endpoints.collect::<Vec<_>>(), &counts)
+    }
+
     /// Group multiple keys and the connections that own the keys.
     pub fn get_conns<'a, 'b, K: AsRef<[u8]> + 'b>(
         &'a mut self,
         keys: &'b [K],
-    ) -> Vec<(&'a mut C, Vec<&'b K>)> {
+    ) -> Vec<(&'a mut Node<C>, Vec<&'b K>)> {
         let pipelines = self.get_pipelines(keys);
         self.into_iter()
             .zip(pipelines)
@@ -67,21 +679,220 @@ impl<C: Connection> Ring<C> {
         out
     }
 
-    fn find_bucket(&self, mut key: &[u8]) -> usize {
-        // Find the position of the hash on the ring
-        let ring_pos = murmur3_32(&mut key, 0).unwrap();
-        // Find the bucket containing the ring position
-        let bucket_search = self.buckets.binary_search_by_key(&ring_pos, |(i, _)| *i);
-        let bucket_index = bucket_search.unwrap_or_else(|next_bucket| next_bucket);
-        // Return the connection owning that bucket
-        let (_, conn_index) = self.buckets.get(bucket_index).unwrap_or(&self.buckets[0]);
-        *conn_index
+    fn find_bucket(&self, key: &[u8]) -> usize {
+        if let Some(index) = self.find_vbucket_server(key) {
+            return index;
+        }
+        // Rings are never built with fewer buckets than nodes, so every key
+        // has an owner.
+        let (hash, bucket, conn_index) = self.placement.locate(key).unwrap();
+        #[cfg(feature = "tracing")]
+        if self.sampler.as_ref().is_some_and(RouteSampler::sample) {
+            tracing::debug!(
+                key = %String::from_utf8_lossy(key),
+                hash,
+                bucket,
+                endpoint = %self.conns[conn_index].endpoint,
+                "routed key to node",
+            );
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = (hash, bucket);
+        conn_index
+    }
+
+    fn find_vbucket_server(&self, key: &[u8]) -> Option<usize> {
+        let (vbucket, server) = self.vbuckets.as_ref()?.route(key)?;
+        let conn_index = self.conns.iter().position(|node| node.endpoint == server)?;
+        #[cfg(feature = "tracing")]
+        if self.sampler.as_ref().is_some_and(RouteSampler::sample) {
+            tracing::debug!(
+                key = %String::from_utf8_lossy(key),
+                vbucket,
+                endpoint = %server,
+                "routed key to vbucket",
+            );
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = vbucket;
+        Some(conn_index)
+    }
+
+    /// Emit a debug event for a fraction of routing decisions, given as a
+    /// rate between 0 and 1. A rate of 0 disables tracing.
+    #[cfg(feature = "tracing")]
+    pub fn set_trace_sample_rate(&mut self, rate: f64) {
+        self.sampler = if rate > 0.0 {
+            Some(RouteSampler {
+                every: (1.0 / rate.min(1.0)).round() as u64,
+                count: Arc::new(AtomicU64::new(0)),
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Route keys using the given vbucket map instead of consistent hashing,
+    /// and send the vbucket id of every request in its header. Keys mapped to
+    /// servers that are not in the ring fall back to consistent hashing.
+    pub fn set_vbucket_router(&mut self, router: VbucketRouter) {
+        for node in self.conns.iter_mut() {
+            node.vbuckets = Some(router.clone());
+        }
+        self.vbuckets = Some(router);
+    }
+
+    /// Hash keys and node points with the given seeds, which moves keys
+    /// between nodes unless the seeds are unchanged.
+    pub fn set_hash_seeds(&mut self, seeds: HashSeeds) {
+        let endpoints = self.endpoints();
+        let (scheme, size) = (self.placement.scheme, self.placement.size);
+        self.placement = Placement::new_with_seeds(scheme, &endpoints, size, seeds);
+    }
+
+    /// Limit the body length of responses read from every node in the ring.
+    pub fn set_max_response_size(&mut self, bytes: u32) {
+        for node in self.conns.iter_mut() {
+            node.max_response_size = bytes;
+        }
+    }
+
+    /// Encode the keys sent to every node in the ring with the codec.
+    pub fn set_key_codec(&mut self, codec: KeyCodec) {
+        for node in self.conns.iter_mut() {
+            node.key_codec = codec;
+        }
+    }
+
+    /// Swap in spare connections from the warm pool when reconnecting.
+    pub fn set_warm_pool(&mut self, warm: WarmPool<C>) {
+        for node in self.conns.iter_mut() {
+            node.warm = Some(warm.clone());
+        }
+    }
+
+    /// Ramp up the pipeline depth of every node whenever it connects.
+    pub fn set_slow_start(&mut self, slow_start: SlowStart) {
+        for node in self.conns.iter_mut() {
+            node.slow_start = Some(slow_start);
+            node.reset_pipeline_depth();
+        }
+    }
+
+    /// Track an error budget for every node in the ring.
+    pub fn set_error_budget(&mut self, budget: ErrorBudget) {
+        for node in self.conns.iter_mut() {
+            node.budget = Some(Arc::new(Mutex::new(BudgetTracker::new(budget))));
+        }
+    }
+
+    /// Take a snapshot of every node in the ring, and of which of them fail
+    /// open.
+    pub fn topology_snapshot(&self) -> TopologySnapshot {
+        let taken_at = SystemTime::now();
+        let nodes = self
+            .conns
+            .iter()
+            .map(|node| {
+                let budget = node.budget.as_ref();
+                let remaining =
+                    budget.and_then(|budget| budget.lock().unwrap().exhausted_for(C::now()));
+                NodeSnapshot {
+                    endpoint: node.endpoint.clone(),
+                    version: node.version.clone(),
+                    failing_open_until: remaining.map(|remaining| taken_at + remaining),
+                }
+            })
+            .collect();
+        TopologySnapshot { taken_at, nodes }
+    }
+
+    /// Fail open for the nodes which were failing open in the snapshot, for
+    /// the rest of their cooldown. Nodes without an error budget are left
+    /// alone.
+    pub fn restore_topology(&mut self, snapshot: &TopologySnapshot) {
+        for node in self.conns.iter_mut() {
+            let remaining = snapshot
+                .node(&node.endpoint)
+                .and_then(NodeSnapshot::failing_open_for);
+            if let (Some(budget), Some(remaining)) = (&node.budget, remaining) {
+                budget.lock().unwrap().exhaust_for(remaining, C::now());
+            }
+        }
+    }
+
+    /// Speak the protocol to every node, including nodes added by
+    /// [`Ring::rebuild`]. Connections which were already used with another
+    /// protocol are replaced.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+        for node in self.conns.iter_mut() {
+            node.set_protocol(protocol);
+        }
+    }
+
+    /// Authenticate every node with the SASL mechanism, and again whenever
+    /// it reconnects. Without a mechanism, nodes do not authenticate. Use
+    /// [`Ring::rebuild`] to change the mechanism of nodes which are already
+    /// connected.
+    pub(crate) async fn authenticate(
+        &mut self,
+        sasl: Option<Arc<dyn Authenticator>>,
+    ) -> Result<(), Error> {
+        for node in self.conns.iter_mut() {
+            node.sasl = sasl.clone();
+            node.authenticate().await?;
+        }
+        Ok(())
+    }
+
+    /// Close the connection to every node concurrently, returning the first
+    /// error once every node was closed.
+    pub async fn close(&mut self) -> Result<(), Error> {
+        let results = join_all(self.conns.iter_mut().map(Node::close)).await;
+        results.into_iter().collect()
+    }
+
+    /// Detect and record the server version of every node in the ring.
+    pub async fn detect_versions(&mut self) -> Result<(), Error> {
+        for node in self.conns.iter_mut() {
+            node.detect_version().await?;
+        }
+        Ok(())
+    }
+
+    /// Get the versions recorded for every node in the ring.
+    pub fn features(&self) -> ClusterFeatures {
+        let versions = self
+            .conns
+            .iter()
+            .filter_map(|node| Some((node.endpoint.clone(), node.version.clone()?)))
+            .collect();
+        ClusterFeatures { versions }
+    }
+
+    /// Get a snapshot of the statistics for every node in the ring.
+    pub fn stats(&self) -> Vec<NodeStats> {
+        self.conns.iter().map(Node::stats).collect()
+    }
+}
+
+impl<'a, C: Connection> IntoIterator for &'a mut Ring<C> {
+    type Item = &'a mut Node<C>;
+    type IntoIter = std::slice::IterMut<'a, Node<C>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.conns[..].iter_mut()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::client::{Connection, Error};
+    use crate::{
+        client::{Connection, Error},
+        diagnostics::ConfigError,
+        vbucket::{VbucketMap, VbucketRouter},
+    };
     use async_trait::async_trait;
 
     use super::Ring;
@@ -112,12 +923,12 @@ mod tests {
             let c = "localhost:11213";
             let urls = vec![a.to_string(), b.to_string(), c.to_string()];
             let mut ring = Ring::<TestConn>::new(urls).await.unwrap();
-            assert_eq!(a, ring.get_conn(a.as_bytes()).unwrap().url);
-            assert_eq!(b, ring.get_conn(b.as_bytes()).unwrap().url);
-            assert_eq!(c, ring.get_conn(c.as_bytes()).unwrap().url);
-            assert_eq!(c, ring.get_conn(b"").unwrap().url);
-            assert_eq!(c, ring.get_conn(b"q").unwrap().url);
-            assert_eq!(a, ring.get_conn(b"-").unwrap().url);
+            assert_eq!(a, ring.get_conn(a.as_bytes()).unwrap().conn.url);
+            assert_eq!(b, ring.get_conn(b.as_bytes()).unwrap().conn.url);
+            assert_eq!(c, ring.get_conn(c.as_bytes()).unwrap().conn.url);
+            assert_eq!(c, ring.get_conn(b"").unwrap().conn.url);
+            assert_eq!(c, ring.get_conn(b"q").unwrap().conn.url);
+            assert_eq!(a, ring.get_conn(b"-").unwrap().conn.url);
         });
     }
 
@@ -126,17 +937,56 @@ mod tests {
         tokio_test::block_on(async {
             let urls = vec!["localhost:11211".to_string(), "localhost:11212".to_string()];
             let mut ring = Ring::<TestConn>::new_with_size(urls, 2).await.unwrap();
-            assert_eq!(vec![(748582396, 1), (1636863978, 0)], ring.buckets);
-            assert_eq!("localhost:11212", ring.get_conn(b"q").unwrap().url);
+            assert_eq!(
+                vec![(748582396, 1), (1636863978, 0)],
+                ring.placement.buckets
+            );
+            assert_eq!("localhost:11212", ring.get_conn(b"q").unwrap().conn.url);
+
+            // Smaller rings would leave every endpoint without buckets.
+            let urls = vec!["localhost:11211".to_string(), "localhost:11212".to_string()];
+            let err = Ring::<TestConn>::new_with_size(urls, 1).await.unwrap_err();
+            assert!(matches!(err, Error::Config(ConfigError::InvalidRingSize)));
         });
     }
-}
 
-impl<'a, C: Connection> IntoIterator for &'a mut Ring<C> {
-    type Item = &'a mut C;
-    type IntoIter = std::slice::IterMut<'a, C>;
+    #[test]
+    fn test_vbucket_router() {
+        tokio_test::block_on(async {
+            let a = "localhost:11211".to_string();
+            let b = "localhost:11212".to_string();
+            let mut ring = Ring::<TestConn>::new(vec![a.clone(), b.clone()])
+                .await
+                .unwrap();
+            let router = VbucketRouter::new(VbucketMap::new(vec![b.clone()], vec![0; 64]).unwrap());
+            ring.set_vbucket_router(router.clone());
+            assert_eq!(b, ring.get_conn(b"q").unwrap().conn.url);
+            assert_eq!(b, ring.get_conn(b"-").unwrap().conn.url);
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.conns[..].iter_mut()
+            router.update(VbucketMap::new(vec![a.clone()], vec![0; 64]).unwrap());
+            assert_eq!(a, ring.get_conn(b"q").unwrap().conn.url);
+
+            // Every key routes to the single server in the vbucket map.
+            let report = ring.distribution_report(&["q", "-", "x"]);
+            assert_eq!(3, report.nodes[0].keys);
+            assert_eq!(0.0, report.min_share);
+        });
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_route_sampler() {
+        tokio_test::block_on(async {
+            let urls = vec!["localhost:11211".to_string()];
+            let mut ring = Ring::<TestConn>::new(urls).await.unwrap();
+            ring.set_trace_sample_rate(0.25);
+            let sampler = ring.sampler.clone().unwrap();
+            assert_eq!(4, sampler.every);
+            let sampled = (0..8).filter(|_| sampler.sample()).count();
+            assert_eq!(2, sampled);
+
+            ring.set_trace_sample_rate(0.0);
+            assert!(ring.sampler.is_none());
+        });
     }
 }
@@ -0,0 +1,47 @@
+//! A xorshift generator for the few places which need cheap pseudo-random
+//! numbers, such as jitter and self test payloads, without pulling in a
+//! random number generator.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A 32-bit xorshift generator. It is fast and deterministic for a given
+/// seed, which is all jitter and test data need, but it is not suitable
+/// for anything security sensitive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Xorshift(u32);
+
+impl Xorshift {
+    /// Start the generator from the seed. A state of 0 only ever produces 0,
+    /// so the lowest bit of the seed is always set.
+    pub(crate) fn new(seed: u32) -> Self {
+        Self(seed | 1)
+    }
+
+    /// Start the generator from the system clock, so that it differs
+    /// between calls.
+    pub(crate) fn from_clock() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        Self::new(nanos)
+    }
+
+    /// Draw the next number.
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Draw the next number as a fraction between 0 and 1.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 / u32::MAX as f64
+    }
+}
+
+/// A fraction between 0 and 1 which differs between calls, for jitter.
+pub(crate) fn jitter_fraction() -> f64 {
+    Xorshift::from_clock().next_f64()
+}
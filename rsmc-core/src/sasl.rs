@@ -0,0 +1,239 @@
+//! Servers started with SASL enabled refuse every command but VERSION until
+//! the connection has authenticated. A [`SaslMechanism`] configured with
+//! [`crate::client::ClientConfig::with_sasl`] authenticates every connection
+//! the client makes before it is used, and again whenever it reconnects,
+//! including when a spare from the warm pool is swapped in.
+//!
+//! The client drives the exchange for any mechanism: it sends the name and
+//! initial response of the mechanism, then answers every challenge of the
+//! server with the response from [`SaslMechanism::step`] for as long as the
+//! server asks to continue, up to [`MAX_STEPS`] times. [`Plain`] implements
+//! the PLAIN mechanism which memcached supports, and other mechanisms, such
+//! as SCRAM or proprietary ones of a proxy, can be plugged in by
+//! implementing the trait.
+//!
+//! Each exchange runs on its own copy of the configured mechanism, so state
+//! kept between steps, such as a nonce, is never shared by connections.
+//! Rejected credentials fail with [`Status::AuthenticationError`], and
+//! poison the connection so that it authenticates again when reused.
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+use crate::{
+    client::{Connection, Error, NoCompressor},
+    protocol::{Packet, Status},
+    ring::Node,
+};
+
+/// The most challenges answered in a single exchange. A server asking to
+/// continue beyond this fails the exchange with
+/// [`Status::AuthenticationError`], rather than looping forever.
+pub const MAX_STEPS: usize = 16;
+
+/// A SASL mechanism authenticating connections to memcached.
+pub trait SaslMechanism: Debug + Send + Sync {
+    /// The name of the mechanism, such as `PLAIN`.
+    fn name(&self) -> &str;
+
+    /// The response sent along with the name to start the exchange, which
+    /// may be empty.
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error>;
+
+    /// The response to a challenge of the server, which asked to continue
+    /// the exchange.
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Starts every exchange with a fresh copy of the configured mechanism.
+pub(crate) trait Authenticator: Debug + Send + Sync {
+    fn start(&self) -> Box<dyn SaslMechanism>;
+}
+
+impl<M: SaslMechanism + Clone + 'static> Authenticator for M {
+    fn start(&self) -> Box<dyn SaslMechanism> {
+        Box::new(self.clone())
+    }
+}
+
+/// The PLAIN mechanism, which sends the username and password in the clear.
+/// Use it over a trusted network or an encrypted connection.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Plain {
+    username: String,
+    password: String,
+}
+
+impl Plain {
+    /// Authenticate with the username and password.
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    fn message(&self) -> Vec<u8> {
+        format!("\0{}\0{}", self.username, self.password).into_bytes()
+    }
+}
+
+impl Debug for Plain {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Plain")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SaslMechanism for Plain {
+    fn name(&self) -> &str {
+        "PLAIN"
+    }
+
+    fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(self.message())
+    }
+
+    /// Servers may ask for the credentials with an empty challenge, which
+    /// is answered with the same message as the initial response.
+    fn step(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(self.message())
+    }
+}
+
+/// Run a whole exchange with the mechanism on the connection of the node.
+pub(crate) async fn authenticate<C: Connection>(
+    node: &mut Node<C>,
+    mut mechanism: Box<dyn SaslMechanism>,
+) -> Result<(), Error> {
+    let name = mechanism.name().to_string();
+    let mut packet = Packet::sasl_auth(&name, mechanism.initial_response()?)?;
+    let mut steps = 0;
+    loop {
+        let response = node.send(NoCompressor, packet).await?;
+        match response.error_for_status() {
+            Err(Status::AuthenticationContinue) if steps < MAX_STEPS => {
+                steps += 1;
+                packet = Packet::sasl_step(&name, mechanism.step(&response.value)?)?;
+            }
+            Err(Status::AuthenticationContinue) => {
+                return Err(Status::AuthenticationError.into());
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{
+        client::{Client, ClientConfig, Error},
+        mock::{MockConnection, Store},
+        protocol::Status,
+    };
+
+    use super::{Plain, SaslMechanism, MAX_STEPS};
+
+    /// PLAIN, sending the credentials only once the server asks for them,
+    /// and counting how many times it did.
+    #[derive(Debug, Clone)]
+    struct DeferredPlain {
+        plain: Plain,
+        steps: Arc<AtomicUsize>,
+    }
+
+    impl SaslMechanism for DeferredPlain {
+        fn name(&self) -> &str {
+            self.plain.name()
+        }
+
+        fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+            Ok(vec![])
+        }
+
+        fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>, Error> {
+            self.steps.fetch_add(1, Ordering::Relaxed);
+            self.plain.step(challenge)
+        }
+    }
+
+    /// PLAIN, never sending the credentials, and counting how many times the
+    /// server asked for them.
+    #[derive(Debug, Clone)]
+    struct Silent(Arc<AtomicUsize>);
+
+    impl SaslMechanism for Silent {
+        fn name(&self) -> &str {
+            "PLAIN"
+        }
+
+        fn initial_response(&mut self) -> Result<Vec<u8>, Error> {
+            Ok(vec![])
+        }
+
+        fn step(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_sasl() {
+        tokio_test::block_on(async {
+            Store::get("sasl:1")
+                .lock()
+                .unwrap()
+                .require_auth("user", "secret");
+            let cfg = ClientConfig::new_uncompressed(vec!["sasl:1".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            let err = client.set("key", "value", 0).await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::AuthenticationError)));
+
+            let wrong = cfg.clone().with_sasl(Plain::new("user", "wrong"));
+            let err = Client::<MockConnection, _>::new(wrong).await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::AuthenticationError)));
+            assert!(!format!("{:?}", Plain::new("user", "secret")).contains("secret"));
+
+            let plain = cfg.clone().with_sasl(Plain::new("user", "secret"));
+            let mut client = Client::<MockConnection, _>::new(plain).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+
+            let steps = Arc::new(AtomicUsize::new(0));
+            let deferred = DeferredPlain {
+                plain: Plain::new("user", "secret"),
+                steps: steps.clone(),
+            };
+            let mut client = Client::<MockConnection, _>::new(cfg.clone().with_sasl(deferred))
+                .await
+                .unwrap();
+            let value = client.get::<_, String>("key").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+            assert_eq!(1, steps.load(Ordering::Relaxed));
+
+            // Kept nodes authenticate again after the mechanism changed.
+            let steps = Arc::new(AtomicUsize::new(0));
+            let deferred = DeferredPlain {
+                plain: Plain::new("user", "secret"),
+                steps: steps.clone(),
+            };
+            client
+                .apply_config(cfg.clone().with_sasl(deferred))
+                .await
+                .unwrap();
+            client.get::<_, String>("key").await.unwrap();
+            assert_eq!(1, steps.load(Ordering::Relaxed));
+
+            // A server which never stops asking to continue is given up on.
+            let steps = Arc::new(AtomicUsize::new(0));
+            let silent = cfg.with_sasl(Silent(steps.clone()));
+            let err = Client::<MockConnection, _>::new(silent).await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::AuthenticationError)));
+            assert_eq!(MAX_STEPS, steps.load(Ordering::Relaxed));
+        });
+    }
+}
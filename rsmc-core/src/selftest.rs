@@ -0,0 +1,278 @@
+//! A new cluster, or a config change such as a new compressor or key codec,
+//! is best smoke tested by actually using it. [`crate::client::Client::self_test`]
+//! runs the same short sequence of commands against every node, writing,
+//! reading, compressing, CAS-updating and deleting a synthetic key, and
+//! checks the CRC-32 of every value read back against what was written. The
+//! result is a structured [`SelfTestReport`] that deployment pipelines can
+//! check before shifting traffic.
+//!
+//! Synthetic keys are unique to every run and expire after a minute, so an
+//! interrupted run leaves nothing behind for long.
+
+use std::{
+    error::Error as StdError,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Compressor, Connection, NoCompressor},
+    protocol::{Packet, RequestFields, SetExtras, Status},
+    ring::Node,
+    rng::Xorshift,
+    vbucket::crc32,
+};
+
+/// How long synthetic keys live, in seconds, in case a run is interrupted
+/// before deleting them.
+const SELF_TEST_EXPIRE: u32 = 60;
+
+/// The size of the value compressed by [`SelfTestStep::Compress`], large
+/// enough to be compressed by the default compressor settings.
+const COMPRESSIBLE_BYTES: usize = 64 * 1024;
+
+type StepOutcome = Result<(), Box<dyn StdError + Send + Sync>>;
+
+/// A single command of the self test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestStep {
+    /// Connect to the node and write an uncompressed value.
+    Write,
+    /// Read the value back and check its CRC.
+    Read,
+    /// Write a compressible value through the compressor of the client, and
+    /// read it back through the compressor.
+    Compress,
+    /// Update the value with the CAS of the last read, check that a stale
+    /// CAS is rejected, and read the update back.
+    CasUpdate,
+    /// Delete the key and check that it misses.
+    Delete,
+}
+
+impl SelfTestStep {
+    /// Every step, in the order they run.
+    pub const ALL: [SelfTestStep; 5] = [
+        SelfTestStep::Write,
+        SelfTestStep::Read,
+        SelfTestStep::Compress,
+        SelfTestStep::CasUpdate,
+        SelfTestStep::Delete,
+    ];
+}
+
+/// The outcome of a single step against a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepReport {
+    /// The step that ran.
+    pub step: SelfTestStep,
+    /// How long the step took.
+    pub elapsed: Duration,
+    /// The reason the step failed, if it did.
+    pub error: Option<String>,
+}
+
+/// The outcome of the self test against a single node. Steps stop at the
+/// first failure, since every step depends on the ones before it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NodeSelfTest {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// The synthetic key written to the node.
+    pub key: String,
+    /// The steps that ran, in order.
+    pub steps: Vec<StepReport>,
+}
+
+impl NodeSelfTest {
+    /// Whether every step ran and passed.
+    pub fn passed(&self) -> bool {
+        self.steps.len() == SelfTestStep::ALL.len() && self.failure().is_none()
+    }
+
+    /// The step that failed, if any.
+    pub fn failure(&self) -> Option<&StepReport> {
+        self.steps.iter().find(|step| step.error.is_some())
+    }
+}
+
+/// A structured report of running the self test against every node.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SelfTestReport {
+    /// The outcome for each node, in the order they were configured.
+    pub nodes: Vec<NodeSelfTest>,
+}
+
+impl SelfTestReport {
+    /// Whether every step passed on every node.
+    pub fn passed(&self) -> bool {
+        self.nodes.iter().all(NodeSelfTest::passed)
+    }
+
+    /// The nodes with a failing step.
+    pub fn failures(&self) -> Vec<&NodeSelfTest> {
+        self.nodes.iter().filter(|node| !node.passed()).collect()
+    }
+}
+
+/// Run every step against a node with the given synthetic key.
+pub(crate) async fn test_node<C: Connection, P: Compressor>(
+    node: &mut Node<C>,
+    compressor: P,
+    idle_ping: Option<Duration>,
+    key: String,
+) -> NodeSelfTest {
+    let mut out = NodeSelfTest {
+        endpoint: node.endpoint.clone(),
+        key: key.clone(),
+        steps: vec![],
+    };
+    let mut runner = Runner {
+        node,
+        compressor,
+        idle_ping,
+        key: key.into_bytes(),
+        cas: 0,
+    };
+    for step in SelfTestStep::ALL {
+        let start = Instant::now();
+        let error = runner.run(step).await.err().map(|err| err.to_string());
+        let failed = error.is_some();
+        let elapsed = start.elapsed();
+        out.steps.push(StepReport {
+            step,
+            elapsed,
+            error,
+        });
+        if failed {
+            break;
+        }
+    }
+    out
+}
+
+struct Runner<'a, C: Connection, P: Compressor> {
+    node: &'a mut Node<C>,
+    compressor: P,
+    idle_ping: Option<Duration>,
+    key: Vec<u8>,
+    cas: u64,
+}
+
+impl<C: Connection, P: Compressor> Runner<'_, C, P> {
+    async fn run(&mut self, step: SelfTestStep) -> StepOutcome {
+        match step {
+            SelfTestStep::Write => {
+                self.node.ensure_connected(self.idle_ping).await?;
+                self.store(NoCompressor, payload(&self.key, 1024), 0)
+                    .await?;
+                Ok(())
+            }
+            SelfTestStep::Read => self.check(NoCompressor, payload(&self.key, 1024)).await,
+            SelfTestStep::Compress => {
+                let value = b"rsmc".repeat(COMPRESSIBLE_BYTES / 4);
+                self.store(self.compressor, value.clone(), 0).await?;
+                self.check(self.compressor, value).await
+            }
+            SelfTestStep::CasUpdate => {
+                let (value, stale) = (payload(&self.key, 2048), self.cas);
+                self.store(NoCompressor, value.clone(), stale).await?;
+                match self.store(NoCompressor, vec![], stale).await {
+                    Err(err) if err.downcast_ref() == Some(&Status::KeyExists) => {}
+                    Err(err) => return Err(err),
+                    Ok(()) => return Err("a stale CAS was accepted".into()),
+                }
+                self.check(NoCompressor, value).await
+            }
+            SelfTestStep::Delete => {
+                let packet = self.node.send(NoCompressor, Packet::delete(&self.key)?);
+                packet.await?.error_for_status()?;
+                let packet = self.node.send(NoCompressor, Packet::get(&self.key)?);
+                match packet.await?.error_for_status() {
+                    Err(Status::KeyNotFound) => Ok(()),
+                    Err(status) => Err(status.into()),
+                    Ok(()) => Err("the deleted key was still found".into()),
+                }
+            }
+        }
+    }
+
+    /// Store a value for the key, only if its CAS matches when `cas` is
+    /// not 0.
+    async fn store<Q: Compressor>(
+        &mut self,
+        compressor: Q,
+        value: Vec<u8>,
+        cas: u64,
+    ) -> StepOutcome {
+        let extras = SetExtras::new(0, SELF_TEST_EXPIRE);
+        let fields = RequestFields::new(cas, 0);
+        let packet = Packet::set_raw_with(&self.key, value, extras, fields)?;
+        let packet = self.node.send(compressor, packet).await?;
+        packet.error_for_status()?;
+        self.cas = packet.header.cas;
+        Ok(())
+    }
+
+    /// Read the key back and check that its CRC matches the value.
+    async fn check<Q: Compressor>(&mut self, compressor: Q, value: Vec<u8>) -> StepOutcome {
+        let packet = self.node.send(compressor, Packet::get(&self.key)?).await?;
+        packet.error_for_status()?;
+        self.cas = packet.header.cas;
+        check_crc(&value, &packet.value)
+    }
+}
+
+/// Check that the CRC-32 of a value read back matches the value written.
+fn check_crc(written: &[u8], read: &[u8]) -> StepOutcome {
+    let (expected, actual) = (crc32(written), crc32(read));
+    match expected == actual {
+        true => Ok(()),
+        false => Err(format!("CRC mismatch: wrote {:08x}, read {:08x}", expected, actual).into()),
+    }
+}
+
+/// Generate a value of pseudo-random bytes, seeded by the key, which does
+/// not compress.
+fn payload(key: &[u8], len: usize) -> Vec<u8> {
+    let mut rng = Xorshift::new(crc32(key));
+    (0..len).map(|_| rng.next_u32() as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        client::{Client, ClientConfig, Error},
+        mock::{MockConnection, Store},
+    };
+
+    use super::{check_crc, payload, SelfTestStep};
+
+    #[test]
+    fn test_self_test() {
+        assert!(check_crc(b"value", b"value").is_ok());
+        let err = check_crc(b"value", b"corrupt").unwrap_err();
+        assert!(err.to_string().starts_with("CRC mismatch"));
+        assert_ne!(payload(b"a", 16), payload(b"b", 16));
+
+        tokio_test::block_on(async {
+            let endpoints = vec!["selftest:1".to_string(), "selftest:2".to_string()];
+            let cfg = ClientConfig::new_uncompressed(endpoints.clone());
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let report = client.self_test().await.unwrap();
+            assert!(report.passed());
+            assert!(report.failures().is_empty());
+            for (node, endpoint) in report.nodes.iter().zip(&endpoints) {
+                assert_eq!(endpoint, &node.endpoint);
+                let steps = node.steps.iter().map(|step| step.step).collect::<Vec<_>>();
+                assert_eq!(SelfTestStep::ALL.to_vec(), steps);
+                // Synthetic keys are deleted by the last step.
+                let store = Store::get(endpoint);
+                assert_eq!(None, store.lock().unwrap().expire(node.key.as_bytes()));
+            }
+
+            let cfg = ClientConfig::new_uncompressed(endpoints).with_read_only(true);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            assert!(matches!(client.self_test().await, Err(Error::ReadOnly)));
+        });
+    }
+}
@@ -0,0 +1,382 @@
+//! A deterministic simulation of a memcached cluster, for testing how an
+//! application behaves during reconnect storms and node flaps without
+//! running memcached or waiting on real timers. A [`Simulation`] owns a
+//! cluster of [`crate::mock`] servers reached through a [`SimConnection`],
+//! along with a virtual clock and a seeded random number generator:
+//!
+//! - every request takes the configured latency in virtual time,
+//!   [`Connection::sleep`] advances the clock instead of waiting, and
+//!   [`Connection::now`] reads it;
+//! - nodes go down and come back up at scheduled virtual times, refusing
+//!   connections and dropping the ones that are open while down;
+//! - requests fail at random with the configured fault rate, drawn from the
+//!   generator so that the same seed always fails the same requests.
+//!
+//! Futures must be driven with [`Simulation::run`], which runs them on the
+//! current thread so that the order of events is the same on every run.
+//! The timers of clients read the virtual clock, except for the ones which
+//! start when they are created outside of a client: the window of a
+//! [`crate::cold::ColdStart`], the interval of a
+//! [`crate::counter::BatchedCounter`], deadlines set with
+//! [`crate::options::RequestOptions::with_timeout`] and
+//! [`crate::limit::LoadShedder::is_shedding`] all read the system clock.
+//! Enable the `testing` feature to use this module.
+
+use async_trait::async_trait;
+use std::{
+    cell::RefCell,
+    future::Future,
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Connection, Error},
+    mock::MockConnection,
+};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Mutex<State>>>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Default)]
+struct NodeState {
+    outages: Vec<(Duration, Duration)>,
+    connects: u64,
+    faults: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    id: usize,
+    endpoints: Vec<String>,
+    epoch: Instant,
+    now: Duration,
+    rng: u64,
+    latency: Duration,
+    fault_rate: f64,
+    nodes: Vec<NodeState>,
+}
+
+impl State {
+    fn is_down(&self, node: usize) -> bool {
+        let now = self.now;
+        let outages = &self.nodes[node].outages;
+        outages
+            .iter()
+            .any(|(from, until)| (*from..*until).contains(&now))
+    }
+
+    /// Draw the next number from a xorshift generator, between 0 and 1.
+    fn next_f64(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+/// A simulated cluster with a virtual clock and seeded faults. Connect to
+/// its [`Simulation::endpoints`] with a [`SimConnection`], from inside of
+/// [`Simulation::run`]. Every simulation has the same endpoints, so that keys
+/// are routed to the same nodes on every run, but its own servers.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    state: Arc<Mutex<State>>,
+    endpoints: Vec<String>,
+}
+
+impl Simulation {
+    /// Create a cluster of `nodes` servers, drawing faults from a generator
+    /// seeded with `seed`. Requests take no virtual time and never fail at
+    /// random by default.
+    pub fn new(nodes: usize, seed: u64) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let endpoints = (0..nodes.max(1))
+            .map(|node| format!("sim:{}", 11211 + node))
+            .collect::<Vec<_>>();
+        let state = Arc::new(Mutex::new(State {
+            id,
+            endpoints: endpoints.clone(),
+            epoch: Instant::now(),
+            now: Duration::ZERO,
+            rng: seed | 1,
+            latency: Duration::ZERO,
+            fault_rate: 0.0,
+            nodes: endpoints.iter().map(|_| NodeState::default()).collect(),
+        }));
+        Self { state, endpoints }
+    }
+
+    /// Advance the virtual clock by `latency` on every request.
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.state.lock().unwrap().latency = latency;
+        self
+    }
+
+    /// From now on, drop the connection of every request with the chance
+    /// `rate`, between 0 and 1.
+    pub fn set_fault_rate(&self, rate: f64) {
+        self.state.lock().unwrap().fault_rate = rate;
+    }
+
+    /// The endpoints of the simulated servers, to configure clients with.
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    /// Take a node down `at` a virtual time, for the given duration.
+    pub fn flap(&self, node: usize, at: Duration, down_for: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.nodes[node].outages.push((at, at + down_for));
+    }
+
+    /// Whether the node is down at the current virtual time.
+    pub fn is_down(&self, node: usize) -> bool {
+        self.state.lock().unwrap().is_down(node)
+    }
+
+    /// The current virtual time, since the simulation was created.
+    pub fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    /// Advance the virtual clock.
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock().unwrap().now += duration;
+    }
+
+    /// The number of connections made to a node, including reconnects.
+    pub fn connects(&self, node: usize) -> u64 {
+        self.state.lock().unwrap().nodes[node].connects
+    }
+
+    /// The number of requests to a node which failed at random.
+    pub fn faults(&self, node: usize) -> u64 {
+        self.state.lock().unwrap().nodes[node].faults
+    }
+
+    /// Run a future to completion on the current thread. Connections made by
+    /// the future connect to this simulation, and their sleeps advance its
+    /// virtual clock.
+    pub fn run<F: Future>(&self, future: F) -> F::Output {
+        let previous = CURRENT.with(|current| current.replace(Some(self.state.clone())));
+        let output = futures::executor::block_on(future);
+        CURRENT.with(|current| current.replace(previous));
+        output
+    }
+}
+
+/// A connection to a server of a [`Simulation`].
+#[derive(Debug, Clone)]
+pub struct SimConnection {
+    inner: MockConnection,
+    state: Arc<Mutex<State>>,
+    node: usize,
+}
+
+#[async_trait]
+impl Connection for SimConnection {
+    async fn connect(url: String) -> Result<Self, Error> {
+        let state = CURRENT.with(|current| current.borrow().clone());
+        let state = match state {
+            Some(state) => state,
+            None => return Err(std::io::Error::from(ErrorKind::NotFound).into()),
+        };
+        let (store, node) = {
+            let mut state = state.lock().unwrap();
+            let node = state.endpoints.iter().position(|e| *e == url);
+            match node {
+                Some(node) if state.is_down(node) => {
+                    return Err(std::io::Error::from(ErrorKind::ConnectionRefused).into());
+                }
+                Some(node) => {
+                    state.nodes[node].connects += 1;
+                    (format!("sim-{}/{}", state.id, url), node)
+                }
+                None => return Err(std::io::Error::from(ErrorKind::NotFound).into()),
+            }
+        };
+        let inner = MockConnection::connect(store).await?;
+        Ok(SimConnection { inner, state, node })
+    }
+
+    async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        self.inner.read(buf).await
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let dropped = {
+            let mut state = self.state.lock().unwrap();
+            let latency = state.latency;
+            state.now += latency;
+            let fault = state.fault_rate > 0.0 && state.next_f64() < state.fault_rate;
+            if fault {
+                state.nodes[self.node].faults += 1;
+            }
+            fault || state.is_down(self.node)
+        };
+        if dropped {
+            self.inner.close();
+        }
+        self.inner.write(data).await
+    }
+
+    async fn sleep(duration: Duration) {
+        CURRENT.with(|current| {
+            if let Some(state) = current.borrow().as_ref() {
+                state.lock().unwrap().now += duration;
+            }
+        });
+    }
+
+    fn now() -> Instant {
+        CURRENT.with(|current| match current.borrow().as_ref() {
+            Some(state) => {
+                let state = state.lock().unwrap();
+                state.epoch + state.now
+            }
+            None => Instant::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        budget::ErrorBudget,
+        client::{drain_pool, Client, ClientConfig, Connection, Error, Pool},
+        local::LocalTier,
+        options::{ReadPreference, RequestOptions},
+    };
+
+    use super::{SimConnection, Simulation};
+
+    /// Read and write a few keys every virtual second, recording which
+    /// requests succeeded.
+    fn scenario(seed: u64) -> (Vec<bool>, Vec<u64>) {
+        let sim = Simulation::new(3, seed).with_latency(Duration::from_millis(1));
+        sim.flap(1, Duration::from_secs(2), Duration::from_secs(3));
+        let cfg = ClientConfig::new_uncompressed(sim.endpoints().to_vec());
+        let outcomes = sim.run(async {
+            let mut client = Client::<SimConnection, _>::new(cfg).await.unwrap();
+            sim.set_fault_rate(0.2);
+            let mut outcomes = vec![];
+            for _ in 0..8 {
+                for key in ["a", "b", "c", "d"] {
+                    outcomes.push(client.set(key, "value", 0).await.is_ok());
+                    outcomes.push(client.get::<_, String>(key).await.is_ok());
+                }
+                SimConnection::sleep(Duration::from_secs(1)).await;
+            }
+            outcomes
+        });
+        assert!(sim.now() >= Duration::from_secs(8));
+        assert!(!sim.is_down(1));
+        let connects = (0..3).map(|node| sim.connects(node)).collect();
+        (outcomes, connects)
+    }
+
+    #[test]
+    fn test_simulation() {
+        let (outcomes, connects) = scenario(7);
+        assert!(outcomes.contains(&false) && outcomes.contains(&true));
+        assert!(connects.iter().any(|&n| n > 1), "{:?}", connects);
+        assert_eq!((outcomes, connects), scenario(7));
+    }
+
+    #[test]
+    fn test_error_budget_cooldown_in_virtual_time() {
+        let sim = Simulation::new(1, 3);
+        sim.flap(0, Duration::from_secs(1), Duration::from_secs(1));
+        let budget = ErrorBudget {
+            window: 4,
+            min_requests: 2,
+            max_error_rate: 0.5,
+            cooldown: Duration::from_secs(30),
+        };
+        let cfg =
+            ClientConfig::new_uncompressed(sim.endpoints().to_vec()).with_error_budget(budget);
+        sim.run(async {
+            let mut client = Client::<SimConnection, _>::new(cfg).await.unwrap();
+            client.set("key", "value", 0).await.unwrap();
+            SimConnection::sleep(Duration::from_secs(1)).await;
+            for _ in 0..4 {
+                let _ = client.set("key", "value", 0).await;
+            }
+            let failing_open = || client.topology_snapshot().failing_open().len();
+            assert_eq!(1, failing_open());
+            SimConnection::sleep(Duration::from_secs(10)).await;
+            assert_eq!(1, failing_open());
+            SimConnection::sleep(Duration::from_secs(30)).await;
+            assert_eq!(0, failing_open());
+        });
+    }
+
+    #[test]
+    fn test_drain_in_virtual_time() {
+        let sim = Simulation::new(1, 5);
+        let cfg = ClientConfig::new_uncompressed(sim.endpoints().to_vec());
+        sim.run(async {
+            let pool = Pool::<SimConnection, _>::builder(cfg).build().unwrap();
+            let used = pool.get().await.unwrap();
+            let err = drain_pool(&pool, Duration::from_secs(2)).await.unwrap_err();
+            assert!(matches!(err, Error::DeadlineExceeded));
+            drop(used);
+        });
+        assert_eq!(Duration::from_secs(2), sim.now());
+    }
+
+    #[test]
+    fn test_client_timers_in_virtual_time() {
+        let sim = Simulation::new(1, 9).with_latency(Duration::from_millis(1));
+        let local = LocalTier::new(8, Duration::from_secs(5));
+        let cfg =
+            ClientConfig::new_uncompressed(sim.endpoints().to_vec()).with_local_tier(local.clone());
+        sim.run(async {
+            let mut client = Client::<SimConnection, _>::new(cfg).await.unwrap();
+            let created = sim.now();
+            client.set("key", "value", 0).await.unwrap();
+            client.get::<_, String>("key").await.unwrap();
+            SimConnection::sleep(Duration::from_secs(3)).await;
+            assert_eq!(sim.now() - created, client.age());
+
+            // The local entry expires in virtual time.
+            let local_only = RequestOptions::new().with_read_preference(ReadPreference::LocalOnly);
+            let value = client.get_with_options::<_, String>("key", &local_only);
+            assert_eq!(Some("value".to_string()), value.await.unwrap());
+            SimConnection::sleep(Duration::from_secs(3)).await;
+            let value = client.get_with_options::<_, String>("key", &local_only);
+            assert_eq!(None, value.await.unwrap());
+
+            // Bulk reads are timed in virtual time.
+            let options = RequestOptions::new()
+                .with_timing(true)
+                .with_skip_local_tier(true);
+            let result = client.get_multi_with_options::<_, String>(&["key"], &options);
+            let timing = result.await.unwrap().timings.remove(0);
+            assert!(timing.last_response >= Some(Duration::from_millis(1)));
+
+            // So do deadlines read from the connection clock.
+            let deadline = SimConnection::now() + Duration::from_secs(1);
+            let options = RequestOptions::new()
+                .with_deadline(deadline)
+                .with_skip_local_tier(true);
+            client
+                .get_with_options::<_, String>("key", &options)
+                .await
+                .unwrap();
+            SimConnection::sleep(Duration::from_secs(1)).await;
+            let err = client.get_with_options::<_, String>("key", &options).await;
+            assert!(matches!(err, Err(Error::DeadlineExceeded)));
+        });
+    }
+}
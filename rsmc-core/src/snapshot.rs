@@ -0,0 +1,169 @@
+//! Staging environments and disaster recovery need caches primed from a
+//! known state instead of warming up from scratch. [`Client::export`] writes
+//! the raw values and flags of a selected keyspace to a snapshot, and
+//! [`Client::import`] stores them back, into the same cluster or another
+//! one. Values are copied exactly as they are stored, compressed or in an
+//! envelope if they were, so they read back the same.
+//!
+//! Memcached cannot list its keys over the binary protocol, so the keys to
+//! export are supplied by the caller, for example from the database the
+//! cache is in front of. Memcached does not report expirations either, so
+//! every imported entry is stored with the expiration given to the import.
+//!
+//! A snapshot starts with the magic bytes `RSMC` and a format version byte,
+//! followed by an entry per key: the length of the key, the key, the flags,
+//! the length of the value and the value, with lengths and flags as
+//! big-endian u32s.
+//!
+//! [`Client::export`]: crate::client::Client::export
+//! [`Client::import`]: crate::client::Client::import
+
+use std::io::ErrorKind;
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::client::Error;
+
+/// The bytes every snapshot starts with.
+const MAGIC: &[u8; 4] = b"RSMC";
+
+/// The version of the format written by this crate.
+const VERSION: u8 = 1;
+
+/// The longest value accepted in a snapshot, which is the largest item
+/// memcached can be configured to store.
+const MAX_VALUE_LEN: u32 = 1 << 30;
+
+/// How many entries an import stores in a single pipeline per node.
+pub(crate) const IMPORT_CHUNK: usize = 1000;
+
+/// A key of a snapshot, with its raw value and flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub key: Vec<u8>,
+    pub flags: u32,
+    pub value: Vec<u8>,
+}
+
+fn invalid(reason: &str) -> Error {
+    std::io::Error::new(ErrorKind::InvalidData, reason.to_string()).into()
+}
+
+/// Write the start of a snapshot.
+pub(crate) async fn write_header<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<(), Error> {
+    writer.write_all(MAGIC).await?;
+    writer.write_all(&[VERSION]).await?;
+    Ok(())
+}
+
+/// Read the start of a snapshot, failing if it is not one this crate can
+/// read.
+pub(crate) async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(), Error> {
+    let mut header = [0_u8; 5];
+    reader.read_exact(&mut header).await?;
+    match (&header[..4], header[4]) {
+        (magic, VERSION) if magic == MAGIC => Ok(()),
+        (magic, _) if magic == MAGIC => Err(invalid("unsupported snapshot version")),
+        _ => Err(invalid("not a snapshot")),
+    }
+}
+
+/// Write a single entry.
+pub(crate) async fn write_entry<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    key: &[u8],
+    flags: u32,
+    value: &[u8],
+) -> Result<(), Error> {
+    writer.write_all(&(key.len() as u32).to_be_bytes()).await?;
+    writer.write_all(key).await?;
+    writer.write_all(&flags.to_be_bytes()).await?;
+    writer
+        .write_all(&(value.len() as u32).to_be_bytes())
+        .await?;
+    writer.write_all(value).await?;
+    Ok(())
+}
+
+/// Read the next entry, or None at the end of the snapshot.
+pub(crate) async fn read_entry<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Entry>, Error> {
+    let mut len = [0_u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(invalid("truncated snapshot entry")),
+            n => filled += n,
+        }
+    }
+    let key_len = u32::from_be_bytes(len);
+    if key_len == 0 || key_len > u16::MAX as u32 {
+        return Err(invalid("invalid key length in snapshot"));
+    }
+    let mut key = vec![0_u8; key_len as usize];
+    reader.read_exact(&mut key).await?;
+    let flags = read_u32(reader).await?;
+    let value_len = read_u32(reader).await?;
+    if value_len > MAX_VALUE_LEN {
+        return Err(invalid("invalid value length in snapshot"));
+    }
+    let mut value = vec![0_u8; value_len as usize];
+    reader.read_exact(&mut value).await?;
+    Ok(Some(Entry { key, flags, value }))
+}
+
+async fn read_u32<R: AsyncRead + Unpin>(reader: &mut R) -> Result<u32, Error> {
+    let mut bytes = [0_u8; 4];
+    reader.read_exact(&mut bytes).await?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::io::Cursor;
+
+    use crate::{
+        client::{Client, ClientConfig, Error},
+        mock::{MockConnection, Store},
+    };
+
+    #[test]
+    fn test_snapshot() {
+        tokio_test::block_on(async {
+            let source = ClientConfig::new_uncompressed(vec!["snapshot:1".into()]);
+            let mut source = Client::<MockConnection, _>::new(source).await.unwrap();
+            source.set("user:1", "alice", 0).await.unwrap();
+            source.set("user:2", "bob", 0).await.unwrap();
+            source.set_with_flags("raw", "bytes", 7, 0).await.unwrap();
+
+            // Keys are exported even when the cache is disabled.
+            source.set_enabled(false);
+            let mut snapshot = Cursor::new(vec![]);
+            let keys = ["user:1", "user:2", "raw", "missing"];
+            assert_eq!(3, source.export(keys, &mut snapshot).await.unwrap());
+
+            let endpoints = vec!["snapshot:2".into(), "snapshot:3".into()];
+            let target = ClientConfig::new_uncompressed(endpoints);
+            let mut target = Client::<MockConnection, _>::new(target).await.unwrap();
+            snapshot.set_position(0);
+            assert!(target.import(&mut snapshot, 60).await.unwrap().is_empty());
+            let value = target.get::<_, String>("user:2").await.unwrap();
+            assert_eq!(Some("bob".to_string()), value);
+            let raw = target.get_raw_with_flags("raw").await.unwrap();
+            assert_eq!(Some((b"bytes".to_vec(), 7)), raw);
+            let expires = ["snapshot:2", "snapshot:3"]
+                .iter()
+                .filter_map(|url| Store::get(url).lock().unwrap().expire(b"user:1"))
+                .collect::<Vec<_>>();
+            assert_eq!(vec![60], expires);
+
+            let mut truncated = Cursor::new(snapshot.into_inner()[..20].to_vec());
+            let err = target.import(&mut truncated, 0).await.unwrap_err();
+            assert!(matches!(err, Error::IoError(_)));
+            let mut garbage = Cursor::new(b"not a snapshot".to_vec());
+            assert!(target.import(&mut garbage, 0).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,850 @@
+//! This module tracks statistics about each node in the ring, giving
+//! visibility into the traffic and errors on every connection without
+//! needing external packet captures.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    client::{Connection, Error, NoCompressor},
+    instrument::ExtstoreWarning,
+    options::BAGGAGE_FLAG,
+    protocol::Packet,
+    ring::Node,
+};
+
+/// A snapshot of the statistics tracked for a single node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStats {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// The number of requests written to the node.
+    pub requests: u64,
+    /// The number of reads or writes that failed.
+    pub errors: u64,
+    /// The number of times the connection was re-established.
+    pub reconnects: u64,
+    /// The number of bytes written to the node.
+    pub bytes_sent: u64,
+    /// The number of bytes read from the node.
+    pub bytes_received: u64,
+    /// A description of the most recent error, if any.
+    pub last_error: Option<String>,
+    /// When the current connection to the node was established.
+    pub connected_since: SystemTime,
+    /// The sizes of values stored on the node in bytes, measured after
+    /// serialization and compression.
+    pub value_sizes: Histogram,
+    /// The expirations of values stored on the node in seconds. Note that
+    /// expirations over 30 days are unix timestamps.
+    pub ttls: Histogram,
+    /// An exponentially weighted moving average of the time between writing
+    /// a request to the node and reading its response, or None if no
+    /// response was read yet. Recent requests weigh the most, so the average
+    /// follows the node as it slows down or recovers.
+    pub latency: Option<Duration>,
+    /// The number of responses discarded because they answered an earlier
+    /// request on the same connection, such as late responses to requests
+    /// that were abandoned after a timeout.
+    pub discarded_responses: u64,
+}
+
+/// How healthy a node is, from [`NodeHealth::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    /// The last request to the node succeeded.
+    Healthy,
+    /// The connection to the node is poisoned or its last request failed,
+    /// but requests are still sent to it.
+    Degraded,
+    /// The node exhausted its error budget, so reads to it fail open
+    /// without touching the network until the cooldown has passed.
+    Ejected,
+}
+
+/// A summary of the health of a single node, suitable for a service's
+/// health endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeHealth {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// Whether the node is healthy, degraded or ejected.
+    pub state: NodeState,
+    /// The number of reads or writes that failed since the last response
+    /// was read from the node.
+    pub consecutive_failures: u64,
+    /// A description of the most recent error, if any.
+    pub last_error: Option<String>,
+    /// The moving average of the latency of the node, as in
+    /// [`NodeStats::latency`].
+    pub latency: Option<Duration>,
+    /// How long ago a response was last read from the node, or None if no
+    /// response was read yet.
+    pub since_last_success: Option<Duration>,
+}
+
+/// The weight of each new sample in the latency average. Older samples
+/// decay by `1 - LATENCY_WEIGHT` with every new one.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// The number of buckets in a histogram, enough for any `u32`.
+const HISTOGRAM_BUCKETS: usize = 33;
+
+/// The number of opaques remembered per connection to recognize late or
+/// duplicate responses.
+const RECENT_OPAQUES: usize = 64;
+
+/// A histogram whose buckets grow by powers of two. Bucket `0` counts zeros
+/// and bucket `i` counts values from `2^(i-1)` to `2^i - 1`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// The number of values in each bucket.
+    pub buckets: Vec<u64>,
+    /// The number of values recorded.
+    pub count: u64,
+    /// The sum of every value recorded.
+    pub sum: u64,
+}
+
+impl Histogram {
+    /// The smallest and largest values counted by a bucket.
+    pub fn bucket_bounds(index: usize) -> (u64, u64) {
+        match index {
+            0 => (0, 0),
+            i => (1 << (i - 1), (1 << i) - 1),
+        }
+    }
+
+    /// The mean of every value recorded, or None if nothing was recorded.
+    pub fn mean(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count => Some(self.sum as f64 / count as f64),
+        }
+    }
+}
+
+/// The live counters behind a [`Histogram`].
+#[derive(Debug)]
+struct AtomicHistogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    sum: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, value: u32) {
+        let index = (u32::BITS - value.leading_zeros()) as usize;
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let buckets = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        Histogram {
+            count: buckets.iter().sum(),
+            buckets,
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The live counters behind [`NodeStats`], updated as requests are made.
+#[derive(Debug)]
+pub(crate) struct NodeCounters {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    reconnects: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    connected_since: Mutex<SystemTime>,
+    poisoned: AtomicBool,
+    value_sizes: AtomicHistogram,
+    ttls: AtomicHistogram,
+    latency: Mutex<Option<Duration>>,
+    next_opaque: AtomicU32,
+    recent_opaques: Mutex<VecDeque<u32>>,
+    discarded_responses: AtomicU64,
+    consecutive_failures: AtomicU64,
+    last_success: Mutex<Option<Instant>>,
+}
+
+impl NodeCounters {
+    pub fn new() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            connected_since: Mutex::new(SystemTime::now()),
+            poisoned: AtomicBool::new(false),
+            value_sizes: AtomicHistogram::new(),
+            ttls: AtomicHistogram::new(),
+            latency: Mutex::new(None),
+            next_opaque: AtomicU32::new(1),
+            recent_opaques: Mutex::new(VecDeque::new()),
+            discarded_responses: AtomicU64::new(0),
+            consecutive_failures: AtomicU64::new(0),
+            last_success: Mutex::new(None),
+        }
+    }
+
+    pub fn record_write(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_read(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_store(&self, value_size: usize, ttl: u32) {
+        self.value_sizes.record(value_size as u32);
+        self.ttls.record(ttl);
+    }
+
+    pub fn record_latency(&self, sample: Duration) {
+        let mut latency = self.latency.lock().unwrap();
+        *latency = Some(match *latency {
+            Some(average) => average.mul_f64(1.0 - LATENCY_WEIGHT) + sample.mul_f64(LATENCY_WEIGHT),
+            None => sample,
+        });
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        *self.latency.lock().unwrap()
+    }
+
+    pub fn record_error<E: ToString>(&self, err: &E) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(err.to_string());
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.last_success.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        *self.connected_since.lock().unwrap() = SystemTime::now();
+        self.poisoned.store(false, Ordering::Relaxed);
+        self.recent_opaques.lock().unwrap().clear();
+    }
+
+    /// The opaque for the next request, which is never 0 and never carries
+    /// baggage.
+    pub fn next_opaque(&self) -> u32 {
+        loop {
+            let opaque = self.next_opaque.fetch_add(1, Ordering::Relaxed) & !BAGGAGE_FLAG;
+            if opaque != 0 {
+                return opaque;
+            }
+        }
+    }
+
+    /// Remember the opaque of a request written to the connection.
+    pub fn record_sent(&self, opaque: u32) {
+        let mut recent = self.recent_opaques.lock().unwrap();
+        if recent.len() == RECENT_OPAQUES {
+            recent.pop_front();
+        }
+        recent.push_back(opaque);
+    }
+
+    /// Whether a request with the opaque was recently written to the
+    /// connection.
+    pub fn is_recent(&self, opaque: u32) -> bool {
+        self.recent_opaques.lock().unwrap().contains(&opaque)
+    }
+
+    pub fn record_discarded(&self) {
+        self.discarded_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn poison(&self) {
+        self.poisoned.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self, endpoint: &str) -> NodeStats {
+        NodeStats {
+            endpoint: endpoint.to_string(),
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+            connected_since: *self.connected_since.lock().unwrap(),
+            value_sizes: self.value_sizes.snapshot(),
+            ttls: self.ttls.snapshot(),
+            latency: self.latency(),
+            discarded_responses: self.discarded_responses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn health(&self, endpoint: &str, poisoned: bool, ejected: bool) -> NodeHealth {
+        let consecutive_failures = self.consecutive_failures.load(Ordering::Relaxed);
+        let state = if ejected {
+            NodeState::Ejected
+        } else if poisoned || consecutive_failures > 0 {
+            NodeState::Degraded
+        } else {
+            NodeState::Healthy
+        };
+        NodeHealth {
+            endpoint: endpoint.to_string(),
+            state,
+            consecutive_failures,
+            last_error: self.last_error.lock().unwrap().clone(),
+            latency: self.latency(),
+            since_last_success: self.last_success.lock().unwrap().map(|at| at.elapsed()),
+        }
+    }
+}
+
+/// A single slab class from `stats slabs`, which holds items of up to
+/// `chunk_size` bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlabClass {
+    /// The largest item stored in the class, in bytes.
+    pub chunk_size: u64,
+    /// The number of chunks in each page of the class.
+    pub chunks_per_page: u64,
+    /// The number of pages allocated to the class.
+    pub total_pages: u64,
+    /// The number of chunks allocated to the class.
+    pub total_chunks: u64,
+    /// The number of chunks holding items.
+    pub used_chunks: u64,
+    /// The number of chunks which are free to hold items.
+    pub free_chunks: u64,
+    /// The number of bytes requested by the items in the class.
+    pub mem_requested: u64,
+}
+
+/// The slab allocator stats of a node, from `stats slabs`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// Every slab class with allocated pages, by class id.
+    pub classes: BTreeMap<u32, SlabClass>,
+    /// The number of slab classes allocated.
+    pub active_slabs: u64,
+    /// The number of bytes allocated to slab pages.
+    pub total_malloced: u64,
+}
+
+/// The items of a single slab class from `stats items`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemClass {
+    /// The number of items stored in the class.
+    pub number: u64,
+    /// The age of the oldest item in the class.
+    pub age: Duration,
+    /// The number of items evicted from the class to make room.
+    pub evicted: u64,
+    /// The number of evicted items which had an expiration set.
+    pub evicted_nonzero: u64,
+    /// How long the most recently evicted item had gone unused.
+    pub evicted_time: Duration,
+    /// The number of evicted items which were never read.
+    pub evicted_unfetched: u64,
+    /// The number of expired items which were never read.
+    pub expired_unfetched: u64,
+    /// The number of times an item could not be stored for lack of memory.
+    pub outofmemory: u64,
+    /// The number of times an expired item's memory was reused.
+    pub reclaimed: u64,
+}
+
+/// The item stats of a node by slab class, from `stats items`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemStats {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// Every slab class holding items, by class id.
+    pub classes: BTreeMap<u32, ItemClass>,
+}
+
+impl ItemStats {
+    /// The number of items evicted from every class.
+    pub fn evictions(&self) -> u64 {
+        self.classes.values().map(|class| class.evicted).sum()
+    }
+}
+
+/// The commands counted for a single key prefix from `stats detail dump`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefixStats {
+    /// The number of gets for keys with the prefix.
+    pub gets: u64,
+    /// The number of gets which hit.
+    pub hits: u64,
+    /// The number of sets.
+    pub sets: u64,
+    /// The number of deletes.
+    pub deletes: u64,
+}
+
+/// The per-prefix command counts of a node, from `stats detail dump`. The
+/// server only counts them once `stats detail on` was sent over the text
+/// protocol, and uses the part of the key before the first `:` as prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DetailStats {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// The commands counted for every prefix.
+    pub prefixes: BTreeMap<String, PrefixStats>,
+}
+
+/// The extstore stats of a node, from the general stats of memcached 1.6
+/// nodes which store values on flash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtstoreStats {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// Whether the node reported any extstore stats at all.
+    pub enabled: bool,
+    /// The number of reads and writes queued for flash.
+    pub io_queue: u64,
+    /// The number of flash pages allocated.
+    pub page_allocs: u64,
+    /// The number of flash pages evicted to make room.
+    pub page_evictions: u64,
+    /// The number of flash pages reclaimed after their values expired.
+    pub page_reclaims: u64,
+    /// The number of flash pages which are free.
+    pub pages_free: u64,
+    /// The number of flash pages in use.
+    pub pages_used: u64,
+    /// The number of values read from flash.
+    pub objects_read: u64,
+    /// The number of values written to flash.
+    pub objects_written: u64,
+    /// The number of bytes read from flash.
+    pub bytes_read: u64,
+    /// The number of bytes written to flash.
+    pub bytes_written: u64,
+    /// The number of bytes on flash no longer used by any value.
+    pub bytes_fragmented: u64,
+    /// The number of gets which read a value from flash.
+    pub gets: u64,
+    /// The number of gets from flash which were aborted.
+    pub aborted_gets: u64,
+    /// The number of gets from flash which ran out of memory.
+    pub oom_gets: u64,
+    /// The number of values read from flash whose CRC did not match.
+    pub bad_crc: u64,
+    /// The number of values read from flash which had already been removed.
+    pub misses: u64,
+    /// The number of values read from flash which were moved back to memory.
+    pub recaches: u64,
+}
+
+impl ExtstoreStats {
+    /// The thresholds crossed by the stats. Nodes without extstore never
+    /// cross any.
+    pub fn warnings(&self, thresholds: &ExtstoreThresholds) -> Vec<ExtstoreWarning> {
+        let mut out = vec![];
+        if !self.enabled {
+            return out;
+        }
+        if self.io_queue > thresholds.max_io_queue {
+            out.push(ExtstoreWarning::IoQueue(self.io_queue));
+        }
+        if self.gets > 0 {
+            let aborted = (self.aborted_gets + self.oom_gets) as f64 / self.gets as f64;
+            if aborted > thresholds.max_aborted_ratio {
+                out.push(ExtstoreWarning::AbortedReads(aborted));
+            }
+        }
+        if self.bad_crc > 0 {
+            out.push(ExtstoreWarning::BadCrc(self.bad_crc));
+        }
+        if self.pages_free == 0 && self.pages_used > 0 {
+            out.push(ExtstoreWarning::NoFreePages);
+        }
+        out
+    }
+}
+
+/// The thresholds above which extstore stats are reported as warnings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtstoreThresholds {
+    /// The longest IO queue before warning. Defaults to 64.
+    pub max_io_queue: u64,
+    /// The largest fraction of aborted or out of memory gets from flash
+    /// before warning. Defaults to 1%.
+    pub max_aborted_ratio: f64,
+}
+
+impl Default for ExtstoreThresholds {
+    fn default() -> Self {
+        Self {
+            max_io_queue: 64,
+            max_aborted_ratio: 0.01,
+        }
+    }
+}
+
+fn parse_u64(value: &str) -> u64 {
+    value.trim().parse().unwrap_or_default()
+}
+
+/// Parse the response to `stats slabs`, whose per-class stats are named
+/// `<class>:<stat>`.
+pub(crate) fn parse_slab_stats(endpoint: &str, stats: &HashMap<String, String>) -> SlabStats {
+    let mut out = SlabStats {
+        endpoint: endpoint.to_string(),
+        ..SlabStats::default()
+    };
+    for (key, value) in stats {
+        let (class, stat) = match key.split_once(':') {
+            Some((class, stat)) => match class.parse::<u32>() {
+                Ok(class) => (class, stat),
+                Err(_) => continue,
+            },
+            None => {
+                match key.as_str() {
+                    "active_slabs" => out.active_slabs = parse_u64(value),
+                    "total_malloced" => out.total_malloced = parse_u64(value),
+                    _ => (),
+                }
+                continue;
+            }
+        };
+        let class = out.classes.entry(class).or_default();
+        let value = parse_u64(value);
+        match stat {
+            "chunk_size" => class.chunk_size = value,
+            "chunks_per_page" => class.chunks_per_page = value,
+            "total_pages" => class.total_pages = value,
+            "total_chunks" => class.total_chunks = value,
+            "used_chunks" => class.used_chunks = value,
+            "free_chunks" => class.free_chunks = value,
+            "mem_requested" => class.mem_requested = value,
+            _ => (),
+        }
+    }
+    out
+}
+
+/// Parse the response to `stats items`, whose stats are named
+/// `items:<class>:<stat>`.
+pub(crate) fn parse_item_stats(endpoint: &str, stats: &HashMap<String, String>) -> ItemStats {
+    let mut out = ItemStats {
+        endpoint: endpoint.to_string(),
+        ..ItemStats::default()
+    };
+    for (key, value) in stats {
+        let mut parts = key.splitn(3, ':');
+        let (class, stat) = match (parts.next(), parts.next(), parts.next()) {
+            (Some("items"), Some(class), Some(stat)) => match class.parse::<u32>() {
+                Ok(class) => (class, stat),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        let class = out.classes.entry(class).or_default();
+        let value = parse_u64(value);
+        match stat {
+            "number" => class.number = value,
+            "age" => class.age = Duration::from_secs(value),
+            "evicted" => class.evicted = value,
+            "evicted_nonzero" => class.evicted_nonzero = value,
+            "evicted_time" => class.evicted_time = Duration::from_secs(value),
+            "evicted_unfetched" => class.evicted_unfetched = value,
+            "expired_unfetched" => class.expired_unfetched = value,
+            "outofmemory" => class.outofmemory = value,
+            "reclaimed" => class.reclaimed = value,
+            _ => (),
+        }
+    }
+    out
+}
+
+/// Parse the extstore stats from the general stats.
+pub(crate) fn parse_extstore_stats(
+    endpoint: &str,
+    stats: &HashMap<String, String>,
+) -> ExtstoreStats {
+    let mut out = ExtstoreStats {
+        endpoint: endpoint.to_string(),
+        ..ExtstoreStats::default()
+    };
+    for (key, value) in stats {
+        out.enabled |= key.starts_with("extstore_");
+        let value = parse_u64(value);
+        match key.as_str() {
+            "extstore_io_queue" => out.io_queue = value,
+            "extstore_page_allocs" => out.page_allocs = value,
+            "extstore_page_evictions" => out.page_evictions = value,
+            "extstore_page_reclaims" => out.page_reclaims = value,
+            "extstore_pages_free" => out.pages_free = value,
+            "extstore_pages_used" => out.pages_used = value,
+            "extstore_objects_read" => out.objects_read = value,
+            "extstore_objects_written" => out.objects_written = value,
+            "extstore_bytes_read" => out.bytes_read = value,
+            "extstore_bytes_written" => out.bytes_written = value,
+            "extstore_bytes_fragmented" => out.bytes_fragmented = value,
+            "get_extstore" => out.gets = value,
+            "get_aborted_extstore" => out.aborted_gets = value,
+            "get_oom_extstore" => out.oom_gets = value,
+            "badcrc_from_extstore" => out.bad_crc = value,
+            "miss_from_extstore" => out.misses = value,
+            "recache_from_extstore" => out.recaches = value,
+            _ => (),
+        }
+    }
+    out
+}
+
+/// Parse the response to `stats detail dump`, a single stat holding lines
+/// of the form `PREFIX <prefix> get <n> hit <n> set <n> del <n>`.
+pub(crate) fn parse_detail_stats(endpoint: &str, stats: &HashMap<String, String>) -> DetailStats {
+    let mut out = DetailStats {
+        endpoint: endpoint.to_string(),
+        ..DetailStats::default()
+    };
+    for line in stats.values().flat_map(|dump| dump.lines()) {
+        let mut words = line.split_whitespace();
+        let prefix = match (words.next(), words.next()) {
+            (Some("PREFIX"), Some(prefix)) => prefix.to_string(),
+            _ => continue,
+        };
+        let mut counts = PrefixStats::default();
+        while let (Some(name), Some(value)) = (words.next(), words.next()) {
+            let value = parse_u64(value);
+            match name {
+                "get" => counts.gets = value,
+                "hit" => counts.hits = value,
+                "set" => counts.sets = value,
+                "del" => counts.deletes = value,
+                _ => (),
+            }
+        }
+        out.prefixes.insert(prefix, counts);
+    }
+    out
+}
+
+/// Request a group of stats from a server (the empty string requests the
+/// general stats) and read the stream of responses, which is terminated by a
+/// packet with an empty key.
+pub(crate) async fn read_server_stats<C: Connection>(
+    conn: &mut C,
+    group: &str,
+) -> Result<HashMap<String, String>, Error> {
+    conn.write_packet(NoCompressor, Packet::stat(group)?)
+        .await?;
+    let mut out = HashMap::new();
+    while collect_stat(&mut out, conn.read_packet(NoCompressor).await?)? {}
+    Ok(out)
+}
+
+/// Read a group of stats like [`read_server_stats`], through the node, so
+/// that the protocol of the node is spoken and the exchange is recorded in
+/// its stats.
+pub(crate) async fn read_node_stats<C: Connection>(
+    node: &mut Node<C>,
+    group: &str,
+) -> Result<HashMap<String, String>, Error> {
+    node.write_packet(NoCompressor, Packet::stat(group)?)
+        .await?;
+    let mut out = HashMap::new();
+    while collect_stat(&mut out, node.read_packet(NoCompressor).await?)? {}
+    Ok(out)
+}
+
+/// Add the stat in the response, returning false once the stream ended.
+fn collect_stat(out: &mut HashMap<String, String>, packet: Packet) -> Result<bool, Error> {
+    packet.error_for_status()?;
+    if packet.key.is_empty() {
+        return Ok(false);
+    }
+    let key = String::from_utf8_lossy(&packet.key).to_string();
+    let value = String::from_utf8_lossy(&packet.value).to_string();
+    out.insert(key, value);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use crate::{
+        client::{Client, ClientConfig},
+        instrument::{ExtstoreWarning, MetricsHook, NoMetrics},
+        mock::MockConnection,
+    };
+
+    use super::{
+        parse_extstore_stats, AtomicHistogram, ExtstoreThresholds, Histogram, NodeCounters,
+        PrefixStats,
+    };
+
+    static WARNINGS: Mutex<Vec<(String, ExtstoreWarning)>> = Mutex::new(vec![]);
+
+    #[derive(Debug, Default, Clone)]
+    struct RecordingHook;
+
+    impl MetricsHook for RecordingHook {
+        fn on_extstore_warning(&self, endpoint: &str, warning: ExtstoreWarning) {
+            WARNINGS
+                .lock()
+                .unwrap()
+                .push((endpoint.to_string(), warning));
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = AtomicHistogram::new();
+        for value in [0, 1, 2, 3, 100, u32::MAX] {
+            histogram.record(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(6, snapshot.count);
+        assert_eq!(vec![1, 1, 2], snapshot.buckets[..3].to_vec());
+        assert_eq!(1, snapshot.buckets[7]);
+        assert_eq!(1, snapshot.buckets[32]);
+        assert_eq!((64, 127), Histogram::bucket_bounds(7));
+        assert_eq!((0, 0), Histogram::bucket_bounds(0));
+        assert_eq!(
+            Some(106.0 + u32::MAX as f64),
+            snapshot.mean().map(|m| m * 6.0)
+        );
+    }
+
+    #[test]
+    fn test_latency() {
+        let counters = NodeCounters::new();
+        assert_eq!(None, counters.latency());
+        counters.record_latency(Duration::from_millis(10));
+        assert_eq!(Some(Duration::from_millis(10)), counters.latency());
+        counters.record_latency(Duration::from_millis(60));
+        assert_eq!(Some(Duration::from_millis(20)), counters.latency());
+        for _ in 0..50 {
+            counters.record_latency(Duration::from_millis(1));
+        }
+        let latency = counters.latency().unwrap();
+        assert!(latency < Duration::from_micros(1100), "{:?}", latency);
+    }
+
+    #[test]
+    fn test_stats_groups() {
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["stats-groups".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            for key in ["user:1", "user:2", "page:1"] {
+                client.set(key, "value", 0).await.unwrap();
+            }
+
+            let stats = client.stats().await.unwrap();
+            assert_eq!(
+                Some(&"3".to_string()),
+                stats["stats-groups"].get("curr_items")
+            );
+
+            let slabs = client.slab_stats().await.unwrap();
+            assert_eq!("stats-groups", slabs[0].endpoint);
+            assert_eq!(1, slabs[0].active_slabs);
+            assert_eq!(96, slabs[0].classes[&1].chunk_size);
+            assert_eq!(3, slabs[0].classes[&1].used_chunks);
+
+            let items = client.item_stats().await.unwrap();
+            assert_eq!(3, items[0].classes[&1].number);
+            assert_eq!(Duration::ZERO, items[0].classes[&1].age);
+            assert_eq!(0, items[0].evictions());
+
+            let detail = client.detail_stats().await.unwrap();
+            let user = PrefixStats {
+                sets: 2,
+                ..PrefixStats::default()
+            };
+            assert_eq!(Some(&user), detail[0].prefixes.get("user"));
+            assert_eq!(2, detail[0].prefixes.len());
+        });
+    }
+
+    #[test]
+    fn test_extstore_stats() {
+        let stats = [
+            ("bytes_read", "123"),
+            ("extstore_io_queue", "100"),
+            ("extstore_pages_free", "0"),
+            ("extstore_pages_used", "64"),
+            ("extstore_bytes_read", "4096"),
+            ("get_extstore", "1000"),
+            ("get_aborted_extstore", "15"),
+            ("get_oom_extstore", "5"),
+        ];
+        let stats = stats
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        let extstore = parse_extstore_stats("flash", &stats);
+        assert!(extstore.enabled);
+        assert_eq!(4096, extstore.bytes_read);
+        let thresholds = ExtstoreThresholds::default();
+        assert_eq!(
+            vec![
+                ExtstoreWarning::IoQueue(100),
+                ExtstoreWarning::AbortedReads(0.02),
+                ExtstoreWarning::NoFreePages,
+            ],
+            extstore.warnings(&thresholds)
+        );
+        let relaxed = ExtstoreThresholds {
+            max_io_queue: 128,
+            max_aborted_ratio: 0.05,
+        };
+        assert_eq!(
+            vec![ExtstoreWarning::NoFreePages],
+            extstore.warnings(&relaxed)
+        );
+
+        tokio_test::block_on(async {
+            let cfg = ClientConfig::new_uncompressed(vec!["extstore".into()]);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let stats = client.extstore_stats().await.unwrap();
+            assert!(!stats[0].enabled);
+            client
+                .check_extstore::<RecordingHook>(&ExtstoreThresholds::default())
+                .await
+                .unwrap();
+            client
+                .check_extstore::<NoMetrics>(&ExtstoreThresholds::default())
+                .await
+                .unwrap();
+            assert!(WARNINGS.lock().unwrap().is_empty());
+        });
+    }
+}
@@ -0,0 +1,118 @@
+//! Utilities for testing applications that use rsmc, without running
+//! memcached. A [`FakePool`] yields clients backed by the in-memory
+//! [`crate::mock`] server, and can fail requests to chosen keys to simulate
+//! cache failures. Enable the `testing` feature to use this module.
+
+use deadpool::managed::Object;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    client::{ClientConfig, Compressor, Error, NoCompressor, Pool},
+    mock::{MockConnection, Store},
+};
+
+pub use crate::{mock::Failure, protocol::Status};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A pool of clients connected to in-memory mock servers. Every fake pool
+/// has its own servers, so tests running concurrently do not share data.
+#[derive(Debug, Clone)]
+pub struct FakePool<P: Compressor = NoCompressor> {
+    pool: Pool<MockConnection, P>,
+    endpoints: Vec<String>,
+}
+
+impl FakePool<NoCompressor> {
+    /// Create a pool of uncompressed clients for a cluster of `nodes` mock
+    /// servers.
+    pub fn new(nodes: usize) -> Self {
+        Self::with_config(nodes, ClientConfig::new_uncompressed)
+    }
+}
+
+impl<P: Compressor> FakePool<P> {
+    /// Create a pool for a cluster of `nodes` mock servers, with clients
+    /// configured by `config` from the endpoints of the servers.
+    pub fn with_config<F>(nodes: usize, config: F) -> Self
+    where
+        F: FnOnce(Vec<String>) -> ClientConfig<MockConnection, P>,
+    {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let endpoints = (0..nodes.max(1))
+            .map(|node| format!("fake-{}:{}", id, 11211 + node))
+            .collect::<Vec<_>>();
+        let pool = Pool::builder(config(endpoints.clone()))
+            .build()
+            .expect("fake pool has no timeouts to configure");
+        Self { pool, endpoints }
+    }
+
+    /// Get the pool, to pass to the code under test.
+    pub fn pool(&self) -> &Pool<MockConnection, P> {
+        &self.pool
+    }
+
+    /// The endpoints of the mock servers.
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    /// Fail every request to the key with the given failure, until the
+    /// failures are cleared.
+    pub fn fail_key<K: AsRef<[u8]>>(&self, key: K, failure: Failure) {
+        for endpoint in &self.endpoints {
+            Store::get(endpoint)
+                .lock()
+                .unwrap()
+                .fail(key.as_ref(), failure);
+        }
+    }
+
+    /// Stop failing requests to every key.
+    pub fn clear_failures(&self) {
+        for endpoint in &self.endpoints {
+            Store::get(endpoint).lock().unwrap().clear_failures();
+        }
+    }
+
+    /// Get a client from the pool.
+    pub async fn client(&self) -> Result<Object<ClientConfig<MockConnection, P>>, Error> {
+        Ok(self.pool.get().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Failure, FakePool, Status};
+    use crate::client::Error;
+
+    #[test]
+    fn test_fake_pool() {
+        tokio_test::block_on(async {
+            let fake = FakePool::new(3);
+            let mut client = fake.client().await.unwrap();
+            client.set("a", "1", 0).await.unwrap();
+            client.set("b", "2", 0).await.unwrap();
+
+            fake.fail_key("a", Failure::Status(Status::Busy));
+            fake.fail_key("b", Failure::Disconnect);
+            let err = client.get::<_, String>("a").await.unwrap_err();
+            assert!(matches!(err, Error::Status(Status::Busy)));
+            let err = client.get::<_, String>("b").await.unwrap_err();
+            assert!(err.is_connection_closed());
+            let (values, errors) = client.get_multi::<_, String>(&["a"]).await.unwrap();
+            assert!(values.is_empty() && errors.contains_key(b"a".as_slice()));
+
+            fake.clear_failures();
+            drop(client);
+            let mut client = fake.client().await.unwrap();
+            assert_eq!(Some("1".to_string()), client.get("a").await.unwrap());
+            assert_eq!(Some("2".to_string()), client.get("b").await.unwrap());
+
+            // Every fake pool has its own servers.
+            let mut other = FakePool::new(1).client().await.unwrap();
+            assert_eq!(None, other.get::<_, String>("a").await.unwrap());
+        });
+    }
+}
@@ -0,0 +1,130 @@
+//! A restarted process starts without knowing which nodes were failing, so
+//! every client sends requests to a bad node until its error budget is
+//! exhausted again, which shows up as a burst of timeouts right after every
+//! deploy. A [`TopologySnapshot`] records the nodes of a client and which of
+//! them fail open, so that it can be saved before shutting down and loaded
+//! with [`crate::client::ClientConfig::with_topology_snapshot`] at startup.
+//!
+//! Failing nodes are only restored for the rest of their cooldown, and only
+//! when an error budget is configured. Nodes which are no longer configured
+//! are ignored.
+
+use std::time::{Duration, SystemTime};
+
+use crate::client::Error;
+
+/// The state of a single node when a snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq, ::serde_derive::Serialize, ::serde_derive::Deserialize)]
+pub struct NodeSnapshot {
+    /// The endpoint of the node.
+    pub endpoint: String,
+    /// The server version detected for the node.
+    pub version: Option<String>,
+    /// When reads to the node stop failing open, if they were failing open.
+    pub failing_open_until: Option<SystemTime>,
+}
+
+impl NodeSnapshot {
+    /// How much longer reads to the node fail open, measured from now.
+    pub fn failing_open_for(&self) -> Option<Duration> {
+        let until = self.failing_open_until?;
+        until.duration_since(SystemTime::now()).ok()
+    }
+}
+
+/// The nodes of a client and which of them fail open.
+#[derive(Debug, Clone, PartialEq, Eq, ::serde_derive::Serialize, ::serde_derive::Deserialize)]
+pub struct TopologySnapshot {
+    /// When the snapshot was taken.
+    pub taken_at: SystemTime,
+    /// Every node of the client, in the order they were configured.
+    pub nodes: Vec<NodeSnapshot>,
+}
+
+impl TopologySnapshot {
+    /// Encode the snapshot to save it, for example to a file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Decode a snapshot saved with [`TopologySnapshot::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Get the snapshot of the node with the endpoint.
+    pub fn node(&self, endpoint: &str) -> Option<&NodeSnapshot> {
+        self.nodes.iter().find(|node| node.endpoint == endpoint)
+    }
+
+    /// The endpoints of the nodes which still fail open.
+    pub fn failing_open(&self) -> Vec<&str> {
+        self.nodes
+            .iter()
+            .filter(|node| node.failing_open_for().is_some())
+            .map(|node| node.endpoint.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{
+        budget::ErrorBudget,
+        client::{Client, ClientConfig},
+        mock::{MockConnection, Store},
+    };
+
+    use super::{NodeSnapshot, TopologySnapshot};
+
+    #[test]
+    fn test_topology_snapshot() {
+        tokio_test::block_on(async {
+            let now = SystemTime::now();
+            let node = |endpoint: &str, failing_open_until| NodeSnapshot {
+                endpoint: endpoint.into(),
+                version: None,
+                failing_open_until,
+            };
+            let snapshot = TopologySnapshot {
+                taken_at: now,
+                nodes: vec![
+                    node("topology:1", Some(now + Duration::from_secs(30))),
+                    node("topology:2", Some(now - Duration::from_secs(30))),
+                    node("removed:1", Some(now + Duration::from_secs(30))),
+                ],
+            };
+            let bytes = snapshot.to_bytes().unwrap();
+            let snapshot = TopologySnapshot::from_bytes(&bytes).unwrap();
+            assert_eq!(vec!["topology:1", "removed:1"], snapshot.failing_open());
+
+            // Only configured nodes which still fail open are restored.
+            let endpoints = vec!["topology:1".into(), "topology:2".into()];
+            let cfg = ClientConfig::new_uncompressed(endpoints)
+                .with_error_budget(ErrorBudget::default())
+                .with_topology_snapshot(snapshot);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            let restored = client.topology_snapshot();
+            assert_eq!(vec!["topology:1"], restored.failing_open());
+            let remaining = restored.node("topology:1").unwrap().failing_open_for();
+            assert!(remaining.unwrap() <= Duration::from_secs(30));
+            assert!(restored.node("topology:2").unwrap().version.is_some());
+
+            let keys = (0..20).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+            for key in &keys {
+                client.set(key, "value", 0).await.unwrap();
+            }
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            // Keys on the failing node miss without touching the network.
+            let store = Store::get("topology:2");
+            let store = store.lock().unwrap();
+            let stored = keys
+                .iter()
+                .filter(|key| store.expire(key.as_bytes()).is_some());
+            assert_eq!(stored.count(), values.len());
+            assert!(values.len() < keys.len());
+        });
+    }
+}
@@ -0,0 +1,83 @@
+//! Sessions and similar values should expire some time after they were last
+//! used rather than after they were written, which takes a touch on every
+//! read that callers easily forget. A [`TouchOnRead`] policy configured with
+//! [`crate::client::ClientConfig::with_touch_on_read`] makes the client read
+//! keys with matching prefixes using GAT instead of GET, which refreshes
+//! their expiration in the same round trip, for single and bulk gets alike.
+//!
+//! Reads served by the local tier never reach memcached, so they do not
+//! refresh the expiration, and clients in read-only mode read without
+//! touching. The refresh is part of the read, so it is not reported to the
+//! audit hook.
+
+use std::cmp::Reverse;
+
+/// The expirations set on read for keys by prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TouchOnRead {
+    prefixes: Vec<(Vec<u8>, u32)>,
+}
+
+impl TouchOnRead {
+    /// Create a policy which touches no keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the expiration of keys starting with `prefix` to `expire` when
+    /// they are read. When prefixes overlap, the longest one applies. An
+    /// empty prefix matches every key.
+    pub fn with_prefix<K: Into<Vec<u8>>>(mut self, prefix: K, expire: u32) -> Self {
+        self.prefixes.push((prefix.into(), expire));
+        self.prefixes
+            .sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        self
+    }
+
+    /// The expiration to set when the key is read, if any.
+    pub fn expire_for(&self, key: &[u8]) -> Option<u32> {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix))
+            .map(|(_, expire)| *expire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::{MockConnection, Store},
+    };
+
+    use super::TouchOnRead;
+
+    #[test]
+    fn test_touch_on_read() {
+        tokio_test::block_on(async {
+            let policy = TouchOnRead::new()
+                .with_prefix("session:", 600)
+                .with_prefix("session:admin:", 60);
+            assert_eq!(Some(60), policy.expire_for(b"session:admin:1"));
+            assert_eq!(None, policy.expire_for(b"user:1"));
+
+            let cfg = ClientConfig::new_uncompressed(vec!["touch_on_read".into()])
+                .with_touch_on_read(policy);
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            for key in ["session:1", "session:2", "session:admin:1", "user:1"] {
+                client.set(key, "value", 10).await.unwrap();
+            }
+            let value = client.get::<_, String>("session:1").await.unwrap();
+            assert_eq!(Some("value".to_string()), value);
+            let keys = ["session:2", "session:admin:1", "user:1", "session:3"];
+            let (values, _) = client.get_multi::<_, String>(&keys).await.unwrap();
+            assert_eq!(3, values.len());
+
+            let store = Store::get("touch_on_read");
+            let store = store.lock().unwrap();
+            let expires = ["session:1", "session:2", "session:admin:1", "user:1"]
+                .map(|key| store.expire(key.as_bytes()).unwrap());
+            assert_eq!([600, 600, 60, 10], expires);
+        });
+    }
+}
@@ -0,0 +1,730 @@
+//! Values cached from other systems often carry personal data which has no
+//! business being in a cache, and relying on every call site to sanitize
+//! them before a set is bound to miss one. A [`ValueTransform`] configured
+//! with [`crate::client::ClientConfig::with_value_transform`] is applied to
+//! every value serialized by the client, single or bulk, and redacts the
+//! fields it selects before the value is cached.
+//!
+//! Fields are selected by name: the fields of structs and the entries of
+//! maps with string keys, such as JSON objects from `serde_json`, at any
+//! depth of the value. A redacted field is blanked, not removed, so that the
+//! value still reads back into the same type: numbers become zero, strings,
+//! sequences and maps become empty, and options become None, recursively
+//! for fields which are themselves structs.
+//!
+//! Values are encoded with bincode, which cannot decode self-describing
+//! types such as `serde_json::Value`. A JSON value is redacted when it is
+//! stored, but must be read back into a struct or map of the same shape.
+//!
+//! Raw values, such as those of [`crate::client::Client::set_with_flags`],
+//! are not serialized by the client and are stored as given.
+
+use std::{
+    collections::HashSet,
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+};
+
+use serde::{
+    ser::{
+        Impossible, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+        SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+    },
+    Serialize, Serializer,
+};
+
+/// Selects the fields of values which are redacted before they are cached.
+pub trait ValueTransform: Debug + Send + Sync {
+    /// Whether to redact the field or map entry with the name, in a value
+    /// stored under the key.
+    fn redacts(&self, key: &[u8], field: &str) -> bool;
+}
+
+/// Redacts the fields with any of the given names, in every value. The
+/// fields are blanked rather than removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedactFields {
+    fields: HashSet<String>,
+}
+
+impl RedactFields {
+    /// Redact the fields with any of the names.
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(fields: I) -> Self {
+        Self {
+            fields: fields.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl ValueTransform for RedactFields {
+    fn redacts(&self, _key: &[u8], field: &str) -> bool {
+        self.fields.contains(field)
+    }
+}
+
+/// A value serialized with the fields selected by the transform redacted,
+/// or as-is without a transform.
+pub(crate) struct Redacted<'a, V: ?Sized> {
+    value: &'a V,
+    key: &'a [u8],
+    transform: Option<&'a dyn ValueTransform>,
+}
+
+impl<'a, V: ?Sized> Redacted<'a, V> {
+    pub(crate) fn new(
+        value: &'a V,
+        key: &'a [u8],
+        transform: Option<&'a dyn ValueTransform>,
+    ) -> Self {
+        Self {
+            value,
+            key,
+            transform,
+        }
+    }
+
+    /// The value before it is redacted.
+    pub(crate) fn value(&self) -> &V {
+        self.value
+    }
+}
+
+impl<V: Serialize + ?Sized> Serialize for Redacted<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.transform {
+            Some(transform) => {
+                let scope = Scope {
+                    key: self.key,
+                    transform,
+                    blank: false,
+                };
+                self.value.serialize(Redactor {
+                    inner: serializer,
+                    scope,
+                })
+            }
+            None => self.value.serialize(serializer),
+        }
+    }
+}
+
+/// Where in a value the serializer is: the key it is stored under, and
+/// whether it is within a redacted field.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    key: &'a [u8],
+    transform: &'a dyn ValueTransform,
+    blank: bool,
+}
+
+impl Scope<'_> {
+    fn field(self, name: &str) -> Self {
+        Self {
+            blank: self.blank || self.transform.redacts(self.key, name),
+            ..self
+        }
+    }
+}
+
+/// A value nested in another, serialized within the scope of its parent.
+struct Nested<'a, T: ?Sized> {
+    value: &'a T,
+    scope: Scope<'a>,
+}
+
+impl<T: Serialize + ?Sized> Serialize for Nested<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(Redactor {
+            inner: serializer,
+            scope: self.scope,
+        })
+    }
+}
+
+/// Forwards a value to the inner serializer, blanking it within redacted
+/// fields.
+struct Redactor<'a, S> {
+    inner: S,
+    scope: Scope<'a>,
+}
+
+/// Forwards the elements of a compound value, skipping them entirely if it
+/// was blanked into an empty sequence or map.
+struct Compound<'a, C> {
+    inner: C,
+    scope: Scope<'a>,
+    skip: bool,
+    entry: Option<String>,
+}
+
+impl<'a, C> Compound<'a, C> {
+    fn new(inner: C, scope: Scope<'a>, skip: bool) -> Self {
+        Self {
+            inner,
+            scope,
+            skip,
+            entry: None,
+        }
+    }
+}
+
+macro_rules! blank_or_forward {
+    ($($method:ident($ty:ty) = $zero:expr;)*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<S::Ok, S::Error> {
+                match self.scope.blank {
+                    true => self.inner.$method($zero),
+                    false => self.inner.$method(v),
+                }
+            }
+        )*
+    };
+}
+
+impl<'a, S: Serializer> Serializer for Redactor<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Compound<'a, S::SerializeSeq>;
+    type SerializeTuple = Compound<'a, S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<'a, S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<'a, S::SerializeTupleVariant>;
+    type SerializeMap = Compound<'a, S::SerializeMap>;
+    type SerializeStruct = Compound<'a, S::SerializeStruct>;
+    type SerializeStructVariant = Compound<'a, S::SerializeStructVariant>;
+
+    blank_or_forward! {
+        serialize_bool(bool) = false;
+        serialize_i8(i8) = 0;
+        serialize_i16(i16) = 0;
+        serialize_i32(i32) = 0;
+        serialize_i64(i64) = 0;
+        serialize_i128(i128) = 0;
+        serialize_u8(u8) = 0;
+        serialize_u16(u16) = 0;
+        serialize_u32(u32) = 0;
+        serialize_u64(u64) = 0;
+        serialize_u128(u128) = 0;
+        serialize_f32(f32) = 0.0;
+        serialize_f64(f64) = 0.0;
+        serialize_char(char) = '\0';
+        serialize_str(&str) = "";
+        serialize_bytes(&[u8]) = &[];
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        match self.scope.blank {
+            true => self.inner.serialize_none(),
+            false => self.inner.serialize_some(&Nested {
+                value,
+                scope: self.scope,
+            }),
+        }
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let value = Nested {
+            value,
+            scope: self.scope,
+        };
+        self.inner.serialize_newtype_struct(name, &value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let value = Nested {
+            value,
+            scope: self.scope,
+        };
+        self.inner
+            .serialize_newtype_variant(name, index, variant, &value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let blank = self.scope.blank;
+        let len = if blank { Some(0) } else { len };
+        let inner = self.inner.serialize_seq(len)?;
+        Ok(Compound::new(inner, self.scope, blank))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let inner = self.inner.serialize_tuple(len)?;
+        Ok(Compound::new(inner, self.scope, false))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let inner = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(Compound::new(inner, self.scope, false))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let inner = self
+            .inner
+            .serialize_tuple_variant(name, index, variant, len)?;
+        Ok(Compound::new(inner, self.scope, false))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let blank = self.scope.blank;
+        let len = if blank { Some(0) } else { len };
+        let inner = self.inner.serialize_map(len)?;
+        Ok(Compound::new(inner, self.scope, blank))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let inner = self.inner.serialize_struct(name, len)?;
+        Ok(Compound::new(inner, self.scope, false))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let inner = self
+            .inner
+            .serialize_struct_variant(name, index, variant, len)?;
+        Ok(Compound::new(inner, self.scope, false))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<C: SerializeSeq> SerializeSeq for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        if self.skip {
+            return Ok(());
+        }
+        let value = Nested {
+            value,
+            scope: self.scope,
+        };
+        self.inner.serialize_element(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTuple> SerializeTuple for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = Nested {
+            value,
+            scope: self.scope,
+        };
+        self.inner.serialize_element(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleStruct> SerializeTupleStruct for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = Nested {
+            value,
+            scope: self.scope,
+        };
+        self.inner.serialize_field(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleVariant> SerializeTupleVariant for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = Nested {
+            value,
+            scope: self.scope,
+        };
+        self.inner.serialize_field(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeMap> SerializeMap for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), C::Error> {
+        if self.skip {
+            return Ok(());
+        }
+        self.entry = key.serialize(EntryName).ok();
+        let key = Nested {
+            value: key,
+            scope: self.scope,
+        };
+        self.inner.serialize_key(&key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        if self.skip {
+            return Ok(());
+        }
+        let scope = match self.entry.take() {
+            Some(name) => self.scope.field(&name),
+            None => self.scope,
+        };
+        self.inner.serialize_value(&Nested { value, scope })
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStruct> SerializeStruct for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        let scope = self.scope.field(name);
+        self.inner.serialize_field(name, &Nested { value, scope })
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStructVariant> SerializeStructVariant for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        let scope = self.scope.field(name);
+        self.inner.serialize_field(name, &Nested { value, scope })
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+/// The key of a map entry was not a string, so it has no field name.
+#[derive(Debug)]
+struct NotAName;
+
+impl Display for NotAName {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "map key is not a string")
+    }
+}
+
+impl StdError for NotAName {}
+
+impl serde::ser::Error for NotAName {
+    fn custom<T: Display>(_msg: T) -> Self {
+        NotAName
+    }
+}
+
+macro_rules! not_a_name {
+    ($($method:ident($($ty:ty),*);)*) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<String, NotAName> {
+                Err(NotAName)
+            }
+        )*
+    };
+}
+
+/// Reads the name of a map entry from its key, if it is a string.
+struct EntryName;
+
+impl Serializer for EntryName {
+    type Ok = String;
+    type Error = NotAName;
+    type SerializeSeq = Impossible<String, NotAName>;
+    type SerializeTuple = Impossible<String, NotAName>;
+    type SerializeTupleStruct = Impossible<String, NotAName>;
+    type SerializeTupleVariant = Impossible<String, NotAName>;
+    type SerializeMap = Impossible<String, NotAName>;
+    type SerializeStruct = Impossible<String, NotAName>;
+    type SerializeStructVariant = Impossible<String, NotAName>;
+
+    fn serialize_str(self, v: &str) -> Result<String, NotAName> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, NotAName> {
+        value.serialize(self)
+    }
+
+    not_a_name! {
+        serialize_bool(bool);
+        serialize_i8(i8);
+        serialize_i16(i16);
+        serialize_i32(i32);
+        serialize_i64(i64);
+        serialize_u8(u8);
+        serialize_u16(u16);
+        serialize_u32(u32);
+        serialize_u64(u64);
+        serialize_f32(f32);
+        serialize_f64(f64);
+        serialize_char(char);
+        serialize_bytes(&[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(&'static str);
+        serialize_unit_variant(&'static str, u32, &'static str);
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, NotAName> {
+        Err(NotAName)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, NotAName> {
+        Err(NotAName)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{
+        client::{Client, ClientConfig},
+        mock::MockConnection,
+    };
+
+    use super::{RedactFields, ValueTransform};
+
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        street: String,
+        zip: u32,
+    }
+
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: u64,
+        name: String,
+        email: Option<String>,
+        address: Address,
+        tags: Vec<String>,
+        attributes: HashMap<String, String>,
+    }
+
+    /// Redacts addresses only in values stored under `public:` keys.
+    #[derive(Debug)]
+    struct PublicKeys;
+
+    impl ValueTransform for PublicKeys {
+        fn redacts(&self, key: &[u8], field: &str) -> bool {
+            key.starts_with(b"public:") && field == "address"
+        }
+    }
+
+    #[test]
+    fn test_redact_json() {
+        tokio_test::block_on(async {
+            let value = serde_json::json!({
+                "user": {"name": "Alice", "ssn": "123-45-6789"},
+                "address": {"street": "1 Main St", "zip": "12345"},
+            });
+
+            let redact = RedactFields::new(["ssn", "street"]);
+            let cfg = ClientConfig::new_uncompressed(vec!["transform:json".into()])
+                .with_value_transform(Arc::new(redact));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("user", &value, 0).await.unwrap();
+            assert!(client.get::<_, serde_json::Value>("user").await.is_err());
+
+            let cached = client.get::<_, HashMap<String, HashMap<String, String>>>("user");
+            let cached = cached.await.unwrap().unwrap();
+            let field = |object: &str, field: &str| cached[object][field].as_str();
+            assert_eq!(("Alice", ""), (field("user", "name"), field("user", "ssn")));
+            assert_eq!(
+                ("", "12345"),
+                (field("address", "street"), field("address", "zip"))
+            );
+        });
+    }
+
+    #[test]
+    fn test_value_transform() {
+        tokio_test::block_on(async {
+            let user = User {
+                id: 7,
+                name: "Alice".into(),
+                email: Some("alice@example.com".into()),
+                address: Address {
+                    street: "1 Main St".into(),
+                    zip: 12345,
+                },
+                tags: vec!["admin".into()],
+                attributes: HashMap::from([
+                    ("ssn".into(), "123-45-6789".into()),
+                    ("plan".into(), "pro".into()),
+                ]),
+            };
+
+            let redact = RedactFields::new(["email", "address", "ssn"]);
+            let cfg = ClientConfig::new_uncompressed(vec!["transform:1".into()])
+                .with_value_transform(Arc::new(redact));
+            let mut client = Client::<MockConnection, _>::new(cfg.clone()).await.unwrap();
+            client.set("user", &user, 0).await.unwrap();
+            let data = HashMap::from([("bulk", user.clone())]);
+            assert!(client.set_multi(data, 0).await.unwrap().is_empty());
+            let expected = User {
+                email: None,
+                address: Address::default(),
+                attributes: HashMap::from([
+                    ("ssn".into(), "".into()),
+                    ("plan".into(), "pro".into()),
+                ]),
+                ..user.clone()
+            };
+            for key in ["user", "bulk"] {
+                let cached = client.get::<_, User>(key).await.unwrap();
+                assert_eq!(Some(&expected), cached.as_ref());
+            }
+
+            let cfg = cfg.with_value_transform(Arc::new(PublicKeys));
+            let mut client = Client::<MockConnection, _>::new(cfg).await.unwrap();
+            client.set("public:user", &user, 0).await.unwrap();
+            client.set("private:user", &user, 0).await.unwrap();
+            let public = client.get::<_, User>("public:user").await.unwrap();
+            assert_eq!(Address::default(), public.unwrap().address);
+            let private = client.get::<_, User>("private:user").await.unwrap();
+            assert_eq!(Some(user), private);
+        });
+    }
+}
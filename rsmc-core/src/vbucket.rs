@@ -0,0 +1,156 @@
+//! Couchbase-style vbucket routing. Instead of consistent hashing, keys are
+//! hashed into a fixed number of vbuckets, and a vbucket map assigns each
+//! vbucket to a server. The vbucket id is sent in the request header so the
+//! server can reject requests for vbuckets it does not own, and the map can
+//! be refreshed at runtime when the cluster topology changes.
+
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    diagnostics::ConfigError,
+    protocol::{Packet, RequestFields},
+};
+
+/// The most vbuckets a map may have, since vbucket ids are 16 bits.
+const MAX_VBUCKETS: usize = 1 << 16;
+
+/// A map assigning every vbucket to one of the servers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VbucketMap {
+    /// The endpoints of the servers owning vbuckets.
+    pub servers: Vec<String>,
+    /// The index into `servers` owning each vbucket.
+    pub vbuckets: Vec<usize>,
+}
+
+impl VbucketMap {
+    /// Create a new vbucket map from the list of servers and the index of
+    /// the server owning each vbucket. Fails with
+    /// [`ConfigError::InvalidVbucketMap`] if there are no servers, no
+    /// vbuckets or more than 65536 of them, or a vbucket is assigned to a
+    /// server which is not in the list.
+    pub fn new(servers: Vec<String>, vbuckets: Vec<usize>) -> Result<Self, ConfigError> {
+        let valid = !servers.is_empty()
+            && (1..=MAX_VBUCKETS).contains(&vbuckets.len())
+            && vbuckets.iter().all(|server| *server < servers.len());
+        match valid {
+            true => Ok(Self { servers, vbuckets }),
+            false => Err(ConfigError::InvalidVbucketMap),
+        }
+    }
+
+    /// Create a map with `count` vbuckets assigned round-robin to servers.
+    /// Fails like [`VbucketMap::new`].
+    pub fn round_robin(servers: Vec<String>, count: usize) -> Result<Self, ConfigError> {
+        let vbuckets = (0..count).map(|i| i % servers.len().max(1)).collect();
+        Self::new(servers, vbuckets)
+    }
+
+    /// Get the vbucket id for a key.
+    pub fn vbucket_id(&self, key: &[u8]) -> u16 {
+        let hash = ((crc32(key) >> 16) & 0x7fff) as usize;
+        (hash % self.vbuckets.len().max(1)) as u16
+    }
+
+    /// Get the vbucket id for a key and the endpoint of the server owning it.
+    pub fn route(&self, key: &[u8]) -> Option<(u16, &str)> {
+        let id = self.vbucket_id(key);
+        let server = self.vbuckets.get(id as usize)?;
+        Some((id, self.servers.get(*server)?.as_str()))
+    }
+}
+
+/// A shared, refreshable handle to a vbucket map. Clones of the router share
+/// the same map, so keep a clone around to update the map at runtime.
+#[derive(Debug, Clone)]
+pub struct VbucketRouter {
+    map: Arc<RwLock<VbucketMap>>,
+}
+
+impl VbucketRouter {
+    /// Create a new router from a vbucket map.
+    pub fn new(map: VbucketMap) -> Self {
+        Self {
+            map: Arc::new(RwLock::new(map)),
+        }
+    }
+
+    /// Replace the vbucket map used by every client sharing this router.
+    pub fn update(&self, map: VbucketMap) {
+        *self.map.write().unwrap() = map;
+    }
+
+    /// Get a copy of the current vbucket map.
+    pub fn map(&self) -> VbucketMap {
+        self.map.read().unwrap().clone()
+    }
+
+    /// Get the vbucket id for a key and the endpoint of the server owning it.
+    pub fn route(&self, key: &[u8]) -> Option<(u16, String)> {
+        let map = self.map.read().unwrap();
+        map.route(key).map(|(id, server)| (id, server.to_string()))
+    }
+
+    /// Set the vbucket id of a request in its header.
+    pub(crate) fn apply(&self, packet: &mut Packet) {
+        if packet.key.is_empty() {
+            return;
+        }
+        let map = self.map.read().unwrap();
+        let vbucket = map.vbucket_id(&packet.key);
+        packet.set_fields(RequestFields {
+            vbucket,
+            ..packet.fields()
+        });
+    }
+}
+
+/// The CRC-32 (IEEE) checksum used by Couchbase to hash keys to vbuckets.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{diagnostics::ConfigError, protocol::Packet};
+
+    use super::{crc32, VbucketMap, VbucketRouter};
+
+    #[test]
+    fn test_vbucket_routing() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+
+        let servers = vec!["a:11211".to_string(), "b:11211".to_string()];
+        let map = VbucketMap::round_robin(servers.clone(), 1024).unwrap();
+        let id = map.vbucket_id(b"hello");
+        assert!(id < 1024);
+        let expect = ["a:11211", "b:11211"][id as usize % 2];
+        assert_eq!(Some((id, expect)), map.route(b"hello"));
+
+        let router = VbucketRouter::new(map);
+        let mut packet = Packet::get(b"hello").unwrap();
+        router.apply(&mut packet);
+        assert_eq!(id, packet.header.vbucket_or_status);
+
+        let map = VbucketMap::new(vec!["c:11211".into()], vec![0; 1024]).unwrap();
+        router.update(map);
+        assert_eq!(Some((id, "c:11211".into())), router.route(b"hello"));
+
+        // Maps every key could not be routed with are rejected.
+        let invalid = Err(ConfigError::InvalidVbucketMap);
+        assert_eq!(invalid, VbucketMap::round_robin(vec![], 1024));
+        assert_eq!(invalid, VbucketMap::round_robin(servers.clone(), 0));
+        assert_eq!(invalid, VbucketMap::round_robin(servers.clone(), 65537));
+        assert_eq!(invalid, VbucketMap::new(servers.clone(), vec![2]));
+        let largest = VbucketMap::round_robin(servers, 65536).unwrap();
+        assert!(largest.route(b"hello").is_some());
+    }
+}
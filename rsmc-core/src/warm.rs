@@ -0,0 +1,133 @@
+//! Making a connection can be expensive, for example when it requires a TLS
+//! handshake and SASL authentication, and requests routed to a node wait
+//! while it reconnects. A [`WarmPool`] keeps spare connections to every node
+//! ready, so that a node whose connection dies can swap in a spare instead.
+//!
+//! The core is runtime agnostic, so it cannot maintain the pool in the
+//! background by itself. Call [`WarmPool::maintain`] periodically from a
+//! task of your runtime, or use the maintainer provided by its adapter.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::client::{Connection, Error};
+
+/// The spare connections to each endpoint, with the time they were made.
+type Spares<C> = HashMap<String, VecDeque<(C, Instant)>>;
+
+/// Spare connections to the nodes of a cluster, shared by every client
+/// created from the same config.
+#[derive(Debug, Clone)]
+pub struct WarmPool<C: Connection> {
+    spares: usize,
+    max_age: Duration,
+    conns: Arc<Mutex<Spares<C>>>,
+}
+
+impl<C: Connection> WarmPool<C> {
+    /// Keep the given number of spare connections to every node. Spares are
+    /// discarded once they are older than `max_age`, which should be below
+    /// the server's `idle_timeout` so that spares are not closed before use.
+    pub fn new(spares: usize, max_age: Duration) -> Self {
+        Self {
+            spares,
+            max_age,
+            conns: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a spare connection to the endpoint, if one is ready.
+    pub fn take(&self, endpoint: &str) -> Option<C> {
+        let mut conns = self.conns.lock().unwrap();
+        let spares = conns.get_mut(endpoint)?;
+        while let Some((conn, created_at)) = spares.pop_back() {
+            if C::now().saturating_duration_since(created_at) < self.max_age {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    /// The number of spare connections ready for the endpoint.
+    pub fn ready(&self, endpoint: &str) -> usize {
+        let conns = self.conns.lock().unwrap();
+        conns.get(endpoint).map_or(0, |spares| spares.len())
+    }
+
+    /// Shut down every spare connection to the endpoint, for example when
+    /// the client using it is closed. Spares never sent a request, so they
+    /// are shut down without a QUIT. The first error is returned.
+    pub async fn close(&self, endpoint: &str) -> Result<(), Error> {
+        let spares = self.conns.lock().unwrap().remove(endpoint);
+        let mut result = Ok(());
+        for (mut conn, _) in spares.into_iter().flatten() {
+            result = result.and(conn.shutdown().await);
+        }
+        result
+    }
+
+    /// Discard expired spares and connect to every endpoint until it has the
+    /// configured number of spares. Endpoints that cannot be reached are
+    /// retried on the next call, and the last error is returned.
+    pub async fn maintain(&self, endpoints: &[String]) -> Result<(), Error> {
+        let mut result = Ok(());
+        for endpoint in endpoints {
+            let missing = {
+                let mut conns = self.conns.lock().unwrap();
+                let spares = conns.entry(endpoint.clone()).or_default();
+                let now = C::now();
+                spares.retain(|(_, created_at)| {
+                    now.saturating_duration_since(*created_at) < self.max_age
+                });
+                self.spares.saturating_sub(spares.len())
+            };
+            for _ in 0..missing {
+                match C::connect(endpoint.clone()).await {
+                    Ok(conn) => {
+                        let mut conns = self.conns.lock().unwrap();
+                        let spares = conns.entry(endpoint.clone()).or_default();
+                        spares.push_back((conn, C::now()));
+                    }
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::mock::MockConnection;
+
+    use super::WarmPool;
+
+    #[test]
+    fn test_warm_pool() {
+        tokio_test::block_on(async {
+            let endpoints = vec!["warm".to_string()];
+            let warm = WarmPool::<MockConnection>::new(2, Duration::from_secs(60));
+            warm.maintain(&endpoints).await.unwrap();
+            assert_eq!(2, warm.ready("warm"));
+            assert!(warm.take("warm").is_some());
+            assert!(warm.take("other").is_none());
+            warm.maintain(&endpoints).await.unwrap();
+            assert_eq!(2, warm.ready("warm"));
+
+            warm.close("warm").await.unwrap();
+            assert_eq!(0, warm.ready("warm"));
+
+            let expired = WarmPool::<MockConnection>::new(1, Duration::from_secs(0));
+            expired.maintain(&endpoints).await.unwrap();
+            assert!(expired.take("warm").is_none());
+        });
+    }
+}
@@ -0,0 +1,77 @@
+//! The binary protocol header codec used by the client, exposed for people
+//! building memcached-speaking middleware such as proxies, sniffers and test
+//! servers. These functions are stable: the 24 byte layout is fixed by the
+//! protocol, and decoding never panics on malformed input.
+//!
+//! The packet builders used by the client are exported too, so that such
+//! tools can build requests with a CAS value or vbucket id in their header
+//! using [`RequestFields`] and the `*_with` constructors of [`Packet`].
+
+pub use crate::protocol::{
+    CounterExtras, FlushExtras, Header, Packet, ProtocolError, RequestFields, SetExtras, Status,
+    TouchExtras,
+};
+
+/// The length of every packet header.
+pub const HEADER_LEN: usize = 24;
+
+/// The magic byte starting every request.
+pub const MAGIC_REQUEST: u8 = crate::protocol::MAGIC_REQUEST_VALUE;
+
+/// The magic byte starting every response.
+pub const MAGIC_RESPONSE: u8 = crate::protocol::MAGIC_RESPONSE_VALUE;
+
+/// Encode a header into its bytes on the wire.
+pub fn encode_header(header: &Header) -> [u8; HEADER_LEN] {
+    header.encode()
+}
+
+/// Decode a request header from the start of the buffer. Fails if the buffer
+/// is shorter than [`HEADER_LEN`] or does not start with [`MAGIC_REQUEST`].
+pub fn decode_request_header(bytes: &[u8]) -> Result<Header, ProtocolError> {
+    Header::read_request(bytes)
+}
+
+/// Decode a response header from the start of the buffer. Fails if the
+/// buffer is shorter than [`HEADER_LEN`] or does not start with
+/// [`MAGIC_RESPONSE`].
+pub fn decode_response_header(bytes: &[u8]) -> Result<Header, ProtocolError> {
+    Header::read_response(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_request_header, decode_response_header, encode_header, Header, ProtocolError,
+        MAGIC_REQUEST,
+    };
+    use crate::protocol::Packet;
+
+    #[test]
+    fn test_header_codec() {
+        let packet = Packet::get("key").unwrap();
+        let bytes: Vec<u8> = packet.clone().into();
+        let header = decode_request_header(&bytes).unwrap();
+        assert_eq!(packet.header, header);
+        assert_eq!(bytes[..24], encode_header(&header));
+
+        let header = Header {
+            magic: MAGIC_REQUEST,
+            opaque: 0xdeadbeef,
+            cas: u64::MAX,
+            ..Header::default()
+        };
+        assert_eq!(
+            header,
+            decode_request_header(&encode_header(&header)).unwrap()
+        );
+        assert_eq!(
+            Err(ProtocolError::InvalidMagic(MAGIC_REQUEST)),
+            decode_response_header(&encode_header(&header))
+        );
+        assert_eq!(
+            Err(ProtocolError::PacketTooSmall),
+            decode_request_header(&[MAGIC_REQUEST; 23])
+        );
+    }
+}
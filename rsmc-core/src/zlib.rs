@@ -1,11 +1,16 @@
 use flate2::{
-    write::{ZlibDecoder, ZlibEncoder},
+    write::{DeflateDecoder, DeflateEncoder, ZlibDecoder, ZlibEncoder},
     Compression,
 };
-use std::io::Write;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Write,
+    marker::PhantomData,
+};
 
 use crate::{
     client::{Compressor, Error},
+    instrument::{CompressionEvent, MetricsHook, NoMetrics},
     protocol::Packet,
 };
 
@@ -13,11 +18,181 @@ use crate::{
 /// compressing data. About 5 times the size of a packet header.
 pub const DEFAULT_MIN_BYTES: usize = 128;
 
-/// A compressor that implements zlib compression and decompression.
-#[derive(Debug, Clone, Copy)]
-pub struct ZlibCompressor {
+/// The largest number of bands in [`CompressionBands`].
+pub const MAX_BANDS: usize = 8;
+
+/// The largest number of dictionaries in [`CompressionDictionaries`].
+pub const MAX_DICTIONARIES: usize = 8;
+
+/// The flag bit set, along with the zlib flag, on values compressed with a
+/// dictionary. This lives in the high byte of the flags, which is reserved
+/// for rsmc.
+pub const DICTIONARY_FLAG: u32 = 0x0400_0000;
+
+/// Compression levels by value size. A single level is either too slow for
+/// small hot values or too weak for large ones, so each band sets the level
+/// for values of at least its size, up to the size of the next band. For
+/// example, level 1 from 1KB and level 6 from 64KB:
+///
+/// ```
+/// # use flate2::Compression;
+/// # use rsmc_core::zlib::{CompressionBands, ZlibCompressor};
+/// let bands = CompressionBands::new()
+///     .with_band(1024, Compression::new(1))
+///     .with_band(64 * 1024, Compression::new(6));
+/// let compressor = ZlibCompressor::default().with_bands(bands);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionBands {
+    bands: [(usize, Compression); MAX_BANDS],
+    len: usize,
+}
+
+impl CompressionBands {
+    /// Create bands which leave the level of the compressor unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compress values of at least `min_bytes` at the given level, up to the
+    /// size of the next band. A band of the same size is replaced, and bands
+    /// beyond the first [`MAX_BANDS`] are ignored.
+    pub fn with_band(mut self, min_bytes: usize, compression: Compression) -> Self {
+        let bands = &mut self.bands[..self.len];
+        if let Some(band) = bands.iter_mut().find(|(size, _)| *size == min_bytes) {
+            band.1 = compression;
+        } else if self.len < MAX_BANDS {
+            self.bands[self.len] = (min_bytes, compression);
+            self.len += 1;
+            self.bands[..self.len].sort_by_key(|(size, _)| *size);
+        }
+        self
+    }
+
+    /// The level of the largest band no larger than the value, if any.
+    pub fn level_for(&self, len: usize) -> Option<Compression> {
+        let mut bands = self.bands[..self.len].iter().rev();
+        bands
+            .find(|(size, _)| *size <= len)
+            .map(|(_, compression)| *compression)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Dictionary {
+    id: u8,
+    prefix: &'static [u8],
+    bytes: &'static [u8],
+}
+
+/// Compression dictionaries by key prefix. Small values compress poorly on
+/// their own, and values of different kinds (such as sessions and product
+/// blobs) share little with each other, so each kind of value can be primed
+/// with a dictionary of its own typical content. For example:
+///
+/// ```
+/// # use rsmc_core::zlib::{CompressionDictionaries, ZlibCompressor};
+/// let dictionaries = CompressionDictionaries::new()
+///     .with_dictionary(1, b"session:", b"{\"user_id\":,\"expires_at\":}")
+///     .with_dictionary(2, b"product:", b"{\"sku\":,\"price\":,\"title\":}");
+/// let compressor = ZlibCompressor::default().with_dictionaries(dictionaries);
+/// ```
+///
+/// Values compressed with a dictionary are raw deflate streams which start
+/// with the id of their dictionary, so every client reading them must know
+/// the dictionary with that id. Only the last 32KB of a dictionary are used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionDictionaries {
+    dictionaries: [Dictionary; MAX_DICTIONARIES],
+    len: usize,
+}
+
+impl CompressionDictionaries {
+    /// Create an empty set of dictionaries, which compresses every value
+    /// without one.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compress the values of keys starting with `prefix` with the given
+    /// dictionary, recording `id` with every value. A dictionary with the
+    /// same id is replaced, and dictionaries beyond the first
+    /// [`MAX_DICTIONARIES`] are ignored.
+    pub fn with_dictionary(
+        mut self,
+        id: u8,
+        prefix: &'static [u8],
+        dictionary: &'static [u8],
+    ) -> Self {
+        let dictionary = Dictionary {
+            id,
+            prefix,
+            bytes: dictionary,
+        };
+        let dictionaries = &mut self.dictionaries[..self.len];
+        if let Some(existing) = dictionaries.iter_mut().find(|d| d.id == id) {
+            *existing = dictionary;
+        } else if self.len < MAX_DICTIONARIES {
+            self.dictionaries[self.len] = dictionary;
+            self.len += 1;
+        }
+        self
+    }
+
+    /// The id and bytes of the dictionary with the longest prefix of the
+    /// key, if any.
+    pub fn for_key(&self, key: &[u8]) -> Option<(u8, &'static [u8])> {
+        self.dictionaries[..self.len]
+            .iter()
+            .filter(|d| key.starts_with(d.prefix))
+            .max_by_key(|d| d.prefix.len())
+            .map(|d| (d.id, d.bytes))
+    }
+
+    /// The bytes of the dictionary with the id, if any.
+    pub fn get(&self, id: u8) -> Option<&'static [u8]> {
+        let mut dictionaries = self.dictionaries[..self.len].iter();
+        dictionaries.find(|d| d.id == id).map(|d| d.bytes)
+    }
+}
+
+/// Compress a value as a raw deflate stream primed with a dictionary. The
+/// dictionary is compressed first and flushed to a byte boundary, so the
+/// stream of the value can refer back to it, and only the stream of the
+/// value is kept.
+fn deflate_with(dictionary: &[u8], value: &[u8], level: Compression) -> Result<Vec<u8>, Error> {
+    let mut enc = DeflateEncoder::new(vec![], level);
+    enc.write_all(dictionary)?;
+    enc.flush()?;
+    let primed = enc.get_ref().len();
+    enc.write_all(value)?;
+    let mut out = enc.finish()?;
+    out.drain(..primed);
+    Ok(out)
+}
+
+/// Decompress a value compressed by [`deflate_with`], by priming the decoder
+/// with the dictionary stored uncompressed.
+fn inflate_with(dictionary: &[u8], stream: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut enc = DeflateEncoder::new(vec![], Compression::none());
+    enc.write_all(dictionary)?;
+    enc.flush()?;
+    let mut dec = DeflateDecoder::new(vec![]);
+    dec.write_all(enc.get_ref())?;
+    dec.write_all(stream)?;
+    let mut out = dec.finish()?;
+    out.drain(..dictionary.len());
+    Ok(out)
+}
+
+/// A compressor that implements zlib compression and decompression. Every
+/// decision to compress a value or not is reported to the hook `H`.
+pub struct ZlibCompressor<H: MetricsHook = NoMetrics> {
     compression: Compression,
     min_bytes: usize,
+    bands: CompressionBands,
+    dictionaries: CompressionDictionaries,
+    hook: PhantomData<H>,
 }
 
 impl ZlibCompressor {
@@ -28,26 +203,101 @@ impl ZlibCompressor {
         ZlibCompressor {
             compression,
             min_bytes,
+            bands: CompressionBands::new(),
+            dictionaries: CompressionDictionaries::new(),
+            hook: PhantomData,
         }
     }
 }
 
+impl<H: MetricsHook> ZlibCompressor<H> {
+    /// Report compression decisions to the hook `G`, which is created with
+    /// [`Default`] for every value.
+    pub fn with_metrics_hook<G: MetricsHook>(self) -> ZlibCompressor<G> {
+        ZlibCompressor {
+            compression: self.compression,
+            min_bytes: self.min_bytes,
+            bands: self.bands,
+            dictionaries: self.dictionaries,
+            hook: PhantomData,
+        }
+    }
+
+    /// Compress values at the level of their size band, falling back to the
+    /// level of the compressor for values smaller than every band. Values
+    /// smaller than `min_bytes` are still never compressed.
+    pub fn with_bands(mut self, bands: CompressionBands) -> Self {
+        self.bands = bands;
+        self
+    }
+
+    /// Compress the values of keys matching a dictionary prefix with that
+    /// dictionary, and decompress values recording a dictionary id with the
+    /// dictionary of that id.
+    pub fn with_dictionaries(mut self, dictionaries: CompressionDictionaries) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+}
+
+impl<H: MetricsHook> Clone for ZlibCompressor<H> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H: MetricsHook> Copy for ZlibCompressor<H> {}
+
+impl<H: MetricsHook> Debug for ZlibCompressor<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ZlibCompressor")
+            .field("compression", &self.compression)
+            .field("min_bytes", &self.min_bytes)
+            .field("bands", &self.bands)
+            .field("dictionaries", &self.dictionaries)
+            .finish()
+    }
+}
+
 impl Default for ZlibCompressor {
     fn default() -> Self {
         ZlibCompressor::new(Compression::default(), DEFAULT_MIN_BYTES)
     }
 }
 
-impl Compressor for ZlibCompressor {
+impl<H: MetricsHook> Compressor for ZlibCompressor<H> {
     fn compress(&self, mut packet: Packet) -> Result<Packet, Error> {
-        if packet.value.len() < self.min_bytes {
+        let original = packet.value.len();
+        if original < self.min_bytes {
+            H::default().on_compress(CompressionEvent::SkippedSmall(original));
             return Ok(packet);
         }
 
-        let mut out = vec![];
-        let mut enc = ZlibEncoder::new(&mut out, self.compression);
-        enc.write_all(&packet.value)?;
-        enc.finish()?;
+        let compression = self.bands.level_for(original);
+        let compression = compression.unwrap_or(self.compression);
+        let dictionary = self.dictionaries.for_key(&packet.key);
+        let out = match dictionary {
+            Some((id, dictionary)) => {
+                let stream = deflate_with(dictionary, &packet.value, compression)?;
+                [&[id][..], &stream].concat()
+            }
+            None => {
+                let mut out = vec![];
+                let mut enc = ZlibEncoder::new(&mut out, compression);
+                enc.write_all(&packet.value)?;
+                enc.finish()?;
+                out
+            }
+        };
+        if out.len() >= original {
+            H::default().on_compress(CompressionEvent::SkippedIncompressible(original));
+            return Ok(packet);
+        }
+        let compressed = out.len();
+        H::default().on_compress(CompressionEvent::Compressed {
+            original,
+            compressed,
+        });
 
         // Update the header lengths to match the new value.
         let key_len = packet.header.key_length as u32;
@@ -56,21 +306,35 @@ impl Compressor for ZlibCompressor {
         packet.header.body_len = key_len + ext_len + val_len;
         // Set a flag indicating that this data is compressed with zlib.
         // NB: extras must be non-empty to compress packets.
-        packet.extras[0] = 1;
+        packet.extras[0] |= 1;
+        if dictionary.is_some() {
+            packet.extras[0] |= (DICTIONARY_FLAG >> 24) as u8;
+        }
         packet.value = out;
         Ok(packet)
     }
 
     fn decompress(&self, mut packet: Packet) -> Result<Packet, Error> {
-        if packet.extras.get(0) != Some(&1) {
+        if packet.extras.first().map_or(true, |flags| flags & 1 == 0) {
             // This packet did not have the compression flag enabled.
             return Ok(packet);
         }
 
-        let mut out = vec![];
-        let mut dec = ZlibDecoder::new(&mut out);
-        dec.write_all(&packet.value)?;
-        dec.finish()?;
+        let dictionary_flag = (DICTIONARY_FLAG >> 24) as u8;
+        let out = if packet.extras[0] & dictionary_flag != 0 {
+            let (id, stream) = packet
+                .value
+                .split_first()
+                .ok_or(Error::UnknownDictionary(0))?;
+            let dictionary = self.dictionaries.get(*id);
+            inflate_with(dictionary.ok_or(Error::UnknownDictionary(*id))?, stream)?
+        } else {
+            let mut out = vec![];
+            let mut dec = ZlibDecoder::new(&mut out);
+            dec.write_all(&packet.value)?;
+            dec.finish()?;
+            out
+        };
 
         // Update the header lengths to match the new value.
         let key_len = packet.header.key_length as u32;
@@ -78,7 +342,7 @@ impl Compressor for ZlibCompressor {
         let val_len = out.len() as u32;
         packet.header.body_len = key_len + ext_len + val_len;
         // Unset the flag indicating that this data is compressed with zlib.
-        packet.extras[0] = 0;
+        packet.extras[0] &= !(1 | dictionary_flag);
         packet.value = out;
         Ok(packet)
     }
@@ -87,13 +351,110 @@ impl Compressor for ZlibCompressor {
 #[cfg(test)]
 mod tests {
     use flate2::Compression;
+    use std::sync::Mutex;
 
     use crate::{
-        client::Compressor,
+        client::{Compressor, Error},
+        instrument::{CompressionEvent, MetricsHook},
         protocol::{Packet, SetExtras},
     };
 
-    use super::ZlibCompressor;
+    use super::{
+        CompressionBands, CompressionDictionaries, ZlibCompressor, DICTIONARY_FLAG, MAX_BANDS,
+    };
+
+    static EVENTS: Mutex<Vec<CompressionEvent>> = Mutex::new(vec![]);
+
+    #[derive(Debug, Default, Clone)]
+    struct RecordingHook;
+
+    impl MetricsHook for RecordingHook {
+        fn on_compress(&self, event: CompressionEvent) {
+            EVENTS.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn test_compression_metrics() {
+        let compressor =
+            ZlibCompressor::new(Compression::new(9), 64).with_metrics_hook::<RecordingHook>();
+        let extras = SetExtras::new(0, 0);
+        let small = Packet::set(&b"small"[..], &b"0000"[..], extras).unwrap();
+        let zeros = Packet::set(&b"zeros"[..], &[0_u8; 256][..], extras).unwrap();
+        let noise = (0..64_u32)
+            .map(|i| (i * 7919 % 251) as u8)
+            .collect::<Vec<_>>();
+        let random = Packet::set(&b"random"[..], &noise[..], extras).unwrap();
+
+        assert_eq!(small, compressor.compress(small.clone()).unwrap());
+        assert_ne!(zeros, compressor.compress(zeros.clone()).unwrap());
+        assert_eq!(random, compressor.compress(random.clone()).unwrap());
+
+        let events = EVENTS.lock().unwrap();
+        assert_eq!(CompressionEvent::SkippedSmall(small.value.len()), events[0]);
+        assert!(events[1].bytes_saved() > 200);
+        assert_eq!(
+            CompressionEvent::SkippedIncompressible(random.value.len()),
+            events[2]
+        );
+    }
+
+    #[test]
+    fn test_compression_bands() {
+        let bands = CompressionBands::new()
+            .with_band(64 * 1024, Compression::new(6))
+            .with_band(1024, Compression::new(1));
+        assert_eq!(None, bands.level_for(1023));
+        assert_eq!(Some(Compression::new(1)), bands.level_for(1024));
+        assert_eq!(Some(Compression::new(1)), bands.level_for(32 * 1024));
+        assert_eq!(Some(Compression::new(6)), bands.level_for(1 << 20));
+        let replaced = bands.with_band(1024, Compression::new(2));
+        assert_eq!(Some(Compression::new(2)), replaced.level_for(1024));
+        let full = (0..MAX_BANDS + 1).fold(CompressionBands::new(), |bands, i| {
+            bands.with_band(i * 100, Compression::new(i as u32))
+        });
+        assert_eq!(Some(Compression::new(7)), full.level_for(usize::MAX));
+
+        // Values in the stored band are left uncompressed by level 0.
+        let compressor = ZlibCompressor::new(Compression::new(9), 1)
+            .with_bands(CompressionBands::new().with_band(256, Compression::none()));
+        let extras = SetExtras::new(0, 0);
+        let small = Packet::set(&b"small"[..], &[0_u8; 128][..], extras).unwrap();
+        let large = Packet::set(&b"large"[..], &[0_u8; 512][..], extras).unwrap();
+        assert_ne!(small, compressor.compress(small.clone()).unwrap());
+        assert_eq!(large, compressor.compress(large.clone()).unwrap());
+    }
+
+    #[test]
+    fn test_compression_dictionaries() {
+        const SESSION: &[u8] =
+            b"{\"user_id\":12345,\"roles\":[\"admin\",\"editor\"],\"theme\":\"dark\"}";
+        let dictionaries = CompressionDictionaries::new()
+            .with_dictionary(1, b"session:", SESSION)
+            .with_dictionary(2, b"session:admin:", SESSION)
+            .with_dictionary(3, b"product:", b"sku price title");
+        assert_eq!(Some(1), dictionaries.for_key(b"session:1").map(|d| d.0));
+        assert_eq!(
+            Some(2),
+            dictionaries.for_key(b"session:admin:1").map(|d| d.0)
+        );
+        assert_eq!(None, dictionaries.for_key(b"other"));
+
+        let plain = ZlibCompressor::new(Compression::new(9), 1);
+        let compressor = plain.with_dictionaries(dictionaries);
+        let value = br#"{"user_id":67890,"roles":["editor"],"theme":"dark"}"#;
+        let packet = Packet::set(&b"session:1"[..], &value[..], SetExtras::new(0, 0)).unwrap();
+        let primed = compressor.compress(packet.clone()).unwrap();
+        let unprimed = plain.compress(packet.clone()).unwrap();
+        assert_eq!(DICTIONARY_FLAG | 0x0100_0000, primed.flags());
+        assert_eq!(1, primed.value[0]);
+        assert!(primed.value.len() < unprimed.value.len());
+        assert_eq!(packet, compressor.decompress(primed.clone()).unwrap());
+
+        // Values with an unknown dictionary cannot be decompressed.
+        let err = plain.decompress(primed).unwrap_err();
+        assert!(matches!(err, Error::UnknownDictionary(1)));
+    }
 
     #[test]
     fn test_zlib() {
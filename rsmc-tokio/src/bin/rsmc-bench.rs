@@ -0,0 +1,237 @@
+//! A load generator for memcached clusters using this client. It drives a
+//! configurable mix of gets and sets against the cluster and reports the
+//! latency percentiles of each, so that performance regressions and config
+//! choices can be evaluated with the same stack applications use.
+//!
+//! ```text
+//! cargo run -p rsmc-tokio --features bench --bin rsmc-bench -- \
+//!     --endpoints localhost:11211 --requests 100000 --distribution zipfian
+//! ```
+
+use rand::prelude::*;
+use rsmc_tokio::{ClientConfig, Pool};
+use std::{
+    env, process,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "Usage: rsmc-bench [options]
+
+Options:
+    --endpoints <a,b,..>     memcached endpoints (default: localhost:11211)
+    --requests <n>           total number of requests (default: 100000)
+    --concurrency <n>        number of concurrent workers (default: 16)
+    --get-ratio <f>          fraction of requests that are gets (default: 0.9)
+    --value-size <bytes>     size of values written by sets (default: 100)
+    --keys <n>               number of distinct keys (default: 10000)
+    --distribution <d>       uniform or zipfian (default: uniform)
+    --zipf-exponent <f>      skew of the zipfian distribution (default: 0.99)";
+
+#[derive(Debug, Clone)]
+struct Options {
+    endpoints: Vec<String>,
+    requests: usize,
+    concurrency: usize,
+    get_ratio: f64,
+    value_size: usize,
+    keys: usize,
+    distribution: Distribution,
+}
+
+#[derive(Debug, Clone)]
+enum Distribution {
+    Uniform,
+    /// The cumulative probability of choosing each key, by rank.
+    Zipfian(Vec<f64>),
+}
+
+impl Distribution {
+    fn zipfian(keys: usize, exponent: f64) -> Self {
+        let weights = (1..=keys).map(|rank| 1.0 / (rank as f64).powf(exponent));
+        let total: f64 = weights.clone().sum();
+        let cdf = weights
+            .scan(0.0, |acc, weight| {
+                *acc += weight / total;
+                Some(*acc)
+            })
+            .collect();
+        Distribution::Zipfian(cdf)
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R, keys: usize) -> usize {
+        match self {
+            Distribution::Uniform => rng.gen_range(0..keys),
+            Distribution::Zipfian(cdf) => {
+                let p: f64 = rng.gen();
+                cdf.partition_point(|c| *c < p).min(keys - 1)
+            }
+        }
+    }
+}
+
+fn parse<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", arg, value))
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut out = Self {
+            endpoints: vec!["localhost:11211".into()],
+            requests: 100_000,
+            concurrency: 16,
+            get_ratio: 0.9,
+            value_size: 100,
+            keys: 10_000,
+            distribution: Distribution::Uniform,
+        };
+        let mut distribution = "uniform".to_string();
+        let mut exponent = 0.99;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(USAGE.into());
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--endpoints" => out.endpoints = value.split(',').map(String::from).collect(),
+                "--requests" => out.requests = parse(&arg, &value)?,
+                "--concurrency" => out.concurrency = parse(&arg, &value)?,
+                "--get-ratio" => out.get_ratio = parse(&arg, &value)?,
+                "--value-size" => out.value_size = parse(&arg, &value)?,
+                "--keys" => out.keys = parse(&arg, &value)?,
+                "--distribution" => distribution = value,
+                "--zipf-exponent" => exponent = parse(&arg, &value)?,
+                _ => return Err(format!("Unknown option: {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        if out.keys == 0 || out.concurrency == 0 {
+            return Err("--keys and --concurrency must be positive".into());
+        }
+        out.distribution = match distribution.as_str() {
+            "uniform" => Distribution::Uniform,
+            "zipfian" => Distribution::zipfian(out.keys, exponent),
+            other => return Err(format!("Unknown distribution: {}", other)),
+        };
+        Ok(out)
+    }
+}
+
+/// The latencies recorded by a worker for each kind of request.
+#[derive(Debug, Default)]
+struct Latencies {
+    gets: Vec<Duration>,
+    sets: Vec<Duration>,
+    errors: usize,
+}
+
+async fn worker(pool: Pool<rsmc_tokio::NoCompressor>, opts: Options, requests: usize) -> Latencies {
+    let mut rng = StdRng::from_entropy();
+    let value = vec![b'x'; opts.value_size];
+    let mut out = Latencies::default();
+    for _ in 0..requests {
+        let key = format!(
+            "rsmc-bench:{}",
+            opts.distribution.sample(&mut rng, opts.keys)
+        );
+        let is_get = rng.gen_bool(opts.get_ratio.clamp(0.0, 1.0));
+        let mut client = match pool.get().await {
+            Ok(client) => client,
+            Err(_) => {
+                out.errors += 1;
+                continue;
+            }
+        };
+        let start = Instant::now();
+        let result = if is_get {
+            client.get::<_, Vec<u8>>(&key).await.map(|_| ())
+        } else {
+            client.set(&key, &value, 0).await
+        };
+        let elapsed = start.elapsed();
+        match (result, is_get) {
+            (Err(_), _) => out.errors += 1,
+            (Ok(_), true) => out.gets.push(elapsed),
+            (Ok(_), false) => out.sets.push(elapsed),
+        }
+    }
+    out
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort_unstable();
+    let percentile = |p: f64| {
+        let index = ((latencies.len() as f64 * p).ceil() as usize).saturating_sub(1);
+        latencies[index.min(latencies.len() - 1)]
+    };
+    println!(
+        "{:<4} n={:<8} p50={:?} p90={:?} p99={:?} p99.9={:?} max={:?}",
+        name,
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(0.999),
+        latencies[latencies.len() - 1],
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let opts = match Options::parse() {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    };
+
+    let cfg = ClientConfig::new_uncompressed(opts.endpoints.clone());
+    if let Err(err) = cfg.validate() {
+        eprintln!("Invalid configuration: {}", err);
+        process::exit(2);
+    }
+    let pool = match Pool::builder(cfg).max_size(opts.concurrency).build() {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Could not build pool: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let start = Instant::now();
+    let workers = (0..opts.concurrency).map(|i| {
+        // Spread the remainder over the first workers.
+        let requests =
+            opts.requests / opts.concurrency + usize::from(i < opts.requests % opts.concurrency);
+        tokio::spawn(worker(pool.clone(), opts.clone(), requests))
+    });
+    let mut total = Latencies::default();
+    for result in futures::future::join_all(workers).await {
+        let latencies = result.expect("worker panicked");
+        total.gets.extend(latencies.gets);
+        total.sets.extend(latencies.sets);
+        total.errors += latencies.errors;
+    }
+    let elapsed = start.elapsed();
+
+    let completed = total.gets.len() + total.sets.len();
+    println!(
+        "{} requests in {:?} ({:.0} req/s), {} errors",
+        completed,
+        elapsed,
+        completed as f64 / elapsed.as_secs_f64(),
+        total.errors,
+    );
+    report("get", total.gets);
+    report("set", total.sets);
+}
@@ -1,16 +1,41 @@
 use async_trait::async_trait;
-use rsmc_core::client::Connection;
-use std::{ops::DerefMut, sync::Arc};
+use bytes::{Bytes, BytesMut};
+use rsmc_core::{
+    client::Connection,
+    options::DeadlineSource,
+    wire::{decode_response_header, Header, ProtocolError, HEADER_LEN},
+};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    future::Future,
+    io::ErrorKind,
+    ops::DerefMut,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
     sync::Mutex,
+    task::JoinHandle,
 };
 
-pub use rsmc_core::client::{ClientConfig, Compressor, Error, NoCompressor};
+pub use rsmc_core::client::{
+    drain_pool, reconfigure_pool, ClientConfig, Compressor, Error, NoCompressor, Result,
+};
 #[cfg(feature = "zlib")]
 pub use rsmc_core::zlib::ZlibCompressor;
 
+/// The prelude re-exports the runtime-neutral [`rsmc_core::prelude`] along
+/// with the tokio connection and pool types.
+pub mod prelude {
+    pub use crate::{
+        spawn_warm_pool_maintainer, with_deadline, Endpoint, Pool, TaskDeadline, TokioConnection,
+        WarmPool,
+    };
+    pub use rsmc_core::prelude::*;
+}
+
 /// A pool of connections to memcached using tokio for async I/O and
 /// the desired compression scheme. Use this to create a connection pool.
 /// For example:
@@ -23,32 +48,208 @@ pub use rsmc_core::zlib::ZlibCompressor;
 /// ```
 pub type Pool<P> = rsmc_core::client::Pool<TokioConnection, P>;
 
-/// A TokioConnection uses the tokio runtime to form TCP connections to
-/// memcached.
-#[derive(Debug, Clone)]
+/// A pool of spare connections to swap in when a connection dies. Keep it
+/// filled with [`spawn_warm_pool_maintainer`].
+pub type WarmPool = rsmc_core::warm::WarmPool<TokioConnection>;
+
+/// Spawn a task which tops up the warm pool with spare connections to the
+/// endpoints at every interval, until the returned handle is aborted.
+pub fn spawn_warm_pool_maintainer(
+    warm: WarmPool,
+    endpoints: Vec<String>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            // Unreachable endpoints are retried on the next tick.
+            let _ = warm.maintain(&endpoints).await;
+        }
+    })
+}
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Run a future with a deadline which bounds every cache operation made
+/// inside of it by a client configured with [`TaskDeadline`]. Nested
+/// deadlines keep the earliest. For example:
+///
+/// ```ignore
+/// use rsmc_tokio::{with_deadline, ClientConfig, TaskDeadline};
+///
+/// let cfg = ClientConfig::new_uncompressed(vec!["localhost:11211".into()])
+///     .with_deadline_source(Arc::new(TaskDeadline));
+/// let deadline = Instant::now() + Duration::from_millis(50);
+/// with_deadline(deadline, handle_request(pool)).await;
+/// ```
+pub async fn with_deadline<F: Future>(deadline: Instant, future: F) -> F::Output {
+    let deadline = match TaskDeadline.deadline() {
+        Some(outer) => outer.min(deadline),
+        None => deadline,
+    };
+    DEADLINE.scope(deadline, future).await
+}
+
+/// Reads the deadline set by [`with_deadline`] for the current task.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TaskDeadline;
+
+impl DeadlineSource for TaskDeadline {
+    fn deadline(&self) -> Option<Instant> {
+        DEADLINE.try_with(|deadline| *deadline).ok()
+    }
+}
+
+/// The transport a [`TokioConnection`] connects over, parsed from the
+/// endpoint strings of a [`ClientConfig`]. Local transports let memcached
+/// running in WSL or a local emulator be reached without TCP, while the
+/// client code stays the same as in production. For example:
+///
+/// ```ignore
+/// use rsmc_tokio::{ClientConfig, Endpoint};
+///
+/// let endpoint = Endpoint::Pipe(r"\\.\pipe\memcached".into());
+/// let cfg = ClientConfig::new_uncompressed(vec![endpoint.to_string()]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP address of the form `host:port`.
+    Tcp(String),
+    /// A Unix domain socket path, written as `unix:<path>`.
+    Unix(String),
+    /// A Windows named pipe, written as `pipe:<name>`.
+    Pipe(String),
+}
+
+impl Endpoint {
+    /// Parse an endpoint string, which is a TCP address unless it starts
+    /// with the `unix:` or `pipe:` scheme.
+    pub fn parse(url: &str) -> Self {
+        if let Some(path) = url.strip_prefix("unix:") {
+            return Endpoint::Unix(path.into());
+        }
+        if let Some(name) = url.strip_prefix("pipe:") {
+            return Endpoint::Pipe(name.into());
+        }
+        Endpoint::Tcp(url.into())
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path),
+            Endpoint::Pipe(name) => write!(f, "pipe:{}", name),
+        }
+    }
+}
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Open the stream of an endpoint, split into its read and write halves.
+async fn open(endpoint: Endpoint) -> Result<(Reader, Writer), Error> {
+    match endpoint {
+        Endpoint::Tcp(addr) => {
+            let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            let (reader, writer) = stream.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        #[cfg(windows)]
+        Endpoint::Pipe(name) => {
+            let pipe = tokio::net::windows::named_pipe::ClientOptions::new().open(name)?;
+            let (reader, writer) = tokio::io::split(pipe);
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        #[allow(unreachable_patterns)]
+        endpoint => {
+            let message = format!("Unsupported transport on this platform: {}", endpoint);
+            Err(std::io::Error::new(ErrorKind::Unsupported, message).into())
+        }
+    }
+}
+
+/// A TokioConnection uses the tokio runtime to connect to memcached over
+/// TCP, or over a local transport chosen by the [`Endpoint`]. The read and
+/// write halves of the stream are locked independently, so a pipeline can be
+/// written while responses are read. Clones share the stream, and every
+/// response is read while holding the read lock, so a response is never
+/// split between clones. Responses are not matched to the clone which sent
+/// the request, however, so clones must not have requests in flight at the
+/// same time: either clone may read either response.
+#[derive(Clone)]
 pub struct TokioConnection {
-    stream: Arc<Mutex<TcpStream>>,
+    reader: Arc<Mutex<Reader>>,
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl std::fmt::Debug for TokioConnection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("TokioConnection").finish_non_exhaustive()
+    }
 }
 
 #[async_trait]
 impl Connection for TokioConnection {
     async fn connect(url: String) -> Result<Self, Error> {
-        let stream = TcpStream::connect(url).await?;
-        let stream = Arc::new(Mutex::new(stream));
-        Ok(TokioConnection { stream })
+        let (reader, writer) = open(Endpoint::parse(&url)).await?;
+        let reader = Arc::new(Mutex::new(reader));
+        let writer = Arc::new(Mutex::new(writer));
+        Ok(TokioConnection { reader, writer })
     }
 
     async fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
-        let mut lock = self.stream.lock().await;
+        let mut lock = self.reader.lock().await;
         let stream = lock.deref_mut();
         Ok(stream.read(buf).await?)
     }
 
+    async fn read_owned(&mut self, n: usize) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::zeroed(n);
+        let mut lock = self.reader.lock().await;
+        let stream = lock.deref_mut();
+        stream.read_exact(&mut buf).await?;
+        Ok(buf.freeze())
+    }
+
+    async fn read_frame(&mut self, max_body: u32) -> Result<(Header, Bytes), Error> {
+        let mut lock = self.reader.lock().await;
+        let stream = lock.deref_mut();
+        let mut buf = [0; HEADER_LEN];
+        stream.read_exact(&mut buf).await?;
+        let header = decode_response_header(&buf)?;
+        if header.body_len > max_body {
+            return Err(ProtocolError::ResponseTooLarge(header.body_len).into());
+        }
+        let mut body = BytesMut::zeroed(header.body_len as usize);
+        stream.read_exact(&mut body).await?;
+        Ok((header, body.freeze()))
+    }
+
     async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
-        let mut lock = self.stream.lock().await;
+        let mut lock = self.writer.lock().await;
         let stream = lock.deref_mut();
         Ok(stream.write_all(data).await?)
     }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        let mut lock = self.writer.lock().await;
+        let stream = lock.deref_mut();
+        Ok(stream.shutdown().await?)
+    }
 }
 
 #[cfg(test)]
@@ -97,7 +298,7 @@ mod test {
 
         fn new_proc(name: &str, port: usize) -> Child {
             let mut proc = Command::new("docker")
-                .args(&[
+                .args([
                     "run",
                     "--rm",
                     "-t",
@@ -130,7 +331,7 @@ mod test {
         fn drop(&mut self) {
             for name in self.names.iter() {
                 Command::new("docker")
-                    .args(&["stop", &name])
+                    .args(["stop", name])
                     .output()
                     .unwrap();
             }
@@ -141,6 +342,106 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_cloned_reads() {
+        use rsmc_core::wire::{encode_header, MAGIC_RESPONSE};
+
+        tokio_test::block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let host = listener.local_addr().unwrap().to_string();
+            let server = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                for opaque in 0..2 {
+                    let header = Header {
+                        magic: MAGIC_RESPONSE,
+                        key_length: 3,
+                        body_len: 3,
+                        opaque,
+                        ..Header::default()
+                    };
+                    // Split every response to give clones a chance to
+                    // interleave their reads.
+                    socket.write_all(&encode_header(&header)).await.unwrap();
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    socket.write_all(b"key").await.unwrap();
+                }
+            });
+
+            let mut conn = TokioConnection::connect(host).await.unwrap();
+            let mut clone = conn.clone();
+            let (a, b) = futures::join!(
+                conn.read_packet(NoCompressor),
+                clone.read_packet(NoCompressor)
+            );
+            let (a, b) = (a.unwrap(), b.unwrap());
+            assert_eq!((b"key".to_vec(), b"key".to_vec()), (a.key, b.key));
+            // Each clone reads a whole response, but not necessarily the
+            // one it would expect.
+            assert_eq!(1, a.header.opaque + b.header.opaque);
+            server.await.unwrap();
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        use rsmc_core::wire::{encode_header, MAGIC_RESPONSE};
+
+        tokio_test::block_on(async {
+            let path = std::env::temp_dir().join(format!("rsmc-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = tokio::net::UnixListener::bind(&path).unwrap();
+            let server = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let header = Header {
+                    magic: MAGIC_RESPONSE,
+                    key_length: 3,
+                    body_len: 3,
+                    ..Header::default()
+                };
+                socket.write_all(&encode_header(&header)).await.unwrap();
+                socket.write_all(b"key").await.unwrap();
+            });
+
+            let endpoint = Endpoint::Unix(path.to_string_lossy().into());
+            assert_eq!(endpoint, Endpoint::parse(&endpoint.to_string()));
+            let mut conn = TokioConnection::connect(endpoint.to_string())
+                .await
+                .unwrap();
+            let packet = conn.read_packet(NoCompressor).await.unwrap();
+            assert_eq!(b"key".to_vec(), packet.key);
+            server.await.unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let pipe = Endpoint::Pipe(r"\\.\pipe\memcached".into());
+            let err = TokioConnection::connect(pipe.to_string())
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("Unsupported transport"), "{}", err);
+        })
+    }
+
+    #[test]
+    fn test_task_deadline() {
+        tokio_test::block_on(async {
+            assert_eq!(None, TaskDeadline.deadline());
+            let (early, late) = (Instant::now(), Instant::now() + Duration::from_secs(1));
+            let nested = with_deadline(late, async {
+                assert_eq!(Some(late), TaskDeadline.deadline());
+                with_deadline(late + Duration::from_secs(1), async {
+                    TaskDeadline.deadline()
+                })
+                .await
+            });
+            assert_eq!(Some(late), nested.await);
+            let earlier = with_deadline(
+                late,
+                with_deadline(early, async { TaskDeadline.deadline() }),
+            );
+            assert_eq!(Some(early), earlier.await);
+        })
+    }
+
     #[test]
     fn test_connect() {
         let mut rng = rand::thread_rng();